@@ -11,6 +11,6 @@ fn main() {
         .collect();
 
     tonic_build::configure()
-        .compile(&proto_files, &[proto_dir])
+        .compile_protos(&proto_files, &[proto_dir])
         .expect("Failed to compile protos")
 }
@@ -1,5 +1,12 @@
+// The binary does not drive a node yet; until it does, the platform and
+// Raft code is only exercised by the tests.
+#[cfg_attr(not(test), allow(dead_code))]
 mod platform;
+#[cfg(test)]
 mod platform_testing;
+#[cfg_attr(not(test), allow(dead_code))]
+mod raft;
+#[cfg(test)]
 mod tests;
 
 fn main() {
@@ -0,0 +1,260 @@
+use crate::platform::network::{Endpoint, NetworkInterface};
+use crate::platform::rpc::RpcClient;
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::spawn;
+use std::time::{Duration, Instant};
+
+/// How often each peer's connections are checked for broken streams.
+const CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Debug)]
+pub struct ConnectionConfig {
+    /// Connections kept open to each endpoint; calls take turns using them.
+    pub pool_size: usize,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// How far each backoff may stray from its nominal value, as a fraction
+    /// of it, so that peers cut off together don't retry in lockstep.
+    pub jitter: f64,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        ConnectionConfig {
+            pool_size: 1,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+            jitter: 0.2,
+        }
+    }
+}
+
+/// How long to wait before the next attempt after `failures` attempts in a
+/// row have failed: doubling from `initial_backoff` up to `max_backoff`, then
+/// spread by up to `jitter` either way according to `sample`, drawn from
+/// `[0, 1)`.
+pub fn backoff(config: &ConnectionConfig, failures: u32, sample: f64) -> Duration {
+    let exponent = failures.saturating_sub(1).min(31);
+    let nominal = config
+        .initial_backoff
+        .saturating_mul(1 << exponent)
+        .min(config.max_backoff);
+    if config.jitter <= 0.0 {
+        return nominal;
+    }
+    let factor = 1.0 - config.jitter + 2.0 * config.jitter * sample;
+    nominal.mul_f64(factor.max(0.0))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// No connection is open yet and an attempt is under way.
+    Connecting,
+    Connected,
+    /// The last attempt failed or every connection broke; another attempt
+    /// follows after a backoff.
+    Disconnected,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectionStateChange {
+    pub endpoint: Endpoint,
+    pub state: ConnectionState,
+}
+
+/// Keeps long-lived connections to peers, opened with
+/// `NetworkInterface::connect` once a peer is first asked for. A thread per
+/// peer drops connections whose stream failed or that a caller reported as
+/// broken, and reconnects with exponential backoff and jitter until the pool
+/// is full again, so a peer comes back by itself once the network heals.
+pub struct PeerConnectionManager {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    network_interface: Arc<dyn NetworkInterface>,
+    config: ConnectionConfig,
+    peers: Mutex<HashMap<Endpoint, Peer>>,
+    /// Signalled when a connection opens or breaks, or the manager shuts
+    /// down.
+    changed: Condvar,
+    subscribers: Mutex<Vec<Sender<ConnectionStateChange>>>,
+    is_shutdown: AtomicBool,
+}
+
+struct Peer {
+    pool: Vec<Arc<RpcClient>>,
+    next: usize,
+    state: ConnectionState,
+}
+
+impl PeerConnectionManager {
+    pub fn new(network_interface: Arc<dyn NetworkInterface>, config: ConnectionConfig) -> Self {
+        PeerConnectionManager {
+            shared: Arc::new(Shared {
+                network_interface,
+                config,
+                peers: Mutex::new(HashMap::new()),
+                changed: Condvar::new(),
+                subscribers: Mutex::new(Vec::new()),
+                is_shutdown: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Returns an open connection to `endpoint`, waiting up to `timeout` for
+    /// one if there is none. Fails with `NotConnected` if none opens in time.
+    pub fn get(&self, endpoint: &Endpoint, timeout: Duration) -> io::Result<Arc<RpcClient>> {
+        let deadline = Instant::now() + timeout;
+        let mut peers = self.shared.peers.lock().unwrap();
+        loop {
+            self.shared.prune(&mut peers, endpoint, None);
+            let peer = peers.get_mut(endpoint).unwrap();
+            if !peer.pool.is_empty() {
+                peer.next = (peer.next + 1) % peer.pool.len();
+                return Ok(Arc::clone(&peer.pool[peer.next]));
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    format!("no connection to {:?}", endpoint),
+                ));
+            }
+            peers = self
+                .shared
+                .changed
+                .wait_timeout(peers, remaining)
+                .unwrap()
+                .0;
+        }
+    }
+
+    /// Drops `client` from `endpoint`'s pool, for a caller that found the
+    /// stream dead although it has not failed, such as when nothing arrives
+    /// on it any more.
+    pub fn report_broken(&self, endpoint: &Endpoint, client: &Arc<RpcClient>) {
+        let mut peers = self.shared.peers.lock().unwrap();
+        self.shared.prune(&mut peers, endpoint, Some(client));
+    }
+
+    pub fn state(&self, endpoint: &Endpoint) -> Option<ConnectionState> {
+        let peers = self.shared.peers.lock().unwrap();
+        peers.get(endpoint).map(|peer| peer.state)
+    }
+
+    /// Reports every later change in any peer's connection state.
+    pub fn subscribe(&self) -> Receiver<ConnectionStateChange> {
+        let (sender, receiver) = channel();
+        self.shared.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+}
+
+impl Drop for PeerConnectionManager {
+    fn drop(&mut self) {
+        self.shared.is_shutdown.store(true, Ordering::SeqCst);
+        self.shared.changed.notify_all();
+    }
+}
+
+impl Shared {
+    /// Drops `endpoint`'s failed connections and `broken`, and starts looking
+    /// after the peer the first time it is asked for.
+    fn prune(
+        self: &Arc<Self>,
+        peers: &mut HashMap<Endpoint, Peer>,
+        endpoint: &Endpoint,
+        broken: Option<&Arc<RpcClient>>,
+    ) {
+        let peer = peers.entry(endpoint.clone()).or_insert_with(|| {
+            let shared = Arc::clone(self);
+            let endpoint = endpoint.clone();
+            spawn(move || shared.supervise(&endpoint));
+            Peer {
+                pool: Vec::new(),
+                next: 0,
+                state: ConnectionState::Connecting,
+            }
+        });
+        let size = peer.pool.len();
+        peer.pool.retain(|client| {
+            !client.is_closed() && !broken.is_some_and(|broken| Arc::ptr_eq(client, broken))
+        });
+        if peer.pool.len() < size {
+            if peer.pool.is_empty() {
+                self.set_state(peer, endpoint, ConnectionState::Disconnected);
+            }
+            self.changed.notify_all();
+        }
+    }
+
+    fn set_state(&self, peer: &mut Peer, endpoint: &Endpoint, state: ConnectionState) {
+        if peer.state == state {
+            return;
+        }
+        peer.state = state;
+        let change = ConnectionStateChange {
+            endpoint: endpoint.clone(),
+            state,
+        };
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.send(change.clone()).is_ok());
+    }
+
+    /// Keeps `endpoint`'s pool full until the manager is dropped: checks its
+    /// connections every `CHECK_INTERVAL` and opens new ones in place of
+    /// those that broke, backing off after each failed attempt.
+    fn supervise(self: Arc<Self>, endpoint: &Endpoint) {
+        let mut failures = 0;
+        let mut peers = self.peers.lock().unwrap();
+        while !self.is_shutdown.load(Ordering::SeqCst) {
+            self.prune(&mut peers, endpoint, None);
+            let peer = peers.get_mut(endpoint).unwrap();
+            if peer.pool.len() >= self.config.pool_size {
+                peers = self.changed.wait_timeout(peers, CHECK_INTERVAL).unwrap().0;
+                continue;
+            }
+            if peer.pool.is_empty() {
+                self.set_state(peer, endpoint, ConnectionState::Connecting);
+            }
+            drop(peers);
+
+            let result = self.network_interface.connect(&endpoint.ip, endpoint.port);
+            peers = self.peers.lock().unwrap();
+            let peer = peers.get_mut(endpoint).unwrap();
+            match result {
+                Ok(stream) => {
+                    failures = 0;
+                    peer.pool.push(Arc::new(RpcClient::new(stream)));
+                    self.set_state(peer, endpoint, ConnectionState::Connected);
+                    self.changed.notify_all();
+                }
+                Err(_) => {
+                    failures += 1;
+                    if peer.pool.is_empty() {
+                        self.set_state(peer, endpoint, ConnectionState::Disconnected);
+                    }
+                    let sample = self
+                        .network_interface
+                        .sample(&format!("backoff to {}:{}", endpoint.ip, endpoint.port));
+                    let retry_at = Instant::now() + backoff(&self.config, failures, sample);
+                    while !self.is_shutdown.load(Ordering::SeqCst) {
+                        let remaining = retry_at.saturating_duration_since(Instant::now());
+                        if remaining.is_zero() {
+                            break;
+                        }
+                        peers = self.changed.wait_timeout(peers, remaining).unwrap().0;
+                    }
+                }
+            }
+        }
+    }
+}
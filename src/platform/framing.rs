@@ -0,0 +1,332 @@
+use crate::platform::network::{Control, Endpoint, Packet, TcpStream};
+use prost::Message;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Bytes before each frame's payload: its length and the CRC32 of the
+/// payload, both little-endian.
+pub const FRAME_HEADER_SIZE: usize = 8;
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 << 20;
+
+fn check_frame_size(length: usize, max_frame_size: usize) -> io::Result<()> {
+    if length > max_frame_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "frame of {} bytes exceeds the limit of {}",
+                length, max_frame_size
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Frames `payload` for sending.
+pub fn encode_frame(payload: &[u8], max_frame_size: usize) -> io::Result<Vec<u8>> {
+    check_frame_size(payload.len(), max_frame_size)?;
+    let mut data = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+    data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    data.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+    data.extend_from_slice(payload);
+    Ok(data)
+}
+
+/// Frames the protobuf encoding of `message`, encoding it straight into the
+/// frame rather than through an intermediate buffer.
+pub fn encode_message_frame(message: &impl Message, max_frame_size: usize) -> io::Result<Vec<u8>> {
+    let length = message.encoded_len();
+    check_frame_size(length, max_frame_size)?;
+    let mut data = Vec::with_capacity(FRAME_HEADER_SIZE + length);
+    data.extend_from_slice(&(length as u32).to_le_bytes());
+    data.extend_from_slice(&[0; 4]);
+    message.encode(&mut data).unwrap();
+    let crc = crc32fast::hash(&data[FRAME_HEADER_SIZE..]);
+    data[4..FRAME_HEADER_SIZE].copy_from_slice(&crc.to_le_bytes());
+    Ok(data)
+}
+
+/// Reassembles frames from bytes that arrive in chunks of any size: a chunk
+/// may hold part of a frame, several frames, or the end of one and the start
+/// of the next.
+#[derive(Debug)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    max_frame_size: usize,
+}
+
+impl FrameDecoder {
+    pub fn new(max_frame_size: usize) -> Self {
+        FrameDecoder {
+            buffer: Vec::new(),
+            max_frame_size,
+        }
+    }
+
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Bytes received that are not yet part of a complete frame.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Takes the next complete frame's payload, or returns `None` until more
+    /// bytes arrive. Fails with `InvalidData` when a frame is larger than
+    /// allowed, which is caught from its header alone, or fails its checksum;
+    /// the stream cannot be resynchronized after that.
+    pub fn decode(&mut self) -> io::Result<Option<Box<[u8]>>> {
+        if self.buffer.len() < FRAME_HEADER_SIZE {
+            return Ok(None);
+        }
+        let length = u32::from_le_bytes(self.buffer[0..4].try_into().unwrap()) as usize;
+        check_frame_size(length, self.max_frame_size)?;
+        if self.buffer.len() < FRAME_HEADER_SIZE + length {
+            return Ok(None);
+        }
+
+        let crc = u32::from_le_bytes(self.buffer[4..8].try_into().unwrap());
+        let payload = &self.buffer[FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + length];
+        if crc32fast::hash(payload) != crc {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "frame failed its checksum",
+            ));
+        }
+        let payload = payload.into();
+        self.buffer.drain(..FRAME_HEADER_SIZE + length);
+        Ok(Some(payload))
+    }
+}
+
+/// Sends and receives whole frames over a `TcpStream`, so that messages keep
+/// their boundaries whether or not the stream underneath preserves them. A
+/// simulated stream hands over exactly what each `send` was given, while a
+/// system stream may split or merge writes on the way.
+#[derive(Debug)]
+pub struct FramedStream {
+    stream: Arc<dyn TcpStream>,
+    /// Keeps frames sent from different threads from interleaving when the
+    /// stream writes them in pieces.
+    send_lock: Mutex<()>,
+    decoder: Mutex<FrameDecoder>,
+    max_frame_size: usize,
+}
+
+impl FramedStream {
+    pub fn new(stream: Arc<dyn TcpStream>) -> Self {
+        Self::with_max_frame_size(stream, DEFAULT_MAX_FRAME_SIZE)
+    }
+
+    pub fn with_max_frame_size(stream: Arc<dyn TcpStream>, max_frame_size: usize) -> Self {
+        FramedStream {
+            stream,
+            send_lock: Mutex::new(()),
+            decoder: Mutex::new(FrameDecoder::new(max_frame_size)),
+            max_frame_size,
+        }
+    }
+
+    pub fn stream(&self) -> &Arc<dyn TcpStream> {
+        &self.stream
+    }
+
+    pub fn send_frame(&self, payload: &[u8]) -> io::Result<()> {
+        let frame = encode_frame(payload, self.max_frame_size)?;
+        let _guard = self.send_lock.lock().unwrap();
+        self.stream.send(&frame);
+        Ok(())
+    }
+
+    pub fn send_message(&self, message: &impl Message) -> io::Result<()> {
+        let frame = encode_message_frame(message, self.max_frame_size)?;
+        let _guard = self.send_lock.lock().unwrap();
+        self.stream.send(&frame);
+        Ok(())
+    }
+
+    /// Blocks until a whole frame arrives. Fails with `UnexpectedEof` once the
+    /// stream closes, even partway through a frame.
+    pub fn receive_frame(&self) -> io::Result<Box<[u8]>> {
+        let mut decoder = self.decoder.lock().unwrap();
+        loop {
+            if let Some(payload) = decoder.decode()? {
+                return Ok(payload);
+            }
+            let data = self.stream.receive().ok_or(io::ErrorKind::UnexpectedEof)?;
+            decoder.extend(&data);
+        }
+    }
+
+    /// Like `receive_frame`, but fails with `TimedOut` when the whole frame
+    /// does not arrive in time. Whatever part of it did arrive is kept for the
+    /// next call.
+    pub fn receive_frame_timeout(&self, timeout: Duration) -> io::Result<Box<[u8]>> {
+        let deadline = Instant::now() + timeout;
+        let mut decoder = self.decoder.lock().unwrap();
+        loop {
+            if let Some(payload) = decoder.decode()? {
+                return Ok(payload);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            decoder.extend(&self.stream.receive_timeout(remaining)?);
+        }
+    }
+
+    pub fn receive_message<M: Message + Default>(&self) -> io::Result<M> {
+        decode_message(&self.receive_frame()?)
+    }
+
+    #[allow(dead_code)]
+    pub fn receive_message_timeout<M: Message + Default>(
+        &self,
+        timeout: Duration,
+    ) -> io::Result<M> {
+        decode_message(&self.receive_frame_timeout(timeout)?)
+    }
+}
+
+fn decode_message<M: Message + Default>(payload: &[u8]) -> io::Result<M> {
+    M::decode(payload).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+/// Bytes before each payload of a numbered frame: its sequence number,
+/// little-endian.
+pub const SEQUENCE_SIZE: usize = 8;
+pub const DEFAULT_REORDER_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The sequence number of the next frame to hand out, and the frames that
+/// arrived ahead of it.
+#[derive(Debug, Default)]
+struct Reorderer {
+    next_read: u64,
+    out_of_order: BTreeMap<u64, Box<[u8]>>,
+    /// When the frame at `next_read` was first found missing behind later
+    /// ones.
+    gap_since: Option<Instant>,
+}
+
+/// A `TcpStream` that delivers what each `send` was given whole and in
+/// order, over a system stream that splits and merges writes as well as a
+/// simulated one that delays and reorders them. Each send goes out as a
+/// numbered frame and receiving puts the frames back in order. Like a real
+/// connection whose retransmissions keep failing, it gives up with
+/// `ConnectionReset` once a frame has stayed missing behind later ones for
+/// the reorder timeout, so a lost frame ends the connection.
+#[derive(Debug)]
+pub struct SequencedStream {
+    stream: FramedStream,
+    next_write: Mutex<u64>,
+    reorderer: Mutex<Reorderer>,
+    reorder_timeout: Duration,
+}
+
+impl SequencedStream {
+    pub fn new(stream: Arc<dyn TcpStream>) -> Self {
+        Self::with_reorder_timeout(stream, DEFAULT_REORDER_TIMEOUT)
+    }
+
+    pub fn with_reorder_timeout(stream: Arc<dyn TcpStream>, reorder_timeout: Duration) -> Self {
+        SequencedStream {
+            stream: FramedStream::new(stream),
+            next_write: Mutex::new(0),
+            reorderer: Mutex::new(Reorderer::default()),
+            reorder_timeout,
+        }
+    }
+
+    fn receive_until(&self, deadline: Option<Instant>) -> io::Result<Box<[u8]>> {
+        let mut reorderer = self.reorderer.lock().unwrap();
+        loop {
+            let next_read = reorderer.next_read;
+            if let Some(data) = reorderer.out_of_order.remove(&next_read) {
+                reorderer.next_read += 1;
+                reorderer.gap_since = None;
+                return Ok(data);
+            }
+
+            let gap_deadline = if reorderer.out_of_order.is_empty() {
+                None
+            } else {
+                Some(*reorderer.gap_since.get_or_insert_with(Instant::now) + self.reorder_timeout)
+            };
+            let frame = match deadline.into_iter().chain(gap_deadline).min() {
+                None => self.stream.receive_frame()?,
+                Some(wait_until) => {
+                    let remaining = wait_until.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        if gap_deadline == Some(wait_until) {
+                            return Err(io::Error::new(
+                                io::ErrorKind::ConnectionReset,
+                                format!("frame {} was lost", next_read),
+                            ));
+                        }
+                        return Err(io::ErrorKind::TimedOut.into());
+                    }
+                    match self.stream.receive_frame_timeout(remaining) {
+                        Err(error) if error.kind() == io::ErrorKind::TimedOut => continue,
+                        result => result?,
+                    }
+                }
+            };
+            if frame.len() < SEQUENCE_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "frame too short for a sequence number",
+                ));
+            }
+            let sequence = u64::from_le_bytes(frame[..SEQUENCE_SIZE].try_into().unwrap());
+            if sequence >= next_read {
+                reorderer
+                    .out_of_order
+                    .insert(sequence, Box::from(&frame[SEQUENCE_SIZE..]));
+            }
+        }
+    }
+}
+
+impl TcpStream for SequencedStream {
+    fn send(&self, data: &[u8]) {
+        let mut next_write = self.next_write.lock().unwrap();
+        let mut frame = Vec::with_capacity(SEQUENCE_SIZE + data.len());
+        frame.extend_from_slice(&next_write.to_le_bytes());
+        frame.extend_from_slice(data);
+        match self.stream.send_frame(&frame) {
+            Ok(()) => *next_write += 1,
+            Err(error) => println!(
+                "Failed to send to {:?}: {}",
+                self.get_remote_endpoint(),
+                error
+            ),
+        }
+    }
+
+    fn receive(&self) -> Option<Box<[u8]>> {
+        self.receive_until(None).ok()
+    }
+
+    fn receive_timeout(&self, timeout: Duration) -> io::Result<Box<[u8]>> {
+        self.receive_until(Some(Instant::now() + timeout))
+    }
+
+    fn send_control(&self, control: Control) {
+        self.stream.stream().send_control(control);
+    }
+
+    fn get_local_endpoint(&self) -> Endpoint {
+        self.stream.stream().get_local_endpoint()
+    }
+
+    fn get_remote_endpoint(&self) -> Endpoint {
+        self.stream.stream().get_remote_endpoint()
+    }
+
+    fn on_packet_received(&self, packet: &Packet) {
+        self.stream.stream().on_packet_received(packet);
+    }
+}
@@ -0,0 +1,303 @@
+use crate::platform::framing::{FramedStream, DEFAULT_REORDER_TIMEOUT, SEQUENCE_SIZE};
+use crate::platform::network::{Endpoint, Incoming, NetworkInterface, TcpListener, TcpStream};
+use hyper_util::rt::TokioIo;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::thread::spawn;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, Sleep};
+use tokio_stream::{Stream, StreamExt};
+use tonic::transport::server::Connected;
+use tonic::transport::{Channel, Uri};
+use tower_service::Service;
+
+/// How long the reader thread waits for a segment before checking whether the
+/// adapter is still around.
+const RECEIVE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// The most data sent in one segment.
+const MAX_SEGMENT_SIZE: usize = 64 * 1024;
+
+/// The connection details tonic attaches to each request it serves.
+#[derive(Debug, Clone)]
+pub struct TcpStreamInfo {
+    #[allow(dead_code)]
+    pub local_endpoint: Endpoint,
+    pub remote_endpoint: Endpoint,
+}
+
+/// A `TcpStream` as the ordered byte stream that tokio, hyper and tonic
+/// expect. Every write goes out as a numbered segment in a frame of its own:
+/// the frame keeps the segment whole over a system stream, which merges and
+/// splits writes, and the number lets the reader undo the simulator delaying,
+/// reordering or losing them. The reader puts segments back in order and,
+/// like a real connection whose retransmissions keep failing, gives up with
+/// `ConnectionReset` once a segment has stayed missing behind later ones for
+/// the reorder timeout. Shutting down writes, or dropping the adapter, sends
+/// an empty segment, which the other side reads as end of file.
+#[derive(Debug)]
+pub struct AsyncTcpStream {
+    stream: Arc<FramedStream>,
+    segments: mpsc::UnboundedReceiver<Box<[u8]>>,
+    next_read: u64,
+    next_write: u64,
+    /// Segments that arrived ahead of `next_read`.
+    out_of_order: BTreeMap<u64, Box<[u8]>>,
+    /// The segment being read and how much of it has been read.
+    current: Option<(Box<[u8]>, usize)>,
+    reorder_timeout: Duration,
+    /// When the gap before the first out-of-order segment turns fatal.
+    gap_deadline: Option<Pin<Box<Sleep>>>,
+    is_eof: bool,
+    is_write_shutdown: bool,
+}
+
+impl AsyncTcpStream {
+    pub fn new(stream: Arc<dyn TcpStream>) -> Self {
+        Self::with_reorder_timeout(stream, DEFAULT_REORDER_TIMEOUT)
+    }
+
+    /// Spawns a thread that moves segments off the blocking platform stream
+    /// until the adapter is dropped or the stream fails.
+    pub fn with_reorder_timeout(stream: Arc<dyn TcpStream>, reorder_timeout: Duration) -> Self {
+        let (sender, segments) = mpsc::unbounded_channel();
+        let stream = Arc::new(FramedStream::new(stream));
+        let reader = Arc::clone(&stream);
+        spawn(move || loop {
+            match reader.receive_frame_timeout(RECEIVE_POLL_INTERVAL) {
+                Ok(data) => {
+                    if sender.send(data).is_err() {
+                        return;
+                    }
+                }
+                Err(error) if error.kind() == io::ErrorKind::TimedOut => {
+                    if sender.is_closed() {
+                        return;
+                    }
+                }
+                Err(_) => return,
+            }
+        });
+        AsyncTcpStream {
+            stream,
+            segments,
+            next_read: 0,
+            next_write: 0,
+            out_of_order: BTreeMap::new(),
+            current: None,
+            reorder_timeout,
+            gap_deadline: None,
+            is_eof: false,
+            is_write_shutdown: false,
+        }
+    }
+
+    pub fn get_local_endpoint(&self) -> Endpoint {
+        self.stream.stream().get_local_endpoint()
+    }
+
+    pub fn get_remote_endpoint(&self) -> Endpoint {
+        self.stream.stream().get_remote_endpoint()
+    }
+
+    fn on_segment_received(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.len() < SEQUENCE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "segment too short for a sequence number",
+            ));
+        }
+        let sequence = u64::from_le_bytes(data[..SEQUENCE_SIZE].try_into().unwrap());
+        if sequence >= self.next_read {
+            self.out_of_order
+                .insert(sequence, Box::from(&data[SEQUENCE_SIZE..]));
+        }
+        Ok(())
+    }
+
+    /// Sends the empty segment that marks the end of the data, once.
+    fn close_write(&mut self) {
+        if !self.is_write_shutdown {
+            self.is_write_shutdown = true;
+            // An empty segment is well within the frame size limit.
+            let _ = self.send_segment(&[]);
+        }
+    }
+
+    fn send_segment(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut segment = Vec::with_capacity(SEQUENCE_SIZE + data.len());
+        segment.extend_from_slice(&self.next_write.to_le_bytes());
+        segment.extend_from_slice(data);
+        self.stream.send_frame(&segment)?;
+        self.next_write += 1;
+        Ok(())
+    }
+}
+
+impl AsyncRead for AsyncTcpStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            if let Some((segment, offset)) = &mut this.current {
+                let length = buf.remaining().min(segment.len() - *offset);
+                buf.put_slice(&segment[*offset..*offset + length]);
+                *offset += length;
+                if *offset == segment.len() {
+                    this.current = None;
+                }
+                return Poll::Ready(Ok(()));
+            }
+            if this.is_eof {
+                return Poll::Ready(Ok(()));
+            }
+            if let Some(segment) = this.out_of_order.remove(&this.next_read) {
+                this.next_read += 1;
+                this.gap_deadline = None;
+                if segment.is_empty() {
+                    this.is_eof = true;
+                } else {
+                    this.current = Some((segment, 0));
+                }
+                continue;
+            }
+
+            match this.segments.poll_recv(cx) {
+                Poll::Ready(Some(data)) => {
+                    this.on_segment_received(&data)?;
+                    continue;
+                }
+                Poll::Ready(None) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::ConnectionReset,
+                        "connection closed",
+                    )))
+                }
+                Poll::Pending => {}
+            }
+            if !this.out_of_order.is_empty() {
+                let reorder_timeout = this.reorder_timeout;
+                let gap_deadline = this
+                    .gap_deadline
+                    .get_or_insert_with(|| Box::pin(sleep(reorder_timeout)));
+                if gap_deadline.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::ConnectionReset,
+                        format!("segment {} was lost", this.next_read),
+                    )));
+                }
+            }
+            return Poll::Pending;
+        }
+    }
+}
+
+impl AsyncWrite for AsyncTcpStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.is_write_shutdown {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        let length = buf.len().min(MAX_SEGMENT_SIZE);
+        self.send_segment(&buf[..length])?;
+        Poll::Ready(Ok(length))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.close_write();
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Closing the connection, including after a read failed, tells the other
+/// side it is over, so it stops waiting on a connection nobody reads.
+impl Drop for AsyncTcpStream {
+    fn drop(&mut self) {
+        self.close_write();
+    }
+}
+
+impl Connected for AsyncTcpStream {
+    type ConnectInfo = TcpStreamInfo;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        TcpStreamInfo {
+            local_endpoint: self.get_local_endpoint(),
+            remote_endpoint: self.get_remote_endpoint(),
+        }
+    }
+}
+
+/// Connections accepted by `listener`, for tonic's
+/// `Server::serve_with_incoming`. The stream ends once the listener is shut
+/// down, which the server's owner does explicitly.
+pub fn incoming(listener: Arc<dyn TcpListener>) -> impl Stream<Item = io::Result<AsyncTcpStream>> {
+    Incoming::new(listener).map(|result| result.map(AsyncTcpStream::new))
+}
+
+/// Opens connections for a tonic `Channel` through a `NetworkInterface`, so a
+/// generated client talks over whichever network the interface belongs to.
+#[derive(Clone)]
+pub struct TcpConnector {
+    network_interface: Arc<dyn NetworkInterface>,
+}
+
+impl TcpConnector {
+    pub fn new(network_interface: Arc<dyn NetworkInterface>) -> Self {
+        TcpConnector { network_interface }
+    }
+}
+
+impl Service<Uri> for TcpConnector {
+    type Response = TokioIo<AsyncTcpStream>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let network_interface = Arc::clone(&self.network_interface);
+        Box::pin(async move {
+            let host = uri
+                .host()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "URI has no host"))?
+                .to_string();
+            let port = uri.port_u16().unwrap_or(80);
+            // Connecting blocks through the handshake.
+            let stream =
+                tokio::task::spawn_blocking(move || network_interface.connect_by_name(&host, port))
+                    .await
+                    .map_err(io::Error::other)??;
+            Ok(TokioIo::new(AsyncTcpStream::new(stream)))
+        })
+    }
+}
+
+/// A channel to the gRPC server at `endpoint`, reached through
+/// `network_interface`. It connects lazily and reconnects after failures, like
+/// any tonic channel.
+pub fn channel(
+    network_interface: Arc<dyn NetworkInterface>,
+    endpoint: &Endpoint,
+) -> Result<Channel, tonic::transport::Error> {
+    let uri = format!("http://{}:{}", endpoint.ip, endpoint.port);
+    Ok(tonic::transport::Endpoint::from_shared(uri)?
+        .connect_with_connector_lazy(TcpConnector::new(network_interface)))
+}
@@ -1 +1,9 @@
+pub mod connection_manager;
+pub mod disk;
+pub mod framing;
+pub mod grpc;
 pub mod network;
+pub mod network_interface;
+pub mod rpc;
+pub mod storage;
+pub mod tls;
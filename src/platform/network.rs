@@ -1,7 +1,18 @@
 use std::fmt::Debug;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio_stream::Stream;
 
-#[derive(Clone, Debug)]
+pub const BROADCAST_IP: &str = "255.255.255.255";
+pub const DEFAULT_BACKLOG: usize = 128;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Endpoint {
     pub ip: String,
     pub port: u16,
@@ -9,13 +20,17 @@ pub struct Endpoint {
 
 #[derive(Debug, Clone)]
 pub enum Control {
-    Sync,
+    Syn,
+    SynAck,
+    Ack,
+    Reset,
 }
 
 #[derive(Debug, Clone)]
 pub enum PacketType {
     Data,
     Control,
+    Datagram,
 }
 
 #[derive(Debug, Clone)]
@@ -27,14 +42,45 @@ pub struct Packet {
     pub payload: Option<Box<[u8]>>,
 }
 
-pub trait NetworkInterface {
-    fn connect(&self, remote_ip: &str, remote_port: u16) -> Option<Arc<dyn TcpStream>>;
-    fn bind_tcp(&self, local_ip: &str, local_port: u16) -> Option<Arc<dyn TcpListener>>;
+pub trait NetworkInterface: Send + Sync {
+    fn connect(&self, remote_ip: &str, remote_port: u16) -> io::Result<Arc<dyn TcpStream>>;
+    fn bind_tcp_with_backlog(
+        &self,
+        local_ip: &str,
+        local_port: u16,
+        backlog: usize,
+    ) -> Option<Arc<dyn TcpListener>>;
+    fn bind_udp(&self, local_ip: &str, local_port: u16) -> Option<Arc<dyn UdpSocket>>;
+    fn resolve(&self, hostname: &str) -> io::Result<Vec<String>>;
+
+    fn bind_tcp(&self, local_ip: &str, local_port: u16) -> Option<Arc<dyn TcpListener>> {
+        self.bind_tcp_with_backlog(local_ip, local_port, DEFAULT_BACKLOG)
+    }
+
+    fn connect_by_name(&self, hostname: &str, remote_port: u16) -> io::Result<Arc<dyn TcpStream>> {
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no addresses to connect to");
+        for remote_ip in self.resolve(hostname)? {
+            match self.connect(&remote_ip, remote_port) {
+                Ok(tcp_stream) => return Ok(tcp_stream),
+                Err(error) => last_error = error,
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Draws a number from `[0, 1)` for a random choice named by `stream`,
+    /// such as how long to back off before reconnecting. A simulated interface
+    /// draws it from its network, so that the network's seed reproduces it.
+    fn sample(&self, _stream: &str) -> f64 {
+        rand::random()
+    }
 }
 
 pub trait TcpStream: Debug + Send + Sync {
     fn send(&self, data: &[u8]);
     fn receive(&self) -> Option<Box<[u8]>>;
+    /// Like `receive`, but fails with `TimedOut` when nothing arrives in time.
+    fn receive_timeout(&self, timeout: Duration) -> io::Result<Box<[u8]>>;
     fn send_control(&self, control: Control);
     fn get_local_endpoint(&self) -> Endpoint;
     fn get_remote_endpoint(&self) -> Endpoint;
@@ -42,5 +88,91 @@ pub trait TcpStream: Debug + Send + Sync {
 }
 
 pub trait TcpListener: Send + Sync {
-    fn accept(&self) -> Option<Arc<dyn TcpStream>>;
+    /// Blocks until a connection is available or the listener is shut down.
+    fn accept(&self) -> io::Result<Arc<dyn TcpStream>> {
+        self.accept_cancellable(&AtomicBool::new(false))
+    }
+    /// Like `accept`, but gives up with `Interrupted` once `is_cancelled` is
+    /// set and `wake_acceptors` is called.
+    fn accept_cancellable(&self, is_cancelled: &AtomicBool) -> io::Result<Arc<dyn TcpStream>>;
+    /// Wakes blocked acceptors so they notice that they were cancelled.
+    fn wake_acceptors(&self);
+    /// Returns a queued connection, or `WouldBlock` when none is pending.
+    fn try_accept(&self) -> io::Result<Arc<dyn TcpStream>>;
+    /// Stops accepting connections and wakes blocked acceptors with an error.
+    fn shutdown(&self);
+    fn is_shutdown(&self) -> bool;
+    fn incoming(self: Arc<Self>) -> Incoming;
+}
+
+/// Stream of connections accepted by a `TcpListener`. Failed accepts are
+/// yielded as errors and accepting goes on; once the listener is shut down the
+/// stream yields that error and ends. Each accept blocks a thread of the
+/// runtime's blocking pool, so dropping the stream cancels its pending accept.
+/// The listener itself stays open for its other users.
+pub struct Incoming {
+    listener: Arc<dyn TcpListener>,
+    pending_accept: Option<JoinHandle<io::Result<Arc<dyn TcpStream>>>>,
+    is_cancelled: Arc<AtomicBool>,
+    done: bool,
+}
+
+impl Incoming {
+    pub fn new(listener: Arc<dyn TcpListener>) -> Self {
+        Incoming {
+            listener,
+            pending_accept: None,
+            is_cancelled: Arc::new(AtomicBool::new(false)),
+            done: false,
+        }
+    }
+}
+
+impl Stream for Incoming {
+    type Item = io::Result<Arc<dyn TcpStream>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+
+        let listener = Arc::clone(&self.listener);
+        let is_cancelled = Arc::clone(&self.is_cancelled);
+        let pending_accept = self.pending_accept.get_or_insert_with(|| {
+            tokio::task::spawn_blocking(move || listener.accept_cancellable(&is_cancelled))
+        });
+        let result = match Pin::new(pending_accept).poll(cx) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(Ok(result)) => result,
+            Poll::Ready(Err(error)) => Err(io::Error::other(error)),
+        };
+        self.pending_accept = None;
+        if result.is_err() && self.listener.is_shutdown() {
+            self.done = true;
+        }
+        Poll::Ready(Some(result))
+    }
+}
+
+impl Drop for Incoming {
+    fn drop(&mut self) {
+        self.is_cancelled.store(true, Ordering::SeqCst);
+        self.listener.wake_acceptors();
+    }
+}
+
+pub trait UdpSocket: Debug + Send + Sync {
+    fn send_to(&self, data: &[u8], remote_ip: &str, remote_port: u16) -> io::Result<()>;
+    fn recv_from(&self) -> Option<(Box<[u8]>, Endpoint)>;
+    fn set_broadcast(&self, broadcast: bool) -> io::Result<()>;
+    fn join_multicast(&self, group_ip: &str) -> io::Result<()>;
+    fn leave_multicast(&self, group_ip: &str) -> io::Result<()>;
+    fn get_local_endpoint(&self) -> Endpoint;
+    fn on_packet_received(&self, packet: &Packet);
+}
+
+pub fn is_multicast_ip(ip: &str) -> bool {
+    ip.parse::<std::net::Ipv4Addr>()
+        .map(|ip| ip.is_multicast())
+        .unwrap_or(false)
 }
@@ -0,0 +1,260 @@
+use crate::platform::network::{
+    Control, Endpoint, Incoming, NetworkInterface, Packet, TcpListener, TcpStream, UdpSocket,
+};
+use std::io;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::Duration;
+
+const MAX_DATAGRAM_SIZE: usize = 65536;
+const RECEIVE_BUFFER_SIZE: usize = 65536;
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Default)]
+pub struct SystemNetworkInterface {}
+
+impl SystemNetworkInterface {
+    pub fn new() -> Self {
+        SystemNetworkInterface {}
+    }
+}
+
+fn to_bind_ip(local_ip: &str) -> &str {
+    if local_ip.is_empty() {
+        "0.0.0.0"
+    } else {
+        local_ip
+    }
+}
+
+fn to_endpoint(address: SocketAddr) -> Endpoint {
+    Endpoint {
+        ip: address.ip().to_string(),
+        port: address.port(),
+    }
+}
+
+fn parse_multicast_ip(group_ip: &str) -> io::Result<Ipv4Addr> {
+    group_ip
+        .parse::<Ipv4Addr>()
+        .ok()
+        .filter(|ip| ip.is_multicast())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a multicast address"))
+}
+
+impl NetworkInterface for SystemNetworkInterface {
+    fn connect(&self, remote_ip: &str, remote_port: u16) -> io::Result<Arc<dyn TcpStream>> {
+        let stream = std::net::TcpStream::connect((remote_ip, remote_port))?;
+        Ok(Arc::new(SystemTcpStream::new(stream)?))
+    }
+
+    /// The standard library does not expose `listen(2)`, so the operating
+    /// system's default backlog is used regardless of `backlog`.
+    fn bind_tcp_with_backlog(
+        &self,
+        local_ip: &str,
+        local_port: u16,
+        _backlog: usize,
+    ) -> Option<Arc<dyn TcpListener>> {
+        let listener = std::net::TcpListener::bind((to_bind_ip(local_ip), local_port)).ok()?;
+        listener.set_nonblocking(true).ok()?;
+        Some(Arc::new(SystemTcpListener {
+            listener,
+            is_shutdown: AtomicBool::new(false),
+        }))
+    }
+
+    fn bind_udp(&self, local_ip: &str, local_port: u16) -> Option<Arc<dyn UdpSocket>> {
+        let socket = std::net::UdpSocket::bind((to_bind_ip(local_ip), local_port)).ok()?;
+        let local_endpoint = to_endpoint(socket.local_addr().ok()?);
+        Some(Arc::new(SystemUdpSocket {
+            socket,
+            local_endpoint,
+        }))
+    }
+
+    fn resolve(&self, hostname: &str) -> io::Result<Vec<String>> {
+        let mut ips: Vec<String> = Vec::new();
+        for address in (hostname, 0).to_socket_addrs()? {
+            let ip = address.ip().to_string();
+            if !ips.contains(&ip) {
+                ips.push(ip);
+            }
+        }
+        Ok(ips)
+    }
+}
+
+/// The read timeout is a property of the socket, so reads are serialized to
+/// keep the timeout of one `receive_timeout` from leaking into another read.
+#[derive(Debug)]
+struct SystemTcpStream {
+    stream: std::net::TcpStream,
+    read_lock: Mutex<()>,
+    local_endpoint: Endpoint,
+    remote_endpoint: Endpoint,
+}
+
+impl SystemTcpStream {
+    fn new(stream: std::net::TcpStream) -> io::Result<Self> {
+        let local_endpoint = to_endpoint(stream.local_addr()?);
+        let remote_endpoint = to_endpoint(stream.peer_addr()?);
+        Ok(SystemTcpStream {
+            stream,
+            read_lock: Mutex::new(()),
+            local_endpoint,
+            remote_endpoint,
+        })
+    }
+
+    fn receive_with_error(&self) -> io::Result<Box<[u8]>> {
+        let mut buffer = vec![0; RECEIVE_BUFFER_SIZE];
+        let size = (&self.stream).read(&mut buffer)?;
+        if size == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        buffer.truncate(size);
+        Ok(buffer.into_boxed_slice())
+    }
+}
+
+impl TcpStream for SystemTcpStream {
+    fn send(&self, data: &[u8]) {
+        if let Err(error) = (&self.stream).write_all(data) {
+            println!("Failed to send to {:?}: {}", self.remote_endpoint, error);
+        }
+    }
+
+    fn receive(&self) -> Option<Box<[u8]>> {
+        let _read_lock = self.read_lock.lock().unwrap();
+        self.receive_with_error().ok()
+    }
+
+    fn receive_timeout(&self, timeout: Duration) -> io::Result<Box<[u8]>> {
+        // The socket rejects a zero read timeout.
+        if timeout.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        let _read_lock = self.read_lock.lock().unwrap();
+        self.stream.set_read_timeout(Some(timeout))?;
+        let result = self.receive_with_error();
+        self.stream.set_read_timeout(None)?;
+        result.map_err(|error| match error.kind() {
+            io::ErrorKind::WouldBlock => io::ErrorKind::TimedOut.into(),
+            _ => error,
+        })
+    }
+
+    fn send_control(&self, _control: Control) {
+        // Handshakes are performed by the operating system.
+    }
+
+    fn get_local_endpoint(&self) -> Endpoint {
+        self.local_endpoint.clone()
+    }
+
+    fn get_remote_endpoint(&self) -> Endpoint {
+        self.remote_endpoint.clone()
+    }
+
+    fn on_packet_received(&self, _packet: &Packet) {
+        // Packets are delivered by the operating system.
+    }
+}
+
+/// The standard library cannot interrupt a blocking `accept`, so the socket is
+/// kept non-blocking and `accept` polls it until a connection arrives, the
+/// listener is shut down or the accept is cancelled.
+struct SystemTcpListener {
+    listener: std::net::TcpListener,
+    is_shutdown: AtomicBool,
+}
+
+impl TcpListener for SystemTcpListener {
+    fn accept_cancellable(&self, is_cancelled: &AtomicBool) -> io::Result<Arc<dyn TcpStream>> {
+        loop {
+            match self.try_accept() {
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                    if is_cancelled.load(Ordering::SeqCst) {
+                        return Err(io::ErrorKind::Interrupted.into());
+                    }
+                    sleep(ACCEPT_POLL_INTERVAL)
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn wake_acceptors(&self) {
+        // Acceptors poll, so they notice cancellation on their own.
+    }
+
+    fn try_accept(&self) -> io::Result<Arc<dyn TcpStream>> {
+        if self.is_shutdown.load(Ordering::SeqCst) {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "listener is shut down",
+            ));
+        }
+        let (stream, _) = self.listener.accept()?;
+        stream.set_nonblocking(false)?;
+        Ok(Arc::new(SystemTcpStream::new(stream)?))
+    }
+
+    fn shutdown(&self) {
+        self.is_shutdown.store(true, Ordering::SeqCst);
+    }
+
+    fn is_shutdown(&self) -> bool {
+        self.is_shutdown.load(Ordering::SeqCst)
+    }
+
+    fn incoming(self: Arc<Self>) -> Incoming {
+        Incoming::new(self)
+    }
+}
+
+#[derive(Debug)]
+struct SystemUdpSocket {
+    socket: std::net::UdpSocket,
+    local_endpoint: Endpoint,
+}
+
+impl UdpSocket for SystemUdpSocket {
+    fn send_to(&self, data: &[u8], remote_ip: &str, remote_port: u16) -> io::Result<()> {
+        self.socket.send_to(data, (remote_ip, remote_port))?;
+        Ok(())
+    }
+
+    fn recv_from(&self) -> Option<(Box<[u8]>, Endpoint)> {
+        let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+        let (size, source) = self.socket.recv_from(&mut buffer).ok()?;
+        buffer.truncate(size);
+        Some((buffer.into_boxed_slice(), to_endpoint(source)))
+    }
+
+    fn set_broadcast(&self, broadcast: bool) -> io::Result<()> {
+        self.socket.set_broadcast(broadcast)
+    }
+
+    fn join_multicast(&self, group_ip: &str) -> io::Result<()> {
+        self.socket
+            .join_multicast_v4(&parse_multicast_ip(group_ip)?, &Ipv4Addr::UNSPECIFIED)
+    }
+
+    fn leave_multicast(&self, group_ip: &str) -> io::Result<()> {
+        self.socket
+            .leave_multicast_v4(&parse_multicast_ip(group_ip)?, &Ipv4Addr::UNSPECIFIED)
+    }
+
+    fn get_local_endpoint(&self) -> Endpoint {
+        self.local_endpoint.clone()
+    }
+
+    fn on_packet_received(&self, _packet: &Packet) {
+        // Packets are delivered by the operating system.
+    }
+}
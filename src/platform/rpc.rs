@@ -0,0 +1,275 @@
+use crate::platform::framing::FramedStream;
+use crate::platform::network::TcpStream;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+use std::time::{Duration, Instant};
+
+const REQUEST: u8 = 1;
+const RESPONSE: u8 = 2;
+const CANCEL: u8 = 3;
+const DROPPED: u8 = 4;
+
+/// Bytes before each message's payload: its kind and correlation ID.
+const HEADER_SIZE: usize = 9;
+/// A request also carries how long the caller will wait for it, in
+/// milliseconds.
+const REQUEST_HEADER_SIZE: usize = HEADER_SIZE + 4;
+
+/// How often the client's reader checks whether the client was dropped.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Answers a request's payload, or returns `None` to drop it, which fails the
+/// call with `ConnectionAborted`.
+pub type RpcHandler = Arc<dyn Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync>;
+
+type CallResult = io::Result<Box<[u8]>>;
+
+fn encode(kind: u8, id: u64, payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(HEADER_SIZE + payload.len());
+    data.push(kind);
+    data.extend_from_slice(&id.to_le_bytes());
+    data.extend_from_slice(payload);
+    data
+}
+
+fn decode(data: &[u8]) -> io::Result<(u8, u64, &[u8])> {
+    if data.len() < HEADER_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "truncated RPC header",
+        ));
+    }
+    let id = u64::from_le_bytes(data[1..HEADER_SIZE].try_into().unwrap());
+    Ok((data[0], id, &data[HEADER_SIZE..]))
+}
+
+/// The client end of a connection that carries many calls at once. Each call
+/// is tagged with a correlation ID, so calls can be pipelined without waiting
+/// for earlier ones and their responses can arrive in any order. A reader
+/// thread hands each response to its caller until the client is dropped or
+/// the stream fails, at which point every outstanding call fails.
+pub struct RpcClient {
+    shared: Arc<ClientShared>,
+}
+
+struct ClientShared {
+    stream: FramedStream,
+    next_id: AtomicU64,
+    pending: Mutex<HashMap<u64, Sender<CallResult>>>,
+    is_closed: AtomicBool,
+    last_received: Mutex<Instant>,
+}
+
+impl RpcClient {
+    pub fn new(stream: Arc<dyn TcpStream>) -> Self {
+        let shared = Arc::new(ClientShared {
+            stream: FramedStream::new(stream),
+            next_id: AtomicU64::new(1),
+            pending: Mutex::new(HashMap::new()),
+            is_closed: AtomicBool::new(false),
+            last_received: Mutex::new(Instant::now()),
+        });
+        let reader = Arc::clone(&shared);
+        spawn(move || reader.read_responses());
+        RpcClient { shared }
+    }
+
+    #[allow(dead_code)]
+    pub fn stream(&self) -> &Arc<dyn TcpStream> {
+        self.shared.stream.stream()
+    }
+
+    /// Whether the stream failed or sent something that could not be read.
+    /// A closed client fails every call.
+    pub fn is_closed(&self) -> bool {
+        self.shared.is_closed.load(Ordering::SeqCst)
+    }
+
+    /// Whether any response arrived after `instant`, which tells a peer that
+    /// is slow apart from one that can no longer be reached.
+    pub fn has_received_since(&self, instant: Instant) -> bool {
+        *self.shared.last_received.lock().unwrap() > instant
+    }
+
+    /// Sends `payload` and waits up to `timeout` for the response.
+    pub fn call(&self, payload: &[u8], timeout: Duration) -> CallResult {
+        self.start(payload, timeout)?.wait()
+    }
+
+    /// Sends `payload` without waiting, so that more calls can follow it on
+    /// the stream before its response arrives. The server is told how long
+    /// the call will wait, and skips it if that passes before it gets to it.
+    pub fn start(&self, payload: &[u8], timeout: Duration) -> io::Result<PendingCall> {
+        if self.is_closed() {
+            return Err(io::ErrorKind::ConnectionAborted.into());
+        }
+        let id = self.shared.next_id.fetch_add(1, Ordering::SeqCst);
+        let (sender, receiver) = channel();
+        self.shared.pending.lock().unwrap().insert(id, sender);
+        // The reader may have failed every outstanding call just before.
+        if self.is_closed() {
+            self.shared.pending.lock().unwrap().remove(&id);
+            return Err(io::ErrorKind::ConnectionAborted.into());
+        }
+
+        let timeout_ms = timeout.as_millis().min(u32::MAX as u128) as u32;
+        let mut data = Vec::with_capacity(REQUEST_HEADER_SIZE + payload.len());
+        data.push(REQUEST);
+        data.extend_from_slice(&id.to_le_bytes());
+        data.extend_from_slice(&timeout_ms.to_le_bytes());
+        data.extend_from_slice(payload);
+        if let Err(error) = self.shared.stream.send_frame(&data) {
+            self.shared.pending.lock().unwrap().remove(&id);
+            return Err(error);
+        }
+        Ok(PendingCall {
+            shared: Arc::clone(&self.shared),
+            id,
+            receiver,
+            deadline: Instant::now() + timeout,
+            is_done: false,
+        })
+    }
+}
+
+impl Drop for RpcClient {
+    fn drop(&mut self) {
+        self.shared.close();
+    }
+}
+
+impl ClientShared {
+    fn read_responses(&self) {
+        while !self.is_closed.load(Ordering::SeqCst) {
+            let data = match self.stream.receive_frame_timeout(POLL_INTERVAL) {
+                Ok(data) => data,
+                Err(error) if error.kind() == io::ErrorKind::TimedOut => continue,
+                Err(_) => break,
+            };
+            let Ok((kind, id, payload)) = decode(&data) else {
+                break;
+            };
+            *self.last_received.lock().unwrap() = Instant::now();
+            let result = match kind {
+                RESPONSE => Ok(payload.into()),
+                DROPPED => Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "request dropped by the server",
+                )),
+                _ => break,
+            };
+            // The call may have timed out or been cancelled already.
+            if let Some(sender) = self.pending.lock().unwrap().remove(&id) {
+                let _ = sender.send(result);
+            }
+        }
+        self.close();
+    }
+
+    /// Fails every outstanding call and turns away new ones.
+    fn close(&self) {
+        self.is_closed.store(true, Ordering::SeqCst);
+        for (_, sender) in self.pending.lock().unwrap().drain() {
+            let _ = sender.send(Err(io::ErrorKind::ConnectionAborted.into()));
+        }
+    }
+
+    fn cancel(&self, id: u64) {
+        if self.pending.lock().unwrap().remove(&id).is_some() {
+            let _ = self.stream.send_frame(&encode(CANCEL, id, &[]));
+        }
+    }
+}
+
+/// A call sent by `RpcClient::start` whose response has not been taken yet.
+/// Dropping it cancels the call.
+pub struct PendingCall {
+    shared: Arc<ClientShared>,
+    id: u64,
+    receiver: Receiver<CallResult>,
+    deadline: Instant,
+    is_done: bool,
+}
+
+impl PendingCall {
+    /// Waits for the response until the call's deadline, failing with
+    /// `TimedOut` and cancelling the call once it passes.
+    pub fn wait(mut self) -> CallResult {
+        let timeout = self.deadline.saturating_duration_since(Instant::now());
+        let result = match self.receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => {
+                self.shared.cancel(self.id);
+                Err(io::ErrorKind::TimedOut.into())
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(io::ErrorKind::ConnectionAborted.into()),
+        };
+        self.is_done = true;
+        result
+    }
+
+    /// Tells the server to skip the call if it has not answered yet. A
+    /// handler that is already running finishes, but its response is thrown
+    /// away.
+    pub fn cancel(mut self) {
+        self.shared.cancel(self.id);
+        self.is_done = true;
+    }
+}
+
+impl Drop for PendingCall {
+    fn drop(&mut self) {
+        if !self.is_done {
+            self.shared.cancel(self.id);
+        }
+    }
+}
+
+/// Answers the requests an `RpcClient` sends on `stream` until the stream
+/// fails. Each request runs on its own thread, so a slow one does not hold up
+/// those behind it, and responses go out as they are ready. Requests that
+/// were cancelled or whose caller stopped waiting before they started are
+/// skipped.
+pub fn serve_connection(stream: Arc<dyn TcpStream>, handler: RpcHandler) {
+    let stream = Arc::new(FramedStream::new(stream));
+    let in_flight = Arc::new(Mutex::new(HashSet::new()));
+    while let Ok(data) = stream.receive_frame() {
+        let Ok((kind, id, payload)) = decode(&data) else {
+            return;
+        };
+        match kind {
+            REQUEST if payload.len() >= 4 => {
+                let timeout_ms = u32::from_le_bytes(payload[..4].try_into().unwrap());
+                let deadline = Instant::now() + Duration::from_millis(timeout_ms as u64);
+                in_flight.lock().unwrap().insert(id);
+                let stream = Arc::clone(&stream);
+                let in_flight = Arc::clone(&in_flight);
+                let handler = Arc::clone(&handler);
+                spawn(move || {
+                    let is_wanted =
+                        in_flight.lock().unwrap().contains(&id) && Instant::now() < deadline;
+                    if !is_wanted {
+                        in_flight.lock().unwrap().remove(&id);
+                        return;
+                    }
+                    let response = handler(&data[REQUEST_HEADER_SIZE..]);
+                    if !in_flight.lock().unwrap().remove(&id) {
+                        return;
+                    }
+                    let _ = match response {
+                        Some(response) => stream.send_frame(&encode(RESPONSE, id, &response)),
+                        None => stream.send_frame(&encode(DROPPED, id, &[])),
+                    };
+                });
+            }
+            CANCEL => {
+                in_flight.lock().unwrap().remove(&id);
+            }
+            _ => return,
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use std::fmt::Debug;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The file operations durable state is built on. Data appended to a file
+/// survives a crash once the file is synced, and a file's creation or removal
+/// once its directory is synced; nothing else is promised.
+pub trait Storage: Debug + Send + Sync {
+    fn create_dir_all(&self, dir: &Path) -> io::Result<()>;
+    /// Returns the paths of the files directly inside `dir`.
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>>;
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+    /// Creates an empty file, failing with `AlreadyExists` if there is one.
+    fn create(&self, path: &Path) -> io::Result<Box<dyn StorageFile>>;
+    fn open_append(&self, path: &Path) -> io::Result<Box<dyn StorageFile>>;
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+    /// Atomically moves the file at `from` to `to`, replacing any file there.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    /// Makes the files created in and removed from `dir` so far durable.
+    fn sync_dir(&self, dir: &Path) -> io::Result<()>;
+}
+
+pub trait StorageFile: Debug + Send {
+    fn append(&mut self, data: &[u8]) -> io::Result<()>;
+    fn truncate(&mut self, len: u64) -> io::Result<()>;
+    /// Makes every preceding change to this file durable.
+    fn sync(&mut self) -> io::Result<()>;
+}
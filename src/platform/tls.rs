@@ -0,0 +1,606 @@
+use crate::platform::framing::SequencedStream;
+use crate::platform::network::{
+    Control, Endpoint, Incoming, NetworkInterface, Packet, TcpListener, TcpStream, UdpSocket,
+};
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{
+    AlertDescription, ClientConfig, ClientConnection, Connection, RootCertStore, ServerConfig,
+    ServerConnection,
+};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::spawn;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A node's certificate chain and private key, and the roots that its peers'
+/// certificates must chain to. Nodes are named in their certificates by the
+/// IP addresses or DNS names others reach them at.
+pub struct TlsCredentials {
+    pub certificate_chain: Vec<CertificateDer<'static>>,
+    pub private_key: PrivateKeyDer<'static>,
+    pub trusted_roots: Vec<CertificateDer<'static>>,
+}
+
+impl TlsCredentials {
+    pub fn from_pem(
+        certificate_chain: &[u8],
+        private_key: &[u8],
+        trusted_roots: &[u8],
+    ) -> io::Result<Self> {
+        let certificates = |pem: &[u8]| {
+            CertificateDer::pem_slice_iter(pem)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(invalid_input)
+        };
+        Ok(TlsCredentials {
+            certificate_chain: certificates(certificate_chain)?,
+            private_key: PrivateKeyDer::from_pem_slice(private_key).map_err(invalid_input)?,
+            trusted_roots: certificates(trusted_roots)?,
+        })
+    }
+}
+
+fn invalid_input(error: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, error)
+}
+
+fn tls_error(error: rustls::Error) -> io::Error {
+    let kind = match &error {
+        rustls::Error::InvalidCertificate(_) | rustls::Error::NoCertificatesPresented => {
+            io::ErrorKind::PermissionDenied
+        }
+        rustls::Error::AlertReceived(
+            AlertDescription::BadCertificate
+            | AlertDescription::UnsupportedCertificate
+            | AlertDescription::CertificateRevoked
+            | AlertDescription::CertificateExpired
+            | AlertDescription::CertificateUnknown
+            | AlertDescription::UnknownCA
+            | AlertDescription::CertificateRequired
+            | AlertDescription::AccessDenied,
+        ) => io::ErrorKind::PermissionDenied,
+        _ => io::ErrorKind::InvalidData,
+    };
+    io::Error::new(kind, error)
+}
+
+struct Configs {
+    client: Arc<ClientConfig>,
+    server: Arc<ServerConfig>,
+}
+
+/// Client and server TLS settings for mutually authenticated connections:
+/// each side presents its certificate and checks the other's against the
+/// trusted roots. `rotate` swaps in new credentials for the connections
+/// that follow, while open ones carry on with the ones they were set up
+/// with, so certificates can be renewed without restarting the node.
+pub struct TlsContext {
+    configs: RwLock<Configs>,
+}
+
+impl TlsContext {
+    pub fn new(credentials: &TlsCredentials) -> io::Result<Self> {
+        Ok(TlsContext {
+            configs: RwLock::new(Self::configs(credentials)?),
+        })
+    }
+
+    pub fn rotate(&self, credentials: &TlsCredentials) -> io::Result<()> {
+        *self.configs.write().unwrap() = Self::configs(credentials)?;
+        Ok(())
+    }
+
+    fn configs(credentials: &TlsCredentials) -> io::Result<Configs> {
+        let provider = Arc::new(ring::default_provider());
+        let mut roots = RootCertStore::empty();
+        for root in credentials.trusted_roots.iter() {
+            roots.add(root.clone()).map_err(tls_error)?;
+        }
+        let roots = Arc::new(roots);
+
+        let client = ClientConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?
+            .with_root_certificates(Arc::clone(&roots))
+            .with_client_auth_cert(
+                credentials.certificate_chain.clone(),
+                credentials.private_key.clone_key(),
+            )
+            .map_err(tls_error)?;
+
+        let verifier = WebPkiClientVerifier::builder_with_provider(roots, Arc::clone(&provider))
+            .build()
+            .map_err(invalid_input)?;
+        let server = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(
+                credentials.certificate_chain.clone(),
+                credentials.private_key.clone_key(),
+            )
+            .map_err(tls_error)?;
+
+        Ok(Configs {
+            client: Arc::new(client),
+            server: Arc::new(server),
+        })
+    }
+
+    /// Opens TLS over `stream`, checking that the server's certificate names
+    /// `server_name`, an IP address or DNS name.
+    pub fn connect(
+        &self,
+        stream: Arc<dyn TcpStream>,
+        server_name: &str,
+        timeout: Duration,
+    ) -> io::Result<TlsStream> {
+        let server_name = ServerName::try_from(server_name.to_string()).map_err(invalid_input)?;
+        let config = Arc::clone(&self.configs.read().unwrap().client);
+        let connection = ClientConnection::new(config, server_name).map_err(tls_error)?;
+        TlsStream::handshake(
+            Arc::new(SequencedStream::new(stream)),
+            connection.into(),
+            timeout,
+        )
+    }
+
+    /// Accepts TLS over `stream` from a client whose certificate chains to a
+    /// trusted root. Which client it is can be checked afterwards with
+    /// `TlsStream::verify_peer_name`.
+    pub fn accept(&self, stream: Arc<dyn TcpStream>, timeout: Duration) -> io::Result<TlsStream> {
+        let config = Arc::clone(&self.configs.read().unwrap().server);
+        let connection = ServerConnection::new(config).map_err(tls_error)?;
+        TlsStream::handshake(
+            Arc::new(SequencedStream::new(stream)),
+            connection.into(),
+            timeout,
+        )
+    }
+}
+
+/// A TLS session and the plaintext it has decrypted that nobody has taken
+/// yet.
+struct Session {
+    connection: Connection,
+    plaintext: Vec<u8>,
+    /// Whether the peer ended the session.
+    is_closed: bool,
+}
+
+impl Session {
+    /// Hands records received from the peer to the session, taking out the
+    /// plaintext as it goes so that the session's own buffer never fills.
+    fn feed(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            self.connection.read_tls(&mut data)?;
+            self.connection.process_new_packets().map_err(tls_error)?;
+            match self.connection.reader().read_to_end(&mut self.plaintext) {
+                Ok(_) => self.is_closed = true,
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => {}
+                Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => self.is_closed = true,
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends whatever TLS records the session has ready.
+    fn flush(&mut self, stream: &dyn TcpStream) {
+        let mut data = Vec::new();
+        while self.connection.wants_write() {
+            if self.connection.write_tls(&mut data).is_err() {
+                break;
+            }
+        }
+        if !data.is_empty() {
+            stream.send(&data);
+        }
+    }
+}
+
+/// A `TcpStream` that encrypts everything sent over the stream it wraps.
+/// Each `send` is written as TLS records in one send on the inner stream,
+/// and `receive` returns whatever plaintext the records received so far
+/// hold, so message boundaries are not kept; callers frame their messages.
+/// TLS records must arrive in order, so they travel in a `SequencedStream`,
+/// which undoes the simulator's reordering; a record the simulator drops
+/// ends the connection.
+pub struct TlsStream {
+    stream: Arc<dyn TcpStream>,
+    session: Mutex<Session>,
+    /// Held by whoever is waiting on the inner stream, so that records are
+    /// fed to the session in the order they arrived.
+    receive_lock: Mutex<()>,
+}
+
+impl TlsStream {
+    fn handshake(
+        stream: Arc<dyn TcpStream>,
+        mut connection: Connection,
+        timeout: Duration,
+    ) -> io::Result<Self> {
+        connection.set_buffer_limit(None);
+        let mut session = Session {
+            connection,
+            plaintext: Vec::new(),
+            is_closed: false,
+        };
+        let deadline = Instant::now() + timeout;
+        loop {
+            session.flush(stream.as_ref());
+            if !session.connection.is_handshaking() {
+                break;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "TLS handshake timed out",
+                ));
+            }
+            let data = stream.receive_timeout(remaining)?;
+            if let Err(error) = session.feed(&data) {
+                // Let the peer know why with the alert the session queued.
+                session.flush(stream.as_ref());
+                return Err(error);
+            }
+        }
+        Ok(TlsStream {
+            stream,
+            session: Mutex::new(session),
+            receive_lock: Mutex::new(()),
+        })
+    }
+
+    /// The peer's certificate chain, starting with its own certificate.
+    pub fn peer_certificates(&self) -> Vec<CertificateDer<'static>> {
+        let session = self.session.lock().unwrap();
+        session
+            .connection
+            .peer_certificates()
+            .map(|certificates| {
+                certificates
+                    .iter()
+                    .map(|c| c.clone().into_owned())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Checks that the peer's certificate names `name`, an IP address or DNS
+    /// name. The certificate was already checked against the trusted roots
+    /// during the handshake.
+    pub fn verify_peer_name(&self, name: &str) -> io::Result<()> {
+        let certificates = self.peer_certificates();
+        let certificate = certificates.first().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::PermissionDenied, "peer sent no certificate")
+        })?;
+        let server_name = ServerName::try_from(name).map_err(invalid_input)?;
+        webpki::EndEntityCert::try_from(certificate)
+            .and_then(|certificate| certificate.verify_is_valid_for_subject_name(&server_name))
+            .map_err(|error| {
+                io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("peer certificate is not valid for {}: {}", name, error),
+                )
+            })
+    }
+
+    fn receive_until(&self, deadline: Option<Instant>) -> io::Result<Box<[u8]>> {
+        let _guard = self.receive_lock.lock().unwrap();
+        loop {
+            {
+                let mut session = self.session.lock().unwrap();
+                if !session.plaintext.is_empty() {
+                    return Ok(std::mem::take(&mut session.plaintext).into_boxed_slice());
+                }
+                if session.is_closed {
+                    return Err(io::ErrorKind::ConnectionAborted.into());
+                }
+            }
+
+            let data = match deadline {
+                None => self
+                    .stream
+                    .receive()
+                    .ok_or(io::ErrorKind::ConnectionAborted)?,
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Err(io::ErrorKind::TimedOut.into());
+                    }
+                    self.stream.receive_timeout(remaining)?
+                }
+            };
+            let mut session = self.session.lock().unwrap();
+            let result = session.feed(&data);
+            // Records such as key updates may call for an answer.
+            session.flush(self.stream.as_ref());
+            result?;
+        }
+    }
+}
+
+impl fmt::Debug for TlsStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsStream")
+            .field("stream", &self.stream)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TcpStream for TlsStream {
+    fn send(&self, data: &[u8]) {
+        let mut session = self.session.lock().unwrap();
+        if let Err(error) = session.connection.writer().write_all(data) {
+            println!(
+                "Failed to encrypt data for {:?}: {}",
+                self.stream.get_remote_endpoint(),
+                error
+            );
+            return;
+        }
+        session.flush(self.stream.as_ref());
+    }
+
+    fn receive(&self) -> Option<Box<[u8]>> {
+        self.receive_until(None).ok()
+    }
+
+    fn receive_timeout(&self, timeout: Duration) -> io::Result<Box<[u8]>> {
+        self.receive_until(Some(Instant::now() + timeout))
+    }
+
+    fn send_control(&self, control: Control) {
+        self.stream.send_control(control);
+    }
+
+    fn get_local_endpoint(&self) -> Endpoint {
+        self.stream.get_local_endpoint()
+    }
+
+    fn get_remote_endpoint(&self) -> Endpoint {
+        self.stream.get_remote_endpoint()
+    }
+
+    fn on_packet_received(&self, packet: &Packet) {
+        self.stream.on_packet_received(packet);
+    }
+}
+
+impl Drop for TlsStream {
+    fn drop(&mut self) {
+        let session = self.session.get_mut().unwrap();
+        session.connection.send_close_notify();
+        session.flush(self.stream.as_ref());
+    }
+}
+
+/// Runs every TCP connection made or accepted through `network_interface`
+/// over mutually authenticated TLS. Servers are checked against the address
+/// or name they were reached at, and clients against the address they
+/// connect from, so a node can only speak for the address its certificate
+/// names. Datagrams are passed through as they are.
+pub struct TlsNetworkInterface {
+    network_interface: Arc<dyn NetworkInterface>,
+    context: Arc<TlsContext>,
+    handshake_timeout: Duration,
+}
+
+impl TlsNetworkInterface {
+    pub fn new(network_interface: Arc<dyn NetworkInterface>, context: Arc<TlsContext>) -> Self {
+        TlsNetworkInterface {
+            network_interface,
+            context,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn set_handshake_timeout(&mut self, timeout: Duration) {
+        self.handshake_timeout = timeout;
+    }
+
+    fn connect_as(
+        &self,
+        remote_ip: &str,
+        remote_port: u16,
+        server_name: &str,
+    ) -> io::Result<Arc<dyn TcpStream>> {
+        let stream = self.network_interface.connect(remote_ip, remote_port)?;
+        let stream = self
+            .context
+            .connect(stream, server_name, self.handshake_timeout)?;
+        Ok(Arc::new(stream))
+    }
+}
+
+impl NetworkInterface for TlsNetworkInterface {
+    fn connect(&self, remote_ip: &str, remote_port: u16) -> io::Result<Arc<dyn TcpStream>> {
+        self.connect_as(remote_ip, remote_port, remote_ip)
+    }
+
+    fn bind_tcp_with_backlog(
+        &self,
+        local_ip: &str,
+        local_port: u16,
+        backlog: usize,
+    ) -> Option<Arc<dyn TcpListener>> {
+        let listener = self
+            .network_interface
+            .bind_tcp_with_backlog(local_ip, local_port, backlog)?;
+        Some(Arc::new(TlsListener::new(
+            listener,
+            Arc::clone(&self.context),
+            self.handshake_timeout,
+        )))
+    }
+
+    fn bind_udp(&self, local_ip: &str, local_port: u16) -> Option<Arc<dyn UdpSocket>> {
+        self.network_interface.bind_udp(local_ip, local_port)
+    }
+
+    fn resolve(&self, hostname: &str) -> io::Result<Vec<String>> {
+        self.network_interface.resolve(hostname)
+    }
+
+    fn sample(&self, stream: &str) -> f64 {
+        self.network_interface.sample(stream)
+    }
+
+    /// Checks the server's certificate against `hostname` rather than the
+    /// address it resolved to.
+    fn connect_by_name(&self, hostname: &str, remote_port: u16) -> io::Result<Arc<dyn TcpStream>> {
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no addresses to connect to");
+        for remote_ip in self.resolve(hostname)? {
+            match self.connect_as(&remote_ip, remote_port, hostname) {
+                Ok(tcp_stream) => return Ok(tcp_stream),
+                Err(error) => last_error = error,
+            }
+        }
+        Err(last_error)
+    }
+}
+
+/// Connections whose handshake has finished, waiting to be accepted.
+#[derive(Default)]
+struct Handshaken {
+    streams: Mutex<VecDeque<Arc<dyn TcpStream>>>,
+    changed: Condvar,
+}
+
+/// Accepts connections on the listener it wraps from a thread of its own and
+/// runs each handshake on a thread of its own, so a peer that is slow to
+/// finish, or never starts, holds up nobody else. Only connections whose
+/// handshake succeeded are queued for `accept`; failed ones are logged and
+/// dropped. Dropping the listener shuts down the one it wraps, which ends
+/// the accepting thread.
+struct TlsListener {
+    listener: Arc<dyn TcpListener>,
+    handshaken: Arc<Handshaken>,
+}
+
+impl TlsListener {
+    fn new(
+        listener: Arc<dyn TcpListener>,
+        context: Arc<TlsContext>,
+        handshake_timeout: Duration,
+    ) -> Self {
+        let handshaken = Arc::new(Handshaken::default());
+        let acceptor = Arc::clone(&listener);
+        let queue = Arc::clone(&handshaken);
+        spawn(move || loop {
+            let stream = match acceptor.accept() {
+                Ok(stream) => stream,
+                Err(_) if acceptor.is_shutdown() => return,
+                Err(error) => {
+                    println!("Failed to accept a connection: {}", error);
+                    continue;
+                }
+            };
+            let listener = Arc::clone(&acceptor);
+            let context = Arc::clone(&context);
+            let handshaken = Arc::clone(&queue);
+            spawn(move || {
+                let remote_endpoint = stream.get_remote_endpoint();
+                match Self::handshake(&context, stream, handshake_timeout) {
+                    Ok(stream) => {
+                        let mut streams = handshaken.streams.lock().unwrap();
+                        if !listener.is_shutdown() {
+                            streams.push_back(stream);
+                            handshaken.changed.notify_one();
+                        }
+                    }
+                    Err(error) => {
+                        println!("TLS handshake with {:?} failed: {}", remote_endpoint, error)
+                    }
+                }
+            });
+        });
+        TlsListener {
+            listener,
+            handshaken,
+        }
+    }
+
+    fn handshake(
+        context: &TlsContext,
+        stream: Arc<dyn TcpStream>,
+        timeout: Duration,
+    ) -> io::Result<Arc<dyn TcpStream>> {
+        let remote_ip = stream.get_remote_endpoint().ip;
+        let stream = context.accept(stream, timeout)?;
+        stream.verify_peer_name(&remote_ip)?;
+        Ok(Arc::new(stream))
+    }
+
+    fn shutdown_error() -> io::Error {
+        io::Error::new(io::ErrorKind::ConnectionAborted, "listener is shut down")
+    }
+}
+
+impl TcpListener for TlsListener {
+    fn accept_cancellable(&self, is_cancelled: &AtomicBool) -> io::Result<Arc<dyn TcpStream>> {
+        let mut streams = self.handshaken.streams.lock().unwrap();
+        loop {
+            if self.listener.is_shutdown() {
+                return Err(Self::shutdown_error());
+            }
+            if is_cancelled.load(Ordering::SeqCst) {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            if let Some(stream) = streams.pop_front() {
+                return Ok(stream);
+            }
+            streams = self.handshaken.changed.wait(streams).unwrap();
+        }
+    }
+
+    fn wake_acceptors(&self) {
+        let _streams = self.handshaken.streams.lock().unwrap();
+        self.handshaken.changed.notify_all();
+    }
+
+    fn try_accept(&self) -> io::Result<Arc<dyn TcpStream>> {
+        if self.listener.is_shutdown() {
+            return Err(Self::shutdown_error());
+        }
+        match self.handshaken.streams.lock().unwrap().pop_front() {
+            Some(stream) => Ok(stream),
+            None => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    /// Connections that finished their handshake but were not accepted yet
+    /// are closed.
+    fn shutdown(&self) {
+        self.listener.shutdown();
+        let mut streams = self.handshaken.streams.lock().unwrap();
+        streams.clear();
+        self.handshaken.changed.notify_all();
+    }
+
+    fn is_shutdown(&self) -> bool {
+        self.listener.is_shutdown()
+    }
+
+    fn incoming(self: Arc<Self>) -> Incoming {
+        Incoming::new(self)
+    }
+}
+
+impl Drop for TlsListener {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
@@ -0,0 +1,371 @@
+use crate::platform::storage::{Storage, StorageFile};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct SimulatedDiskConfig {
+    /// Probability that a change not yet synced still reaches the disk before
+    /// a crash.
+    pub unsynced_write_survival_rate: f32,
+    /// Whether unsynced changes can reach the disk out of order, so that a
+    /// crash keeps a later write while losing an earlier one.
+    pub reorder_writes: bool,
+    /// Probability that an unsynced write that survives a crash is cut short.
+    pub torn_write_rate: f32,
+    /// Probability that an operation fails with an I/O error.
+    pub io_error_rate: f32,
+    pub latency_range: Range<Duration>,
+}
+
+#[derive(Debug, Clone)]
+enum Write {
+    Append { offset: usize, data: Vec<u8> },
+    Truncate(usize),
+}
+
+#[derive(Debug, Default, Clone)]
+struct SimulatedFile {
+    /// The contents readers see.
+    contents: Vec<u8>,
+    /// The contents as of the last sync.
+    durable_contents: Vec<u8>,
+    unsynced_writes: Vec<Write>,
+    /// Whether the file's directory entry has been synced.
+    is_durable: bool,
+}
+
+/// A rename whose directory has not been synced since, so a crash may undo it.
+#[derive(Debug)]
+struct PendingRename {
+    from: PathBuf,
+    to: PathBuf,
+    /// The file that was at `to` before.
+    replaced: Option<SimulatedFile>,
+}
+
+#[derive(Debug, Default)]
+struct DiskState {
+    files: HashMap<PathBuf, SimulatedFile>,
+    /// Directories are durable as soon as they are created.
+    dirs: HashSet<PathBuf>,
+    /// Durable files removed since their directory was last synced; a crash
+    /// may bring them back.
+    removed_files: HashMap<PathBuf, SimulatedFile>,
+    /// Oldest first.
+    pending_renames: Vec<PendingRename>,
+    /// Bumped by every crash, invalidating files opened before it.
+    generation: u64,
+}
+
+/// An in-memory disk that loses, reorders and tears unsynced writes when it
+/// crashes, fails operations and adds latency. Every random decision comes
+/// from one generator seeded at creation, so a failing run can be replayed.
+#[derive(Debug, Clone)]
+pub struct SimulatedDisk {
+    state: Arc<Mutex<DiskState>>,
+    rng: Arc<Mutex<StdRng>>,
+    config: Arc<Mutex<SimulatedDiskConfig>>,
+}
+
+impl SimulatedDisk {
+    pub fn new(config: SimulatedDiskConfig, seed: u64) -> Self {
+        SimulatedDisk {
+            state: Arc::new(Mutex::new(DiskState::default())),
+            rng: Arc::new(Mutex::new(StdRng::seed_from_u64(seed))),
+            config: Arc::new(Mutex::new(config)),
+        }
+    }
+
+    pub fn set_io_error_rate(&self, io_error_rate: f32) {
+        self.config.lock().unwrap().io_error_rate = io_error_rate;
+    }
+
+    /// Simulates power loss: every file falls back to what was synced plus
+    /// whichever unsynced writes happened to reach the disk, and files opened
+    /// before the crash can no longer be used.
+    pub fn crash(&self) {
+        let config = self.config.lock().unwrap().clone();
+        let mut rng = self.rng.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+
+        // Each rename either reached the disk or is undone as a whole, newest
+        // first so that renames of the same file unwind in order.
+        let pending_renames = std::mem::take(&mut state.pending_renames);
+        for rename in pending_renames.into_iter().rev() {
+            if rng.gen_bool(config.unsynced_write_survival_rate as f64) {
+                if let Some(file) = state.files.get_mut(&rename.to) {
+                    file.is_durable = true;
+                }
+                continue;
+            }
+            if let Some(file) = state.files.remove(&rename.to) {
+                state.files.insert(rename.from, file);
+            }
+            if let Some(replaced) = rename.replaced {
+                state.files.insert(rename.to, replaced);
+            }
+        }
+
+        // Iterate in a fixed order so the same seed always makes the same
+        // choices.
+        let mut paths: Vec<PathBuf> = state.files.keys().cloned().collect();
+        paths.sort();
+        for path in paths {
+            let file = state.files.get_mut(&path).unwrap();
+            if !file.is_durable && !rng.gen_bool(config.unsynced_write_survival_rate as f64) {
+                state.files.remove(&path);
+                continue;
+            }
+
+            let mut contents = file.durable_contents.clone();
+            for write in file.unsynced_writes.iter() {
+                if !rng.gen_bool(config.unsynced_write_survival_rate as f64) {
+                    if config.reorder_writes {
+                        continue;
+                    }
+                    break;
+                }
+                match write {
+                    Write::Append { offset, data } => {
+                        let length =
+                            if !data.is_empty() && rng.gen_bool(config.torn_write_rate as f64) {
+                                rng.gen_range(0..data.len())
+                            } else {
+                                data.len()
+                            };
+                        if contents.len() < offset + length {
+                            contents.resize(offset + length, 0);
+                        }
+                        contents[*offset..offset + length].copy_from_slice(&data[..length]);
+                    }
+                    Write::Truncate(length) => contents.resize(*length, 0),
+                }
+            }
+            *file = SimulatedFile {
+                contents: contents.clone(),
+                durable_contents: contents,
+                unsynced_writes: Vec::new(),
+                is_durable: true,
+            };
+        }
+
+        let mut removed_paths: Vec<PathBuf> = state.removed_files.keys().cloned().collect();
+        removed_paths.sort();
+        for path in removed_paths {
+            let file = state.removed_files.remove(&path).unwrap();
+            if !rng.gen_bool(config.unsynced_write_survival_rate as f64) {
+                state.files.insert(
+                    path,
+                    SimulatedFile {
+                        contents: file.durable_contents.clone(),
+                        durable_contents: file.durable_contents,
+                        unsynced_writes: Vec::new(),
+                        is_durable: true,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Waits out the configured latency and then decides whether the
+    /// operation fails.
+    fn begin_operation(&self, operation: &str) -> io::Result<()> {
+        let config = self.config.lock().unwrap().clone();
+        let (latency, fails) = {
+            let mut rng = self.rng.lock().unwrap();
+            let latency = if config.latency_range.is_empty() {
+                Duration::ZERO
+            } else {
+                rng.gen_range(config.latency_range.clone())
+            };
+            (latency, rng.gen_bool(config.io_error_rate as f64))
+        };
+        if !latency.is_zero() {
+            sleep(latency);
+        }
+        if fails {
+            return Err(io::Error::other(format!(
+                "injected I/O error in {}",
+                operation
+            )));
+        }
+        Ok(())
+    }
+
+    fn open_file(&self, state: &DiskState, path: &Path) -> Box<dyn StorageFile> {
+        Box::new(SimulatedDiskFile {
+            disk: self.clone(),
+            path: path.to_path_buf(),
+            generation: state.generation,
+        })
+    }
+}
+
+impl Storage for SimulatedDisk {
+    fn create_dir_all(&self, dir: &Path) -> io::Result<()> {
+        self.begin_operation("create_dir_all")?;
+        let mut state = self.state.lock().unwrap();
+        for ancestor in dir.ancestors() {
+            state.dirs.insert(ancestor.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        self.begin_operation("read_dir")?;
+        let state = self.state.lock().unwrap();
+        if !state.dirs.contains(dir) {
+            return Err(not_found(dir));
+        }
+        Ok(state
+            .files
+            .keys()
+            .filter(|path| path.parent() == Some(dir))
+            .cloned()
+            .collect())
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.begin_operation("read")?;
+        let state = self.state.lock().unwrap();
+        state
+            .files
+            .get(path)
+            .map(|file| file.contents.clone())
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn StorageFile>> {
+        self.begin_operation("create")?;
+        let mut state = self.state.lock().unwrap();
+        if !path.parent().is_some_and(|dir| state.dirs.contains(dir)) {
+            return Err(not_found(path));
+        }
+        if state.files.contains_key(path) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{:?} already exists", path),
+            ));
+        }
+        state
+            .files
+            .insert(path.to_path_buf(), SimulatedFile::default());
+        Ok(self.open_file(&state, path))
+    }
+
+    fn open_append(&self, path: &Path) -> io::Result<Box<dyn StorageFile>> {
+        self.begin_operation("open_append")?;
+        let state = self.state.lock().unwrap();
+        if !state.files.contains_key(path) {
+            return Err(not_found(path));
+        }
+        Ok(self.open_file(&state, path))
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.begin_operation("remove_file")?;
+        let mut state = self.state.lock().unwrap();
+        let file = state.files.remove(path).ok_or_else(|| not_found(path))?;
+        if file.is_durable {
+            state.removed_files.insert(path.to_path_buf(), file);
+        }
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.begin_operation("rename")?;
+        let mut state = self.state.lock().unwrap();
+        if !to.parent().is_some_and(|dir| state.dirs.contains(dir)) {
+            return Err(not_found(to));
+        }
+        let file = state.files.remove(from).ok_or_else(|| not_found(from))?;
+        let replaced = state.files.insert(to.to_path_buf(), file);
+        state.pending_renames.push(PendingRename {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+            replaced,
+        });
+        Ok(())
+    }
+
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        self.begin_operation("sync_dir")?;
+        let mut state = self.state.lock().unwrap();
+        state
+            .pending_renames
+            .retain(|rename| rename.to.parent() != Some(dir));
+        for (path, file) in state.files.iter_mut() {
+            if path.parent() == Some(dir) {
+                file.is_durable = true;
+            }
+        }
+        state
+            .removed_files
+            .retain(|path, _| path.parent() != Some(dir));
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct SimulatedDiskFile {
+    disk: SimulatedDisk,
+    path: PathBuf,
+    generation: u64,
+}
+
+impl SimulatedDiskFile {
+    fn with_file<T>(
+        &self,
+        operation: &str,
+        f: impl FnOnce(&mut SimulatedFile) -> T,
+    ) -> io::Result<T> {
+        self.disk.begin_operation(operation)?;
+        let mut state = self.disk.state.lock().unwrap();
+        if state.generation != self.generation {
+            return Err(io::Error::other("disk crashed since the file was opened"));
+        }
+        let file = state
+            .files
+            .get_mut(&self.path)
+            .ok_or_else(|| not_found(&self.path))?;
+        Ok(f(file))
+    }
+}
+
+impl StorageFile for SimulatedDiskFile {
+    fn append(&mut self, data: &[u8]) -> io::Result<()> {
+        self.with_file("append", |file| {
+            file.unsynced_writes.push(Write::Append {
+                offset: file.contents.len(),
+                data: data.to_vec(),
+            });
+            file.contents.extend_from_slice(data);
+        })
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.with_file("truncate", |file| {
+            file.unsynced_writes.push(Write::Truncate(len as usize));
+            file.contents.resize(len as usize, 0);
+        })
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.with_file("sync", |file| {
+            file.durable_contents = file.contents.clone();
+            file.unsynced_writes.clear();
+        })
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{:?} not found", path))
+}
@@ -0,0 +1,498 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Columns the timeline of a counterexample is drawn across.
+const TIMELINE_WIDTH: usize = 48;
+
+/// An operation a client ran against the system under test, with when it
+/// was invoked and when it completed, measured from the start of the
+/// history.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Operation<I, O> {
+    pub client: usize,
+    pub input: I,
+    /// `None` while the operation is pending, or if its outcome is unknown,
+    /// as when the client gave up waiting. Such an operation may have taken
+    /// effect at any point after it was invoked, or not at all.
+    pub output: Option<O>,
+    pub invoked: Duration,
+    pub completed: Option<Duration>,
+}
+
+/// Records the operations clients run concurrently, for checking with
+/// `check` once they are done. Times are taken from the clock the simulated
+/// network and nodes run on, so they order operations across clients.
+pub struct History<I, O> {
+    started: Instant,
+    operations: Mutex<Vec<Operation<I, O>>>,
+}
+
+impl<I: Clone, O: Clone> Default for History<I, O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I: Clone, O: Clone> History<I, O> {
+    pub fn new() -> Self {
+        History {
+            started: Instant::now(),
+            operations: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Records that `client` invoked `input` and returns the operation's ID
+    /// for `complete`.
+    pub fn invoke(&self, client: usize, input: I) -> usize {
+        let mut operations = self.operations.lock().unwrap();
+        operations.push(Operation {
+            client,
+            input,
+            output: None,
+            invoked: self.started.elapsed(),
+            completed: None,
+        });
+        operations.len() - 1
+    }
+
+    /// Records that operation `id` completed with `output`. An operation that
+    /// is never completed is taken to have an unknown outcome.
+    pub fn complete(&self, id: usize, output: O) {
+        let completed = self.started.elapsed();
+        let mut operations = self.operations.lock().unwrap();
+        let operation = &mut operations[id];
+        operation.output = Some(output);
+        operation.completed = Some(completed);
+    }
+
+    /// Runs `operation` as `client`, recording `input` and whatever it
+    /// returns. Returning `None` leaves the outcome unknown.
+    pub fn record(
+        &self,
+        client: usize,
+        input: I,
+        operation: impl FnOnce(&I) -> Option<O>,
+    ) -> Option<O> {
+        let id = self.invoke(client, input.clone());
+        let output = operation(&input)?;
+        self.complete(id, output.clone());
+        Some(output)
+    }
+
+    pub fn operations(&self) -> Vec<Operation<I, O>> {
+        self.operations.lock().unwrap().clone()
+    }
+}
+
+/// A sequential specification of the object under test, which the checker
+/// searches for an order of the history's operations that it accepts.
+pub trait Model {
+    type State: Clone + Eq + Hash + fmt::Debug;
+    type Input: Clone + fmt::Debug;
+    type Output: Clone + fmt::Debug;
+
+    fn init(&self) -> Self::State;
+
+    /// Returns the state after `input` if the object in `state` could have
+    /// answered it with `output`. An output of `None` stands for an unknown
+    /// outcome, which any answer fits.
+    fn step(
+        &self,
+        state: &Self::State,
+        input: &Self::Input,
+        output: Option<&Self::Output>,
+    ) -> Option<Self::State>;
+
+    /// Splits the operations, by index, into groups that touch independent
+    /// parts of the object, such as the keys of a key-value store. Since
+    /// linearizability is compositional, each group is checked on its own,
+    /// which is much faster than checking them together.
+    fn partition(&self, operations: &[Operation<Self::Input, Self::Output>]) -> Vec<Vec<usize>> {
+        vec![(0..operations.len()).collect()]
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RegisterInput {
+    Read,
+    Write(String),
+}
+
+/// A single register holding a string, initially empty. Reads answer with
+/// its value; what writes answer is ignored.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RegisterModel;
+
+impl Model for RegisterModel {
+    type State = String;
+    type Input = RegisterInput;
+    type Output = String;
+
+    fn init(&self) -> String {
+        String::new()
+    }
+
+    fn step(
+        &self,
+        state: &String,
+        input: &RegisterInput,
+        output: Option<&String>,
+    ) -> Option<String> {
+        match input {
+            RegisterInput::Read => match output {
+                Some(value) if value != state => None,
+                _ => Some(state.clone()),
+            },
+            RegisterInput::Write(value) => Some(value.clone()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KvInput {
+    Get { key: String },
+    Put { key: String, value: String },
+    Append { key: String, value: String },
+}
+
+impl KvInput {
+    pub fn key(&self) -> &str {
+        match self {
+            KvInput::Get { key } | KvInput::Put { key, .. } | KvInput::Append { key, .. } => key,
+        }
+    }
+}
+
+/// A key-value store of strings, where missing keys read as empty. Gets
+/// answer with the key's value; what puts and appends answer is ignored.
+/// Keys are checked one at a time, so the state is a single key's value.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct KvModel;
+
+impl Model for KvModel {
+    type State = String;
+    type Input = KvInput;
+    type Output = String;
+
+    fn init(&self) -> String {
+        String::new()
+    }
+
+    fn step(&self, state: &String, input: &KvInput, output: Option<&String>) -> Option<String> {
+        match input {
+            KvInput::Get { .. } => match output {
+                Some(value) if value != state => None,
+                _ => Some(state.clone()),
+            },
+            KvInput::Put { value, .. } => Some(value.clone()),
+            KvInput::Append { value, .. } => Some(format!("{}{}", state, value)),
+        }
+    }
+
+    fn partition(&self, operations: &[Operation<KvInput, String>]) -> Vec<Vec<usize>> {
+        let mut keys: Vec<&str> = Vec::new();
+        let mut partitions: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (i, operation) in operations.iter().enumerate() {
+            let key = operation.input.key();
+            if !partitions.contains_key(key) {
+                keys.push(key);
+            }
+            partitions.entry(key).or_default().push(i);
+        }
+        keys.into_iter()
+            .map(|key| partitions.remove(key).unwrap())
+            .collect()
+    }
+}
+
+/// Checks whether `operations` are linearizable under `model`: whether each
+/// can be taken to happen at one instant between its invocation and
+/// completion such that, in that order, the model accepts every output.
+/// Operations with an unknown outcome may also be taken never to have
+/// happened. Returns the first group that is not, as a `Counterexample`.
+///
+/// This is the search of Wing and Gong, with Lowe's memoization of the
+/// states already explored, as in Knossos and Porcupine.
+pub fn check<M: Model>(
+    model: &M,
+    operations: &[Operation<M::Input, M::Output>],
+) -> Result<(), Counterexample> {
+    for partition in model.partition(operations) {
+        check_partition(model, operations, &partition)?;
+    }
+    Ok(())
+}
+
+/// Marks an entry of the list with no neighbour.
+const NONE: usize = usize::MAX;
+
+/// The invocations and completions of a partition's operations in order of
+/// time, as a doubly linked list that linearized operations are lifted out
+/// of and put back into as the search backtracks.
+struct Events {
+    /// Each event's operation, and whether it is the completion.
+    events: Vec<(usize, bool)>,
+    /// The completion of the operation each invocation starts, if any.
+    completions: Vec<usize>,
+    next: Vec<usize>,
+    prev: Vec<usize>,
+}
+
+impl Events {
+    /// Index of the sentinel that heads the list.
+    fn head(&self) -> usize {
+        self.events.len()
+    }
+
+    fn first(&self) -> usize {
+        self.next[self.head()]
+    }
+
+    fn unlink(&mut self, event: usize) {
+        let (prev, next) = (self.prev[event], self.next[event]);
+        self.next[prev] = next;
+        if next != NONE {
+            self.prev[next] = prev;
+        }
+    }
+
+    fn relink(&mut self, event: usize) {
+        let (prev, next) = (self.prev[event], self.next[event]);
+        self.next[prev] = event;
+        if next != NONE {
+            self.prev[next] = event;
+        }
+    }
+
+    /// Takes out an invocation and its completion.
+    fn lift(&mut self, invocation: usize) {
+        self.unlink(invocation);
+        if self.completions[invocation] != NONE {
+            self.unlink(self.completions[invocation]);
+        }
+    }
+
+    /// Puts back what `lift` took out, in the reverse order.
+    fn unlift(&mut self, invocation: usize) {
+        if self.completions[invocation] != NONE {
+            self.relink(self.completions[invocation]);
+        }
+        self.relink(invocation);
+    }
+}
+
+fn check_partition<M: Model>(
+    model: &M,
+    operations: &[Operation<M::Input, M::Output>],
+    partition: &[usize],
+) -> Result<(), Counterexample> {
+    let mut times = Vec::new();
+    for &operation in partition.iter() {
+        times.push((operations[operation].invoked, false, operation));
+        if let Some(completed) = operations[operation].completed {
+            times.push((completed, true, operation));
+        }
+    }
+    // An invocation at the same instant as a completion is taken to overlap
+    // it.
+    times.sort();
+
+    let size = times.len();
+    // The sentinel at `size` heads the list.
+    let head = size;
+    let mut events = Events {
+        events: times
+            .iter()
+            .map(|(_, is_completion, operation)| (*operation, *is_completion))
+            .collect(),
+        completions: vec![NONE; size],
+        next: (0..=size)
+            .map(|event| match event {
+                _ if event == head && size > 0 => 0,
+                _ if event + 1 < size => event + 1,
+                _ => NONE,
+            })
+            .collect(),
+        prev: (0..=size)
+            .map(|event| match event {
+                0 => head,
+                _ if event == head => NONE,
+                _ => event - 1,
+            })
+            .collect(),
+    };
+    let mut invocations = HashMap::new();
+    for (event, (operation, is_completion)) in events.events.iter().enumerate() {
+        if *is_completion {
+            events.completions[invocations[operation]] = event;
+        } else {
+            invocations.insert(*operation, event);
+        }
+    }
+
+    let mut linearized = vec![0u64; operations.len().div_ceil(64)];
+    let mut explored: HashSet<(Vec<u64>, M::State)> = HashSet::new();
+    // Each linearized invocation, with the state before it.
+    let mut stack: Vec<(usize, M::State)> = Vec::new();
+    let mut state = model.init();
+    let mut longest: (Vec<usize>, M::State) = (Vec::new(), state.clone());
+    let mut event = events.first();
+    loop {
+        // Only operations with unknown outcomes are left, and they may never
+        // have happened.
+        if event == NONE {
+            return Ok(());
+        }
+        let (operation, is_completion) = events.events[event];
+        if !is_completion {
+            let Operation { input, output, .. } = &operations[operation];
+            if let Some(next_state) = model.step(&state, input, output.as_ref()) {
+                linearized[operation / 64] |= 1 << (operation % 64);
+                if explored.insert((linearized.clone(), next_state.clone())) {
+                    stack.push((event, std::mem::replace(&mut state, next_state)));
+                    events.lift(event);
+                    if stack.len() > longest.0.len() {
+                        let order = stack.iter().map(|(e, _)| events.events[*e].0).collect();
+                        longest = (order, state.clone());
+                    }
+                    event = events.first();
+                    continue;
+                }
+                linearized[operation / 64] &= !(1 << (operation % 64));
+            }
+            event = events.next[event];
+        } else {
+            // The operation that completes first among those left cannot
+            // have happened yet, so the last choice was wrong.
+            let Some((invocation, previous)) = stack.pop() else {
+                return Err(Counterexample::new(operations, partition, longest));
+            };
+            let operation = events.events[invocation].0;
+            linearized[operation / 64] &= !(1 << (operation % 64));
+            state = previous;
+            events.unlift(invocation);
+            event = events.next[invocation];
+        }
+    }
+}
+
+/// One operation of a counterexample, ready to draw.
+#[derive(Clone, Debug)]
+struct Row {
+    client: usize,
+    description: String,
+    invoked: Duration,
+    completed: Option<Duration>,
+    /// Where the operation comes in the longest linearizable prefix.
+    position: Option<usize>,
+    /// Whether it completed before anything else left could happen.
+    is_stuck: bool,
+}
+
+/// A group of operations that is not linearizable, with the longest prefix
+/// of it that is, the state that prefix leaves the model in, and the first
+/// operation to complete that no order can fit after it. Its `Display` draws
+/// the operations on a timeline.
+#[derive(Clone, Debug)]
+pub struct Counterexample {
+    rows: Vec<Row>,
+    state: String,
+}
+
+impl Counterexample {
+    fn new<I: fmt::Debug, O: fmt::Debug, S: fmt::Debug>(
+        operations: &[Operation<I, O>],
+        partition: &[usize],
+        (order, state): (Vec<usize>, S),
+    ) -> Self {
+        let stuck = partition
+            .iter()
+            .filter(|operation| !order.contains(operation))
+            .filter(|operation| operations[**operation].completed.is_some())
+            .min_by_key(|operation| operations[**operation].completed);
+        let mut rows: Vec<Row> = partition
+            .iter()
+            .map(|&operation| {
+                let Operation {
+                    client,
+                    input,
+                    output,
+                    invoked,
+                    completed,
+                } = &operations[operation];
+                let output = match output {
+                    Some(output) => format!("{:?}", output),
+                    None => "?".to_string(),
+                };
+                Row {
+                    client: *client,
+                    description: format!("{:?} -> {}", input, output),
+                    invoked: *invoked,
+                    completed: *completed,
+                    position: order.iter().position(|o| *o == operation),
+                    is_stuck: stuck == Some(&operation),
+                }
+            })
+            .collect();
+        rows.sort_by_key(|row| row.invoked);
+        Counterexample {
+            rows,
+            state: format!("{:?}", state),
+        }
+    }
+}
+
+impl fmt::Display for Counterexample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "not linearizable: after the operations numbered in order, the state is {}, \
+             and the one marked ! cannot follow",
+            self.state
+        )?;
+        let start = self
+            .rows
+            .iter()
+            .map(|row| row.invoked)
+            .min()
+            .unwrap_or_default();
+        let end = self
+            .rows
+            .iter()
+            .map(|row| row.completed.unwrap_or(row.invoked))
+            .max()
+            .unwrap_or_default();
+        let span = (end - start).as_secs_f64().max(f64::EPSILON);
+        let column = |time: Duration| {
+            (((time - start).as_secs_f64() / span) * (TIMELINE_WIDTH - 1) as f64).round() as usize
+        };
+
+        for row in self.rows.iter() {
+            let mark = match (row.position, row.is_stuck) {
+                (_, true) => "!".to_string(),
+                (Some(position), _) => (position + 1).to_string(),
+                (None, _) => String::new(),
+            };
+            let mut timeline = vec![' '; TIMELINE_WIDTH];
+            let from = column(row.invoked);
+            let to = row.completed.map_or(TIMELINE_WIDTH - 1, column).max(from);
+            for cell in timeline[from..=to].iter_mut() {
+                *cell = '-';
+            }
+            timeline[from] = '[';
+            timeline[to] = if row.completed.is_some() { ']' } else { '>' };
+            writeln!(
+                f,
+                "{:>3}  client {:<3} |{}| {}",
+                mark,
+                row.client,
+                timeline.into_iter().collect::<String>(),
+                row.description
+            )?;
+        }
+        Ok(())
+    }
+}
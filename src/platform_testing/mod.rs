@@ -1,2 +1,7 @@
+pub mod disk;
+pub mod linearizability;
+pub mod nemesis;
 pub mod network;
 pub mod network_interface;
+pub mod raft_cluster;
+pub mod replay;
@@ -0,0 +1,486 @@
+use crate::platform_testing::linearizability::{check, History, KvInput, KvModel};
+use crate::platform_testing::network::SimulatedNetworkConfig;
+use crate::platform_testing::raft_cluster::{reliable_network, AppliedCommands, RaftCluster};
+use crate::platform_testing::replay::Journal;
+use crate::raft::node::{RaftError, RaftNode};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How many times a shrunk schedule is run before deciding it passes, since
+/// the cluster runs on the real clock and a failure may not show every time.
+const SHRINK_ATTEMPTS: usize = 2;
+
+/// Waits are not shrunk below this, leaving the cluster time to react.
+const MIN_WAIT: Duration = Duration::from_millis(10);
+
+/// The running nodes clients send operations to, with what each applied.
+pub type KvNodes = Arc<Mutex<Vec<(Arc<RaftNode>, AppliedCommands)>>>;
+
+/// A fault the nemesis brings on, or takes away, in a running cluster.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fault {
+    /// Splits the cluster into groups that reach only their own members.
+    Partition(Vec<Vec<usize>>),
+    Heal,
+    Crash(usize),
+    Restart(usize),
+    /// Delays many packets, some past the election timeout.
+    DelaySpike,
+    /// Ends a delay spike.
+    Calm,
+    /// Runs a node's clock `rate` times as fast as real time.
+    ClockSkew {
+        node: usize,
+        rate: f64,
+    },
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::Partition(groups) => write!(f, "partition {:?}", groups),
+            Fault::Heal => write!(f, "heal"),
+            Fault::Crash(node) => write!(f, "crash {}", node),
+            Fault::Restart(node) => write!(f, "restart {}", node),
+            Fault::DelaySpike => write!(f, "delay spike"),
+            Fault::Calm => write!(f, "calm"),
+            Fault::ClockSkew { node, rate } => write!(f, "skew clock of {} to {}x", node, rate),
+        }
+    }
+}
+
+/// A fault brought on once `wait` has passed since the previous step.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub wait: Duration,
+    pub fault: Fault,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schedule {
+    pub seed: u64,
+    pub steps: Vec<Step>,
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "schedule of seed {}:", self.seed)?;
+        for step in self.steps.iter() {
+            write!(
+                f,
+                "\n  after {:>4}ms: {}",
+                step.wait.as_millis(),
+                step.fault
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct NemesisConfig {
+    /// Nodes in the cluster under test.
+    pub size: usize,
+    /// Faults in each generated schedule.
+    pub steps: usize,
+    /// How long to wait between faults.
+    pub interval: Range<Duration>,
+    /// Clients running operations throughout.
+    pub clients: usize,
+}
+
+impl Default for NemesisConfig {
+    fn default() -> Self {
+        NemesisConfig {
+            size: 5,
+            steps: 10,
+            interval: Duration::from_millis(100)..Duration::from_millis(800),
+            clients: 3,
+        }
+    }
+}
+
+/// Delays half the packets a little and one in ten past the election
+/// timeout.
+pub fn delay_spike() -> SimulatedNetworkConfig {
+    SimulatedNetworkConfig {
+        drop_rate: 0.0,
+        short_delay_rate: 0.5,
+        long_delay_rate: 0.1,
+        short_delay_range: Duration::from_millis(5)..Duration::from_millis(50),
+        long_delay_range: Duration::from_millis(100)..Duration::from_millis(400),
+        duplicate_rate: 0.0,
+    }
+}
+
+impl Schedule {
+    /// Generates the schedule for `seed`; the same seed always gives the same
+    /// schedule. At most a minority of nodes is down at any time, and only
+    /// crashed nodes are restarted.
+    pub fn generate(seed: u64, config: &NemesisConfig) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut crashed = BTreeSet::new();
+        let mut steps = Vec::new();
+        for _ in 0..config.steps {
+            let wait = Duration::from_millis(rng.gen_range(
+                config.interval.start.as_millis() as u64..config.interval.end.as_millis() as u64,
+            ));
+            let fault = match rng.gen_range(0..100) {
+                0..=24 => {
+                    let mut nodes: Vec<usize> = (0..config.size).collect();
+                    nodes.shuffle(&mut rng);
+                    let split = rng.gen_range(1..config.size);
+                    let mut groups = vec![nodes[..split].to_vec(), nodes[split..].to_vec()];
+                    for group in groups.iter_mut() {
+                        group.sort();
+                    }
+                    groups.sort();
+                    Fault::Partition(groups)
+                }
+                25..=39 => Fault::Heal,
+                40..=54 if crashed.len() < (config.size - 1) / 2 => {
+                    let running: Vec<usize> = (0..config.size)
+                        .filter(|node| !crashed.contains(node))
+                        .collect();
+                    let node = *running.choose(&mut rng).unwrap();
+                    crashed.insert(node);
+                    Fault::Crash(node)
+                }
+                40..=69 if !crashed.is_empty() => {
+                    let down: Vec<usize> = crashed.iter().copied().collect();
+                    let node = *down.choose(&mut rng).unwrap();
+                    crashed.remove(&node);
+                    Fault::Restart(node)
+                }
+                40..=69 => Fault::Heal,
+                70..=79 => Fault::DelaySpike,
+                80..=89 => Fault::Calm,
+                _ => Fault::ClockSkew {
+                    node: rng.gen_range(0..config.size),
+                    rate: rng.gen_range(70..=130) as f64 / 100.0,
+                },
+            };
+            steps.push(Step { wait, fault });
+        }
+        Schedule { seed, steps }
+    }
+}
+
+fn encode(input: &KvInput) -> Vec<u8> {
+    match input {
+        KvInput::Get { key } => format!("get\t{}", key),
+        KvInput::Put { key, value } => format!("put\t{}\t{}", key, value),
+        KvInput::Append { key, value } => format!("append\t{}\t{}", key, value),
+    }
+    .into_bytes()
+}
+
+/// The value of `key` once the first `count` commands a node applied have
+/// run against an empty store.
+fn value_after(applied: &AppliedCommands, count: usize, key: &str) -> String {
+    let mut value = String::new();
+    for (_, command) in applied.lock().unwrap()[..count].iter() {
+        let command = String::from_utf8(command.clone()).unwrap();
+        let fields: Vec<&str> = command.split('\t').collect();
+        match fields[..] {
+            ["put", k, v] if k == key => value = v.to_string(),
+            ["append", k, v] if k == key => value.push_str(v),
+            _ => {}
+        }
+    }
+    value
+}
+
+/// Runs random operations on two keys through whichever node leads until
+/// `done` is set, leaving the outcome unknown when the leader does not
+/// answer. Every operation, reads included, goes through the log of a
+/// `RaftCluster` built with `RecordingStateMachine`s.
+pub async fn run_kv_client(
+    client: usize,
+    seed: u64,
+    nodes: KvNodes,
+    history: Arc<History<KvInput, String>>,
+    done: Arc<AtomicBool>,
+) {
+    let mut rng = StdRng::seed_from_u64(seed.wrapping_add(client as u64));
+    let mut n = 0;
+    while !done.load(Ordering::SeqCst) {
+        let key = ["x", "y"][rng.gen_range(0..2)].to_string();
+        let value = format!("{}.{} ", client, n);
+        let input = match rng.gen_range(0..3) {
+            0 => KvInput::Get { key },
+            1 => KvInput::Put { key, value },
+            _ => KvInput::Append { key, value },
+        };
+        n += 1;
+
+        let id = history.invoke(client, input.clone());
+        let mut output = None;
+        'attempts: for _ in 0..10 {
+            let running = nodes.lock().unwrap().clone();
+            for (node, applied) in running.iter() {
+                let proposal = node.propose(encode(&input));
+                match tokio::time::timeout(Duration::from_secs(1), proposal).await {
+                    Ok(Ok(response)) => {
+                        let count = u64::from_be_bytes(response.try_into().unwrap());
+                        output = Some(value_after(applied, count as usize, input.key()));
+                        break 'attempts;
+                    }
+                    Ok(Err(RaftError::NotLeader { .. })) => continue,
+                    _ => break 'attempts,
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        if let Some(output) = output {
+            history.complete(id, output);
+        }
+    }
+}
+
+/// Points clients at the nodes of `cluster` that are running.
+fn publish(cluster: &RaftCluster, nodes: &KvNodes) {
+    *nodes.lock().unwrap() = (0..cluster.nodes.len())
+        .filter(|i| cluster.running[*i])
+        .map(|i| {
+            (
+                Arc::clone(&cluster.nodes[i]),
+                Arc::clone(&cluster.applied[i]),
+            )
+        })
+        .collect();
+}
+
+/// Runs `schedule` against a fresh cluster while clients run operations,
+/// then lifts every fault and checks the cluster recovers and the history
+/// is linearizable. Panics on the first failure.
+async fn run(schedule: Schedule, config: NemesisConfig, journal: Option<Arc<Journal>>) {
+    let mut cluster = match journal {
+        Some(journal) => RaftCluster::new_with_journal(config.size, journal),
+        None => RaftCluster::new_with_seed(config.size, schedule.seed),
+    };
+    let nodes: KvNodes = Arc::new(Mutex::new(Vec::new()));
+    publish(&cluster, &nodes);
+    let history = Arc::new(History::new());
+    let done = Arc::new(AtomicBool::new(false));
+    let clients: Vec<_> = (0..config.clients)
+        .map(|client| {
+            tokio::spawn(run_kv_client(
+                client,
+                schedule.seed,
+                Arc::clone(&nodes),
+                Arc::clone(&history),
+                Arc::clone(&done),
+            ))
+        })
+        .collect();
+
+    let mut skews = HashMap::new();
+    for step in schedule.steps.iter() {
+        tokio::time::sleep(step.wait).await;
+        println!("Nemesis: {}", step.fault);
+        match &step.fault {
+            Fault::Partition(groups) => {
+                let groups: Vec<&[usize]> = groups.iter().map(|group| &group[..]).collect();
+                cluster.partition(&groups);
+            }
+            Fault::Heal => cluster.heal(),
+            // Shrinking may leave a crash or restart with nothing to do.
+            Fault::Crash(i) if cluster.running[*i] => {
+                cluster.crash(*i);
+                publish(&cluster, &nodes);
+            }
+            Fault::Restart(i) if !cluster.running[*i] => {
+                cluster.restart(*i);
+                if let Some(rate) = skews.get(i) {
+                    cluster.nodes[*i].set_clock_rate(*rate);
+                }
+                publish(&cluster, &nodes);
+            }
+            Fault::Crash(_) | Fault::Restart(_) => {}
+            Fault::DelaySpike => cluster.network.set_config(delay_spike()),
+            Fault::Calm => cluster.network.set_config(reliable_network()),
+            Fault::ClockSkew { node, rate } => {
+                cluster.nodes[*node].set_clock_rate(*rate);
+                skews.insert(*node, *rate);
+            }
+        }
+    }
+
+    cluster.heal();
+    cluster.network.set_config(reliable_network());
+    for i in 0..config.size {
+        if !cluster.running[i] {
+            cluster.restart(i);
+        }
+        cluster.nodes[i].set_clock_rate(1.0);
+    }
+    publish(&cluster, &nodes);
+    cluster.check_one_leader().await;
+    cluster.one(b"final", config.size).await;
+
+    done.store(true, Ordering::SeqCst);
+    for client in clients {
+        client.await.unwrap();
+    }
+    if let Err(counterexample) = check(&KvModel, &history.operations()) {
+        panic!("{}", counterexample);
+    }
+    cluster.stop();
+}
+
+/// Runs `schedule` on a runtime of its own, making the cluster's decisions
+/// through `journal` if given, and returns why it failed if it did.
+pub fn run_schedule(
+    schedule: &Schedule,
+    config: &NemesisConfig,
+    journal: Option<Arc<Journal>>,
+) -> Result<(), String> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let task = runtime.spawn(run(schedule.clone(), config.clone(), journal));
+    match runtime.block_on(task) {
+        Ok(()) => Ok(()),
+        Err(error) => {
+            let panic = error.into_panic();
+            match (panic.downcast_ref::<String>(), panic.downcast_ref::<&str>()) {
+                (Some(message), _) => Err(message.clone()),
+                (_, Some(message)) => Err(message.to_string()),
+                _ => Err("panicked".to_string()),
+            }
+        }
+    }
+}
+
+/// Cuts `schedule` down to a smaller one that still `fails`, first dropping
+/// ever smaller runs of steps and then shortening the waits between them.
+pub fn shrink(schedule: &Schedule, mut fails: impl FnMut(&Schedule) -> bool) -> Schedule {
+    let mut smallest = schedule.clone();
+    let mut chunk = (smallest.steps.len() / 2).max(1);
+    loop {
+        let mut removed = false;
+        let mut start = 0;
+        while start < smallest.steps.len() {
+            let mut candidate = smallest.clone();
+            let end = (start + chunk).min(candidate.steps.len());
+            candidate.steps.drain(start..end);
+            if fails(&candidate) {
+                smallest = candidate;
+                removed = true;
+            } else {
+                start += chunk;
+            }
+        }
+        if !removed {
+            if chunk == 1 {
+                break;
+            }
+            chunk /= 2;
+        }
+    }
+
+    for i in 0..smallest.steps.len() {
+        while smallest.steps[i].wait / 2 >= MIN_WAIT {
+            let mut candidate = smallest.clone();
+            candidate.steps[i].wait /= 2;
+            if !fails(&candidate) {
+                break;
+            }
+            smallest = candidate;
+        }
+    }
+    smallest
+}
+
+/// A seed whose schedule failed, shrunk to a minimal reproduction.
+#[derive(Debug)]
+pub struct Failure {
+    pub seed: u64,
+    pub schedule: Schedule,
+    pub message: String,
+    /// Where the failing run was saved for `replay_seed`, unless saving it
+    /// failed.
+    pub recording: Option<PathBuf>,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "seed {} failed: {}\nminimal {}",
+            self.seed, self.message, self.schedule
+        )?;
+        if let Some(recording) = self.recording.as_ref() {
+            write!(
+                f,
+                "\nreplay with NEMESIS_SEED_START={} NEMESIS_SEEDS=1 NEMESIS_REPLAY={}",
+                self.seed,
+                recording.display()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Where the run of `seed` is saved when it fails.
+fn recording_path(seed: u64) -> PathBuf {
+    env::temp_dir().join(format!("nemesis-seed-{}.replay", seed))
+}
+
+/// Runs the schedule of every seed in `seeds`, saving the run of the first
+/// that fails and shrinking its schedule.
+pub fn run_seeds(seeds: Range<u64>, config: &NemesisConfig) -> Result<(), Failure> {
+    for seed in seeds {
+        let schedule = Schedule::generate(seed, config);
+        println!("Nemesis: running {}", schedule);
+        let journal = Arc::new(Journal::new());
+        if let Err(message) = run_schedule(&schedule, config, Some(Arc::clone(&journal))) {
+            let recording = recording_path(seed);
+            let recording = match journal.save(&recording) {
+                Ok(()) => Some(recording),
+                Err(error) => {
+                    println!(
+                        "Nemesis: failed to save the run of seed {}: {}",
+                        seed, error
+                    );
+                    None
+                }
+            };
+            let schedule = shrink(&schedule, |candidate| {
+                (0..SHRINK_ATTEMPTS).any(|_| run_schedule(candidate, config, None).is_err())
+            });
+            return Err(Failure {
+                seed,
+                schedule,
+                message,
+                recording,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Replays the run of `seed` saved at `recording`, returning why it failed
+/// if it did.
+pub fn replay_seed(seed: u64, config: &NemesisConfig, recording: &Path) -> Result<(), String> {
+    let journal = Arc::new(Journal::load(recording).map_err(|error| error.to_string())?);
+    let schedule = Schedule::generate(seed, config);
+    println!("Nemesis: replaying {}", schedule);
+    let result = run_schedule(&schedule, config, Some(Arc::clone(&journal)));
+    println!(
+        "Nemesis: the replay diverged from the recording {} times",
+        journal.divergences().len()
+    );
+    result
+}
@@ -1,49 +1,107 @@
-use crate::platform::network::{Packet, TcpStream};
+use crate::platform::network::{is_multicast_ip, Packet, PacketType, BROADCAST_IP};
 use crate::platform_testing::network_interface::SimulatedNetworkInterface;
-use rand::rngs::ThreadRng;
-use rand::Rng;
+use crate::platform_testing::replay::Journal;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::collections::{HashMap, HashSet};
+use std::io;
 use std::ops::Range;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct SimulatedNetworkConfig {
     pub drop_rate: f32,
     pub short_delay_rate: f32,
     pub long_delay_rate: f32,
     pub short_delay_range: Range<Duration>,
     pub long_delay_range: Range<Duration>,
+    /// Probability that a datagram is delivered twice. Stream packets are never
+    /// duplicated because TCP discards duplicate segments.
+    pub duplicate_rate: f32,
+}
+
+#[derive(Debug, Clone)]
+struct HostRecord {
+    ips: Vec<String>,
+    ttl: Duration,
 }
 
 #[derive(Debug)]
 pub struct SimulatedNetwork {
     network_interfaces: Arc<Mutex<HashMap<String, Arc<SimulatedNetworkInterface>>>>,
     connections: Arc<Mutex<HashSet<(String, String)>>>,
-    config: SimulatedNetworkConfig,
+    host_records: Arc<Mutex<HashMap<String, HostRecord>>>,
+    resolution_failures: Arc<Mutex<HashMap<String, io::ErrorKind>>>,
+    config: Mutex<SimulatedNetworkConfig>,
+    journal: Mutex<Option<Arc<Journal>>>,
+    /// Makes every random choice not taken from the journal.
+    rng: Mutex<StdRng>,
+    /// How many packets have been sent over each link.
+    link_sequences: Mutex<HashMap<String, u64>>,
 }
 
 impl SimulatedNetwork {
     pub fn new(config: SimulatedNetworkConfig) -> Self {
+        Self::with_seed(config, rand::random())
+    }
+
+    /// Creates a network whose random choices all come from one generator
+    /// seeded with `seed`, so that the seed reproduces them.
+    pub fn with_seed(config: SimulatedNetworkConfig, seed: u64) -> Self {
         SimulatedNetwork {
             network_interfaces: Arc::new(Mutex::new(HashMap::new())),
             connections: Arc::new(Mutex::new(HashSet::new())),
-            config,
+            host_records: Arc::new(Mutex::new(HashMap::new())),
+            resolution_failures: Arc::new(Mutex::new(HashMap::new())),
+            config: Mutex::new(config),
+            journal: Mutex::new(None),
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+            link_sequences: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Makes every random choice and packet delivery through `journal`, to
+    /// record the run or replay one recorded before.
+    pub fn set_journal(&self, journal: Arc<Journal>) {
+        *self.journal.lock().unwrap() = Some(journal);
+    }
+
+    /// Draws the next number of `stream` from the journal, if there is one,
+    /// else from `draw`.
+    pub fn draw(&self, stream: &str, draw: impl FnOnce() -> u64) -> u64 {
+        match self.journal.lock().unwrap().as_ref() {
+            Some(journal) => journal.draw(stream, draw),
+            None => draw(),
         }
     }
 
+    /// Runs `f` with the network's generator, for random choices that should
+    /// follow from the network's seed.
+    pub fn with_rng<T>(&self, f: impl FnOnce(&mut StdRng) -> T) -> T {
+        f(&mut self.rng.lock().unwrap())
+    }
+
+    /// Changes how packets are dropped, delayed and duplicated from now on,
+    /// such as to bring on a spike in latency.
+    pub fn set_config(&self, config: SimulatedNetworkConfig) {
+        *self.config.lock().unwrap() = config;
+    }
+
     pub fn get_network_interface(&self, ip: &str) -> Option<Arc<SimulatedNetworkInterface>> {
         let network_interfaces = self.network_interfaces.lock().unwrap();
-        network_interfaces
-            .get(ip)
-            .map(|network_interface| network_interface.clone())
+        network_interfaces.get(ip).cloned()
     }
 
     pub fn send_packet(&self, packet: &Packet) {
-        let source_ip = packet.source.ip.to_string();
-        let destination_ip = packet.destination.ip.to_string();
-        let connections = self.connections.lock().unwrap();
-        if !connections.contains(&(source_ip, destination_ip)) {
+        if let PacketType::Datagram = packet.packet_type {
+            if packet.destination.ip == BROADCAST_IP || is_multicast_ip(&packet.destination.ip) {
+                self.send_group_datagram(packet);
+                return;
+            }
+        }
+
+        if !self.is_connected(&packet.source.ip, &packet.destination.ip) {
             println!(
                 "No connection between {}:{}, dropping packet",
                 packet.source.port, packet.destination.ip
@@ -51,50 +109,21 @@ impl SimulatedNetwork {
             return;
         }
 
-        let mut rng = rand::thread_rng();
-        let mut sample: f32 = rng.gen();
-        if sample <= self.config.drop_rate {
-            println!("Drop packet");
-            return;
-        }
-
-        sample -= self.config.drop_rate;
-
-        let network_interfaces = self.network_interfaces.lock().unwrap();
-        let destination_network_interface = match network_interfaces.get(&packet.destination.ip) {
+        let destination_network_interface = match self.get_network_interface(&packet.destination.ip)
+        {
             None => return,
             Some(network_interface) => network_interface,
         };
-        let stream =
-            match destination_network_interface.get_stream(&packet.source, &packet.destination) {
-                None => return,
-                Some(stream) => stream,
-            };
-
-        if sample <= self.config.short_delay_rate {
-            Self::delay_packet(
-                &mut rng,
-                stream,
-                packet.clone(),
-                self.config.short_delay_range.clone(),
-            );
-            return;
-        }
 
-        sample -= self.config.short_delay_rate;
-
-        if sample <= self.config.long_delay_rate {
-            Self::delay_packet(
-                &mut rng,
-                stream,
-                packet.clone(),
-                self.config.long_delay_range.clone(),
-            );
-            return;
+        let link = self.next_on_link(packet);
+        match packet.packet_type {
+            PacketType::Data | PacketType::Control => {
+                self.deliver_packet(&link, packet, destination_network_interface)
+            }
+            PacketType::Datagram => {
+                self.deliver_datagram(&link, packet, destination_network_interface)
+            }
         }
-
-        sample -= self.config.long_delay_rate;
-        stream.on_packet_received(packet)
     }
 
     pub fn connect(&self, ip1: &str, ip2: &str) {
@@ -109,6 +138,13 @@ impl SimulatedNetwork {
         connections.remove(&(ip2.to_string(), ip1.to_string()));
     }
 
+    /// Drops packets from `source_ip` to `destination_ip` while still
+    /// delivering those going the other way.
+    pub fn disconnect_one_way(&self, source_ip: &str, destination_ip: &str) {
+        let mut connections = self.connections.lock().unwrap();
+        connections.remove(&(source_ip.to_string(), destination_ip.to_string()));
+    }
+
     pub fn register_network_interface(&self, network_interface: Arc<SimulatedNetworkInterface>) {
         let mut network_interfaces = self.network_interfaces.lock().unwrap();
         for ip in network_interface.get_ip_addresses() {
@@ -116,22 +152,223 @@ impl SimulatedNetwork {
         }
     }
 
+    pub fn register_host(&self, hostname: &str, ips: Vec<&str>, ttl: Duration) {
+        let mut host_records = self.host_records.lock().unwrap();
+        host_records.insert(
+            hostname.to_lowercase(),
+            HostRecord {
+                ips: ips.iter().map(|ip| ip.to_string()).collect(),
+                ttl,
+            },
+        );
+    }
+
+    pub fn unregister_host(&self, hostname: &str) {
+        let mut host_records = self.host_records.lock().unwrap();
+        host_records.remove(&hostname.to_lowercase());
+    }
+
+    /// Makes every lookup of `hostname` that reaches the name service fail with
+    /// `error_kind` until `clear_resolution_failure` is called. Answers already
+    /// cached by a network interface keep being served until their TTL expires.
+    pub fn inject_resolution_failure(&self, hostname: &str, error_kind: io::ErrorKind) {
+        let mut resolution_failures = self.resolution_failures.lock().unwrap();
+        resolution_failures.insert(hostname.to_lowercase(), error_kind);
+    }
+
+    pub fn clear_resolution_failure(&self, hostname: &str) {
+        let mut resolution_failures = self.resolution_failures.lock().unwrap();
+        resolution_failures.remove(&hostname.to_lowercase());
+    }
+
+    pub fn resolve(&self, hostname: &str) -> io::Result<(Vec<String>, Duration)> {
+        let hostname = hostname.to_lowercase();
+        if let Some(error_kind) = self.resolution_failures.lock().unwrap().get(&hostname) {
+            return Err(io::Error::new(
+                *error_kind,
+                format!("failed to resolve {}", hostname),
+            ));
+        }
+
+        let host_records = self.host_records.lock().unwrap();
+        match host_records.get(&hostname) {
+            Some(host_record) if !host_record.ips.is_empty() => {
+                Ok((host_record.ips.clone(), host_record.ttl))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("unknown host {}", hostname),
+            )),
+        }
+    }
+
+    fn is_connected(&self, source_ip: &str, destination_ip: &str) -> bool {
+        source_ip == destination_ip
+            || self
+                .connections
+                .lock()
+                .unwrap()
+                .contains(&(source_ip.to_string(), destination_ip.to_string()))
+    }
+
+    fn send_group_datagram(&self, packet: &Packet) {
+        let mut reachable_network_interfaces: Vec<Arc<SimulatedNetworkInterface>> = Vec::new();
+        {
+            let network_interfaces = self.network_interfaces.lock().unwrap();
+            for (ip, network_interface) in network_interfaces.iter() {
+                if !self.is_connected(&packet.source.ip, ip) {
+                    continue;
+                }
+                if reachable_network_interfaces
+                    .iter()
+                    .any(|reachable| Arc::ptr_eq(reachable, network_interface))
+                {
+                    continue;
+                }
+                reachable_network_interfaces.push(Arc::clone(network_interface));
+            }
+        }
+
+        // Sorted so that a replay delivers to the hosts in the same order.
+        reachable_network_interfaces.sort_by_key(|network_interface| host(network_interface));
+        let link = self.next_on_link(packet);
+        for network_interface in reachable_network_interfaces {
+            self.deliver_datagram(&link, packet, network_interface);
+        }
+    }
+
+    /// Names the link `packet` travels and numbers the packet on it.
+    fn next_on_link(&self, packet: &Packet) -> (String, u64) {
+        let link = format!(
+            "{}:{} > {}:{}",
+            packet.source.ip, packet.source.port, packet.destination.ip, packet.destination.port
+        );
+        let mut link_sequences = self.link_sequences.lock().unwrap();
+        let sequence = link_sequences.entry(link.clone()).or_default();
+        *sequence += 1;
+        (link, *sequence)
+    }
+
+    /// Draws a sample from `[0, 1)` for a packet on `link`.
+    fn sample(&self, link: &str) -> f32 {
+        let bits = self.draw(link, || {
+            self.with_rng(|rng| rng.gen::<f32>()).to_bits() as u64
+        });
+        f32::from_bits(bits as u32)
+    }
+
+    fn deliver_datagram(
+        &self,
+        link: &(String, u64),
+        packet: &Packet,
+        network_interface: Arc<SimulatedNetworkInterface>,
+    ) {
+        let duplicate_rate = self.config.lock().unwrap().duplicate_rate;
+        if self.sample(&link.0) < duplicate_rate {
+            println!("Duplicate packet");
+            self.deliver_packet(link, packet, Arc::clone(&network_interface));
+        }
+        self.deliver_packet(link, packet, network_interface);
+    }
+
+    fn deliver_packet(
+        &self,
+        link: &(String, u64),
+        packet: &Packet,
+        network_interface: Arc<SimulatedNetworkInterface>,
+    ) {
+        let config = self.config.lock().unwrap().clone();
+        let mut sample = self.sample(&link.0);
+        if sample <= config.drop_rate {
+            println!("Drop packet");
+            return;
+        }
+
+        sample -= config.drop_rate;
+
+        if sample <= config.short_delay_rate {
+            self.delay_packet(
+                link,
+                network_interface,
+                packet.clone(),
+                config.short_delay_range.clone(),
+            );
+            return;
+        }
+
+        sample -= config.short_delay_rate;
+
+        if sample <= config.long_delay_rate {
+            self.delay_packet(
+                link,
+                network_interface,
+                packet.clone(),
+                config.long_delay_range.clone(),
+            );
+            return;
+        }
+
+        let journal = self.journal.lock().unwrap().clone();
+        hand_over(journal, link, packet.clone(), network_interface)
+    }
+
     fn delay_packet(
-        rng: &mut ThreadRng,
-        stream: Arc<dyn TcpStream>,
+        &self,
+        link: &(String, u64),
+        network_interface: Arc<SimulatedNetworkInterface>,
         packet: Packet,
         delay_range: Range<Duration>,
     ) {
-        let delay = rng.gen_range(delay_range);
+        let nanos = self.draw(&link.0, || {
+            self.with_rng(|rng| rng.gen_range(delay_range)).as_nanos() as u64
+        });
+        let delay = Duration::from_nanos(nanos);
         println!(
             "Delay packet to {}:{} for {}ms",
             packet.destination.ip,
             packet.destination.port,
             delay.as_millis()
         );
-        tokio::spawn(async move {
-            tokio::time::sleep(delay).await;
-            stream.on_packet_received(&packet);
-        });
+        let journal = self.journal.lock().unwrap().clone();
+        let link = link.clone();
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    hand_over(journal, &link, packet, network_interface);
+                });
+            }
+            Err(_) => {
+                std::thread::spawn(move || {
+                    std::thread::sleep(delay);
+                    hand_over(journal, &link, packet, network_interface);
+                });
+            }
+        }
     }
 }
+
+/// Hands `packet` to its destination, in the order `journal` recorded when
+/// replaying.
+fn hand_over(
+    journal: Option<Arc<Journal>>,
+    link: &(String, u64),
+    packet: Packet,
+    network_interface: Arc<SimulatedNetworkInterface>,
+) {
+    match journal {
+        Some(journal) => journal.deliver(
+            &format!("to {}", host(&network_interface)),
+            format!("{} #{}", link.0, link.1),
+            move || network_interface.on_packet_received(&packet),
+        ),
+        None => network_interface.on_packet_received(&packet),
+    }
+}
+
+/// Names the host behind `network_interface` by its addresses.
+fn host(network_interface: &SimulatedNetworkInterface) -> String {
+    let mut ips = network_interface.get_ip_addresses();
+    ips.sort();
+    ips.join(",")
+}
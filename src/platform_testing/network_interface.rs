@@ -1,20 +1,55 @@
 use crate::platform::network::{
-    Control, Endpoint, NetworkInterface, Packet, PacketType, TcpListener, TcpStream,
+    is_multicast_ip, Control, Endpoint, Incoming, NetworkInterface, Packet, PacketType,
+    TcpListener, TcpStream, UdpSocket, BROADCAST_IP,
 };
 use crate::platform_testing::network::SimulatedNetwork;
 use rand::Rng;
-use std::collections::{HashMap, HashSet};
-use std::sync::mpsc::{Receiver, Sender};
-use std::sync::{mpsc, Arc, Mutex};
-use std::thread::spawn;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::{mpsc, Arc, Condvar, Mutex, Weak};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+
+const MAX_DATAGRAM_SIZE: usize = 65507;
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const INITIAL_RETRANSMISSION_TIMEOUT: Duration = Duration::from_millis(100);
+const SYN_ACK_RETRIES: u32 = 5;
+
+/// What a host does with a SYN that arrives while the accept queue of the
+/// listening socket is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BacklogOverflow {
+    /// Silently drop the SYN, so the client keeps retransmitting it until the
+    /// queue has room or its connect timeout expires (Linux default).
+    DropSyn,
+    /// Answer with a reset so the client sees the connection refused.
+    Refuse,
+}
+
+type EndpointMap<T> = Arc<Mutex<HashMap<(String, u16), Arc<T>>>>;
+type PortMap = Arc<Mutex<HashMap<String, HashSet<u16>>>>;
+/// UDP sockets are held weakly so that dropping the last handle unbinds them.
+type UdpSocketMap = Arc<Mutex<HashMap<(String, u16), Weak<SimulatedUdpSocket>>>>;
+
+#[derive(Debug)]
+struct CachedHostRecord {
+    ips: Vec<String>,
+    expires_at: Instant,
+}
 
 #[derive(Debug)]
 pub struct SimulatedNetworkInterface {
     network: Arc<SimulatedNetwork>,
     ip_addresses: Arc<Mutex<HashSet<String>>>,
-    allocated_ports: Arc<Mutex<HashMap<String, HashSet<u16>>>>,
-    tcp_listeners: Arc<Mutex<HashMap<(String, u16), Arc<SimulatedTcpListener>>>>,
-    streams: Arc<Mutex<HashMap<(String, u16), Arc<SimulatedTcpStream>>>>,
+    allocated_ports: PortMap,
+    tcp_listeners: EndpointMap<SimulatedTcpListener>,
+    streams: EndpointMap<SimulatedTcpStream>,
+    udp_sockets: UdpSocketMap,
+    resolver_cache: Arc<Mutex<HashMap<String, CachedHostRecord>>>,
+    backlog_overflow: Mutex<BacklogOverflow>,
+    connect_timeout: Mutex<Duration>,
 }
 
 impl SimulatedNetworkInterface {
@@ -25,9 +60,21 @@ impl SimulatedNetworkInterface {
             allocated_ports: Arc::new(Mutex::new(HashMap::new())),
             tcp_listeners: Arc::new(Mutex::new(HashMap::new())),
             streams: Arc::new(Mutex::new(HashMap::new())),
+            udp_sockets: Arc::new(Mutex::new(HashMap::new())),
+            resolver_cache: Arc::new(Mutex::new(HashMap::new())),
+            backlog_overflow: Mutex::new(BacklogOverflow::DropSyn),
+            connect_timeout: Mutex::new(DEFAULT_CONNECT_TIMEOUT),
         }
     }
 
+    pub fn set_backlog_overflow(&self, backlog_overflow: BacklogOverflow) {
+        *self.backlog_overflow.lock().unwrap() = backlog_overflow;
+    }
+
+    pub fn set_connect_timeout(&self, connect_timeout: Duration) {
+        *self.connect_timeout.lock().unwrap() = connect_timeout;
+    }
+
     pub fn get_ip_addresses(&self) -> Vec<String> {
         self.ip_addresses
             .lock()
@@ -44,6 +91,7 @@ impl SimulatedNetworkInterface {
         }
     }
 
+    #[allow(dead_code)]
     pub fn remove_ip_addresses(&self, ip_addresses: Vec<&str>) {
         let mut curr_ip_addresses = self.ip_addresses.lock().unwrap();
         for ip in ip_addresses {
@@ -51,11 +99,19 @@ impl SimulatedNetworkInterface {
         }
     }
 
-    pub fn get_stream(
-        &self,
-        source_endpoint: &Endpoint,
-        destination_endpoint: &Endpoint,
-    ) -> Option<Arc<dyn TcpStream>> {
+    pub fn on_packet_received(&self, packet: &Packet) {
+        match packet.packet_type {
+            PacketType::Data | PacketType::Control => self.on_tcp_packet_received(packet),
+            PacketType::Datagram => {
+                for udp_socket in self.get_udp_sockets(&packet.destination) {
+                    udp_socket.on_packet_received(packet);
+                }
+            }
+        }
+    }
+
+    fn on_tcp_packet_received(&self, packet: &Packet) {
+        let destination_endpoint = &packet.destination;
         let stream = self
             .streams
             .lock()
@@ -64,24 +120,23 @@ impl SimulatedNetworkInterface {
                 destination_endpoint.ip.to_string(),
                 destination_endpoint.port,
             ))
-            .map(|stream| Arc::clone(stream));
-        match stream {
-            Some(stream) => return Some(stream),
-            None => {}
+            .map(Arc::clone);
+        if let Some(stream) = stream {
+            stream.on_packet_received(packet);
+            return;
         }
 
-        let listener = self
-            .tcp_listeners
-            .lock()
-            .unwrap()
-            .get(&(
-                destination_endpoint.ip.to_string(),
-                destination_endpoint.port,
-            ))
-            .map(|listener| Arc::clone(listener))?;
-        listener
-            .get_stream(&source_endpoint)
-            .map(|stream| stream as Arc<dyn TcpStream>)
+        match self.get_tcp_listener(&destination_endpoint.ip, destination_endpoint.port) {
+            Some(tcp_listener) => {
+                let backlog_overflow = *self.backlog_overflow.lock().unwrap();
+                tcp_listener.on_packet_received(packet, backlog_overflow);
+            }
+            None => {
+                if let Some(Control::Syn) = packet.control {
+                    send_reset(&self.network, packet);
+                }
+            }
+        }
     }
 
     fn get_tcp_listener(&self, ip: &str, port: u16) -> Option<Arc<SimulatedTcpListener>> {
@@ -89,10 +144,58 @@ impl SimulatedNetworkInterface {
             .lock()
             .unwrap()
             .get(&(ip.to_string(), port))
-            .map(|tcp_listener| Arc::clone(tcp_listener))
+            .map(Arc::clone)
+    }
+
+    pub fn get_udp_sockets(&self, destination_endpoint: &Endpoint) -> Vec<Arc<dyn UdpSocket>> {
+        let is_broadcast = destination_endpoint.ip == BROADCAST_IP;
+        let is_multicast = is_multicast_ip(&destination_endpoint.ip);
+        if !is_broadcast && !is_multicast {
+            let udp_socket = self
+                .udp_sockets
+                .lock()
+                .unwrap()
+                .get(&(
+                    destination_endpoint.ip.to_string(),
+                    destination_endpoint.port,
+                ))
+                .and_then(Weak::upgrade);
+            return udp_socket
+                .map(|udp_socket| udp_socket as Arc<dyn UdpSocket>)
+                .into_iter()
+                .collect();
+        }
+
+        // Sockets that get passed over are only released once the lock is,
+        // as releasing the last handle unbinds the socket.
+        let bound: Vec<Arc<SimulatedUdpSocket>> = self
+            .udp_sockets
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|((_, port), _)| *port == destination_endpoint.port)
+            .filter_map(|(_, udp_socket)| udp_socket.upgrade())
+            .collect();
+        let mut receivers: Vec<Arc<SimulatedUdpSocket>> = Vec::new();
+        for udp_socket in bound {
+            if is_multicast && !udp_socket.is_multicast_member(&destination_endpoint.ip) {
+                continue;
+            }
+            if receivers
+                .iter()
+                .any(|receiver| Arc::ptr_eq(receiver, &udp_socket))
+            {
+                continue;
+            }
+            receivers.push(udp_socket);
+        }
+        receivers
+            .into_iter()
+            .map(|udp_socket| udp_socket as Arc<dyn UdpSocket>)
+            .collect()
     }
 
-    pub fn pick_local_ip(&self, remote_ip: &str) -> Option<String> {
+    pub fn pick_local_ip(&self, _remote_ip: &str) -> Option<String> {
         self.ip_addresses
             .lock()
             .unwrap()
@@ -101,18 +204,19 @@ impl SimulatedNetworkInterface {
             .map(|ip| ip.to_string())
     }
 
-    fn allocate_port(&self, ip: &str) -> Option<u16> {
-        let mut rng = rand::thread_rng();
+    /// Picks a free port on `ip`, drawing it from `stream` so that a
+    /// replay picks the same.
+    fn allocate_port(&self, ip: &str, stream: &str) -> Option<u16> {
         let mut allocated_ports = self.allocated_ports.lock().unwrap();
-        let ports = allocated_ports
-            .entry(ip.to_string())
-            .or_insert(HashSet::new());
+        let ports = allocated_ports.entry(ip.to_string()).or_default();
         if ports.len() == 65536 {
             return None;
         }
 
         loop {
-            let port = rng.gen_range(0..65535);
+            let port = self.network.draw(stream, || {
+                self.network.with_rng(|rng| rng.gen_range(0..65535))
+            }) as u16;
             if !ports.contains(&port) {
                 ports.insert(port);
                 return Some(port);
@@ -121,17 +225,30 @@ impl SimulatedNetworkInterface {
     }
 
     fn free_port(&self, ip: &str, port: u16) {
-        todo!()
+        let mut allocated_ports = self.allocated_ports.lock().unwrap();
+        if let Some(ports) = allocated_ports.get_mut(ip) {
+            ports.remove(&port);
+        }
+    }
+
+    fn bind_ips(&self, local_ip: &str) -> Vec<String> {
+        if !local_ip.is_empty() {
+            return vec![local_ip.to_string()];
+        }
+        self.get_ip_addresses()
     }
 }
 
 impl NetworkInterface for SimulatedNetworkInterface {
-    fn connect(&self, remote_ip: &str, remote_port: u16) -> Option<Arc<dyn TcpStream>> {
-        let local_ip = self.pick_local_ip(remote_ip)?;
-        let local_port = self.allocate_port(local_ip.as_str())?;
-        let remote_network_interface = self.network.get_network_interface(remote_ip)?;
-        let remote_tcp_listener =
-            remote_network_interface.get_tcp_listener(remote_ip, remote_port)?;
+    fn connect(&self, remote_ip: &str, remote_port: u16) -> io::Result<Arc<dyn TcpStream>> {
+        let local_ip = self.pick_local_ip(remote_ip).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::AddrNotAvailable, "no local address assigned")
+        })?;
+        // Connections to different peers may be opened in either order.
+        let stream = format!("ports of {} to {}:{}", local_ip, remote_ip, remote_port);
+        let local_port = self.allocate_port(&local_ip, &stream).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::AddrNotAvailable, "no local port available")
+        })?;
         let local_endpoint = Endpoint {
             ip: local_ip.clone(),
             port: local_port,
@@ -143,61 +260,139 @@ impl NetworkInterface for SimulatedNetworkInterface {
 
         let local_tcp_stream = Arc::new(SimulatedTcpStream::new(
             Arc::clone(&self.network),
-            local_endpoint.clone(),
-            remote_endpoint.clone(),
+            local_endpoint,
+            remote_endpoint,
         ));
-        let remote_tcp_stream =
-            SimulatedTcpStream::new(Arc::clone(&self.network), remote_endpoint, local_endpoint);
-        self.streams
-            .lock()
-            .unwrap()
-            .insert((local_ip, local_port), Arc::clone(&local_tcp_stream));
-        spawn(move || {
-            remote_tcp_listener.on_new_connection(remote_tcp_stream);
-        });
-        loop {
-            if let Some(control) = local_tcp_stream.receive_control() {
-                println!(
-                    "ClientStream received {:?} from {:?}",
-                    control,
-                    local_tcp_stream.get_remote_endpoint()
-                );
-                match control {
-                    Control::Sync => {
-                        break;
-                    }
-                }
-            }
+        self.streams.lock().unwrap().insert(
+            (local_ip.clone(), local_port),
+            Arc::clone(&local_tcp_stream),
+        );
+
+        let connect_timeout = *self.connect_timeout.lock().unwrap();
+        if let Err(error) = local_tcp_stream.open(connect_timeout) {
+            self.streams
+                .lock()
+                .unwrap()
+                .remove(&(local_ip.clone(), local_port));
+            self.free_port(&local_ip, local_port);
+            return Err(error);
         }
-        Some(local_tcp_stream)
+        Ok(local_tcp_stream)
     }
 
-    fn bind_tcp(&self, local_ip: &str, local_port: u16) -> Option<Arc<dyn TcpListener>> {
+    fn bind_tcp_with_backlog(
+        &self,
+        local_ip: &str,
+        local_port: u16,
+        backlog: usize,
+    ) -> Option<Arc<dyn TcpListener>> {
         let mut allocated_ports = self.allocated_ports.lock().unwrap();
         allocated_ports
             .entry(local_ip.to_string())
-            .or_insert(HashSet::new())
+            .or_default()
             .insert(local_port);
 
-        let tcp_listener = Arc::new(SimulatedTcpListener::new());
+        let tcp_listener = Arc::new(SimulatedTcpListener::new(
+            Arc::clone(&self.network),
+            backlog,
+        ));
         let mut tcp_listeners = self.tcp_listeners.lock().unwrap();
 
-        let mut ips = Vec::new();
-        if local_ip != "" {
-            ips.push(local_ip.to_string());
+        for ip in self.bind_ips(local_ip) {
+            tcp_listeners.insert((ip.to_string(), local_port), Arc::clone(&tcp_listener));
+        }
+
+        Some(tcp_listener)
+    }
+
+    fn bind_udp(&self, local_ip: &str, local_port: u16) -> Option<Arc<dyn UdpSocket>> {
+        let ips = self.bind_ips(local_ip);
+        let source_ip = ips.first()?.to_string();
+        let local_port = if local_port == 0 {
+            self.allocate_port(&source_ip, &format!("ports of {}", source_ip))?
         } else {
-            let ip_addresses = self.ip_addresses.lock().unwrap();
-            for ip in ip_addresses.iter() {
-                ips.push(ip.to_string());
-            }
+            local_port
+        };
+
+        let mut udp_sockets = self.udp_sockets.lock().unwrap();
+        if ips.iter().any(|ip| {
+            udp_sockets
+                .get(&(ip.to_string(), local_port))
+                .is_some_and(|udp_socket| udp_socket.strong_count() > 0)
+        }) {
+            return None;
         }
 
+        let udp_socket = Arc::new(SimulatedUdpSocket::new(
+            Arc::clone(&self.network),
+            Endpoint {
+                ip: source_ip,
+                port: local_port,
+            },
+            UdpBinding {
+                ips: ips.clone(),
+                udp_sockets: Arc::clone(&self.udp_sockets),
+                allocated_ports: Arc::clone(&self.allocated_ports),
+            },
+        ));
         for ip in ips {
-            tcp_listeners.insert((ip.to_string(), local_port), Arc::clone(&tcp_listener));
+            self.allocated_ports
+                .lock()
+                .unwrap()
+                .entry(ip.to_string())
+                .or_default()
+                .insert(local_port);
+            udp_sockets.insert((ip, local_port), Arc::downgrade(&udp_socket));
         }
 
-        Some(tcp_listener)
+        Some(udp_socket)
+    }
+
+    fn resolve(&self, hostname: &str) -> io::Result<Vec<String>> {
+        if hostname.parse::<std::net::IpAddr>().is_ok() {
+            return Ok(vec![hostname.to_string()]);
+        }
+
+        let hostname = hostname.to_lowercase();
+        let mut resolver_cache = self.resolver_cache.lock().unwrap();
+        if let Some(cached_host_record) = resolver_cache.get(&hostname) {
+            if Instant::now() < cached_host_record.expires_at {
+                return Ok(cached_host_record.ips.clone());
+            }
+        }
+
+        let (ips, ttl) = self.network.resolve(&hostname)?;
+        resolver_cache.insert(
+            hostname,
+            CachedHostRecord {
+                ips: ips.clone(),
+                expires_at: Instant::now() + ttl,
+            },
+        );
+        Ok(ips)
     }
+
+    /// Names the stream after this interface's addresses, since interfaces
+    /// draw for the same choice concurrently.
+    fn sample(&self, stream: &str) -> f64 {
+        let mut ips = self.get_ip_addresses();
+        ips.sort();
+        let stream = format!("{} from {}", stream, ips.join(","));
+        let bits = self.network.draw(&stream, || {
+            self.network.with_rng(|rng| rng.gen::<f64>()).to_bits()
+        });
+        f64::from_bits(bits)
+    }
+}
+
+fn send_reset(network: &SimulatedNetwork, packet: &Packet) {
+    network.send_packet(&Packet {
+        source: packet.destination.clone(),
+        destination: packet.source.clone(),
+        packet_type: PacketType::Control,
+        control: Some(Control::Reset),
+        payload: None,
+    });
 }
 
 #[derive(Debug)]
@@ -205,7 +400,9 @@ struct SimulatedTcpStream {
     network: Arc<SimulatedNetwork>,
     local_endpoint: Endpoint,
     remote_endpoint: Endpoint,
-    data_sender: Sender<Box<[u8]>>,
+    is_established: AtomicBool,
+    /// Taken when the peer resets the connection, which ends `receive`.
+    data_sender: Mutex<Option<Sender<Box<[u8]>>>>,
     data_receiver: Arc<Mutex<Receiver<Box<[u8]>>>>,
     control_sender: Sender<Control>,
     control_receiver: Arc<Mutex<Receiver<Control>>>,
@@ -223,15 +420,67 @@ impl SimulatedTcpStream {
             network,
             local_endpoint,
             remote_endpoint,
-            data_sender,
+            is_established: AtomicBool::new(false),
+            data_sender: Mutex::new(Some(data_sender)),
             data_receiver: Arc::new(Mutex::new(data_receiver)),
             control_sender,
             control_receiver: Arc::new(Mutex::new(control_receiver)),
         }
     }
 
-    fn receive_control(&self) -> Option<Control> {
-        self.control_receiver.lock().unwrap().recv().ok()
+    fn receive_control_timeout(&self, timeout: Duration) -> Result<Control, RecvTimeoutError> {
+        self.control_receiver.lock().unwrap().recv_timeout(timeout)
+    }
+
+    fn is_established(&self) -> bool {
+        self.is_established.load(Ordering::SeqCst)
+    }
+
+    /// Performs the client side of the three-way handshake, retransmitting the
+    /// SYN with exponential backoff until `connect_timeout` elapses.
+    fn open(&self, connect_timeout: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + connect_timeout;
+        let mut retransmission_timeout = INITIAL_RETRANSMISSION_TIMEOUT;
+        loop {
+            self.send_control(Control::Syn);
+            let retransmit_at = Instant::now() + retransmission_timeout;
+            loop {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "connection timed out",
+                    ));
+                }
+                if now >= retransmit_at {
+                    break;
+                }
+
+                match self.receive_control_timeout(retransmit_at.min(deadline) - now) {
+                    Ok(Control::SynAck) => {
+                        println!(
+                            "ClientStream received {:?} from {:?}",
+                            Control::SynAck,
+                            self.remote_endpoint
+                        );
+                        self.is_established.store(true, Ordering::SeqCst);
+                        self.send_control(Control::Ack);
+                        return Ok(());
+                    }
+                    Ok(Control::Reset) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::ConnectionRefused,
+                            "connection refused",
+                        ));
+                    }
+                    Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => {
+                        return Err(io::ErrorKind::ConnectionAborted.into());
+                    }
+                }
+            }
+            retransmission_timeout *= 2;
+        }
     }
 }
 
@@ -250,6 +499,17 @@ impl TcpStream for SimulatedTcpStream {
         self.data_receiver.lock().unwrap().recv().ok()
     }
 
+    fn receive_timeout(&self, timeout: Duration) -> io::Result<Box<[u8]>> {
+        self.data_receiver
+            .lock()
+            .unwrap()
+            .recv_timeout(timeout)
+            .map_err(|error| match error {
+                RecvTimeoutError::Timeout => io::ErrorKind::TimedOut.into(),
+                RecvTimeoutError::Disconnected => io::ErrorKind::ConnectionReset.into(),
+            })
+    }
+
     fn send_control(&self, control: Control) {
         self.network.send_packet(&Packet {
             source: self.local_endpoint.clone(),
@@ -272,35 +532,153 @@ impl TcpStream for SimulatedTcpStream {
         match &(packet.packet_type) {
             PacketType::Data => {
                 let data = packet.payload.clone().unwrap();
-                self.data_sender.send(data).unwrap();
+                if let Some(data_sender) = self.data_sender.lock().unwrap().as_ref() {
+                    let _ = data_sender.send(data);
+                }
             }
             PacketType::Control => {
                 let control = packet.control.clone().unwrap();
+                match control {
+                    Control::SynAck if self.is_established() => {
+                        // Our ACK was lost and the server retransmitted.
+                        self.send_control(Control::Ack);
+                        return;
+                    }
+                    Control::Reset if self.is_established() => {
+                        self.data_sender.lock().unwrap().take();
+                        return;
+                    }
+                    _ => {}
+                }
                 self.control_sender.send(control).unwrap();
             }
+            PacketType::Datagram => {}
         }
     }
 }
 
+#[derive(Debug, Default)]
+struct AcceptQueue {
+    half_open: HashMap<(String, u16), Arc<SimulatedTcpStream>>,
+    established: VecDeque<Arc<SimulatedTcpStream>>,
+    is_shutdown: bool,
+}
+
 #[derive(Debug)]
 struct SimulatedTcpListener {
-    connections_sender: Sender<SimulatedTcpStream>,
-    connections_receiver: Mutex<Receiver<SimulatedTcpStream>>,
+    network: Arc<SimulatedNetwork>,
+    backlog: usize,
+    accept_queue: Mutex<AcceptQueue>,
+    accept_queue_changed: Condvar,
     connections: Mutex<HashMap<(String, u16), Arc<SimulatedTcpStream>>>,
 }
 
 impl SimulatedTcpListener {
-    fn new() -> Self {
-        let (connections_sender, connections_receiver) = mpsc::channel();
+    fn new(network: Arc<SimulatedNetwork>, backlog: usize) -> Self {
         SimulatedTcpListener {
-            connections_sender,
-            connections_receiver: Mutex::new(connections_receiver),
+            network,
+            backlog: backlog.max(1),
+            accept_queue: Mutex::new(AcceptQueue::default()),
+            accept_queue_changed: Condvar::new(),
             connections: Mutex::new(HashMap::new()),
         }
     }
 
-    pub fn on_new_connection(&self, tcp_stream: SimulatedTcpStream) {
-        self.connections_sender.send(tcp_stream).unwrap();
+    fn on_packet_received(self: &Arc<Self>, packet: &Packet, backlog_overflow: BacklogOverflow) {
+        let remote_key = (packet.source.ip.clone(), packet.source.port);
+        match (&packet.packet_type, &packet.control) {
+            (PacketType::Control, Some(Control::Syn)) => {
+                self.on_syn_received(packet, backlog_overflow)
+            }
+            (PacketType::Control, Some(Control::Ack)) => self.on_ack_received(&remote_key),
+            (PacketType::Data, _) => {
+                // Data carries an acknowledgement, so it completes a handshake
+                // whose final ACK was lost.
+                self.on_ack_received(&remote_key);
+                if let Some(tcp_stream) = self.get_stream(&packet.source) {
+                    tcp_stream.on_packet_received(packet);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn on_syn_received(self: &Arc<Self>, packet: &Packet, backlog_overflow: BacklogOverflow) {
+        if let Some(tcp_stream) = self.get_stream(&packet.source) {
+            if !tcp_stream.is_established() {
+                // The client retransmitted its SYN because our SYN-ACK was lost.
+                tcp_stream.send_control(Control::SynAck);
+            }
+            return;
+        }
+
+        let mut accept_queue = self.accept_queue.lock().unwrap();
+        if accept_queue.is_shutdown {
+            drop(accept_queue);
+            send_reset(&self.network, packet);
+            return;
+        }
+        if accept_queue.half_open.len() + accept_queue.established.len() >= self.backlog {
+            drop(accept_queue);
+            match backlog_overflow {
+                BacklogOverflow::DropSyn => println!("Accept queue full, dropping SYN"),
+                BacklogOverflow::Refuse => send_reset(&self.network, packet),
+            }
+            return;
+        }
+
+        let remote_key = (packet.source.ip.clone(), packet.source.port);
+        let tcp_stream = Arc::new(SimulatedTcpStream::new(
+            Arc::clone(&self.network),
+            packet.destination.clone(),
+            packet.source.clone(),
+        ));
+        accept_queue
+            .half_open
+            .insert(remote_key.clone(), Arc::clone(&tcp_stream));
+        drop(accept_queue);
+        self.connections
+            .lock()
+            .unwrap()
+            .insert(remote_key.clone(), Arc::clone(&tcp_stream));
+
+        tcp_stream.send_control(Control::SynAck);
+        println!(
+            "TcpListener sent {:?} to {:?}",
+            Control::SynAck,
+            tcp_stream.get_remote_endpoint()
+        );
+
+        let tcp_listener = Arc::clone(self);
+        spawn(move || {
+            let mut retransmission_timeout = INITIAL_RETRANSMISSION_TIMEOUT;
+            for _ in 0..SYN_ACK_RETRIES {
+                sleep(retransmission_timeout);
+                if tcp_stream.is_established() {
+                    return;
+                }
+                tcp_stream.send_control(Control::SynAck);
+                retransmission_timeout *= 2;
+            }
+            sleep(retransmission_timeout);
+            tcp_listener.abandon_half_open(&remote_key);
+        });
+    }
+
+    fn on_ack_received(&self, remote_key: &(String, u16)) {
+        let mut accept_queue = self.accept_queue.lock().unwrap();
+        if let Some(tcp_stream) = accept_queue.half_open.remove(remote_key) {
+            tcp_stream.is_established.store(true, Ordering::SeqCst);
+            accept_queue.established.push_back(tcp_stream);
+            self.accept_queue_changed.notify_one();
+        }
+    }
+
+    fn abandon_half_open(&self, remote_key: &(String, u16)) {
+        let mut accept_queue = self.accept_queue.lock().unwrap();
+        if accept_queue.half_open.remove(remote_key).is_some() {
+            self.connections.lock().unwrap().remove(remote_key);
+        }
     }
 
     fn get_stream(&self, remote_endpoint: &Endpoint) -> Option<Arc<SimulatedTcpStream>> {
@@ -308,25 +686,205 @@ impl SimulatedTcpListener {
             .lock()
             .unwrap()
             .get(&(remote_endpoint.ip.clone(), remote_endpoint.port))
-            .map(|tcp_stream| Arc::clone(tcp_stream))
+            .map(Arc::clone)
+    }
+
+    fn shutdown_error() -> io::Error {
+        io::Error::new(io::ErrorKind::ConnectionAborted, "listener is shut down")
     }
 }
 
 impl TcpListener for SimulatedTcpListener {
-    fn accept(&self) -> Option<Arc<dyn TcpStream>> {
-        let tcp_stream = self.connections_receiver.lock().unwrap().recv().ok()?;
-        let tcp_stream = Arc::new(tcp_stream);
-        let remote_endpoint = &tcp_stream.remote_endpoint;
-        self.connections.lock().unwrap().insert(
-            (remote_endpoint.ip.clone(), remote_endpoint.port),
-            Arc::clone(&tcp_stream),
-        );
-        tcp_stream.send_control(Control::Sync);
-        println!(
-            "TcpListener sent {:?} to {:?}",
-            Control::Sync,
-            tcp_stream.get_remote_endpoint()
-        );
-        Some(tcp_stream)
+    fn accept_cancellable(&self, is_cancelled: &AtomicBool) -> io::Result<Arc<dyn TcpStream>> {
+        let mut accept_queue = self.accept_queue.lock().unwrap();
+        loop {
+            if accept_queue.is_shutdown {
+                return Err(Self::shutdown_error());
+            }
+            if is_cancelled.load(Ordering::SeqCst) {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            if let Some(tcp_stream) = accept_queue.established.pop_front() {
+                return Ok(tcp_stream);
+            }
+            accept_queue = self.accept_queue_changed.wait(accept_queue).unwrap();
+        }
+    }
+
+    fn wake_acceptors(&self) {
+        let _accept_queue = self.accept_queue.lock().unwrap();
+        self.accept_queue_changed.notify_all();
+    }
+
+    fn try_accept(&self) -> io::Result<Arc<dyn TcpStream>> {
+        let mut accept_queue = self.accept_queue.lock().unwrap();
+        if accept_queue.is_shutdown {
+            return Err(Self::shutdown_error());
+        }
+        match accept_queue.established.pop_front() {
+            Some(tcp_stream) => Ok(tcp_stream),
+            None => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    /// Connections still waiting to be accepted are reset, so their clients
+    /// see the connection fail instead of waiting on it forever.
+    fn shutdown(&self) {
+        let mut accept_queue = self.accept_queue.lock().unwrap();
+        accept_queue.is_shutdown = true;
+        let mut dropped: Vec<_> = accept_queue.half_open.drain().map(|(_, s)| s).collect();
+        dropped.extend(accept_queue.established.drain(..));
+        self.accept_queue_changed.notify_all();
+        drop(accept_queue);
+
+        let mut connections = self.connections.lock().unwrap();
+        for tcp_stream in dropped {
+            let remote_endpoint = tcp_stream.get_remote_endpoint();
+            connections.remove(&(remote_endpoint.ip, remote_endpoint.port));
+            tcp_stream.send_control(Control::Reset);
+        }
+    }
+
+    fn is_shutdown(&self) -> bool {
+        self.accept_queue.lock().unwrap().is_shutdown
+    }
+
+    fn incoming(self: Arc<Self>) -> Incoming {
+        Incoming::new(self)
+    }
+}
+
+/// The addresses a UDP socket is bound to, and the tables of the network
+/// interface to remove them from once the socket is dropped.
+#[derive(Debug)]
+struct UdpBinding {
+    ips: Vec<String>,
+    udp_sockets: UdpSocketMap,
+    allocated_ports: PortMap,
+}
+
+#[derive(Debug)]
+struct SimulatedUdpSocket {
+    network: Arc<SimulatedNetwork>,
+    local_endpoint: Endpoint,
+    binding: UdpBinding,
+    broadcast: AtomicBool,
+    multicast_groups: Mutex<HashSet<String>>,
+    datagram_sender: Sender<(Box<[u8]>, Endpoint)>,
+    datagram_receiver: Mutex<Receiver<(Box<[u8]>, Endpoint)>>,
+}
+
+impl SimulatedUdpSocket {
+    fn new(network: Arc<SimulatedNetwork>, local_endpoint: Endpoint, binding: UdpBinding) -> Self {
+        let (datagram_sender, datagram_receiver) = mpsc::channel();
+        SimulatedUdpSocket {
+            network,
+            local_endpoint,
+            binding,
+            broadcast: AtomicBool::new(false),
+            multicast_groups: Mutex::new(HashSet::new()),
+            datagram_sender,
+            datagram_receiver: Mutex::new(datagram_receiver),
+        }
+    }
+
+    fn is_multicast_member(&self, group_ip: &str) -> bool {
+        self.multicast_groups.lock().unwrap().contains(group_ip)
+    }
+}
+
+impl UdpSocket for SimulatedUdpSocket {
+    fn send_to(&self, data: &[u8], remote_ip: &str, remote_port: u16) -> io::Result<()> {
+        if data.len() > MAX_DATAGRAM_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "datagram too large",
+            ));
+        }
+        if remote_ip == BROADCAST_IP && !self.broadcast.load(Ordering::SeqCst) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "broadcast is not enabled",
+            ));
+        }
+
+        self.network.send_packet(&Packet {
+            source: self.local_endpoint.clone(),
+            destination: Endpoint {
+                ip: remote_ip.to_string(),
+                port: remote_port,
+            },
+            packet_type: PacketType::Datagram,
+            control: None,
+            payload: Some(Box::from(data)),
+        });
+        Ok(())
+    }
+
+    fn recv_from(&self) -> Option<(Box<[u8]>, Endpoint)> {
+        self.datagram_receiver.lock().unwrap().recv().ok()
+    }
+
+    fn set_broadcast(&self, broadcast: bool) -> io::Result<()> {
+        self.broadcast.store(broadcast, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn join_multicast(&self, group_ip: &str) -> io::Result<()> {
+        if !is_multicast_ip(group_ip) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not a multicast address",
+            ));
+        }
+        self.multicast_groups
+            .lock()
+            .unwrap()
+            .insert(group_ip.to_string());
+        Ok(())
+    }
+
+    fn leave_multicast(&self, group_ip: &str) -> io::Result<()> {
+        if !self.multicast_groups.lock().unwrap().remove(group_ip) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                "not a member of the multicast group",
+            ));
+        }
+        Ok(())
+    }
+
+    fn get_local_endpoint(&self) -> Endpoint {
+        self.local_endpoint.clone()
+    }
+
+    fn on_packet_received(&self, packet: &Packet) {
+        let data = packet.payload.clone().unwrap();
+        self.datagram_sender
+            .send((data, packet.source.clone()))
+            .unwrap();
+    }
+}
+
+impl Drop for SimulatedUdpSocket {
+    fn drop(&mut self) {
+        let port = self.local_endpoint.port;
+        let mut udp_sockets = self.binding.udp_sockets.lock().unwrap();
+        let mut allocated_ports = self.binding.allocated_ports.lock().unwrap();
+        for ip in &self.binding.ips {
+            // The address may have been bound again since the last handle to
+            // this socket went away.
+            let key = (ip.to_string(), port);
+            if udp_sockets
+                .get(&key)
+                .is_some_and(|udp_socket| udp_socket.strong_count() > 0)
+            {
+                continue;
+            }
+            udp_sockets.remove(&key);
+            if let Some(ports) = allocated_ports.get_mut(ip) {
+                ports.remove(&port);
+            }
+        }
     }
 }
@@ -0,0 +1,781 @@
+use crate::platform::network::Endpoint;
+use crate::platform_testing::disk::{SimulatedDisk, SimulatedDiskConfig};
+use crate::platform_testing::network::{SimulatedNetwork, SimulatedNetworkConfig};
+use crate::platform_testing::network_interface::SimulatedNetworkInterface;
+use crate::platform_testing::replay::Journal;
+use crate::raft::node::{RaftConfig, RaftNode, RaftStatus, Role};
+use crate::raft::proto::LogEntry;
+use crate::raft::state_machine::StateMachine;
+use crate::raft::storage::{MemoryStorage, RaftStorage};
+use crate::raft::tcp_transport::TcpTransport;
+use crate::raft::wal::WalStorage;
+use rand::Rng;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+
+pub const RAFT_PORT: u16 = 7000;
+
+/// How often the cluster's invariants are checked between steps.
+const CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The index and command of everything a state machine has applied.
+pub type AppliedCommands = Arc<Mutex<Vec<(u64, Vec<u8>)>>>;
+
+pub fn applied_commands(applied: &AppliedCommands) -> Vec<Vec<u8>> {
+    let applied = applied.lock().unwrap();
+    applied.iter().map(|(_, command)| command.clone()).collect()
+}
+
+/// Records every command it applies and answers each with the number of
+/// commands applied so far.
+pub struct RecordingStateMachine {
+    pub applied: AppliedCommands,
+}
+
+impl StateMachine for RecordingStateMachine {
+    fn apply(&mut self, index: u64, command: &[u8]) -> Vec<u8> {
+        let mut applied = self.applied.lock().unwrap();
+        applied.push((index, command.to_vec()));
+        (applied.len() as u64).to_be_bytes().to_vec()
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        let mut snapshot = Vec::new();
+        for (index, command) in self.applied.lock().unwrap().iter() {
+            snapshot.extend_from_slice(&index.to_be_bytes());
+            snapshot.extend_from_slice(&(command.len() as u32).to_be_bytes());
+            snapshot.extend_from_slice(command);
+        }
+        snapshot
+    }
+
+    fn restore(&mut self, mut snapshot: &[u8]) {
+        let mut applied = self.applied.lock().unwrap();
+        applied.clear();
+        while snapshot.len() >= 12 {
+            let index = u64::from_be_bytes(snapshot[..8].try_into().unwrap());
+            let length = u32::from_be_bytes(snapshot[8..12].try_into().unwrap()) as usize;
+            applied.push((index, snapshot[12..12 + length].to_vec()));
+            snapshot = &snapshot[12 + length..];
+        }
+    }
+}
+
+pub fn reliable_network() -> SimulatedNetworkConfig {
+    SimulatedNetworkConfig {
+        drop_rate: 0.0,
+        short_delay_rate: 0.0,
+        long_delay_rate: 0.0,
+        short_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+        long_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+        duplicate_rate: 0.0,
+    }
+}
+
+/// Seeds each disk from `network`, so that the network's seed reproduces
+/// the faults of both.
+fn simulated_disks(
+    network: &SimulatedNetwork,
+    size: usize,
+    config: SimulatedDiskConfig,
+) -> Vec<SimulatedDisk> {
+    (0..size)
+        .map(|_| SimulatedDisk::new(config.clone(), network.with_rng(|rng| rng.gen())))
+        .collect()
+}
+
+fn new_network_interface(
+    network: &Arc<SimulatedNetwork>,
+    ip: &str,
+) -> Arc<SimulatedNetworkInterface> {
+    let network_interface = Arc::new(SimulatedNetworkInterface::new(Arc::clone(network)));
+    network_interface.assign_ip_addresses(vec![ip]);
+    network_interface.set_connect_timeout(Duration::from_millis(500));
+    network.register_network_interface(Arc::clone(&network_interface));
+    network_interface
+}
+
+/// An entry seen committed, with the highest term any node had when it was
+/// first seen. The entry was committed in that term or an earlier one.
+#[derive(Debug)]
+struct Committed {
+    entry: LogEntry,
+    seen_in_term: u64,
+}
+
+/// Checks Raft's safety properties against what a cluster's nodes hold,
+/// remembering enough of what it saw before to catch breaches that only show
+/// over time:
+///
+/// - Election safety: no term has two leaders.
+/// - Log matching: logs holding entries of the same index and term agree up
+///   to that index.
+/// - Leader completeness: a leader holds every entry committed in an earlier
+///   term, and no two nodes commit different entries at the same index.
+#[derive(Debug, Default)]
+pub struct InvariantChecker {
+    leaders: HashMap<u64, String>,
+    committed: BTreeMap<u64, Committed>,
+}
+
+impl InvariantChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks the nodes' statuses, given with their IDs, and describes the
+    /// first breach found.
+    pub fn observe(&mut self, statuses: &[(String, RaftStatus)]) -> Result<(), String> {
+        let max_term = statuses
+            .iter()
+            .map(|(_, status)| status.term)
+            .max()
+            .unwrap_or(0);
+
+        for (id, status) in statuses.iter() {
+            if status.role != Role::Leader {
+                continue;
+            }
+            match self.leaders.entry(status.term) {
+                Entry::Occupied(leader) if leader.get() != id => {
+                    return Err(format!(
+                        "both {} and {} led term {}",
+                        leader.get(),
+                        id,
+                        status.term
+                    ));
+                }
+                Entry::Occupied(_) => {}
+                Entry::Vacant(leader) => {
+                    leader.insert(id.clone());
+                }
+            }
+        }
+
+        for (i, (a_id, a)) in statuses.iter().enumerate() {
+            for (b_id, b) in statuses[i + 1..].iter() {
+                check_log_matching(a_id, a, b_id, b)?;
+            }
+        }
+
+        for (id, status) in statuses.iter() {
+            for index in status.snapshot_index + 1..=status.commit_index {
+                let Some(entry) = status.entry(index) else {
+                    break;
+                };
+                match self.committed.get(&index) {
+                    Some(committed) if committed.entry != *entry => {
+                        return Err(format!(
+                            "{} committed {:?} at index {}, where {:?} was committed before",
+                            id, entry, index, committed.entry
+                        ));
+                    }
+                    Some(_) => {}
+                    None => {
+                        self.committed.insert(
+                            index,
+                            Committed {
+                                entry: entry.clone(),
+                                seen_in_term: max_term,
+                            },
+                        );
+                    }
+                }
+            }
+        }
+
+        for (id, status) in statuses.iter() {
+            if status.role != Role::Leader {
+                continue;
+            }
+            // Entries the leader has compacted were committed when it did so.
+            for (index, committed) in self.committed.range(status.snapshot_index + 1..) {
+                if committed.seen_in_term >= status.term {
+                    continue;
+                }
+                if status.entry(*index) != Some(&committed.entry) {
+                    return Err(format!(
+                        "{} leads term {} without {:?}, committed at index {} by term {}",
+                        id, status.term, committed.entry, index, committed.seen_in_term
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_log_matching(
+    a_id: &str,
+    a: &RaftStatus,
+    b_id: &str,
+    b: &RaftStatus,
+) -> Result<(), String> {
+    let first = a.snapshot_index.max(b.snapshot_index) + 1;
+    let last =
+        (a.snapshot_index + a.entries.len() as u64).min(b.snapshot_index + b.entries.len() as u64);
+    let mut is_matched = false;
+    for index in (first..=last).rev() {
+        let (a_entry, b_entry) = (a.entry(index).unwrap(), b.entry(index).unwrap());
+        is_matched |= a_entry.term == b_entry.term;
+        if is_matched && a_entry != b_entry {
+            return Err(format!(
+                "{} holds {:?} and {} holds {:?} at index {}, though their logs match after it",
+                a_id, a_entry, b_id, b_entry, index
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Runs the nodes' invariant checks on a thread of its own, so that a breach
+/// that comes and goes between two steps of a test is caught too.
+struct Watcher {
+    nodes: Mutex<Vec<Arc<RaftNode>>>,
+    checker: Mutex<InvariantChecker>,
+    /// The first breach found.
+    violation: Mutex<Option<String>>,
+    is_stopped: AtomicBool,
+}
+
+impl Watcher {
+    fn check(&self) {
+        let statuses: Vec<(String, RaftStatus)> = self
+            .nodes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|node| (node.id().to_string(), node.status()))
+            .collect();
+        let result = self.checker.lock().unwrap().observe(&statuses);
+        if let Err(violation) = result {
+            self.violation.lock().unwrap().get_or_insert(violation);
+        }
+    }
+
+    fn run(&self) {
+        while !self.is_stopped.load(Ordering::SeqCst) {
+            if self.violation.lock().unwrap().is_none() {
+                self.check();
+            }
+            sleep(CHECK_INTERVAL);
+        }
+    }
+}
+
+/// A cluster of Raft nodes on a `SimulatedNetwork`, each at 192.168.1.n,
+/// that tests drive by starting and stopping nodes and cutting links between
+/// them. The cluster's invariants are checked continuously in the
+/// background and again after every step, which panics with the first
+/// breach found.
+pub struct RaftCluster {
+    pub network: Arc<SimulatedNetwork>,
+    pub network_interfaces: Vec<Arc<SimulatedNetworkInterface>>,
+    pub configs: Vec<RaftConfig>,
+    pub storages: Vec<MemoryStorage>,
+    /// When set, nodes keep their state in a write-ahead log on these disks
+    /// instead of `storages`.
+    pub disks: Vec<SimulatedDisk>,
+    pub nodes: Vec<Arc<RaftNode>>,
+    pub applied: Vec<AppliedCommands>,
+    pub ips: Vec<String>,
+    /// Whether each node is linked to the others that are connected.
+    pub connected: Vec<bool>,
+    /// Whether each node is running, rather than stopped or crashed.
+    pub running: Vec<bool>,
+    /// When set, records the run's decisions, or replays those of an
+    /// earlier run.
+    pub journal: Option<Arc<Journal>>,
+    watcher: Arc<Watcher>,
+}
+
+impl RaftCluster {
+    pub fn new(size: usize) -> Self {
+        Self::build(
+            size,
+            SimulatedNetwork::new(reliable_network()),
+            None,
+            |_| {},
+        )
+    }
+
+    /// Builds a cluster on a network seeded with `seed`.
+    pub fn new_with_seed(size: usize, seed: u64) -> Self {
+        let network = SimulatedNetwork::with_seed(reliable_network(), seed);
+        Self::build(size, network, None, |_| {})
+    }
+
+    /// Builds a cluster whose node configs are adjusted by `configure`.
+    pub fn new_with_config(size: usize, configure: fn(&mut RaftConfig)) -> Self {
+        Self::build(
+            size,
+            SimulatedNetwork::new(reliable_network()),
+            None,
+            configure,
+        )
+    }
+
+    /// Builds a cluster whose nodes each write to a simulated disk, with the
+    /// network and disks all seeded from `seed`.
+    pub fn new_with_disks(size: usize, config: SimulatedDiskConfig, seed: u64) -> Self {
+        let network = SimulatedNetwork::with_seed(reliable_network(), seed);
+        Self::build(size, network, Some(config), |_| {})
+    }
+
+    /// Builds a cluster whose network and nodes make their decisions through
+    /// `journal`.
+    pub fn new_with_journal(size: usize, journal: Arc<Journal>) -> Self {
+        let network = SimulatedNetwork::new(reliable_network());
+        Self::assemble(size, network, None, |_| {}, Some(journal))
+    }
+
+    /// Builds a cluster on `network`. With `disk_config`, nodes keep their
+    /// state on simulated disks seeded from the network.
+    pub fn build(
+        size: usize,
+        network: SimulatedNetwork,
+        disk_config: Option<SimulatedDiskConfig>,
+        configure: fn(&mut RaftConfig),
+    ) -> Self {
+        Self::assemble(size, network, disk_config, configure, None)
+    }
+
+    fn assemble(
+        size: usize,
+        network: SimulatedNetwork,
+        disk_config: Option<SimulatedDiskConfig>,
+        configure: fn(&mut RaftConfig),
+        journal: Option<Arc<Journal>>,
+    ) -> Self {
+        let network = Arc::new(network);
+        let disks =
+            disk_config.map_or_else(Vec::new, |config| simulated_disks(&network, size, config));
+        if let Some(journal) = journal.as_ref() {
+            network.set_journal(Arc::clone(journal));
+        }
+        let ips: Vec<String> = (1..=size).map(|i| format!("192.168.1.{}", i)).collect();
+        let endpoints: HashMap<String, Endpoint> = ips
+            .iter()
+            .map(|ip| {
+                (
+                    ip.to_string(),
+                    Endpoint {
+                        ip: ip.to_string(),
+                        port: RAFT_PORT,
+                    },
+                )
+            })
+            .collect();
+
+        let mut network_interfaces = Vec::new();
+        let mut configs = Vec::new();
+        for ip in ips.iter() {
+            network_interfaces.push(new_network_interface(&network, ip));
+
+            let mut peers = endpoints.clone();
+            let endpoint = peers.remove(ip).unwrap();
+            let mut config = RaftConfig::new(ip, endpoint, peers);
+            configure(&mut config);
+            configs.push(config);
+        }
+
+        let watcher = Arc::new(Watcher {
+            nodes: Mutex::new(Vec::new()),
+            checker: Mutex::new(InvariantChecker::new()),
+            violation: Mutex::new(None),
+            is_stopped: AtomicBool::new(false),
+        });
+        let background = Arc::clone(&watcher);
+        spawn(move || background.run());
+
+        let mut cluster = RaftCluster {
+            network,
+            network_interfaces,
+            configs,
+            storages: (0..size).map(|_| MemoryStorage::new()).collect(),
+            disks,
+            nodes: Vec::new(),
+            applied: Vec::new(),
+            ips,
+            connected: vec![false; size],
+            running: vec![false; size],
+            journal,
+            watcher,
+        };
+        for i in 0..size {
+            let (node, applied) = cluster.start_node(i);
+            cluster.nodes.push(node);
+            cluster.applied.push(applied);
+            cluster.running[i] = true;
+        }
+        cluster.watch();
+        for i in 0..size {
+            cluster.connect(i);
+        }
+        cluster
+    }
+
+    /// Starts a node outside the cluster, for a leader to add with
+    /// `add_node`, and returns its index.
+    pub fn add_server(&mut self) -> usize {
+        let i = self.nodes.len();
+        let ip = format!("192.168.1.{}", i + 1);
+        self.network_interfaces
+            .push(new_network_interface(&self.network, &ip));
+        self.configs.push(RaftConfig {
+            id: ip.clone(),
+            endpoint: Endpoint {
+                ip: ip.clone(),
+                port: RAFT_PORT,
+            },
+            peers: HashMap::new(),
+            joining: true,
+            ..self.configs[0].clone()
+        });
+        self.storages.push(MemoryStorage::new());
+        self.ips.push(ip);
+        self.connected.push(false);
+
+        let (node, applied) = self.start_node(i);
+        self.nodes.push(node);
+        self.applied.push(applied);
+        self.running.push(true);
+        self.watch();
+        self.connect(i);
+        i
+    }
+
+    pub fn endpoint(&self, i: usize) -> Endpoint {
+        Endpoint {
+            ip: self.ips[i].clone(),
+            port: RAFT_PORT,
+        }
+    }
+
+    /// Starts node `i` from whatever its storage holds, with an empty
+    /// state machine.
+    fn start_node(&self, i: usize) -> (Arc<RaftNode>, AppliedCommands) {
+        let applied = Arc::new(Mutex::new(Vec::new()));
+        let storage: Box<dyn RaftStorage> = match self.disks.get(i) {
+            Some(disk) => Box::new(WalStorage::open(Arc::new(disk.clone()), "/raft").unwrap()),
+            None => Box::new(self.storages[i].clone()),
+        };
+        let node = RaftNode::new(
+            self.configs[i].clone(),
+            Arc::new(TcpTransport::new(
+                Arc::clone(&self.network_interfaces[i]) as _,
+                self.configs[i].endpoint.clone(),
+            )),
+            Box::new(RecordingStateMachine {
+                applied: Arc::clone(&applied),
+            }),
+            storage,
+        );
+        if let Some(journal) = self.journal.as_ref() {
+            node.set_scheduler(journal.scheduler(&self.configs[i].id));
+        }
+        node.start().unwrap();
+        (node, applied)
+    }
+
+    /// Points the invariant checks at the current nodes.
+    fn watch(&self) {
+        *self.watcher.nodes.lock().unwrap() = self.nodes.clone();
+    }
+
+    /// Panics with the first breach of the cluster's invariants found so far,
+    /// checking them once more first.
+    pub fn check_invariants(&self) {
+        self.watcher.check();
+        if let Some(violation) = self.watcher.violation.lock().unwrap().as_ref() {
+            panic!("Raft invariant violated: {}", violation);
+        }
+    }
+
+    /// Stops node `i`, which keeps what it has stored until it is restarted.
+    pub fn stop_node(&mut self, i: usize) {
+        self.check_invariants();
+        self.nodes[i].stop();
+        self.running[i] = false;
+    }
+
+    /// Stops node `i` as if it lost power, losing whatever its disk had not
+    /// synced. Without disks this is the same as `stop_node`.
+    pub fn crash(&mut self, i: usize) {
+        self.stop_node(i);
+        if let Some(disk) = self.disks.get(i) {
+            disk.crash();
+        }
+    }
+
+    /// Starts a new node `i` from its storage, with an empty state machine,
+    /// stopping the old one first if it is still running.
+    pub fn restart(&mut self, i: usize) {
+        if self.running[i] {
+            self.stop_node(i);
+        }
+        let (node, applied) = self.start_node(i);
+        self.nodes[i] = node;
+        self.applied[i] = applied;
+        self.running[i] = true;
+        self.watch();
+        self.check_invariants();
+    }
+
+    /// Cuts node `i`'s power and starts it again.
+    pub fn power_loss(&mut self, i: usize) {
+        self.crash(i);
+        self.restart(i);
+    }
+
+    pub fn connect(&mut self, i: usize) {
+        for j in 0..self.nodes.len() {
+            if j != i && self.connected[j] {
+                self.network.connect(&self.ips[i], &self.ips[j]);
+            }
+        }
+        self.connected[i] = true;
+        self.check_invariants();
+    }
+
+    pub fn disconnect(&mut self, i: usize) {
+        for j in 0..self.nodes.len() {
+            if j != i {
+                self.network.disconnect(&self.ips[i], &self.ips[j]);
+            }
+        }
+        self.connected[i] = false;
+        self.check_invariants();
+    }
+
+    /// Splits the cluster into `groups`, whose nodes reach each other but
+    /// none outside their group. Nodes in no group are cut off from all.
+    pub fn partition(&mut self, groups: &[&[usize]]) {
+        let group_of = |i: usize| groups.iter().position(|group| group.contains(&i));
+        for i in 0..self.nodes.len() {
+            for j in i + 1..self.nodes.len() {
+                if group_of(i).is_some() && group_of(i) == group_of(j) {
+                    self.network.connect(&self.ips[i], &self.ips[j]);
+                } else {
+                    self.network.disconnect(&self.ips[i], &self.ips[j]);
+                }
+            }
+            self.connected[i] = group_of(i).is_some();
+        }
+        self.check_invariants();
+    }
+
+    /// Reconnects every node to every other.
+    pub fn heal(&mut self) {
+        let everyone: Vec<usize> = (0..self.nodes.len()).collect();
+        self.partition(&[&everyone]);
+    }
+
+    /// Waits for exactly one connected, running node to believe it leads the
+    /// latest term and returns its index.
+    pub async fn check_one_leader(&self) -> usize {
+        for _ in 0..10 {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            self.check_invariants();
+
+            let mut leaders: HashMap<u64, Vec<usize>> = HashMap::new();
+            for (i, node) in self.nodes.iter().enumerate() {
+                if !self.connected[i] || !self.running[i] {
+                    continue;
+                }
+                let (term, _) = node.get_state();
+                if node.is_leader() {
+                    leaders.entry(term).or_default().push(i);
+                }
+            }
+
+            for (term, term_leaders) in leaders.iter() {
+                assert_eq!(
+                    term_leaders.len(),
+                    1,
+                    "term {} has {} leaders",
+                    term,
+                    term_leaders.len()
+                );
+            }
+            if let Some(last_term) = leaders.keys().max() {
+                return leaders[last_term][0];
+            }
+        }
+        panic!("expected one leader, got none");
+    }
+
+    /// Checks that the connected, running nodes agree on the term and
+    /// returns it.
+    pub fn check_terms(&self) -> u64 {
+        self.check_invariants();
+        let mut cluster_term = None;
+        for (i, node) in self.nodes.iter().enumerate() {
+            if !self.connected[i] || !self.running[i] {
+                continue;
+            }
+            let (term, _) = node.get_state();
+            match cluster_term {
+                None => cluster_term = Some(term),
+                Some(cluster_term) => {
+                    assert_eq!(cluster_term, term, "servers disagree on term")
+                }
+            }
+        }
+        cluster_term.unwrap()
+    }
+
+    pub fn check_no_leader(&self) {
+        self.check_invariants();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if self.connected[i] {
+                assert!(
+                    !node.is_leader(),
+                    "expected no leader among connected servers, but {} claims to be leader",
+                    node.id()
+                );
+            }
+        }
+    }
+
+    /// Returns how many nodes consider `index` committed, checking that
+    /// they all agree on its payload. Entries a node has compacted away
+    /// are looked up in what its state machine applied.
+    pub fn n_committed(&self, index: u64) -> (usize, Option<Vec<u8>>) {
+        let mut count = 0;
+        let mut payload: Option<Vec<u8>> = None;
+        for (node, applied) in self.nodes.iter().zip(self.applied.iter()) {
+            let committed = node.get_committed_entry(index).map(|entry| entry.payload);
+            let committed = committed.or_else(|| {
+                if index > node.snapshot_index() {
+                    return None;
+                }
+                let applied = applied.lock().unwrap();
+                applied
+                    .iter()
+                    .find(|(applied_index, _)| *applied_index == index)
+                    .map(|(_, command)| command.clone())
+            });
+            if let Some(committed) = committed {
+                if let Some(payload) = payload.as_ref() {
+                    assert_eq!(
+                        *payload, committed,
+                        "committed values do not match at index {}",
+                        index
+                    );
+                }
+                count += 1;
+                payload = Some(committed);
+            }
+        }
+        (count, payload)
+    }
+
+    /// Waits for every connected, running node to commit `index` and returns
+    /// its payload.
+    pub async fn wait_committed(&self, index: u64) -> Vec<u8> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            self.check_invariants();
+            let (_, payload) = self.n_committed(index);
+            let everyone_committed = (0..self.nodes.len())
+                .filter(|i| self.connected[*i] && self.running[*i])
+                .all(|i| self.nodes[i].commit_index() >= index);
+            if let (true, Some(payload)) = (everyone_committed, payload) {
+                return payload;
+            }
+            assert!(
+                Instant::now() < deadline,
+                "index {} was not committed everywhere",
+                index
+            );
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    /// Submits `command` through whichever node leads the latest term and
+    /// waits for `expected_servers` nodes to commit it, retrying with the next
+    /// leader if it is lost. Returns the index it was committed at.
+    pub async fn one(&self, command: &[u8], expected_servers: usize) -> u64 {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            self.check_invariants();
+            let mut nodes: Vec<&Arc<RaftNode>> = (0..self.nodes.len())
+                .filter(|i| self.connected[*i] && self.running[*i])
+                .map(|i| &self.nodes[i])
+                .collect();
+            // A leader cut off with a minority may linger in an older term.
+            nodes.sort_by_key(|node| std::cmp::Reverse(node.get_state().0));
+            let index = nodes
+                .into_iter()
+                .find_map(|node| node.append_command(command.to_vec()));
+            let Some((index, _)) = index else {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                continue;
+            };
+
+            let committed_by = Instant::now() + Duration::from_secs(2);
+            while Instant::now() < committed_by {
+                let (count, payload) = self.n_committed(index);
+                if count >= expected_servers && payload.as_deref() == Some(command) {
+                    return index;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }
+        panic!("failed to reach agreement on {:?}", command);
+    }
+
+    /// Waits for every node to have applied exactly `expected`.
+    pub async fn wait_applied(&self, expected: &[Vec<u8>]) {
+        for _ in 0..100 {
+            if self
+                .applied
+                .iter()
+                .all(|applied| applied_commands(applied) == expected)
+            {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        for (node, applied) in self.nodes.iter().zip(self.applied.iter()) {
+            assert_eq!(
+                applied_commands(applied),
+                expected,
+                "{} applied the wrong commands",
+                node.id()
+            );
+        }
+    }
+
+    /// Reads through node `i` and returns the commands its state machine
+    /// had applied by then, or `None` if the read failed or took too long.
+    pub async fn read(&self, i: usize) -> Option<Vec<Vec<u8>>> {
+        let read = tokio::time::timeout(Duration::from_secs(2), self.nodes[i].read_index());
+        match read.await {
+            Ok(Ok(_)) => Some(applied_commands(&self.applied[i])),
+            _ => None,
+        }
+    }
+
+    /// Stops every node, then checks the invariants one last time.
+    pub fn stop(&self) {
+        for node in self.nodes.iter() {
+            node.stop();
+        }
+        self.check_invariants();
+    }
+}
+
+impl Drop for RaftCluster {
+    fn drop(&mut self) {
+        self.watcher.is_stopped.store(true, Ordering::SeqCst);
+        for node in self.nodes.iter() {
+            node.stop();
+        }
+    }
+}
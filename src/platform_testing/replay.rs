@@ -0,0 +1,409 @@
+use crate::raft::scheduler::{ClockScheduler, Scheduler};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+
+/// How long a replayed packet waits for those recorded ahead of it before
+/// the replay gives up on them.
+const PATIENCE: Duration = Duration::from_secs(1);
+
+/// A nondeterministic decision, as made by one stream of a simulation.
+#[derive(Clone, Debug, PartialEq)]
+enum Event {
+    /// A number drawn at random.
+    Draw(u64),
+    /// A timer fired on the given tick.
+    Fire(u64),
+    /// A timer fired no more through the given tick, when the run ended.
+    Quiet(u64),
+    /// The packet with the given ID reached its destination.
+    Deliver(String),
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Draw(value) => write!(f, "draw\t{}", value),
+            Event::Fire(tick) => write!(f, "fire\t{}", tick),
+            Event::Quiet(tick) => write!(f, "quiet\t{}", tick),
+            Event::Deliver(id) => write!(f, "deliver\t{}", id),
+        }
+    }
+}
+
+type Delivery = Box<dyn FnOnce() + Send>;
+
+/// A packet that arrived ahead of its turn.
+struct Held {
+    id: String,
+    since: Instant,
+    deliver: Delivery,
+}
+
+/// The packets whose turn has come to reach one host, handed over one at a
+/// time so that they arrive in the order recorded.
+#[derive(Default)]
+struct Outbox {
+    ready: VecDeque<(String, Delivery)>,
+    is_draining: bool,
+}
+
+#[derive(Default)]
+struct JournalState {
+    /// Every decision made so far, by stream, in the order made.
+    recorded: Vec<(String, Event)>,
+    /// What is left to replay of each stream.
+    remaining: HashMap<String, VecDeque<Event>>,
+    /// Packets waiting for their turn, by stream.
+    held: HashMap<String, Vec<Held>>,
+    outboxes: HashMap<String, Outbox>,
+    divergences: Vec<String>,
+    /// How many times each node has started.
+    incarnations: HashMap<String, usize>,
+    /// The last tick each timer was asked about.
+    last_ticks: HashMap<String, u64>,
+}
+
+/// Records every nondeterministic decision of a simulation — numbers drawn
+/// at random, the order packets reach each host and when nodes' timers
+/// fire — so that a run can be saved to a file and replayed, even after
+/// debug prints change its timing.
+///
+/// Decisions are kept in named streams, such as the packets on one link or
+/// the timeouts of one node, and each stream is replayed in its own order,
+/// so threads that run in a different order than when recording still get
+/// the same decisions. Where a replay asks for a decision the recording does
+/// not hold, it falls back to chance and the clock and notes the divergence.
+/// Threads racing within one host, such as two handling requests that came
+/// in together, are beyond its reach; when that changes the outcome, the
+/// replay diverges.
+pub struct Journal {
+    is_replaying: bool,
+    state: Mutex<JournalState>,
+}
+
+impl Journal {
+    /// A journal that records the decisions of a new run.
+    pub fn new() -> Self {
+        Journal {
+            is_replaying: false,
+            state: Mutex::new(JournalState::default()),
+        }
+    }
+
+    /// A journal that replays the run saved at `path`, while recording the
+    /// decisions of the replay itself.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut remaining: HashMap<String, VecDeque<Event>> = HashMap::new();
+        for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let invalid = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid event on line {}: {:?}", number + 1, line),
+                )
+            };
+            let fields: Vec<&str> = line.split('\t').collect();
+            let event = match fields[..] {
+                [_, "draw", value] => Event::Draw(value.parse().map_err(|_| invalid())?),
+                [_, "fire", tick] => Event::Fire(tick.parse().map_err(|_| invalid())?),
+                [_, "quiet", tick] => Event::Quiet(tick.parse().map_err(|_| invalid())?),
+                [_, "deliver", id] => Event::Deliver(id.to_string()),
+                _ => return Err(invalid()),
+            };
+            remaining
+                .entry(fields[0].to_string())
+                .or_default()
+                .push_back(event);
+        }
+        Ok(Journal {
+            is_replaying: true,
+            state: Mutex::new(JournalState {
+                remaining,
+                ..JournalState::default()
+            }),
+        })
+    }
+
+    /// Writes every decision made so far to `path`, one per line.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let state = self.state.lock().unwrap();
+        let mut contents = String::new();
+        for (stream, event) in state.recorded.iter() {
+            contents.push_str(&format!("{}\t{}\n", stream, event));
+        }
+        // Timers did not fire after their last firing until the run ended.
+        let mut last_ticks: Vec<(&String, &u64)> = state.last_ticks.iter().collect();
+        last_ticks.sort();
+        for (stream, tick) in last_ticks {
+            contents.push_str(&format!("{}\t{}\n", stream, Event::Quiet(*tick)));
+        }
+        fs::write(path, contents)
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.is_replaying
+    }
+
+    /// Where the replay could not follow the recording, in the order found.
+    pub fn divergences(&self) -> Vec<String> {
+        self.state.lock().unwrap().divergences.clone()
+    }
+
+    /// Draws the next number of `stream`: the recorded one when replaying,
+    /// else whatever `draw` returns.
+    pub fn draw(&self, stream: &str, draw: impl FnOnce() -> u64) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        let value = match self.next(&mut state, stream) {
+            Some(Event::Draw(value)) => value,
+            Some(event) => {
+                diverge(
+                    &mut state,
+                    format!("{} replayed {} for a draw", stream, event),
+                );
+                draw()
+            }
+            None => draw(),
+        };
+        state
+            .recorded
+            .push((stream.to_string(), Event::Draw(value)));
+        value
+    }
+
+    /// Whether the timer of `stream` fires on `tick`: when replaying, if it
+    /// was recorded to fire by then, else if `is_due`.
+    pub fn fire(&self, stream: &str, tick: u64, is_due: bool) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.last_ticks.insert(stream.to_string(), tick);
+        let fires = if !self.is_replaying {
+            is_due
+        } else {
+            match state
+                .remaining
+                .get(stream)
+                .and_then(|events| events.front())
+            {
+                Some(Event::Fire(fire_tick)) if *fire_tick <= tick => {
+                    state.remaining.get_mut(stream).unwrap().pop_front();
+                    true
+                }
+                Some(Event::Fire(_)) => false,
+                Some(Event::Quiet(quiet_tick)) if *quiet_tick >= tick => false,
+                Some(Event::Quiet(_)) => {
+                    state.remaining.get_mut(stream).unwrap().pop_front();
+                    is_due
+                }
+                Some(event) => {
+                    let divergence = format!("{} replayed {} for a timer", stream, event);
+                    state.remaining.remove(stream);
+                    diverge(&mut state, divergence);
+                    is_due
+                }
+                None => is_due,
+            }
+        };
+        if fires {
+            state.recorded.push((stream.to_string(), Event::Fire(tick)));
+        }
+        fires
+    }
+
+    /// Calls `deliver` to hand the packet `id` over once every packet
+    /// recorded ahead of it in `stream` has been, or right away when not
+    /// replaying. Packets of one stream are handed over one at a time, so
+    /// one that comes while another is on its way is handed over after it,
+    /// by whichever thread delivers that one.
+    pub fn deliver(
+        self: &Arc<Self>,
+        stream: &str,
+        id: String,
+        deliver: impl FnOnce() + Send + 'static,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        if self.is_replaying {
+            let position = state.remaining.get(stream).and_then(|events| {
+                events
+                    .iter()
+                    .position(|event| *event == Event::Deliver(id.clone()))
+            });
+            match position {
+                Some(0) => {
+                    state.remaining.get_mut(stream).unwrap().pop_front();
+                }
+                Some(_) => {
+                    state
+                        .held
+                        .entry(stream.to_string())
+                        .or_default()
+                        .push(Held {
+                            id,
+                            since: Instant::now(),
+                            deliver: Box::new(deliver),
+                        });
+                    let journal = Arc::clone(self);
+                    let stream = stream.to_string();
+                    spawn(move || {
+                        sleep(PATIENCE);
+                        journal.release_overdue(&stream);
+                    });
+                    return;
+                }
+                None => {
+                    let divergence = format!("{} delivered {} out of turn", stream, id);
+                    diverge(&mut state, divergence);
+                }
+            }
+        }
+        let outbox = state.outboxes.entry(stream.to_string()).or_default();
+        outbox.ready.push_back((id, Box::new(deliver)));
+        release_held(&mut state, stream);
+        self.drain(state, stream);
+    }
+
+    /// Gives up on the packets recorded ahead of one held for too long, and
+    /// delivers whatever that lets through.
+    fn release_overdue(&self, stream: &str) {
+        let mut state = self.state.lock().unwrap();
+        let Some(oldest) = state
+            .held
+            .get(stream)
+            .and_then(|held| held.iter().min_by_key(|held| held.since))
+        else {
+            return;
+        };
+        if oldest.since.elapsed() < PATIENCE {
+            return;
+        }
+        let id = oldest.id.clone();
+        let events = state.remaining.entry(stream.to_string()).or_default();
+        let skipped: Vec<Event> = events
+            .iter()
+            .take_while(|event| **event != Event::Deliver(id.clone()))
+            .cloned()
+            .collect();
+        events.drain(..skipped.len());
+        if events.is_empty() {
+            // Its own turn went to another copy of it.
+            events.push_back(Event::Deliver(id));
+        }
+        for event in skipped {
+            diverge(&mut state, format!("{} never saw {}", stream, event));
+        }
+        release_held(&mut state, stream);
+        self.drain(state, stream);
+    }
+
+    /// Hands over the ready packets of `stream` in order, recording each,
+    /// unless another thread already is.
+    fn drain<'a>(&'a self, mut state: MutexGuard<'a, JournalState>, stream: &str) {
+        let outbox = state.outboxes.entry(stream.to_string()).or_default();
+        if outbox.is_draining {
+            return;
+        }
+        outbox.is_draining = true;
+        loop {
+            let outbox = state.outboxes.get_mut(stream).unwrap();
+            let Some((id, deliver)) = outbox.ready.pop_front() else {
+                outbox.is_draining = false;
+                return;
+            };
+            state
+                .recorded
+                .push((stream.to_string(), Event::Deliver(id)));
+            drop(state);
+            deliver();
+            state = self.state.lock().unwrap();
+        }
+    }
+
+    /// The scheduler for the next start of node `id`, whose draws and timers
+    /// go to streams of their own for each time it starts.
+    pub fn scheduler(self: &Arc<Self>, id: &str) -> Arc<dyn Scheduler> {
+        let mut state = self.state.lock().unwrap();
+        let incarnation = state.incarnations.entry(id.to_string()).or_default();
+        *incarnation += 1;
+        Arc::new(JournalScheduler {
+            journal: Arc::clone(self),
+            timeouts: format!("{} #{} timeouts", id, incarnation),
+            timer: format!("{} #{} election timer", id, incarnation),
+        })
+    }
+
+    /// Takes the next event of `stream` when replaying.
+    fn next(&self, state: &mut JournalState, stream: &str) -> Option<Event> {
+        if !self.is_replaying {
+            return None;
+        }
+        state.remaining.get_mut(stream)?.pop_front()
+    }
+}
+
+impl fmt::Debug for Journal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Journal")
+            .field("is_replaying", &self.is_replaying)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn diverge(state: &mut JournalState, divergence: String) {
+    println!("Replay diverged: {}", divergence);
+    state.divergences.push(divergence);
+}
+
+/// Moves the held packets of `stream` that are next in line to its outbox,
+/// in order.
+fn release_held(state: &mut JournalState, stream: &str) {
+    loop {
+        let Some(Event::Deliver(id)) = state
+            .remaining
+            .get(stream)
+            .and_then(|events| events.front())
+            .cloned()
+        else {
+            return;
+        };
+        let Some(held) = state.held.get_mut(stream) else {
+            return;
+        };
+        let Some(position) = held.iter().position(|held| held.id == id) else {
+            return;
+        };
+        let held = held.remove(position);
+        state.remaining.get_mut(stream).unwrap().pop_front();
+        let outbox = state.outboxes.entry(stream.to_string()).or_default();
+        outbox.ready.push_back((held.id, held.deliver));
+    }
+}
+
+/// Draws a node's timeouts and fires its election timer through a journal.
+struct JournalScheduler {
+    journal: Arc<Journal>,
+    timeouts: String,
+    timer: String,
+}
+
+impl Scheduler for JournalScheduler {
+    fn election_timeout(&self, range: Range<Duration>) -> Duration {
+        let nanos = self.journal.draw(&self.timeouts, || {
+            ClockScheduler.election_timeout(range).as_nanos() as u64
+        });
+        Duration::from_nanos(nanos)
+    }
+
+    fn fire_election_timer(&self, tick: u64, is_due: bool) -> bool {
+        self.journal.fire(&self.timer, tick, is_due)
+    }
+}
@@ -0,0 +1,243 @@
+use crate::platform::grpc::{channel, incoming};
+use crate::platform::network::{Endpoint, NetworkInterface, TcpListener};
+use crate::raft::proto::raft_client::RaftClient;
+use crate::raft::proto::raft_server::{Raft, RaftServer};
+use crate::raft::proto::{
+    AppendEntriesRequest, AppendEntriesResponse, InstallSnapshotRequest, InstallSnapshotResponse,
+    RequestVoteRequest, RequestVoteResponse, TimeoutNowRequest, TimeoutNowResponse,
+};
+use crate::raft::rpc::{RaftRequest, RaftResponse};
+use crate::raft::transport::{RaftHandler, RaftTransport};
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tonic::transport::{Channel, Server};
+use tonic::{Code, Request, Response, Status};
+
+/// Runs the `raft` gRPC service generated from `proto/raft.proto` over the
+/// platform's TCP streams. The transport owns a small runtime for tonic, so
+/// the blocking calls Raft makes can come from any thread.
+pub struct GrpcTransport {
+    network_interface: Arc<dyn NetworkInterface>,
+    endpoint: Endpoint,
+    runtime: Option<Runtime>,
+    clients: Mutex<HashMap<Endpoint, RaftClient<Channel>>>,
+    listener: Mutex<Option<Arc<dyn TcpListener>>>,
+}
+
+impl GrpcTransport {
+    /// A transport that serves on `endpoint` through `network_interface`.
+    pub fn new(
+        network_interface: Arc<dyn NetworkInterface>,
+        endpoint: Endpoint,
+    ) -> io::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()?;
+        Ok(GrpcTransport {
+            network_interface,
+            endpoint,
+            runtime: Some(runtime),
+            clients: Mutex::new(HashMap::new()),
+            listener: Mutex::new(None),
+        })
+    }
+
+    fn runtime(&self) -> &Runtime {
+        self.runtime.as_ref().unwrap()
+    }
+
+    /// Channels connect lazily and reconnect on their own, so one client per
+    /// peer lasts as long as the transport.
+    fn client(&self, peer: &Endpoint) -> io::Result<RaftClient<Channel>> {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(peer) {
+            return Ok(client.clone());
+        }
+        let _guard = self.runtime().enter();
+        let channel = channel(Arc::clone(&self.network_interface), peer)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        let client = RaftClient::new(channel);
+        clients.insert(peer.clone(), client.clone());
+        Ok(client)
+    }
+}
+
+impl RaftTransport for GrpcTransport {
+    fn serve(&self, handler: RaftHandler) -> io::Result<()> {
+        let listener = self
+            .network_interface
+            .bind_tcp(&self.endpoint.ip, self.endpoint.port)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("failed to bind {:?}", self.endpoint),
+                )
+            })?;
+        *self.listener.lock().unwrap() = Some(Arc::clone(&listener));
+        let server = Server::builder()
+            .add_service(RaftServer::new(RaftService { handler }))
+            .serve_with_incoming(incoming(listener));
+        self.runtime().spawn(server);
+        Ok(())
+    }
+
+    fn shutdown(&self) {
+        if let Some(listener) = self.listener.lock().unwrap().take() {
+            listener.shutdown();
+        }
+    }
+
+    fn call(
+        &self,
+        peer: &Endpoint,
+        request: RaftRequest,
+        timeout: Duration,
+    ) -> io::Result<RaftResponse> {
+        let mut client = self.client(peer)?;
+        let response = self.runtime().block_on(async move {
+            match request {
+                RaftRequest::RequestVote(request) => client
+                    .request_vote(with_timeout(request, timeout))
+                    .await
+                    .map(|response| RaftResponse::RequestVote(response.into_inner())),
+                RaftRequest::PreVote(request) => client
+                    .pre_vote(with_timeout(request, timeout))
+                    .await
+                    .map(|response| RaftResponse::PreVote(response.into_inner())),
+                RaftRequest::AppendEntries(request) => client
+                    .append_entries(with_timeout(request, timeout))
+                    .await
+                    .map(|response| RaftResponse::AppendEntries(response.into_inner())),
+                RaftRequest::InstallSnapshot(request) => client
+                    .install_snapshot(with_timeout(request, timeout))
+                    .await
+                    .map(|response| RaftResponse::InstallSnapshot(response.into_inner())),
+                RaftRequest::TimeoutNow(request) => client
+                    .timeout_now(with_timeout(request, timeout))
+                    .await
+                    .map(|response| RaftResponse::TimeoutNow(response.into_inner())),
+            }
+        });
+        response.map_err(status_to_error)
+    }
+}
+
+impl Drop for GrpcTransport {
+    /// The transport may be dropped on a runtime thread, where waiting for
+    /// its own runtime's tasks to finish is not allowed.
+    fn drop(&mut self) {
+        self.shutdown();
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+fn with_timeout<T>(message: T, timeout: Duration) -> Request<T> {
+    let mut request = Request::new(message);
+    request.set_timeout(timeout);
+    request
+}
+
+fn status_to_error(status: Status) -> io::Error {
+    let kind = match status.code() {
+        Code::DeadlineExceeded => io::ErrorKind::TimedOut,
+        Code::Unavailable => io::ErrorKind::ConnectionAborted,
+        Code::Unimplemented | Code::InvalidArgument => io::ErrorKind::InvalidData,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, status.message().to_string())
+}
+
+/// Serves the generated `Raft` service by handing each request to the node.
+/// Handlers block on the node's locks and storage, so they run off the
+/// runtime's worker threads.
+struct RaftService {
+    handler: RaftHandler,
+}
+
+impl RaftService {
+    async fn handle(&self, request: RaftRequest) -> Result<RaftResponse, Status> {
+        let handler = Arc::clone(&self.handler);
+        tokio::task::spawn_blocking(move || handler(request))
+            .await
+            .map_err(|error| Status::internal(error.to_string()))?
+            .ok_or_else(|| Status::unavailable("request dropped"))
+    }
+}
+
+fn unexpected_response() -> Status {
+    Status::internal("handler answered with the wrong response type")
+}
+
+#[tonic::async_trait]
+impl Raft for RaftService {
+    async fn request_vote(
+        &self,
+        request: Request<RequestVoteRequest>,
+    ) -> Result<Response<RequestVoteResponse>, Status> {
+        match self
+            .handle(RaftRequest::RequestVote(request.into_inner()))
+            .await?
+        {
+            RaftResponse::RequestVote(response) => Ok(Response::new(response)),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    async fn pre_vote(
+        &self,
+        request: Request<RequestVoteRequest>,
+    ) -> Result<Response<RequestVoteResponse>, Status> {
+        match self
+            .handle(RaftRequest::PreVote(request.into_inner()))
+            .await?
+        {
+            RaftResponse::PreVote(response) => Ok(Response::new(response)),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    async fn append_entries(
+        &self,
+        request: Request<AppendEntriesRequest>,
+    ) -> Result<Response<AppendEntriesResponse>, Status> {
+        match self
+            .handle(RaftRequest::AppendEntries(request.into_inner()))
+            .await?
+        {
+            RaftResponse::AppendEntries(response) => Ok(Response::new(response)),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    async fn install_snapshot(
+        &self,
+        request: Request<InstallSnapshotRequest>,
+    ) -> Result<Response<InstallSnapshotResponse>, Status> {
+        match self
+            .handle(RaftRequest::InstallSnapshot(request.into_inner()))
+            .await?
+        {
+            RaftResponse::InstallSnapshot(response) => Ok(Response::new(response)),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    async fn timeout_now(
+        &self,
+        request: Request<TimeoutNowRequest>,
+    ) -> Result<Response<TimeoutNowResponse>, Status> {
+        match self
+            .handle(RaftRequest::TimeoutNow(request.into_inner()))
+            .await?
+        {
+            RaftResponse::TimeoutNow(response) => Ok(Response::new(response)),
+            _ => Err(unexpected_response()),
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use crate::raft::proto::LogEntry;
+
+/// The in-memory Raft log. Indices start at 1. Entries up to and including
+/// `snapshot_index` have been compacted into a snapshot; index 0 stands for
+/// the empty prefix and has term 0.
+#[derive(Debug, Default)]
+pub struct RaftLog {
+    snapshot_index: u64,
+    snapshot_term: u64,
+    entries: Vec<LogEntry>,
+}
+
+impl RaftLog {
+    pub fn new() -> Self {
+        RaftLog {
+            snapshot_index: 0,
+            snapshot_term: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    /// The last index covered by the snapshot.
+    pub fn snapshot_index(&self) -> u64 {
+        self.snapshot_index
+    }
+
+    /// The first index still held as an entry.
+    pub fn first_index(&self) -> u64 {
+        self.snapshot_index + 1
+    }
+
+    pub fn last_index(&self) -> u64 {
+        self.snapshot_index + self.entries.len() as u64
+    }
+
+    pub fn last_term(&self) -> u64 {
+        self.entries
+            .last()
+            .map_or(self.snapshot_term, |entry| entry.term)
+    }
+
+    /// Returns the term of the entry at `index`, which is known for the last
+    /// index covered by the snapshot but not for the ones before it.
+    pub fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.snapshot_index {
+            return Some(self.snapshot_term);
+        }
+        self.get(index).map(|entry| entry.term)
+    }
+
+    pub fn get(&self, index: u64) -> Option<&LogEntry> {
+        if index <= self.snapshot_index {
+            return None;
+        }
+        self.entries.get((index - self.first_index()) as usize)
+    }
+
+    /// Returns up to `max_entries` entries starting at `index`.
+    pub fn entries_from(&self, index: u64, max_entries: usize) -> Vec<LogEntry> {
+        let start = (index.max(self.first_index()) - self.first_index()) as usize;
+        self.entries
+            .iter()
+            .skip(start)
+            .take(max_entries)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the first index holding an entry of `term`, searching backwards
+    /// from `index`.
+    pub fn first_index_of_term(&self, term: u64, index: u64) -> u64 {
+        let mut first_index = index;
+        while first_index > self.first_index() && self.term_at(first_index - 1) == Some(term) {
+            first_index -= 1;
+        }
+        first_index
+    }
+
+    pub fn last_index_of_term(&self, term: u64) -> Option<u64> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| entry.term == term)
+            .map(|entry| entry.index)
+    }
+
+    pub fn append(&mut self, entry: LogEntry) {
+        debug_assert_eq!(entry.index, self.last_index() + 1);
+        self.entries.push(entry);
+    }
+
+    /// Removes the entry at `index` and every entry after it.
+    pub fn truncate_from(&mut self, index: u64) {
+        self.entries
+            .truncate((index.max(self.first_index()) - self.first_index()) as usize);
+    }
+
+    /// Discards the entries up to and including `index`, which must be in the
+    /// log, once a snapshot covers them.
+    pub fn compact(&mut self, index: u64) {
+        let term = self.term_at(index).unwrap();
+        self.entries.drain(..(index - self.snapshot_index) as usize);
+        self.snapshot_index = index;
+        self.snapshot_term = term;
+    }
+
+    /// Replaces the whole log with a snapshot ending at (`index`, `term`).
+    pub fn reset(&mut self, index: u64, term: u64) {
+        self.entries.clear();
+        self.snapshot_index = index;
+        self.snapshot_term = term;
+    }
+
+    /// Whether a log ending at (`last_index`, `last_term`) is at least as
+    /// up-to-date as this one (§5.4.1).
+    pub fn is_up_to_date(&self, last_index: u64, last_term: u64) -> bool {
+        last_term > self.last_term()
+            || (last_term == self.last_term() && last_index >= self.last_index())
+    }
+}
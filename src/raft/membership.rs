@@ -0,0 +1,94 @@
+use crate::platform::network::Endpoint;
+use crate::raft::proto::{Configuration, Member};
+use prost::Message;
+use std::collections::{BTreeMap, HashSet};
+
+/// The members of a cluster. Voters elect leaders and make up quorums;
+/// learners receive the log but are not counted, so a new node can catch up
+/// without holding back commits.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Membership {
+    pub voters: BTreeMap<String, Endpoint>,
+    pub learners: BTreeMap<String, Endpoint>,
+}
+
+impl Membership {
+    pub fn new(voters: BTreeMap<String, Endpoint>) -> Self {
+        Membership {
+            voters,
+            learners: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_voter(&self, id: &str) -> bool {
+        self.voters.contains_key(id)
+    }
+
+    pub fn is_learner(&self, id: &str) -> bool {
+        self.learners.contains_key(id)
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.is_voter(id) || self.is_learner(id)
+    }
+
+    /// Every voter and learner.
+    pub fn members(&self) -> impl Iterator<Item = (&String, &Endpoint)> {
+        self.voters.iter().chain(self.learners.iter())
+    }
+
+    /// Whether `ids` include a majority of the voters. Learners among them do
+    /// not count.
+    pub fn is_quorum(&self, ids: &HashSet<String>) -> bool {
+        let votes = self.voters.keys().filter(|id| ids.contains(*id)).count();
+        votes * 2 > self.voters.len()
+    }
+
+    pub fn to_proto(&self) -> Configuration {
+        let members = |members: &BTreeMap<String, Endpoint>| {
+            members
+                .iter()
+                .map(|(id, endpoint)| Member {
+                    id: id.to_string(),
+                    ip: endpoint.ip.clone(),
+                    port: endpoint.port as u32,
+                })
+                .collect()
+        };
+        Configuration {
+            voters: members(&self.voters),
+            learners: members(&self.learners),
+        }
+    }
+
+    pub fn from_proto(configuration: &Configuration) -> Self {
+        let members = |members: &[Member]| {
+            members
+                .iter()
+                .map(|member| {
+                    (
+                        member.id.clone(),
+                        Endpoint {
+                            ip: member.ip.clone(),
+                            port: member.port as u16,
+                        },
+                    )
+                })
+                .collect()
+        };
+        Membership {
+            voters: members(&configuration.voters),
+            learners: members(&configuration.learners),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        self.to_proto().encode_to_vec()
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        Configuration::decode(data)
+            .ok()
+            .map(|configuration| Membership::from_proto(&configuration))
+    }
+}
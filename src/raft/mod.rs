@@ -0,0 +1,16 @@
+pub mod grpc_transport;
+pub mod log;
+pub mod membership;
+pub mod node;
+pub mod read;
+pub mod rpc;
+pub mod scheduler;
+pub mod state_machine;
+pub mod storage;
+pub mod tcp_transport;
+pub mod transport;
+pub mod wal;
+
+pub mod proto {
+    tonic::include_proto!("raft");
+}
@@ -0,0 +1,1773 @@
+use crate::platform::network::Endpoint;
+use crate::raft::log::RaftLog;
+use crate::raft::membership::Membership;
+use crate::raft::proto::{
+    AppendEntriesRequest, AppendEntriesResponse, EntryType, InstallSnapshotRequest,
+    InstallSnapshotResponse, LogEntry, RequestVoteRequest, RequestVoteResponse, TimeoutNowRequest,
+    TimeoutNowResponse,
+};
+use crate::raft::read::{ReadMode, ReadQueue};
+use crate::raft::rpc::{RaftRequest, RaftResponse};
+use crate::raft::scheduler::{ClockScheduler, Scheduler};
+use crate::raft::state_machine::StateMachine;
+use crate::raft::storage::{HardState, RaftStorage, Snapshot};
+use crate::raft::transport::RaftTransport;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+const TICK_INTERVAL: Duration = Duration::from_millis(10);
+const MAX_ENTRIES_PER_REQUEST: usize = 64;
+/// How many replication rounds a learner gets to catch up before promotion.
+const CATCH_UP_ROUNDS: usize = 10;
+
+#[derive(Debug, Clone)]
+pub struct RaftConfig {
+    pub id: String,
+    pub endpoint: Endpoint,
+    /// The voters, other than this node, the cluster starts with. Later
+    /// changes come through the log.
+    pub peers: HashMap<String, Endpoint>,
+    /// Start outside any cluster and wait for a leader to add this node.
+    pub joining: bool,
+    pub election_timeout_range: Range<Duration>,
+    pub heartbeat_interval: Duration,
+    pub rpc_timeout: Duration,
+    /// How many applied entries the log may hold beyond the last snapshot
+    /// before the node takes a new one.
+    pub snapshot_threshold: u64,
+    /// The most snapshot data sent in one InstallSnapshot request.
+    pub snapshot_chunk_size: usize,
+    /// Run a PreVote round before each election, so a node that could not
+    /// win, such as one cut off from the others, never bumps its term.
+    pub pre_vote: bool,
+    /// Have the leader step down when it has not heard from a quorum for an
+    /// election timeout, and have followers that recently heard from a leader
+    /// ignore requests to vote in a later term.
+    pub check_quorum: bool,
+    pub read_mode: ReadMode,
+}
+
+impl RaftConfig {
+    pub fn new(id: &str, endpoint: Endpoint, peers: HashMap<String, Endpoint>) -> Self {
+        RaftConfig {
+            id: id.to_string(),
+            endpoint,
+            peers,
+            joining: false,
+            election_timeout_range: Duration::from_millis(150)..Duration::from_millis(300),
+            heartbeat_interval: Duration::from_millis(50),
+            rpc_timeout: Duration::from_millis(100),
+            snapshot_threshold: 1000,
+            snapshot_chunk_size: 64 * 1024,
+            pre_vote: true,
+            check_quorum: true,
+            read_mode: ReadMode::ReadIndex,
+        }
+    }
+
+    /// A node that waits to be added to an existing cluster with `add_node`.
+    #[allow(dead_code)]
+    pub fn new_joining(id: &str, endpoint: Endpoint) -> Self {
+        RaftConfig {
+            joining: true,
+            ..RaftConfig::new(id, endpoint, HashMap::new())
+        }
+    }
+
+    /// The membership until the log says otherwise.
+    fn initial_membership(&self) -> Membership {
+        if self.joining {
+            return Membership::default();
+        }
+        let mut voters: BTreeMap<String, Endpoint> = self
+            .peers
+            .iter()
+            .map(|(id, endpoint)| (id.to_string(), endpoint.clone()))
+            .collect();
+        voters.insert(self.id.clone(), self.endpoint.clone());
+        Membership::new(voters)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    /// Canvassing for votes in a PreVote round, still in the old term.
+    PreCandidate,
+    Candidate,
+    Leader,
+}
+
+/// A consistent copy of a node's term, role and log, for checking a
+/// cluster's invariants.
+#[derive(Debug, Clone)]
+pub struct RaftStatus {
+    pub term: u64,
+    pub role: Role,
+    pub commit_index: u64,
+    /// The last index and term covered by the snapshot.
+    pub snapshot_index: u64,
+    #[allow(dead_code)]
+    pub snapshot_term: u64,
+    /// The entries after the snapshot, the first at `snapshot_index + 1`.
+    pub entries: Vec<LogEntry>,
+}
+
+impl RaftStatus {
+    /// Returns the entry at `index` unless it is compacted away or missing.
+    pub fn entry(&self, index: u64) -> Option<&LogEntry> {
+        if index <= self.snapshot_index {
+            return None;
+        }
+        self.entries.get((index - self.snapshot_index - 1) as usize)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RaftError {
+    /// This node cannot serve the request; `leader_id` is the leader it last
+    /// heard from, if any.
+    NotLeader {
+        leader_id: Option<String>,
+    },
+    /// Leadership was lost before the request completed; for a proposal,
+    /// another leader's entry was committed in its place.
+    LeadershipLost,
+    /// A membership change is still uncommitted, or the leader has yet to
+    /// commit an entry of its own term.
+    MembershipChangePending,
+    /// A learner failed to catch up with the leader's log, so it was not
+    /// promoted to a voter.
+    CatchUpFailed,
+    /// Leadership is being handed over to another node, so the leader accepts
+    /// no proposals and no other transfer until that succeeds or times out.
+    LeadershipTransferPending,
+    /// The target of a leadership transfer is not a voter, or did not take
+    /// over within an election timeout.
+    TransferFailed,
+    Stopped,
+}
+
+impl fmt::Display for RaftError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RaftError::NotLeader {
+                leader_id: Some(leader_id),
+            } => write!(f, "not the leader, try {}", leader_id),
+            RaftError::NotLeader { leader_id: None } => write!(f, "not the leader"),
+            RaftError::LeadershipLost => write!(f, "leadership lost before the entry committed"),
+            RaftError::MembershipChangePending => {
+                write!(f, "another membership change is in progress")
+            }
+            RaftError::CatchUpFailed => write!(f, "the new node did not catch up with the log"),
+            RaftError::LeadershipTransferPending => {
+                write!(f, "leadership is being transferred")
+            }
+            RaftError::TransferFailed => write!(f, "the target did not take over leadership"),
+            RaftError::Stopped => write!(f, "node stopped"),
+        }
+    }
+}
+
+impl std::error::Error for RaftError {}
+
+#[derive(Debug)]
+struct RaftState {
+    current_term: u64,
+    voted_for: Option<String>,
+    role: Role,
+    votes_received: HashSet<String>,
+    leader_id: Option<String>,
+    /// When this node last heard from a leader of its current term.
+    leader_contact: Option<Instant>,
+    log: RaftLog,
+    commit_index: u64,
+    /// The last index handed to the state machine.
+    last_applied: u64,
+    /// The last index the state machine has finished applying.
+    applied_index: u64,
+    has_unsynced_writes: bool,
+    /// The first storage write to fail since the last sync.
+    storage_error: Option<io::Error>,
+    /// The latest snapshot, which stands in for the compacted log prefix.
+    snapshot: Option<Arc<Snapshot>>,
+    /// A snapshot the state machine has to be restored from before it
+    /// applies anything else.
+    pending_snapshot: Option<Arc<Snapshot>>,
+    /// The snapshot being received from the leader, chunk by chunk.
+    incoming_snapshot: Option<Snapshot>,
+    /// The membership set by the last configuration entry in the log, which
+    /// is in force whether or not it has committed.
+    membership: Membership,
+    /// The index of that entry, or of the snapshot that recorded it.
+    membership_index: u64,
+    next_index: HashMap<String, u64>,
+    match_index: HashMap<String, u64>,
+    election_deadline: Instant,
+    next_heartbeat: Instant,
+    /// The peers a leader has heard from since its last quorum check.
+    recently_active: HashSet<String>,
+    check_quorum_deadline: Instant,
+    /// The index of the no-op that started the leader's term.
+    term_start_index: u64,
+    reads: ReadQueue,
+    /// The voter the leader is handing leadership to, if any.
+    transferee: Option<String>,
+    transfer_deadline: Instant,
+    /// Set once the leader has told a transferee to campaign. Voters no
+    /// longer wait out the leader's lease before voting for it, so the lease
+    /// is gone for the rest of the term.
+    sent_timeout_now: bool,
+}
+
+/// A client waiting on the entry proposed at some index.
+struct Proposal {
+    term: u64,
+    sender: oneshot::Sender<Result<Vec<u8>, RaftError>>,
+}
+
+#[derive(Debug)]
+struct Peer {
+    endpoint: Endpoint,
+    /// Set while a snapshot is being sent, which replaces AppendEntries.
+    is_installing_snapshot: AtomicBool,
+}
+
+impl Peer {
+    fn new(endpoint: &Endpoint) -> Arc<Self> {
+        Arc::new(Peer {
+            endpoint: endpoint.clone(),
+            is_installing_snapshot: AtomicBool::new(false),
+        })
+    }
+}
+
+pub struct RaftNode {
+    config: RaftConfig,
+    transport: Arc<dyn RaftTransport>,
+    /// Every member other than this node, following the membership.
+    peers: Mutex<HashMap<String, Arc<Peer>>>,
+    state: Mutex<RaftState>,
+    commit_index_changed: Condvar,
+    state_machine: Mutex<Box<dyn StateMachine>>,
+    storage: Mutex<Box<dyn RaftStorage>>,
+    proposals: Mutex<HashMap<u64, Proposal>>,
+    is_stopped: AtomicBool,
+    /// How fast this node's clock runs against real time, as `f64` bits.
+    clock_rate: AtomicU64,
+    scheduler: Mutex<Arc<dyn Scheduler>>,
+    /// Ticks since the node started.
+    ticks: AtomicU64,
+}
+
+impl RaftNode {
+    pub fn new(
+        config: RaftConfig,
+        transport: Arc<dyn RaftTransport>,
+        state_machine: Box<dyn StateMachine>,
+        storage: Box<dyn RaftStorage>,
+    ) -> Arc<Self> {
+        let membership = config.initial_membership();
+        let peers = membership
+            .members()
+            .filter(|(id, _)| **id != config.id)
+            .map(|(id, endpoint)| (id.to_string(), Peer::new(endpoint)))
+            .collect();
+        let election_deadline =
+            Instant::now() + ClockScheduler.election_timeout(config.election_timeout_range.clone());
+        Arc::new(RaftNode {
+            config,
+            transport,
+            peers: Mutex::new(peers),
+            state: Mutex::new(RaftState {
+                current_term: 0,
+                voted_for: None,
+                role: Role::Follower,
+                votes_received: HashSet::new(),
+                leader_id: None,
+                leader_contact: None,
+                log: RaftLog::new(),
+                commit_index: 0,
+                last_applied: 0,
+                applied_index: 0,
+                has_unsynced_writes: false,
+                storage_error: None,
+                snapshot: None,
+                pending_snapshot: None,
+                incoming_snapshot: None,
+                membership,
+                membership_index: 0,
+                next_index: HashMap::new(),
+                match_index: HashMap::new(),
+                election_deadline,
+                next_heartbeat: Instant::now(),
+                recently_active: HashSet::new(),
+                check_quorum_deadline: Instant::now(),
+                term_start_index: 0,
+                reads: ReadQueue::new(),
+                transferee: None,
+                transfer_deadline: Instant::now(),
+                sent_timeout_now: false,
+            }),
+            commit_index_changed: Condvar::new(),
+            state_machine: Mutex::new(state_machine),
+            storage: Mutex::new(storage),
+            proposals: Mutex::new(HashMap::new()),
+            is_stopped: AtomicBool::new(false),
+            clock_rate: AtomicU64::new(1.0f64.to_bits()),
+            scheduler: Mutex::new(Arc::new(ClockScheduler)),
+            ticks: AtomicU64::new(0),
+        })
+    }
+
+    /// Recovers the persisted state and starts serving and ticking.
+    pub fn start(self: &Arc<Self>) -> io::Result<()> {
+        let (snapshot, hard_state, entries) = {
+            let mut storage = self.storage.lock().unwrap();
+            let snapshot = storage.load_snapshot()?;
+            let (hard_state, entries) = storage.load()?;
+            (snapshot, hard_state, entries)
+        };
+        {
+            let mut state = self.state.lock().unwrap();
+            state.current_term = hard_state.current_term;
+            state.voted_for = hard_state.voted_for;
+            if let Some(snapshot) = snapshot {
+                let snapshot = Arc::new(snapshot);
+                state
+                    .log
+                    .reset(snapshot.last_included_index, snapshot.last_included_term);
+                state.commit_index = snapshot.last_included_index;
+                state.snapshot = Some(Arc::clone(&snapshot));
+                state.pending_snapshot = Some(snapshot);
+            }
+            for entry in entries {
+                if entry.index <= state.log.snapshot_index() {
+                    continue;
+                }
+                if entry.index != state.log.last_index() + 1 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "stored log resumes at {} after {}",
+                            entry.index,
+                            state.log.last_index()
+                        ),
+                    ));
+                }
+                state.log.append(entry);
+            }
+            let last_index = state.log.last_index();
+            let (membership, membership_index) = self.membership_at(&state, last_index);
+            self.set_membership(&mut state, membership, membership_index);
+            state.election_deadline = Instant::now() + self.random_election_timeout();
+        }
+
+        // The transport holds on to the handler until shut down, so it must
+        // not keep the node alive.
+        let node = Arc::downgrade(self);
+        self.transport.serve(Arc::new(move |request| {
+            node.upgrade()?.handle_request(request)
+        }))?;
+
+        let node = Arc::clone(self);
+        spawn(move || {
+            while !node.is_stopped() {
+                sleep(TICK_INTERVAL);
+                node.tick();
+            }
+        });
+        let node = Arc::clone(self);
+        spawn(move || node.apply_committed());
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        let mut state = self.state.lock().unwrap();
+        self.halt(&mut state);
+    }
+
+    pub fn id(&self) -> &str {
+        &self.config.id
+    }
+
+    /// Skews this node's clock so its timeouts pass `rate` times as fast as
+    /// real time, such as to test how the cluster copes with drift.
+    pub fn set_clock_rate(&self, rate: f64) {
+        assert!(rate > 0.0, "clock rate must be positive");
+        self.clock_rate.store(rate.to_bits(), Ordering::Relaxed);
+    }
+
+    /// The real time `duration` on this node's clock takes to pass.
+    fn local(&self, duration: Duration) -> Duration {
+        duration.div_f64(f64::from_bits(self.clock_rate.load(Ordering::Relaxed)))
+    }
+
+    /// Hands the node's timeouts and timers to `scheduler`, such as to
+    /// record or replay them. Takes effect from the next timeout drawn, so
+    /// it is best set before `start`.
+    pub fn set_scheduler(&self, scheduler: Arc<dyn Scheduler>) {
+        *self.scheduler.lock().unwrap() = scheduler;
+    }
+
+    fn scheduler(&self) -> Arc<dyn Scheduler> {
+        Arc::clone(&self.scheduler.lock().unwrap())
+    }
+
+    fn random_election_timeout(&self) -> Duration {
+        let range = self.config.election_timeout_range.clone();
+        self.local(self.scheduler().election_timeout(range))
+    }
+
+    /// Returns the current term and this node's role in it.
+    pub fn get_state(&self) -> (u64, Role) {
+        let state = self.state.lock().unwrap();
+        (state.current_term, state.role)
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.get_state().1 == Role::Leader
+    }
+
+    #[allow(dead_code)]
+    pub fn leader_id(&self) -> Option<String> {
+        self.state.lock().unwrap().leader_id.clone()
+    }
+
+    /// Appends `command` to the leader's log and returns the index and term it
+    /// will be committed at, if it is ever committed. Returns `None` when this
+    /// node is not the leader.
+    pub fn append_command(&self, command: Vec<u8>) -> Option<(u64, u64)> {
+        let mut state = self.state.lock().unwrap();
+        if state.role != Role::Leader || state.transferee.is_some() || self.is_stopped() {
+            return None;
+        }
+        let index = self.append_entry(&mut state, EntryType::Normal, command)?;
+        Some((index, state.current_term))
+    }
+
+    /// Replicates `command` and resolves to the state machine's response once
+    /// it has been applied on this node.
+    pub async fn propose(&self, command: Vec<u8>) -> Result<Vec<u8>, RaftError> {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            if self.is_stopped() {
+                return Err(RaftError::Stopped);
+            }
+            if state.role != Role::Leader {
+                return Err(RaftError::NotLeader {
+                    leader_id: state.leader_id.clone(),
+                });
+            }
+            self.propose_entry(&mut state, EntryType::Normal, command)?
+        };
+        receiver.await.unwrap_or(Err(RaftError::Stopped))
+    }
+
+    /// The membership in force on this node, committed or not.
+    pub fn membership(&self) -> Membership {
+        self.state.lock().unwrap().membership.clone()
+    }
+
+    /// Adds `id` to the cluster, first as a learner that receives the log
+    /// without counting towards quorums and then, once it has caught up, as a
+    /// voter. The node at `endpoint` should be started with
+    /// `RaftConfig::new_joining`.
+    pub async fn add_node(&self, id: &str, endpoint: Endpoint) -> Result<(), RaftError> {
+        self.change_membership(|membership| {
+            if !membership.contains(id) {
+                membership.learners.insert(id.to_string(), endpoint);
+            }
+        })
+        .await?;
+        self.wait_caught_up(id).await?;
+        self.change_membership(|membership| {
+            if let Some(endpoint) = membership.learners.remove(id) {
+                membership.voters.insert(id.to_string(), endpoint);
+            }
+        })
+        .await
+    }
+
+    /// Removes `id`, voter or learner, from the cluster. The leader may remove
+    /// itself; it keeps managing the cluster until the change commits and then
+    /// steps down.
+    pub async fn remove_node(&self, id: &str) -> Result<(), RaftError> {
+        self.change_membership(|membership| {
+            membership.voters.remove(id);
+            membership.learners.remove(id);
+        })
+        .await
+    }
+
+    /// Appends a configuration entry with `change` applied to the current
+    /// membership and waits for it to commit. Changes go one node at a time,
+    /// so any majority of the old voters overlaps any majority of the new
+    /// ones, and the next one waits for the last to commit.
+    async fn change_membership(
+        &self,
+        change: impl FnOnce(&mut Membership),
+    ) -> Result<(), RaftError> {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            self.check_leader(&state)?;
+            // Until the leader commits an entry of its own term, an older
+            // leader's uncommitted change may still be in flight.
+            if state.membership_index > state.commit_index
+                || state.log.term_at(state.commit_index) != Some(state.current_term)
+            {
+                return Err(RaftError::MembershipChangePending);
+            }
+            let mut membership = state.membership.clone();
+            change(&mut membership);
+            if membership == state.membership {
+                return Ok(());
+            }
+            self.propose_entry(&mut state, EntryType::Configuration, membership.encode())?
+        };
+        receiver
+            .await
+            .unwrap_or(Err(RaftError::Stopped))
+            .map(|_| ())
+    }
+
+    /// Appends an entry and registers a proposal for it. Registering before
+    /// the state lock is released keeps the applier from reaching the entry
+    /// first.
+    fn propose_entry(
+        &self,
+        state: &mut RaftState,
+        entry_type: EntryType,
+        payload: Vec<u8>,
+    ) -> Result<oneshot::Receiver<Result<Vec<u8>, RaftError>>, RaftError> {
+        if state.transferee.is_some() {
+            return Err(RaftError::LeadershipTransferPending);
+        }
+        let Some(index) = self.append_entry(state, entry_type, payload) else {
+            return Err(RaftError::Stopped);
+        };
+        let (sender, receiver) = oneshot::channel();
+        self.proposals.lock().unwrap().insert(
+            index,
+            Proposal {
+                term: state.current_term,
+                sender,
+            },
+        );
+        Ok(receiver)
+    }
+
+    /// Waits for the learner `id` to catch up. Each round replicates what the
+    /// leader had when the round began; once a round is shorter than an
+    /// election timeout, promoting the learner cannot stall commits for long.
+    async fn wait_caught_up(&self, id: &str) -> Result<(), RaftError> {
+        for _ in 0..CATCH_UP_ROUNDS {
+            let target = self.leader_state(|state| state.log.last_index())?;
+            let started = Instant::now();
+            while started.elapsed() < self.local(self.config.election_timeout_range.end) {
+                let match_index = self.leader_state(|state| state.match_index.get(id).copied())?;
+                if match_index.is_some_and(|match_index| match_index >= target) {
+                    break;
+                }
+                tokio::time::sleep(TICK_INTERVAL).await;
+            }
+            if started.elapsed() < self.local(self.config.election_timeout_range.start) {
+                return Ok(());
+            }
+        }
+        Err(RaftError::CatchUpFailed)
+    }
+
+    /// Hands leadership over to the voter `target`: the leader stops accepting
+    /// proposals, brings `target`'s log up to date and then has it start an
+    /// election at once, which it wins without waiting for the other voters'
+    /// election timeouts. Resolves once `target` leads in a later term, or
+    /// fails if that does not happen within an election timeout, in which case
+    /// this node, if it still leads, accepts proposals again.
+    pub async fn transfer_leadership(&self, target: &str) -> Result<(), RaftError> {
+        let term = {
+            let mut state = self.state.lock().unwrap();
+            self.check_leader(&state)?;
+            if target == self.config.id {
+                return Ok(());
+            }
+            if !state.membership.is_voter(target) {
+                return Err(RaftError::TransferFailed);
+            }
+            if state.transferee.is_some() {
+                return Err(RaftError::LeadershipTransferPending);
+            }
+            println!(
+                "[{}] transferring leadership to {} in term {}",
+                self.config.id, target, state.current_term
+            );
+            state.transferee = Some(target.to_string());
+            state.transfer_deadline =
+                Instant::now() + self.local(self.config.election_timeout_range.end);
+            state.next_heartbeat = Instant::now();
+            state.current_term
+        };
+
+        let deadline = Instant::now() + 2 * self.local(self.config.election_timeout_range.end);
+        while Instant::now() < deadline {
+            tokio::time::sleep(TICK_INTERVAL).await;
+            let state = self.state.lock().unwrap();
+            if self.is_stopped() {
+                return Err(RaftError::Stopped);
+            }
+            if state.current_term > term && state.leader_id.as_deref() == Some(target) {
+                return Ok(());
+            }
+            // The transfer timed out while this node kept leading, or another
+            // node took over instead.
+            let gave_up = state.current_term == term
+                && state.role == Role::Leader
+                && state.transferee.is_none();
+            if gave_up || (state.current_term > term && state.leader_id.is_some()) {
+                return Err(RaftError::TransferFailed);
+            }
+        }
+        Err(RaftError::TransferFailed)
+    }
+
+    fn leader_state<T>(&self, read: impl FnOnce(&RaftState) -> T) -> Result<T, RaftError> {
+        let state = self.state.lock().unwrap();
+        self.check_leader(&state)?;
+        Ok(read(&state))
+    }
+
+    fn check_leader(&self, state: &RaftState) -> Result<(), RaftError> {
+        if self.is_stopped() {
+            return Err(RaftError::Stopped);
+        }
+        if state.role != Role::Leader {
+            return Err(RaftError::NotLeader {
+                leader_id: state.leader_id.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether a quorum acknowledged a round recently enough that no other
+    /// leader can have been elected since.
+    fn has_lease(&self, state: &RaftState) -> bool {
+        if !self.config.check_quorum || state.sent_timeout_now {
+            return false;
+        }
+        let Some(lease) = self
+            .config
+            .read_mode
+            .lease_duration(self.local(self.config.election_timeout_range.start))
+        else {
+            return false;
+        };
+        state
+            .reads
+            .confirmed_at()
+            .is_some_and(|sent_at| sent_at.elapsed() < lease)
+    }
+
+    /// Answers the reads that the latest acknowledgements and applied entries
+    /// allow.
+    fn complete_reads(&self, state: &mut RaftState) {
+        state.reads.confirm(&state.membership, &self.config.id);
+        let applied_index = state.applied_index;
+        state.reads.complete(applied_index);
+    }
+
+    pub fn commit_index(&self) -> u64 {
+        self.state.lock().unwrap().commit_index
+    }
+
+    /// The last index covered by this node's latest snapshot.
+    pub fn snapshot_index(&self) -> u64 {
+        self.state.lock().unwrap().log.snapshot_index()
+    }
+
+    /// Resolves once a linearizable read can be served from this node's state
+    /// machine, which by then reflects every write committed before the call,
+    /// and returns the index it has been applied through. Only the leader
+    /// serves reads, confirming that it still leads with a round of
+    /// heartbeats, or with its lease under `ReadMode::Lease`.
+    pub async fn read_index(&self) -> Result<u64, RaftError> {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            self.check_leader(&state)?;
+            // Until its no-op commits, a new leader's commit index may lag
+            // behind entries that earlier leaders committed.
+            let index = state.commit_index.max(state.term_start_index);
+            let round = if self.has_lease(&state) {
+                0
+            } else {
+                state.next_heartbeat = Instant::now();
+                state.reads.next_round()
+            };
+            let (sender, receiver) = oneshot::channel();
+            state.reads.push(round, index, sender);
+            let applied_index = state.applied_index;
+            state.reads.complete(applied_index);
+            receiver
+        };
+        receiver.await.unwrap_or(Err(RaftError::Stopped))
+    }
+
+    pub fn status(&self) -> RaftStatus {
+        let state = self.state.lock().unwrap();
+        let snapshot_index = state.log.snapshot_index();
+        RaftStatus {
+            term: state.current_term,
+            role: state.role,
+            commit_index: state.commit_index,
+            snapshot_index,
+            snapshot_term: state.log.term_at(snapshot_index).unwrap_or(0),
+            entries: state.log.entries_from(snapshot_index + 1, usize::MAX),
+        }
+    }
+
+    /// Returns the entry at `index` if this node knows it to be committed and
+    /// has not compacted it away.
+    pub fn get_committed_entry(&self, index: u64) -> Option<LogEntry> {
+        let state = self.state.lock().unwrap();
+        if index > state.commit_index {
+            return None;
+        }
+        state.log.get(index).cloned()
+    }
+
+    /// Stops the node. Requires the state lock so the applier cannot miss the
+    /// stop between checking for it and waiting.
+    fn halt(&self, state: &mut RaftState) {
+        self.is_stopped.store(true, Ordering::SeqCst);
+        state.role = Role::Follower;
+        self.transport.shutdown();
+        self.commit_index_changed.notify_all();
+        for (_, proposal) in self.proposals.lock().unwrap().drain() {
+            let _ = proposal.sender.send(Err(RaftError::Stopped));
+        }
+        state.reads.fail(RaftError::Stopped);
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.is_stopped.load(Ordering::SeqCst)
+    }
+
+    /// The membership in force at `index`: that of the last configuration
+    /// entry up to it, else the snapshot's, else the configured one, along
+    /// with the index it dates from.
+    fn membership_at(&self, state: &RaftState, index: u64) -> (Membership, u64) {
+        let first_index = state.log.first_index();
+        for index in (first_index..=index.min(state.log.last_index())).rev() {
+            let entry = state.log.get(index).unwrap();
+            if entry.entry_type() != EntryType::Configuration {
+                continue;
+            }
+            if let Some(membership) = Membership::decode(&entry.payload) {
+                return (membership, index);
+            }
+        }
+        match state.snapshot.as_ref() {
+            Some(snapshot) => (snapshot.membership.clone(), snapshot.last_included_index),
+            None => (self.config.initial_membership(), 0),
+        }
+    }
+
+    /// Puts `membership` in force, connecting to new members and, on the
+    /// leader, starting to replicate to them.
+    fn set_membership(&self, state: &mut RaftState, membership: Membership, index: u64) {
+        {
+            let mut peers = self.peers.lock().unwrap();
+            peers.retain(|id, _| membership.contains(id));
+            for (id, endpoint) in membership.members() {
+                if *id != self.config.id && !peers.contains_key(id) {
+                    peers.insert(id.to_string(), Peer::new(endpoint));
+                }
+            }
+        }
+        if state.role == Role::Leader {
+            let next_index = state.log.last_index() + 1;
+            state.next_index.retain(|id, _| membership.contains(id));
+            state.match_index.retain(|id, _| membership.contains(id));
+            for (id, _) in membership.members() {
+                if *id != self.config.id && !state.next_index.contains_key(id) {
+                    state.next_index.insert(id.to_string(), next_index);
+                    state.match_index.insert(id.to_string(), 0);
+                }
+            }
+        }
+        state.membership = membership;
+        state.membership_index = index;
+    }
+
+    fn peer(&self, id: &str) -> Option<Arc<Peer>> {
+        self.peers.lock().unwrap().get(id).cloned()
+    }
+
+    /// Members other than this node that `state`'s membership lets vote.
+    fn other_voters(&self, state: &RaftState) -> Vec<String> {
+        state
+            .membership
+            .voters
+            .keys()
+            .filter(|id| **id != self.config.id)
+            .cloned()
+            .collect()
+    }
+
+    fn tick(self: &Arc<Self>) {
+        let tick = self.ticks.fetch_add(1, Ordering::SeqCst);
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        match state.role {
+            Role::Leader => {
+                if self.config.check_quorum && now >= state.check_quorum_deadline {
+                    let mut active = std::mem::take(&mut state.recently_active);
+                    active.insert(self.config.id.clone());
+                    if !state.membership.is_quorum(&active) {
+                        println!(
+                            "[{}] stepping down after losing touch with a quorum in term {}",
+                            self.config.id, state.current_term
+                        );
+                        self.step_down(&mut state);
+                        return;
+                    }
+                    state.check_quorum_deadline =
+                        now + self.local(self.config.election_timeout_range.end);
+                }
+                if state.transferee.is_some() && now >= state.transfer_deadline {
+                    println!(
+                        "[{}] giving up on transferring leadership in term {}",
+                        self.config.id, state.current_term
+                    );
+                    state.transferee = None;
+                }
+                if now >= state.next_heartbeat {
+                    state.next_heartbeat = now + self.local(self.config.heartbeat_interval);
+                    self.send_append_entries(&mut state);
+                }
+            }
+            Role::Follower | Role::PreCandidate | Role::Candidate => {
+                let is_due = now >= state.election_deadline;
+                if !self.scheduler().fire_election_timer(tick, is_due) {
+                    return;
+                }
+                // Learners and nodes outside the cluster never campaign.
+                if !state.membership.is_voter(&self.config.id) {
+                    state.election_deadline = now + self.random_election_timeout();
+                } else if self.config.pre_vote {
+                    self.start_pre_vote(&mut state);
+                } else {
+                    self.start_election(&mut state, false);
+                }
+            }
+        }
+    }
+
+    /// Asks the voters whether they would vote for this node in the next
+    /// term, without anyone changing state. Only a node that would win goes on
+    /// to a real election.
+    fn start_pre_vote(self: &Arc<Self>, state: &mut RaftState) {
+        state.role = Role::PreCandidate;
+        state.votes_received = HashSet::from([self.config.id.clone()]);
+        state.election_deadline = Instant::now() + self.random_election_timeout();
+        if state.membership.is_quorum(&state.votes_received) {
+            self.start_election(state, false);
+            return;
+        }
+
+        let request = RequestVoteRequest {
+            term: state.current_term + 1,
+            candidate_id: self.config.id.clone(),
+            last_log_index: state.log.last_index(),
+            last_log_term: state.log.last_term(),
+            leadership_transfer: false,
+        };
+        for peer_id in self.other_voters(state) {
+            let node = Arc::clone(self);
+            let request = request.clone();
+            spawn(move || node.pre_vote(&peer_id, request));
+        }
+    }
+
+    fn pre_vote(self: &Arc<Self>, peer_id: &str, request: RequestVoteRequest) {
+        let response = self.call(peer_id, |transport, peer, timeout| {
+            transport.pre_vote(peer, request.clone(), timeout)
+        });
+        let Some(response) = response else {
+            return;
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if !response.vote_granted && response.term > state.current_term {
+            self.become_follower(&mut state, response.term);
+            return;
+        }
+        if state.role != Role::PreCandidate
+            || state.current_term + 1 != request.term
+            || !response.vote_granted
+        {
+            return;
+        }
+        state.votes_received.insert(peer_id.to_string());
+        if state.membership.is_quorum(&state.votes_received) {
+            self.start_election(&mut state, false);
+        }
+    }
+
+    /// Campaigns in the next term. A `leadership_transfer` election was asked
+    /// for by the leader, so voters do not hold out for it.
+    fn start_election(self: &Arc<Self>, state: &mut RaftState, leadership_transfer: bool) {
+        state.current_term += 1;
+        state.role = Role::Candidate;
+        state.voted_for = Some(self.config.id.clone());
+        state.votes_received = HashSet::from([self.config.id.clone()]);
+        state.election_deadline = Instant::now() + self.random_election_timeout();
+        println!(
+            "[{}] starting election for term {}",
+            self.config.id, state.current_term
+        );
+        self.save_hard_state(state);
+        if !self.sync(state) {
+            return;
+        }
+        if state.membership.is_quorum(&state.votes_received) {
+            self.become_leader(state);
+            return;
+        }
+
+        let request = RequestVoteRequest {
+            term: state.current_term,
+            candidate_id: self.config.id.clone(),
+            last_log_index: state.log.last_index(),
+            last_log_term: state.log.last_term(),
+            leadership_transfer,
+        };
+        for peer_id in self.other_voters(state) {
+            let node = Arc::clone(self);
+            let request = request.clone();
+            spawn(move || node.request_vote(&peer_id, request));
+        }
+    }
+
+    /// Asks `peer_id` for its vote. A leadership transfer has no timer of its
+    /// own to fall back on, so a vote request for one is retried for as long
+    /// as the election lasts.
+    fn request_vote(self: &Arc<Self>, peer_id: &str, request: RequestVoteRequest) {
+        let response = loop {
+            let response = self.call(peer_id, |transport, peer, timeout| {
+                transport.request_vote(peer, request.clone(), timeout)
+            });
+            match response {
+                Some(response) => break response,
+                None if !request.leadership_transfer => return,
+                None => {}
+            }
+            sleep(TICK_INTERVAL);
+            let state = self.state.lock().unwrap();
+            if self.is_stopped()
+                || state.role != Role::Candidate
+                || state.current_term != request.term
+            {
+                return;
+            }
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if response.term > state.current_term {
+            self.become_follower(&mut state, response.term);
+            return;
+        }
+        if state.role != Role::Candidate
+            || state.current_term != request.term
+            || !response.vote_granted
+        {
+            return;
+        }
+        state.votes_received.insert(peer_id.to_string());
+        if state.membership.is_quorum(&state.votes_received) {
+            self.become_leader(&mut state);
+        }
+    }
+
+    fn become_leader(&self, state: &mut RaftState) {
+        println!(
+            "[{}] became leader for term {}",
+            self.config.id, state.current_term
+        );
+        state.role = Role::Leader;
+        state.leader_id = Some(self.config.id.clone());
+        state.recently_active.clear();
+        state.check_quorum_deadline =
+            Instant::now() + self.local(self.config.election_timeout_range.end);
+        state.reads = ReadQueue::new();
+        state.transferee = None;
+        state.sent_timeout_now = false;
+        let next_index = state.log.last_index() + 1;
+        let peer_ids: Vec<String> = state
+            .membership
+            .members()
+            .map(|(id, _)| id.to_string())
+            .filter(|id| *id != self.config.id)
+            .collect();
+        state.next_index = peer_ids
+            .iter()
+            .map(|peer_id| (peer_id.to_string(), next_index))
+            .collect();
+        state.match_index = peer_ids.into_iter().map(|peer_id| (peer_id, 0)).collect();
+        // Entries from earlier terms can only be committed once an entry from
+        // the current term is, so start the term with a no-op.
+        state.term_start_index = state.log.last_index() + 1;
+        self.append_entry(state, EntryType::NoOp, Vec::new());
+    }
+
+    fn become_follower(&self, state: &mut RaftState, term: u64) {
+        if term > state.current_term {
+            state.current_term = term;
+            state.voted_for = None;
+            state.leader_id = None;
+            state.leader_contact = None;
+            self.save_hard_state(state);
+        }
+        // A deposed leader's deadline passed long ago; it should give the node
+        // that deposed it, likely a transferee, time to win before campaigning.
+        if state.role == Role::Leader {
+            state.election_deadline = Instant::now() + self.random_election_timeout();
+        }
+        state.role = Role::Follower;
+        state.transferee = None;
+        state.reads.fail(RaftError::LeadershipLost);
+    }
+
+    /// Gives up leadership, or a campaign, without changing term.
+    fn step_down(&self, state: &mut RaftState) {
+        state.role = Role::Follower;
+        state.leader_id = None;
+        state.transferee = None;
+        state.reads.fail(RaftError::LeadershipLost);
+        state.election_deadline = Instant::now() + self.random_election_timeout();
+    }
+
+    /// Whether this node leads, or has heard from a leader recently enough
+    /// that no other node should be campaigning yet.
+    fn is_leader_alive(&self, state: &RaftState) -> bool {
+        state.role == Role::Leader
+            || state.leader_contact.is_some_and(|leader_contact| {
+                leader_contact.elapsed() < self.local(self.config.election_timeout_range.start)
+            })
+    }
+
+    fn append_entry(
+        &self,
+        state: &mut RaftState,
+        entry_type: EntryType,
+        payload: Vec<u8>,
+    ) -> Option<u64> {
+        let index = state.log.last_index() + 1;
+        let entry = LogEntry {
+            index,
+            term: state.current_term,
+            payload,
+            entry_type: entry_type.into(),
+        };
+        self.append_to_log(state, entry);
+        // The leader counts towards a quorum only once the entry is durable.
+        if !self.sync(state) {
+            return None;
+        }
+        self.advance_commit_index(state);
+        state.next_heartbeat = Instant::now();
+        Some(index)
+    }
+
+    fn save_hard_state(&self, state: &mut RaftState) {
+        let hard_state = HardState {
+            current_term: state.current_term,
+            voted_for: state.voted_for.clone(),
+        };
+        self.write_storage(state, |storage| storage.save_hard_state(&hard_state));
+    }
+
+    fn append_to_log(&self, state: &mut RaftState, entry: LogEntry) {
+        self.write_storage(state, |storage| storage.append(slice::from_ref(&entry)));
+        let membership = (entry.entry_type() == EntryType::Configuration)
+            .then(|| Membership::decode(&entry.payload))
+            .flatten();
+        let index = entry.index;
+        state.log.append(entry);
+        if let Some(membership) = membership {
+            self.set_membership(state, membership, index);
+        }
+    }
+
+    fn truncate_log(&self, state: &mut RaftState, index: u64) {
+        self.write_storage(state, |storage| storage.truncate_from(index));
+        state.log.truncate_from(index);
+        // A configuration entry going with the truncated entries takes its
+        // membership with it.
+        if state.membership_index >= index {
+            let last_index = state.log.last_index();
+            let (membership, membership_index) = self.membership_at(state, last_index);
+            self.set_membership(state, membership, membership_index);
+        }
+    }
+
+    fn save_snapshot(&self, state: &mut RaftState, snapshot: &Snapshot) {
+        self.write_storage(state, |storage| storage.save_snapshot(snapshot));
+    }
+
+    /// Requires the state lock. Once the node has stopped, its storage may
+    /// already belong to a restarted node, so the write fails instead.
+    fn write_storage(
+        &self,
+        state: &mut RaftState,
+        write: impl FnOnce(&mut dyn RaftStorage) -> io::Result<()>,
+    ) {
+        let result = if self.is_stopped() {
+            Err(io::Error::other("node stopped"))
+        } else {
+            write(self.storage.lock().unwrap().as_mut())
+        };
+        record_write(state, result);
+    }
+
+    /// Makes every write so far durable before the node acts on it. A node
+    /// whose storage fails can no longer keep its promises to its peers, so it
+    /// stops instead.
+    fn sync(&self, state: &mut RaftState) -> bool {
+        if !state.has_unsynced_writes {
+            return true;
+        }
+        let result = match state.storage_error.take() {
+            Some(error) => Err(error),
+            None => self.storage.lock().unwrap().sync(),
+        };
+        match result {
+            Ok(()) => {
+                state.has_unsynced_writes = false;
+                true
+            }
+            Err(error) => {
+                println!(
+                    "[{}] stopping after storage error: {}",
+                    self.config.id, error
+                );
+                self.halt(state);
+                false
+            }
+        }
+    }
+
+    fn send_append_entries(self: &Arc<Self>, state: &mut RaftState) {
+        let round = state.reads.start_round();
+        self.complete_reads(state);
+        for (peer_id, next_index) in state.next_index.iter() {
+            let Some(peer) = self.peer(peer_id) else {
+                continue;
+            };
+            if peer.is_installing_snapshot.load(Ordering::SeqCst) {
+                continue;
+            }
+            let next_index = *next_index;
+            if next_index <= state.log.snapshot_index() {
+                // The entries the follower needs are gone; send the snapshot
+                // that replaced them instead.
+                let snapshot = Arc::clone(state.snapshot.as_ref().unwrap());
+                peer.is_installing_snapshot.store(true, Ordering::SeqCst);
+                let node = Arc::clone(self);
+                let peer_id = peer_id.to_string();
+                let term = state.current_term;
+                spawn(move || node.install_snapshot(&peer_id, term, snapshot));
+                continue;
+            }
+
+            let prev_log_index = next_index - 1;
+            let request = AppendEntriesRequest {
+                term: state.current_term,
+                leader_id: self.config.id.clone(),
+                prev_log_index,
+                prev_log_term: state.log.term_at(prev_log_index).unwrap_or(0),
+                entries: state.log.entries_from(next_index, MAX_ENTRIES_PER_REQUEST),
+                leader_commit: state.commit_index,
+            };
+            let node = Arc::clone(self);
+            let peer_id = peer_id.to_string();
+            spawn(move || node.append_entries(&peer_id, request, round));
+        }
+    }
+
+    /// Sends `request`, part of heartbeat `round`, and handles the response.
+    fn append_entries(self: &Arc<Self>, peer_id: &str, request: AppendEntriesRequest, round: u64) {
+        let response = self.call(peer_id, |transport, peer, timeout| {
+            transport.append_entries(peer, request.clone(), timeout)
+        });
+        let Some(response) = response else {
+            return;
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if response.term > state.current_term {
+            self.become_follower(&mut state, response.term);
+            return;
+        }
+        if state.role != Role::Leader || state.current_term != request.term {
+            return;
+        }
+        state.recently_active.insert(peer_id.to_string());
+        state.reads.ack(peer_id, round);
+        self.complete_reads(&mut state);
+
+        // The peer may have left the cluster in the meantime.
+        let Some(&match_index) = state.match_index.get(peer_id) else {
+            return;
+        };
+        if response.success {
+            let match_index =
+                match_index.max(request.prev_log_index + request.entries.len() as u64);
+            state.match_index.insert(peer_id.to_string(), match_index);
+            state
+                .next_index
+                .insert(peer_id.to_string(), match_index + 1);
+            self.advance_commit_index(&mut state);
+            if state.transferee.as_deref() == Some(peer_id) && match_index == state.log.last_index()
+            {
+                self.send_timeout_now(&mut state, peer_id);
+            }
+            return;
+        }
+
+        // Skip back over the follower's whole conflicting term at once, or to
+        // the end of its log if it is too short, rather than one entry per
+        // round trip.
+        let next_index = if response.conflict_term == 0 {
+            response.conflict_index
+        } else {
+            match state.log.last_index_of_term(response.conflict_term) {
+                Some(index) => index + 1,
+                None => response.conflict_index,
+            }
+        };
+        state
+            .next_index
+            .insert(peer_id.to_string(), next_index.max(match_index + 1));
+        state.next_heartbeat = Instant::now();
+    }
+
+    /// Tells the transferee, whose log now matches the leader's, to start an
+    /// election. The leader keeps leading until the transferee's higher term
+    /// reaches it.
+    fn send_timeout_now(self: &Arc<Self>, state: &mut RaftState, peer_id: &str) {
+        state.sent_timeout_now = true;
+        let request = TimeoutNowRequest {
+            term: state.current_term,
+            leader_id: self.config.id.clone(),
+        };
+        let node = Arc::clone(self);
+        let peer_id = peer_id.to_string();
+        spawn(move || {
+            let response = node.call(&peer_id, |transport, peer, timeout| {
+                transport.timeout_now(peer, request, timeout)
+            });
+            let Some(response) = response else {
+                return;
+            };
+            let mut state = node.state.lock().unwrap();
+            if response.term > state.current_term {
+                node.become_follower(&mut state, response.term);
+            }
+        });
+    }
+
+    /// Sends `snapshot` to `peer_id` in order, one chunk per request, giving
+    /// up on the first failure; the next heartbeat starts over.
+    fn install_snapshot(&self, peer_id: &str, term: u64, snapshot: Arc<Snapshot>) {
+        let is_installed = self.send_snapshot_chunks(peer_id, term, &snapshot);
+        if is_installed {
+            let mut state = self.state.lock().unwrap();
+            let is_current = state.role == Role::Leader && state.current_term == term;
+            // The peer may have left the cluster in the meantime.
+            if let Some(&match_index) = state.match_index.get(peer_id).filter(|_| is_current) {
+                let match_index = match_index.max(snapshot.last_included_index);
+                state.match_index.insert(peer_id.to_string(), match_index);
+                state
+                    .next_index
+                    .insert(peer_id.to_string(), match_index + 1);
+            }
+        }
+        if let Some(peer) = self.peer(peer_id) {
+            peer.is_installing_snapshot.store(false, Ordering::SeqCst);
+        }
+    }
+
+    fn send_snapshot_chunks(&self, peer_id: &str, term: u64, snapshot: &Snapshot) -> bool {
+        let chunk_size = self.config.snapshot_chunk_size.max(1);
+        let mut offset = 0;
+        loop {
+            let end = (offset + chunk_size).min(snapshot.data.len());
+            let request = InstallSnapshotRequest {
+                term,
+                leader_id: self.config.id.clone(),
+                last_included_index: snapshot.last_included_index,
+                last_included_term: snapshot.last_included_term,
+                offset: offset as u64,
+                data: snapshot.data[offset..end].to_vec(),
+                done: end == snapshot.data.len(),
+                configuration: Some(snapshot.membership.to_proto()),
+            };
+            let done = request.done;
+            let response = self.call(peer_id, |transport, peer, timeout| {
+                transport.install_snapshot(peer, request, timeout)
+            });
+            let Some(response) = response else {
+                return false;
+            };
+
+            let mut state = self.state.lock().unwrap();
+            if response.term > state.current_term {
+                self.become_follower(&mut state, response.term);
+                return false;
+            }
+            if state.role != Role::Leader || state.current_term != term {
+                return false;
+            }
+            state.recently_active.insert(peer_id.to_string());
+            if done {
+                return true;
+            }
+            offset = end;
+        }
+    }
+
+    /// Commits the highest index from the current term stored on a quorum of
+    /// voters. A leader that is no longer a voter itself steps down once the
+    /// change that removed it commits.
+    fn advance_commit_index(&self, state: &mut RaftState) {
+        let mut index = state.log.last_index();
+        while index > state.commit_index {
+            if state.log.term_at(index) != Some(state.current_term) {
+                break;
+            }
+            let mut replicas: HashSet<String> = state
+                .match_index
+                .iter()
+                .filter(|(_, match_index)| **match_index >= index)
+                .map(|(peer_id, _)| peer_id.to_string())
+                .collect();
+            replicas.insert(self.config.id.clone());
+            if state.membership.is_quorum(&replicas) {
+                state.commit_index = index;
+                self.commit_index_changed.notify_all();
+                // Let followers learn the new commit index right away.
+                state.next_heartbeat = Instant::now();
+                break;
+            }
+            index -= 1;
+        }
+
+        if state.commit_index >= state.membership_index
+            && !state.membership.is_voter(&self.config.id)
+        {
+            println!(
+                "[{}] stepping down after leaving the cluster in term {}",
+                self.config.id, state.current_term
+            );
+            self.step_down(state);
+        }
+    }
+
+    /// Sends a request to `peer_id` with `send` and waits for the response.
+    /// Failed calls, including those the transport turns away because the
+    /// peer is busy, are dropped, leaving Raft's own timers to retry.
+    fn call<T>(
+        &self,
+        peer_id: &str,
+        send: impl FnOnce(&dyn RaftTransport, &Endpoint, Duration) -> io::Result<T>,
+    ) -> Option<T> {
+        let peer = self.peer(peer_id)?;
+        send(
+            self.transport.as_ref(),
+            &peer.endpoint,
+            self.config.rpc_timeout,
+        )
+        .ok()
+    }
+
+    /// Feeds committed entries to the state machine in log order, answers the
+    /// proposals waiting on them and snapshots the state machine once enough
+    /// entries have been applied. The state machine is only ever touched from
+    /// here, so snapshots received from the leader are restored here too.
+    fn apply_committed(self: Arc<Self>) {
+        loop {
+            let (snapshot, entries) = {
+                let mut state = self.state.lock().unwrap();
+                while state.pending_snapshot.is_none()
+                    && state.last_applied >= state.commit_index
+                    && !self.is_stopped()
+                {
+                    state = self.commit_index_changed.wait(state).unwrap();
+                }
+                if self.is_stopped() {
+                    return;
+                }
+                if let Some(snapshot) = state.pending_snapshot.take() {
+                    state.last_applied = state.last_applied.max(snapshot.last_included_index);
+                    (Some(snapshot), Vec::new())
+                } else {
+                    let count = (state.commit_index - state.last_applied) as usize;
+                    let entries = state.log.entries_from(state.last_applied + 1, count);
+                    state.last_applied = state.commit_index;
+                    (None, entries)
+                }
+            };
+
+            let mut state_machine = self.state_machine.lock().unwrap();
+            if let Some(snapshot) = snapshot {
+                state_machine.restore(&snapshot.data);
+                // Whatever was proposed at these indices, the outcome is now
+                // buried in the snapshot.
+                let mut proposals = self.proposals.lock().unwrap();
+                let buried: Vec<u64> = proposals
+                    .keys()
+                    .copied()
+                    .filter(|index| *index <= snapshot.last_included_index)
+                    .collect();
+                for index in buried {
+                    let proposal = proposals.remove(&index).unwrap();
+                    let _ = proposal.sender.send(Err(RaftError::LeadershipLost));
+                }
+                drop(proposals);
+                let mut state = self.state.lock().unwrap();
+                state.applied_index = state.applied_index.max(snapshot.last_included_index);
+                self.complete_reads(&mut state);
+                continue;
+            }
+
+            let Some(applied_index) = entries.last().map(|entry| entry.index) else {
+                continue;
+            };
+            for entry in entries {
+                let response = match entry.entry_type() {
+                    EntryType::Normal => state_machine.apply(entry.index, &entry.payload),
+                    EntryType::NoOp | EntryType::Configuration => Vec::new(),
+                };
+                let proposal = self.proposals.lock().unwrap().remove(&entry.index);
+                if let Some(proposal) = proposal {
+                    let result = if proposal.term == entry.term {
+                        Ok(response)
+                    } else {
+                        Err(RaftError::LeadershipLost)
+                    };
+                    let _ = proposal.sender.send(result);
+                }
+            }
+
+            let snapshot_index = {
+                let mut state = self.state.lock().unwrap();
+                state.applied_index = applied_index;
+                self.complete_reads(&mut state);
+                state.log.snapshot_index()
+            };
+            if applied_index >= snapshot_index + self.config.snapshot_threshold {
+                let data = state_machine.snapshot();
+                let mut state = self.state.lock().unwrap();
+                self.compact_log(&mut state, applied_index, data);
+            }
+        }
+    }
+
+    /// Replaces the log up to `index` with a snapshot of the state machine
+    /// taken right after applying it.
+    fn compact_log(&self, state: &mut RaftState, index: u64, data: Vec<u8>) {
+        // A snapshot from the leader may have overtaken this one.
+        if index <= state.log.snapshot_index() {
+            return;
+        }
+        let snapshot = Snapshot {
+            last_included_index: index,
+            last_included_term: state.log.term_at(index).unwrap(),
+            membership: self.membership_at(state, index).0,
+            data,
+        };
+        self.save_snapshot(state, &snapshot);
+        if !self.sync(state) {
+            return;
+        }
+        state.log.compact(index);
+        state.snapshot = Some(Arc::new(snapshot));
+    }
+
+    /// Answers a request from a peer, or returns `None` to drop it once the
+    /// node has stopped or when it could not persist its answer.
+    fn handle_request(self: &Arc<Self>, request: RaftRequest) -> Option<RaftResponse> {
+        if self.is_stopped() {
+            return None;
+        }
+        match request {
+            RaftRequest::RequestVote(request) => self
+                .handle_request_vote(request)
+                .map(RaftResponse::RequestVote),
+            RaftRequest::AppendEntries(request) => self
+                .handle_append_entries(request)
+                .map(RaftResponse::AppendEntries),
+            RaftRequest::InstallSnapshot(request) => self
+                .handle_install_snapshot(request)
+                .map(RaftResponse::InstallSnapshot),
+            RaftRequest::PreVote(request) => {
+                Some(RaftResponse::PreVote(self.handle_pre_vote(request)))
+            }
+            RaftRequest::TimeoutNow(request) => self
+                .handle_timeout_now(request)
+                .map(RaftResponse::TimeoutNow),
+        }
+    }
+
+    /// Returns `None` if the vote could not be persisted.
+    fn handle_request_vote(&self, request: RequestVoteRequest) -> Option<RequestVoteResponse> {
+        let mut state = self.state.lock().unwrap();
+        // A node that has not heard from the leader, such as one removed from
+        // the cluster, should not be able to depose it.
+        if self.config.check_quorum
+            && !request.leadership_transfer
+            && request.term > state.current_term
+            && self.is_leader_alive(&state)
+        {
+            return Some(RequestVoteResponse {
+                term: state.current_term,
+                vote_granted: false,
+            });
+        }
+        if request.term > state.current_term {
+            self.become_follower(&mut state, request.term);
+        }
+
+        let vote_granted = request.term == state.current_term
+            && state
+                .log
+                .is_up_to_date(request.last_log_index, request.last_log_term)
+            && state
+                .voted_for
+                .as_ref()
+                .is_none_or(|voted_for| *voted_for == request.candidate_id);
+        if vote_granted {
+            state.voted_for = Some(request.candidate_id);
+            state.election_deadline = Instant::now() + self.random_election_timeout();
+            self.save_hard_state(&mut state);
+        }
+        let response = RequestVoteResponse {
+            term: state.current_term,
+            vote_granted,
+        };
+        self.sync(&mut state).then_some(response)
+    }
+
+    /// Grants a pre-vote if a vote could be granted in `request.term`, which
+    /// takes the same up-to-date log and no recent word from a leader. Nothing
+    /// is changed, so there is nothing to persist.
+    fn handle_pre_vote(&self, request: RequestVoteRequest) -> RequestVoteResponse {
+        let state = self.state.lock().unwrap();
+        let vote_granted = request.term > state.current_term
+            && !self.is_leader_alive(&state)
+            && state
+                .log
+                .is_up_to_date(request.last_log_index, request.last_log_term);
+        RequestVoteResponse {
+            term: state.current_term,
+            vote_granted,
+        }
+    }
+
+    /// Starts an election right away, skipping PreVote, if the leader of the
+    /// current term asks. Returns `None` if the new term could not be
+    /// persisted.
+    fn handle_timeout_now(
+        self: &Arc<Self>,
+        request: TimeoutNowRequest,
+    ) -> Option<TimeoutNowResponse> {
+        let mut state = self.state.lock().unwrap();
+        if request.term == state.current_term
+            && state.role == Role::Follower
+            && state.membership.is_voter(&self.config.id)
+        {
+            println!(
+                "[{}] asked by {} to take over leadership",
+                self.config.id, request.leader_id
+            );
+            self.start_election(&mut state, true);
+        }
+        let response = TimeoutNowResponse {
+            term: state.current_term,
+        };
+        self.sync(&mut state).then_some(response)
+    }
+
+    /// Returns `None` if the accepted entries could not be persisted.
+    fn handle_append_entries(
+        &self,
+        request: AppendEntriesRequest,
+    ) -> Option<AppendEntriesResponse> {
+        let mut state = self.state.lock().unwrap();
+        let response = self.accept_append_entries(&mut state, request);
+        self.sync(&mut state).then_some(response)
+    }
+
+    fn accept_append_entries(
+        &self,
+        state: &mut RaftState,
+        request: AppendEntriesRequest,
+    ) -> AppendEntriesResponse {
+        if request.term < state.current_term {
+            return AppendEntriesResponse {
+                term: state.current_term,
+                success: false,
+                conflict_index: 0,
+                conflict_term: 0,
+            };
+        }
+
+        self.become_follower(state, request.term);
+        state.leader_id = Some(request.leader_id);
+        state.leader_contact = Some(Instant::now());
+        state.election_deadline = Instant::now() + self.random_election_timeout();
+
+        // Everything up to the snapshot is committed, so it matches the
+        // leader's log whatever the leader believes about it.
+        let prev_log_term = if request.prev_log_index < state.log.snapshot_index() {
+            Some(request.prev_log_term)
+        } else {
+            state.log.term_at(request.prev_log_index)
+        };
+        match prev_log_term {
+            None => {
+                return AppendEntriesResponse {
+                    term: state.current_term,
+                    success: false,
+                    conflict_index: state.log.last_index() + 1,
+                    conflict_term: 0,
+                }
+            }
+            Some(term) if term != request.prev_log_term => {
+                return AppendEntriesResponse {
+                    term: state.current_term,
+                    success: false,
+                    conflict_index: state.log.first_index_of_term(term, request.prev_log_index),
+                    conflict_term: term,
+                }
+            }
+            Some(_) => {}
+        }
+
+        let last_new_index = request.prev_log_index + request.entries.len() as u64;
+        for entry in request.entries {
+            if entry.index <= state.log.snapshot_index() {
+                continue;
+            }
+            match state.log.term_at(entry.index) {
+                Some(term) if term == entry.term => continue,
+                Some(_) => self.truncate_log(state, entry.index),
+                None => {}
+            }
+            self.append_to_log(state, entry);
+        }
+        if request.leader_commit > state.commit_index {
+            state.commit_index = state
+                .commit_index
+                .max(request.leader_commit.min(last_new_index));
+            self.commit_index_changed.notify_all();
+        }
+        AppendEntriesResponse {
+            term: state.current_term,
+            success: true,
+            conflict_index: 0,
+            conflict_term: 0,
+        }
+    }
+
+    /// Returns `None` if the snapshot could not be persisted.
+    fn handle_install_snapshot(
+        &self,
+        request: InstallSnapshotRequest,
+    ) -> Option<InstallSnapshotResponse> {
+        let mut state = self.state.lock().unwrap();
+        self.accept_snapshot_chunk(&mut state, request);
+        let response = InstallSnapshotResponse {
+            term: state.current_term,
+        };
+        self.sync(&mut state).then_some(response)
+    }
+
+    fn accept_snapshot_chunk(&self, state: &mut RaftState, request: InstallSnapshotRequest) {
+        if request.term < state.current_term {
+            return;
+        }
+        self.become_follower(state, request.term);
+        state.leader_id = Some(request.leader_id);
+        state.leader_contact = Some(Instant::now());
+        state.election_deadline = Instant::now() + self.random_election_timeout();
+
+        if request.offset == 0 {
+            state.incoming_snapshot = Some(Snapshot {
+                last_included_index: request.last_included_index,
+                last_included_term: request.last_included_term,
+                membership: request
+                    .configuration
+                    .as_ref()
+                    .map(Membership::from_proto)
+                    .unwrap_or_default(),
+                data: Vec::new(),
+            });
+        }
+        let Some(incoming_snapshot) = state.incoming_snapshot.as_mut() else {
+            return;
+        };
+        if incoming_snapshot.last_included_index != request.last_included_index
+            || incoming_snapshot.data.len() as u64 != request.offset
+        {
+            // A chunk from some other transfer; wait for the leader to start
+            // over.
+            state.incoming_snapshot = None;
+            return;
+        }
+        incoming_snapshot.data.extend_from_slice(&request.data);
+        if !request.done {
+            return;
+        }
+
+        let snapshot = state.incoming_snapshot.take().unwrap();
+        let index = snapshot.last_included_index;
+        if index <= state.commit_index {
+            return;
+        }
+        // Keep the entries after the snapshot if the log agrees with it up to
+        // there; otherwise the whole log is stale.
+        let is_prefix = state.log.term_at(index) == Some(snapshot.last_included_term);
+        if !is_prefix {
+            let first_index = state.log.first_index();
+            self.truncate_log(state, first_index);
+        }
+        self.save_snapshot(state, &snapshot);
+        if is_prefix {
+            state.log.compact(index);
+        } else {
+            state.log.reset(index, snapshot.last_included_term);
+            self.set_membership(state, snapshot.membership.clone(), index);
+        }
+        state.commit_index = index;
+        let snapshot = Arc::new(snapshot);
+        state.snapshot = Some(Arc::clone(&snapshot));
+        state.pending_snapshot = Some(snapshot);
+        self.commit_index_changed.notify_all();
+    }
+}
+
+fn record_write(state: &mut RaftState, result: io::Result<()>) {
+    state.has_unsynced_writes = true;
+    if let Err(error) = result {
+        state.storage_error.get_or_insert(error);
+    }
+}
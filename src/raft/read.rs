@@ -0,0 +1,154 @@
+use crate::raft::membership::Membership;
+use crate::raft::node::RaftError;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// How a leader makes sure it still leads before serving a read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReadMode {
+    /// Confirm leadership with a round of heartbeats for every read.
+    ReadIndex,
+    /// Skip the round while a lease from the last round a quorum acknowledged
+    /// holds. Followers that heard from the leader refuse to elect another for
+    /// the minimum election timeout, so the lease lasts that long from when
+    /// the round was sent, shortened by `max_clock_drift`: how many times
+    /// faster than another any node's clock may run. Only takes effect with
+    /// `check_quorum`.
+    Lease { max_clock_drift: f64 },
+}
+
+impl ReadMode {
+    /// How long after sending a round its acknowledgement guarantees that no
+    /// other leader exists, if this mode relies on leases at all.
+    pub fn lease_duration(&self, min_election_timeout: Duration) -> Option<Duration> {
+        match self {
+            ReadMode::ReadIndex => None,
+            ReadMode::Lease { max_clock_drift } => {
+                Some(min_election_timeout.div_f64(max_clock_drift.max(1.0)))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PendingRead {
+    /// The heartbeat round that has to be acknowledged by a quorum.
+    round: u64,
+    /// The commit index when the read arrived, which has to be applied.
+    index: u64,
+    sender: oneshot::Sender<Result<u64, RaftError>>,
+}
+
+/// A leader's heartbeat rounds and the reads waiting on them. Each round of
+/// AppendEntries is numbered; a response to a round shows that the peer still
+/// took this node for leader when it was sent.
+#[derive(Debug, Default)]
+pub struct ReadQueue {
+    round: u64,
+    acked_rounds: HashMap<String, u64>,
+    sent_at: VecDeque<(u64, Instant)>,
+    confirmed_round: u64,
+    /// When the latest round a quorum acknowledged was sent.
+    confirmed_at: Option<Instant>,
+    pending: Vec<PendingRead>,
+}
+
+impl ReadQueue {
+    pub fn new() -> Self {
+        ReadQueue::default()
+    }
+
+    /// Numbers the round of AppendEntries about to be sent.
+    pub fn start_round(&mut self) -> u64 {
+        self.round += 1;
+        self.sent_at.push_back((self.round, Instant::now()));
+        self.round
+    }
+
+    /// The round the next read has to wait for: one sent after it arrived.
+    pub fn next_round(&self) -> u64 {
+        self.round + 1
+    }
+
+    pub fn confirmed_at(&self) -> Option<Instant> {
+        self.confirmed_at
+    }
+
+    /// Records that `peer_id` responded to `round`.
+    pub fn ack(&mut self, peer_id: &str, round: u64) {
+        let acked_round = self.acked_rounds.entry(peer_id.to_string()).or_default();
+        *acked_round = (*acked_round).max(round);
+    }
+
+    /// Moves on to the latest round a quorum of `membership` has
+    /// acknowledged, counting this node, `id`, as acknowledging its own rounds
+    /// as it sends them. Returns whether that is a later round than before.
+    pub fn confirm(&mut self, membership: &Membership, id: &str) -> bool {
+        let mut candidates: Vec<u64> = self
+            .acked_rounds
+            .values()
+            .copied()
+            .chain([self.round])
+            .filter(|round| *round > self.confirmed_round)
+            .collect();
+        candidates.sort_unstable_by(|a, b| b.cmp(a));
+        let confirmed_round = candidates.into_iter().find(|candidate| {
+            let acked_by: HashSet<String> = self
+                .acked_rounds
+                .iter()
+                .filter(|(_, acked_round)| **acked_round >= *candidate)
+                .map(|(peer_id, _)| peer_id.to_string())
+                .chain([id.to_string()])
+                .collect();
+            membership.is_quorum(&acked_by)
+        });
+        let Some(confirmed_round) = confirmed_round else {
+            return false;
+        };
+
+        self.confirmed_round = confirmed_round;
+        while let Some((sent_round, sent_at)) = self.sent_at.front().copied() {
+            if sent_round > confirmed_round {
+                break;
+            }
+            self.sent_at.pop_front();
+            if sent_round == confirmed_round {
+                self.confirmed_at = Some(sent_at);
+            }
+        }
+        true
+    }
+
+    pub fn push(
+        &mut self,
+        round: u64,
+        index: u64,
+        sender: oneshot::Sender<Result<u64, RaftError>>,
+    ) {
+        self.pending.push(PendingRead {
+            round,
+            index,
+            sender,
+        });
+    }
+
+    /// Answers the reads whose round is confirmed and whose index has been
+    /// applied.
+    pub fn complete(&mut self, applied_index: u64) {
+        let confirmed_round = self.confirmed_round;
+        let (ready, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|read| read.round <= confirmed_round && read.index <= applied_index);
+        self.pending = pending;
+        for read in ready {
+            let _ = read.sender.send(Ok(read.index));
+        }
+    }
+
+    pub fn fail(&mut self, error: RaftError) {
+        for read in self.pending.drain(..) {
+            let _ = read.sender.send(Err(error.clone()));
+        }
+    }
+}
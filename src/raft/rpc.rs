@@ -0,0 +1,106 @@
+use crate::raft::proto::{
+    AppendEntriesRequest, AppendEntriesResponse, InstallSnapshotRequest, InstallSnapshotResponse,
+    RequestVoteRequest, RequestVoteResponse, TimeoutNowRequest, TimeoutNowResponse,
+};
+use prost::Message;
+
+const REQUEST_VOTE: u8 = 1;
+const APPEND_ENTRIES: u8 = 2;
+const INSTALL_SNAPSHOT: u8 = 3;
+const PRE_VOTE: u8 = 4;
+const TIMEOUT_NOW: u8 = 5;
+
+/// A Raft RPC request as sent over a `TcpStream`: a one-byte method tag
+/// followed by the protobuf encoding of the request.
+#[derive(Debug, Clone)]
+pub enum RaftRequest {
+    RequestVote(RequestVoteRequest),
+    AppendEntries(AppendEntriesRequest),
+    InstallSnapshot(InstallSnapshotRequest),
+    PreVote(RequestVoteRequest),
+    TimeoutNow(TimeoutNowRequest),
+}
+
+#[derive(Debug, Clone)]
+pub enum RaftResponse {
+    RequestVote(RequestVoteResponse),
+    AppendEntries(AppendEntriesResponse),
+    InstallSnapshot(InstallSnapshotResponse),
+    PreVote(RequestVoteResponse),
+    TimeoutNow(TimeoutNowResponse),
+}
+
+fn encode_tagged(tag: u8, message: &impl Message) -> Vec<u8> {
+    let mut data = Vec::with_capacity(1 + message.encoded_len());
+    data.push(tag);
+    message.encode(&mut data).unwrap();
+    data
+}
+
+impl RaftRequest {
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            RaftRequest::RequestVote(request) => encode_tagged(REQUEST_VOTE, request),
+            RaftRequest::AppendEntries(request) => encode_tagged(APPEND_ENTRIES, request),
+            RaftRequest::InstallSnapshot(request) => encode_tagged(INSTALL_SNAPSHOT, request),
+            RaftRequest::PreVote(request) => encode_tagged(PRE_VOTE, request),
+            RaftRequest::TimeoutNow(request) => encode_tagged(TIMEOUT_NOW, request),
+        }
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let (tag, body) = data.split_first()?;
+        match *tag {
+            REQUEST_VOTE => RequestVoteRequest::decode(body)
+                .ok()
+                .map(RaftRequest::RequestVote),
+            APPEND_ENTRIES => AppendEntriesRequest::decode(body)
+                .ok()
+                .map(RaftRequest::AppendEntries),
+            INSTALL_SNAPSHOT => InstallSnapshotRequest::decode(body)
+                .ok()
+                .map(RaftRequest::InstallSnapshot),
+            PRE_VOTE => RequestVoteRequest::decode(body)
+                .ok()
+                .map(RaftRequest::PreVote),
+            TIMEOUT_NOW => TimeoutNowRequest::decode(body)
+                .ok()
+                .map(RaftRequest::TimeoutNow),
+            _ => None,
+        }
+    }
+}
+
+impl RaftResponse {
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            RaftResponse::RequestVote(response) => encode_tagged(REQUEST_VOTE, response),
+            RaftResponse::AppendEntries(response) => encode_tagged(APPEND_ENTRIES, response),
+            RaftResponse::InstallSnapshot(response) => encode_tagged(INSTALL_SNAPSHOT, response),
+            RaftResponse::PreVote(response) => encode_tagged(PRE_VOTE, response),
+            RaftResponse::TimeoutNow(response) => encode_tagged(TIMEOUT_NOW, response),
+        }
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let (tag, body) = data.split_first()?;
+        match *tag {
+            REQUEST_VOTE => RequestVoteResponse::decode(body)
+                .ok()
+                .map(RaftResponse::RequestVote),
+            APPEND_ENTRIES => AppendEntriesResponse::decode(body)
+                .ok()
+                .map(RaftResponse::AppendEntries),
+            INSTALL_SNAPSHOT => InstallSnapshotResponse::decode(body)
+                .ok()
+                .map(RaftResponse::InstallSnapshot),
+            PRE_VOTE => RequestVoteResponse::decode(body)
+                .ok()
+                .map(RaftResponse::PreVote),
+            TIMEOUT_NOW => TimeoutNowResponse::decode(body)
+                .ok()
+                .map(RaftResponse::TimeoutNow),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use rand::Rng;
+use std::ops::Range;
+use std::time::Duration;
+
+/// Makes the decisions a node leaves to chance or to the clock, so that a
+/// simulation can record them and play them back.
+pub trait Scheduler: Send + Sync {
+    /// Draws how long to wait for a leader before campaigning.
+    fn election_timeout(&self, range: Range<Duration>) -> Duration;
+
+    /// Whether the election timer fires on the node's `tick`th tick; by the
+    /// clock it would if `is_due`.
+    fn fire_election_timer(&self, tick: u64, is_due: bool) -> bool;
+}
+
+/// Draws timeouts at random and fires timers when the clock says so.
+pub struct ClockScheduler;
+
+impl Scheduler for ClockScheduler {
+    fn election_timeout(&self, range: Range<Duration>) -> Duration {
+        rand::thread_rng().gen_range(range)
+    }
+
+    fn fire_election_timer(&self, _tick: u64, is_due: bool) -> bool {
+        is_due
+    }
+}
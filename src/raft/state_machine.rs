@@ -0,0 +1,14 @@
+/// A deterministic service replicated by Raft. Every node applies the same
+/// committed commands in the same order, so every replica reaches the same
+/// state and produces the same responses.
+pub trait StateMachine: Send {
+    /// Applies the command committed at `index` and returns the response for
+    /// whoever proposed it.
+    fn apply(&mut self, index: u64, command: &[u8]) -> Vec<u8>;
+
+    /// Serializes the state reached by every command applied so far.
+    fn snapshot(&self) -> Vec<u8>;
+
+    /// Replaces the current state with one produced by `snapshot`.
+    fn restore(&mut self, snapshot: &[u8]);
+}
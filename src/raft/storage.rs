@@ -0,0 +1,115 @@
+use crate::raft::membership::Membership;
+use crate::raft::proto::LogEntry;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// The Raft state that must survive a crash.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HardState {
+    pub current_term: u64,
+    pub voted_for: Option<String>,
+}
+
+/// The state machine as of `last_included_index`, standing in for every log
+/// entry up to it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub last_included_index: u64,
+    pub last_included_term: u64,
+    /// The cluster membership as of `last_included_index`.
+    pub membership: Membership,
+    pub data: Vec<u8>,
+}
+
+/// Durable storage for a node's hard state and log. Writes may be buffered;
+/// only those made before a successful `sync` are guaranteed to be returned by
+/// `load` after a crash.
+pub trait RaftStorage: Send {
+    /// Recovers the hard state and log persisted so far. The log may still
+    /// hold entries covered by the latest snapshot.
+    fn load(&mut self) -> io::Result<(HardState, Vec<LogEntry>)>;
+
+    fn load_snapshot(&mut self) -> io::Result<Option<Snapshot>>;
+
+    /// Stores `snapshot`, durably once this returns, and lets the entries it
+    /// covers be discarded.
+    fn save_snapshot(&mut self, snapshot: &Snapshot) -> io::Result<()>;
+
+    fn save_hard_state(&mut self, hard_state: &HardState) -> io::Result<()>;
+
+    /// Appends `entries`, which directly follow the last stored entry.
+    fn append(&mut self, entries: &[LogEntry]) -> io::Result<()>;
+
+    /// Removes the entry at `index` and every entry after it.
+    fn truncate_from(&mut self, index: u64) -> io::Result<()>;
+
+    /// Makes every preceding write durable.
+    fn sync(&mut self) -> io::Result<()>;
+}
+
+#[derive(Debug, Default)]
+struct MemoryState {
+    hard_state: HardState,
+    snapshot: Option<Snapshot>,
+    entries: Vec<LogEntry>,
+}
+
+/// Keeps everything in memory. Clones share the same state, so a test can
+/// hand a clone to a restarted node to simulate a crash that loses nothing.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+}
+
+impl RaftStorage for MemoryStorage {
+    fn load(&mut self) -> io::Result<(HardState, Vec<LogEntry>)> {
+        let state = self.state.lock().unwrap();
+        Ok((state.hard_state.clone(), state.entries.clone()))
+    }
+
+    fn load_snapshot(&mut self) -> io::Result<Option<Snapshot>> {
+        Ok(self.state.lock().unwrap().snapshot.clone())
+    }
+
+    fn save_snapshot(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .entries
+            .retain(|entry| entry.index > snapshot.last_included_index);
+        state.snapshot = Some(snapshot.clone());
+        Ok(())
+    }
+
+    fn save_hard_state(&mut self, hard_state: &HardState) -> io::Result<()> {
+        self.state.lock().unwrap().hard_state = hard_state.clone();
+        Ok(())
+    }
+
+    fn append(&mut self, entries: &[LogEntry]) -> io::Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .entries
+            .extend_from_slice(entries);
+        Ok(())
+    }
+
+    fn truncate_from(&mut self, index: u64) -> io::Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .entries
+            .retain(|entry| entry.index < index);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+use crate::platform::connection_manager::{ConnectionConfig, PeerConnectionManager};
+use crate::platform::network::{Endpoint, NetworkInterface, TcpListener};
+use crate::platform::rpc::serve_connection;
+use crate::raft::rpc::{RaftRequest, RaftResponse};
+use crate::raft::transport::{RaftHandler, RaftTransport};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+use std::time::{Duration, Instant};
+
+/// Sends each RPC as one `rpc` request holding the method tag followed by
+/// the protobuf encoding, as laid out in `raft::rpc`. Calls to a peer share
+/// a connection from a `PeerConnectionManager`, and any number of them can
+/// be in flight at once. A call that times out without anything arriving on
+/// its connection in the meantime reports the connection as broken.
+pub struct TcpTransport {
+    network_interface: Arc<dyn NetworkInterface>,
+    endpoint: Endpoint,
+    connections: PeerConnectionManager,
+    listener: Mutex<Option<Arc<dyn TcpListener>>>,
+}
+
+impl TcpTransport {
+    /// A transport that serves on `endpoint` through `network_interface`.
+    pub fn new(network_interface: Arc<dyn NetworkInterface>, endpoint: Endpoint) -> Self {
+        TcpTransport {
+            connections: PeerConnectionManager::new(
+                Arc::clone(&network_interface),
+                ConnectionConfig::default(),
+            ),
+            network_interface,
+            endpoint,
+            listener: Mutex::new(None),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn connections(&self) -> &PeerConnectionManager {
+        &self.connections
+    }
+}
+
+impl RaftTransport for TcpTransport {
+    fn serve(&self, handler: RaftHandler) -> io::Result<()> {
+        let listener = self
+            .network_interface
+            .bind_tcp(&self.endpoint.ip, self.endpoint.port)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("failed to bind {:?}", self.endpoint),
+                )
+            })?;
+        *self.listener.lock().unwrap() = Some(Arc::clone(&listener));
+        spawn(move || {
+            while let Ok(stream) = listener.accept() {
+                let handler = Arc::clone(&handler);
+                spawn(move || {
+                    serve_connection(
+                        stream,
+                        Arc::new(move |data| {
+                            let response = handler(RaftRequest::decode(data)?)?;
+                            Some(response.encode())
+                        }),
+                    )
+                });
+            }
+        });
+        Ok(())
+    }
+
+    fn shutdown(&self) {
+        if let Some(listener) = self.listener.lock().unwrap().take() {
+            listener.shutdown();
+        }
+    }
+
+    fn call(
+        &self,
+        peer: &Endpoint,
+        request: RaftRequest,
+        timeout: Duration,
+    ) -> io::Result<RaftResponse> {
+        let deadline = Instant::now() + timeout;
+        let client = self.connections.get(peer, timeout)?;
+        let started = Instant::now();
+        let timeout = deadline.saturating_duration_since(started);
+        let data = client
+            .call(&request.encode(), timeout)
+            .inspect_err(|error| {
+                if error.kind() == io::ErrorKind::TimedOut && !client.has_received_since(started) {
+                    self.connections.report_broken(peer, &client);
+                }
+            })?;
+        RaftResponse::decode(&data)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed response"))
+    }
+}
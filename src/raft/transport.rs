@@ -0,0 +1,180 @@
+use crate::platform::network::Endpoint;
+use crate::raft::proto::{
+    AppendEntriesRequest, AppendEntriesResponse, InstallSnapshotRequest, InstallSnapshotResponse,
+    RequestVoteRequest, RequestVoteResponse, TimeoutNowRequest, TimeoutNowResponse,
+};
+use crate::raft::rpc::{RaftRequest, RaftResponse};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Answers a request addressed to this node, or returns `None` to drop it
+/// without a response, as when the node could not persist what it promised.
+pub type RaftHandler = Arc<dyn Fn(RaftRequest) -> Option<RaftResponse> + Send + Sync>;
+
+/// Carries Raft RPCs between nodes, so the consensus logic does not depend on
+/// how they travel. Calls block until the response arrives or `timeout`
+/// passes; implementations may also fail a call right away, such as when the
+/// connection to the peer is busy, and leave retrying to Raft's timers.
+pub trait RaftTransport: Send + Sync {
+    /// Starts handing requests sent to this node to `handler`.
+    fn serve(&self, handler: RaftHandler) -> io::Result<()>;
+
+    /// Stops serving requests and lets go of the handler.
+    fn shutdown(&self);
+
+    /// Sends `request` to the node at `peer` and waits for its response.
+    fn call(
+        &self,
+        peer: &Endpoint,
+        request: RaftRequest,
+        timeout: Duration,
+    ) -> io::Result<RaftResponse>;
+
+    fn request_vote(
+        &self,
+        peer: &Endpoint,
+        request: RequestVoteRequest,
+        timeout: Duration,
+    ) -> io::Result<RequestVoteResponse> {
+        match self.call(peer, RaftRequest::RequestVote(request), timeout)? {
+            RaftResponse::RequestVote(response) => Ok(response),
+            response => Err(unexpected_response(&response)),
+        }
+    }
+
+    fn pre_vote(
+        &self,
+        peer: &Endpoint,
+        request: RequestVoteRequest,
+        timeout: Duration,
+    ) -> io::Result<RequestVoteResponse> {
+        match self.call(peer, RaftRequest::PreVote(request), timeout)? {
+            RaftResponse::PreVote(response) => Ok(response),
+            response => Err(unexpected_response(&response)),
+        }
+    }
+
+    fn append_entries(
+        &self,
+        peer: &Endpoint,
+        request: AppendEntriesRequest,
+        timeout: Duration,
+    ) -> io::Result<AppendEntriesResponse> {
+        match self.call(peer, RaftRequest::AppendEntries(request), timeout)? {
+            RaftResponse::AppendEntries(response) => Ok(response),
+            response => Err(unexpected_response(&response)),
+        }
+    }
+
+    fn install_snapshot(
+        &self,
+        peer: &Endpoint,
+        request: InstallSnapshotRequest,
+        timeout: Duration,
+    ) -> io::Result<InstallSnapshotResponse> {
+        match self.call(peer, RaftRequest::InstallSnapshot(request), timeout)? {
+            RaftResponse::InstallSnapshot(response) => Ok(response),
+            response => Err(unexpected_response(&response)),
+        }
+    }
+
+    fn timeout_now(
+        &self,
+        peer: &Endpoint,
+        request: TimeoutNowRequest,
+        timeout: Duration,
+    ) -> io::Result<TimeoutNowResponse> {
+        match self.call(peer, RaftRequest::TimeoutNow(request), timeout)? {
+            RaftResponse::TimeoutNow(response) => Ok(response),
+            response => Err(unexpected_response(&response)),
+        }
+    }
+}
+
+fn unexpected_response(response: &RaftResponse) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected response {:?}", response),
+    )
+}
+
+/// Connects `MemoryTransport`s in the same process. Nodes are addressed by
+/// endpoint, and a node cut off with `disconnect` neither sends nor receives.
+#[derive(Default)]
+pub struct MemoryNetwork {
+    handlers: Mutex<HashMap<Endpoint, RaftHandler>>,
+    disconnected: Mutex<HashSet<Endpoint>>,
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Arc<Self> {
+        Arc::new(MemoryNetwork::default())
+    }
+
+    pub fn connect(&self, endpoint: &Endpoint) {
+        self.disconnected.lock().unwrap().remove(endpoint);
+    }
+
+    pub fn disconnect(&self, endpoint: &Endpoint) {
+        self.disconnected.lock().unwrap().insert(endpoint.clone());
+    }
+
+    fn is_connected(&self, endpoint: &Endpoint) -> bool {
+        !self.disconnected.lock().unwrap().contains(endpoint)
+    }
+}
+
+/// Calls the peer's handler directly on the calling thread, for unit tests
+/// that want Raft without sockets. A call returns whatever the handler does,
+/// however long that takes.
+pub struct MemoryTransport {
+    network: Arc<MemoryNetwork>,
+    endpoint: Endpoint,
+}
+
+impl MemoryTransport {
+    pub fn new(network: Arc<MemoryNetwork>, endpoint: Endpoint) -> Self {
+        MemoryTransport { network, endpoint }
+    }
+}
+
+impl RaftTransport for MemoryTransport {
+    fn serve(&self, handler: RaftHandler) -> io::Result<()> {
+        let mut handlers = self.network.handlers.lock().unwrap();
+        if handlers.contains_key(&self.endpoint) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{:?} is already served", self.endpoint),
+            ));
+        }
+        handlers.insert(self.endpoint.clone(), handler);
+        Ok(())
+    }
+
+    fn shutdown(&self) {
+        self.network.handlers.lock().unwrap().remove(&self.endpoint);
+    }
+
+    fn call(
+        &self,
+        peer: &Endpoint,
+        request: RaftRequest,
+        _timeout: Duration,
+    ) -> io::Result<RaftResponse> {
+        if !self.network.is_connected(&self.endpoint) || !self.network.is_connected(peer) {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        let handler = self.network.handlers.lock().unwrap().get(peer).cloned();
+        let Some(handler) = handler else {
+            return Err(io::ErrorKind::ConnectionRefused.into());
+        };
+        let response = handler(request).ok_or(io::ErrorKind::ConnectionAborted)?;
+        // The network may have been cut while the peer was handling the call.
+        if !self.network.is_connected(&self.endpoint) || !self.network.is_connected(peer) {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        Ok(response)
+    }
+}
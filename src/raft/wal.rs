@@ -0,0 +1,482 @@
+use crate::platform::storage::{Storage, StorageFile};
+use crate::raft::membership::Membership;
+use crate::raft::proto::LogEntry;
+use crate::raft::storage::{HardState, RaftStorage, Snapshot};
+use prost::Message;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub const DEFAULT_SEGMENT_SIZE: u64 = 4 * 1024 * 1024;
+const SEGMENT_EXTENSION: &str = "wal";
+const SNAPSHOT_EXTENSION: &str = "snap";
+/// A snapshot being written, renamed to its `SNAPSHOT_EXTENSION` name once
+/// synced.
+const TEMP_SNAPSHOT_EXTENSION: &str = "tmp";
+/// Every record starts with its length and the CRC-32 of everything after the
+/// header, both little-endian u32s.
+const RECORD_HEADER_SIZE: usize = 8;
+
+const HARD_STATE_RECORD: u8 = 1;
+const ENTRY_RECORD: u8 = 2;
+const TRUNCATE_RECORD: u8 = 3;
+const COMPACT_RECORD: u8 = 4;
+const SYNCED_RECORD: u8 = 5;
+/// The body of a `Synced` record: its type and the offset it is written at.
+const SYNCED_RECORD_BODY_SIZE: usize = 9;
+
+#[derive(Debug)]
+enum Record {
+    HardState(HardState),
+    Entry(LogEntry),
+    Truncate(u64),
+    /// Entries up to and including this index are covered by a snapshot.
+    Compact(u64),
+    /// Written at this offset of its segment once everything before it is
+    /// durable.
+    Synced(u64),
+}
+
+impl Record {
+    fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        match self {
+            Record::HardState(hard_state) => {
+                body.push(HARD_STATE_RECORD);
+                body.extend_from_slice(&hard_state.current_term.to_le_bytes());
+                if let Some(voted_for) = &hard_state.voted_for {
+                    body.extend_from_slice(voted_for.as_bytes());
+                }
+            }
+            Record::Entry(entry) => {
+                body.push(ENTRY_RECORD);
+                entry.encode(&mut body).unwrap();
+            }
+            Record::Truncate(index) => {
+                body.push(TRUNCATE_RECORD);
+                body.extend_from_slice(&index.to_le_bytes());
+            }
+            Record::Compact(index) => {
+                body.push(COMPACT_RECORD);
+                body.extend_from_slice(&index.to_le_bytes());
+            }
+            Record::Synced(offset) => {
+                body.push(SYNCED_RECORD);
+                body.extend_from_slice(&offset.to_le_bytes());
+            }
+        }
+
+        let mut data = Vec::with_capacity(RECORD_HEADER_SIZE + body.len());
+        data.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        data.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+        data.extend_from_slice(&body);
+        data
+    }
+
+    /// Decodes the record at the start of `data` and returns it with its
+    /// encoded size, or `None` if the record is incomplete or fails its CRC.
+    fn decode(data: &[u8]) -> Option<(Record, usize)> {
+        let length = u32::from_le_bytes(data.get(0..4)?.try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(data.get(4..8)?.try_into().unwrap());
+        let body = data.get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + length)?;
+        if body.is_empty() || crc32fast::hash(body) != crc {
+            return None;
+        }
+
+        let record = match body[0] {
+            HARD_STATE_RECORD => {
+                let current_term = u64::from_le_bytes(body.get(1..9)?.try_into().unwrap());
+                let voted_for = match &body[9..] {
+                    [] => None,
+                    voted_for => Some(String::from_utf8(voted_for.to_vec()).ok()?),
+                };
+                Record::HardState(HardState {
+                    current_term,
+                    voted_for,
+                })
+            }
+            ENTRY_RECORD => Record::Entry(LogEntry::decode(&body[1..]).ok()?),
+            TRUNCATE_RECORD => {
+                Record::Truncate(u64::from_le_bytes(body.get(1..9)?.try_into().unwrap()))
+            }
+            COMPACT_RECORD => {
+                Record::Compact(u64::from_le_bytes(body.get(1..9)?.try_into().unwrap()))
+            }
+            SYNCED_RECORD => {
+                Record::Synced(u64::from_le_bytes(body.get(1..9)?.try_into().unwrap()))
+            }
+            _ => return None,
+        };
+        Some((record, RECORD_HEADER_SIZE + length))
+    }
+}
+
+#[derive(Debug)]
+struct Segment {
+    sequence: u64,
+    file: Box<dyn StorageFile>,
+    /// Records not yet handed to the file.
+    buffer: Vec<u8>,
+    size: u64,
+}
+
+impl Segment {
+    /// Makes the buffered records durable.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.file.append(&self.buffer)?;
+            self.buffer.clear();
+        }
+        self.file.sync()
+    }
+
+    /// Flushes the buffered records and then marks where the durable part of
+    /// the segment ends. The marker itself becomes durable with the next sync.
+    fn sync(&mut self) -> io::Result<()> {
+        let has_new_records = !self.buffer.is_empty();
+        self.flush()?;
+        if has_new_records {
+            let data = Record::Synced(self.size).encode();
+            self.file.append(&data)?;
+            self.size += data.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+/// A write-ahead log split across numbered segment files in one directory.
+/// Records are buffered until `sync`, which flushes and fsyncs the segment
+/// being written and marks how far it is durable. On `load`, a bad record in
+/// the last segment past its last such marker was torn by a crash and is cut
+/// off; a bad record anywhere else is reported as corruption.
+///
+/// Snapshots are kept in their own files next to the segments. Saving one
+/// deletes older snapshots and every sealed segment it fully covers.
+#[derive(Debug)]
+pub struct WalStorage {
+    storage: Arc<dyn Storage>,
+    dir: PathBuf,
+    segment_size: u64,
+    hard_state: HardState,
+    segment: Option<Segment>,
+    /// The highest entry index written to each segment, by sequence number.
+    segment_last_indexes: BTreeMap<u64, u64>,
+}
+
+impl WalStorage {
+    pub fn open(storage: Arc<dyn Storage>, dir: impl AsRef<Path>) -> io::Result<Self> {
+        Self::with_segment_size(storage, dir, DEFAULT_SEGMENT_SIZE)
+    }
+
+    /// Opens a log that starts a new segment once the current one reaches
+    /// `segment_size` bytes.
+    pub fn with_segment_size(
+        storage: Arc<dyn Storage>,
+        dir: impl AsRef<Path>,
+        segment_size: u64,
+    ) -> io::Result<Self> {
+        storage.create_dir_all(dir.as_ref())?;
+        Ok(WalStorage {
+            storage,
+            dir: dir.as_ref().to_path_buf(),
+            segment_size,
+            hard_state: HardState::default(),
+            segment: None,
+            segment_last_indexes: BTreeMap::new(),
+        })
+    }
+
+    fn segment_path(&self, sequence: u64) -> PathBuf {
+        self.dir
+            .join(format!("{:020}.{}", sequence, SEGMENT_EXTENSION))
+    }
+
+    fn snapshot_path(&self, index: u64) -> PathBuf {
+        self.dir
+            .join(format!("{:020}.{}", index, SNAPSHOT_EXTENSION))
+    }
+
+    fn temp_snapshot_path(&self, index: u64) -> PathBuf {
+        self.dir
+            .join(format!("{:020}.{}", index, TEMP_SNAPSHOT_EXTENSION))
+    }
+
+    /// Returns the sequence numbers of the segments on disk, oldest first.
+    fn segment_sequences(&self) -> io::Result<Vec<u64>> {
+        self.numbered_files(SEGMENT_EXTENSION)
+    }
+
+    /// Returns the numbers naming the files with `extension`, lowest first.
+    fn numbered_files(&self, extension: &str) -> io::Result<Vec<u64>> {
+        let mut sequences = Vec::new();
+        for path in self.storage.read_dir(&self.dir)? {
+            if path
+                .extension()
+                .is_some_and(|path_extension| path_extension == extension)
+            {
+                if let Some(sequence) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse().ok())
+                {
+                    sequences.push(sequence);
+                }
+            }
+        }
+        sequences.sort_unstable();
+        Ok(sequences)
+    }
+
+    fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        if self
+            .segment
+            .as_ref()
+            .is_none_or(|segment| segment.size >= self.segment_size)
+        {
+            self.roll()?;
+        }
+        let segment = self.segment.as_mut().unwrap();
+        let data = record.encode();
+        segment.buffer.extend_from_slice(&data);
+        segment.size += data.len() as u64;
+        if let Record::Entry(entry) = record {
+            let last_index = self
+                .segment_last_indexes
+                .entry(segment.sequence)
+                .or_default();
+            *last_index = (*last_index).max(entry.index);
+        }
+        Ok(())
+    }
+
+    /// Seals the current segment and starts the next one.
+    fn roll(&mut self) -> io::Result<()> {
+        let sequence = match self.segment.take() {
+            Some(mut segment) => {
+                segment.flush()?;
+                segment.sequence + 1
+            }
+            None => self.segment_sequences()?.last().map_or(1, |last| last + 1),
+        };
+        let file = self.storage.create(&self.segment_path(sequence))?;
+        // Make the new file's directory entry durable along with its contents.
+        self.storage.sync_dir(&self.dir)?;
+
+        // Each segment restates the hard state, so recovery never depends on a
+        // record in an earlier segment for it.
+        let data = Record::HardState(self.hard_state.clone()).encode();
+        self.segment_last_indexes.insert(sequence, 0);
+        self.segment = Some(Segment {
+            sequence,
+            file,
+            size: data.len() as u64,
+            buffer: data,
+        });
+        Ok(())
+    }
+}
+
+impl RaftStorage for WalStorage {
+    fn load(&mut self) -> io::Result<(HardState, Vec<LogEntry>)> {
+        self.segment = None;
+        self.segment_last_indexes.clear();
+        let mut hard_state = HardState::default();
+        let mut entries: Vec<LogEntry> = Vec::new();
+
+        let sequences = self.segment_sequences()?;
+        for (i, sequence) in sequences.iter().enumerate() {
+            let path = self.segment_path(*sequence);
+            let data = self.storage.read(&path)?;
+            let is_last = i == sequences.len() - 1;
+            self.segment_last_indexes.insert(*sequence, 0);
+
+            let mut offset = 0;
+            while offset < data.len() {
+                let Some((record, size)) = Record::decode(&data[offset..]) else {
+                    if !is_last || is_synced_past(&data, offset) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("corrupt record in {:?} at offset {}", path, offset),
+                        ));
+                    }
+                    println!(
+                        "discarding torn write in {:?} after offset {}",
+                        path, offset
+                    );
+                    let mut file = self.storage.open_append(&path)?;
+                    file.truncate(offset as u64)?;
+                    file.sync()?;
+                    break;
+                };
+                match record {
+                    Record::HardState(record) => hard_state = record,
+                    Record::Entry(entry) => {
+                        // Segments before a snapshot may be gone, so the log
+                        // can start at any index.
+                        if let Some(last) = entries.last() {
+                            if entry.index != last.index + 1 {
+                                return Err(io::Error::new(
+                                    io::ErrorKind::InvalidData,
+                                    format!(
+                                        "entry {} in {:?} does not follow entry {}",
+                                        entry.index, path, last.index
+                                    ),
+                                ));
+                            }
+                        }
+                        let last_index = self.segment_last_indexes.entry(*sequence).or_default();
+                        *last_index = (*last_index).max(entry.index);
+                        entries.push(entry);
+                    }
+                    Record::Truncate(index) => entries.retain(|entry| entry.index < index),
+                    Record::Compact(index) => entries.retain(|entry| entry.index > index),
+                    Record::Synced(_) => {}
+                }
+                offset += size;
+            }
+
+            if is_last {
+                self.segment = Some(Segment {
+                    sequence: *sequence,
+                    file: self.storage.open_append(&path)?,
+                    buffer: Vec::new(),
+                    size: offset as u64,
+                });
+            }
+        }
+
+        self.hard_state = hard_state.clone();
+        Ok((hard_state, entries))
+    }
+
+    fn load_snapshot(&mut self) -> io::Result<Option<Snapshot>> {
+        // A snapshot torn by a crash was never reported saved, so fall back to
+        // the one before it, which is only deleted after its successor is
+        // durable.
+        for index in self.numbered_files(SNAPSHOT_EXTENSION)?.iter().rev() {
+            if let Some(snapshot) =
+                decode_snapshot(&self.storage.read(&self.snapshot_path(*index))?)
+            {
+                return Ok(Some(snapshot));
+            }
+        }
+        Ok(None)
+    }
+
+    fn save_snapshot(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        // The snapshot is written under a temporary name and renamed into
+        // place once durable, so that a crash leaves a snapshot already saved
+        // at the same index intact.
+        let index = snapshot.last_included_index;
+        let temp_path = self.temp_snapshot_path(index);
+        if let Err(error) = self.storage.remove_file(&temp_path) {
+            if error.kind() != io::ErrorKind::NotFound {
+                return Err(error);
+            }
+        }
+        let mut file = self.storage.create(&temp_path)?;
+        file.append(&encode_snapshot(snapshot))?;
+        file.sync()?;
+        self.storage
+            .rename(&temp_path, &self.snapshot_path(index))?;
+        self.storage.sync_dir(&self.dir)?;
+        self.write_record(&Record::Compact(index))?;
+
+        for older_index in self.numbered_files(SNAPSHOT_EXTENSION)? {
+            if older_index < index {
+                self.storage.remove_file(&self.snapshot_path(older_index))?;
+            }
+        }
+        // Left behind by saves a crash interrupted.
+        for temp_index in self.numbered_files(TEMP_SNAPSHOT_EXTENSION)? {
+            self.storage
+                .remove_file(&self.temp_snapshot_path(temp_index))?;
+        }
+        let current_sequence = self.segment.as_ref().map(|segment| segment.sequence);
+        for sequence in self.segment_sequences()? {
+            let is_covered = self
+                .segment_last_indexes
+                .get(&sequence)
+                .is_some_and(|last_index| *last_index <= index);
+            if Some(sequence) == current_sequence || !is_covered {
+                break;
+            }
+            self.storage.remove_file(&self.segment_path(sequence))?;
+            self.segment_last_indexes.remove(&sequence);
+        }
+        self.storage.sync_dir(&self.dir)
+    }
+
+    fn save_hard_state(&mut self, hard_state: &HardState) -> io::Result<()> {
+        self.hard_state = hard_state.clone();
+        self.write_record(&Record::HardState(hard_state.clone()))
+    }
+
+    fn append(&mut self, entries: &[LogEntry]) -> io::Result<()> {
+        for entry in entries {
+            self.write_record(&Record::Entry(entry.clone()))?;
+        }
+        Ok(())
+    }
+
+    fn truncate_from(&mut self, index: u64) -> io::Result<()> {
+        self.write_record(&Record::Truncate(index))
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        match self.segment.as_mut() {
+            Some(segment) => segment.sync(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Encodes `snapshot` as the CRC-32 of the rest of the file followed by the
+/// last included index and term, little-endian, and the data.
+fn encode_snapshot(snapshot: &Snapshot) -> Vec<u8> {
+    let membership = snapshot.membership.encode();
+    let mut body = Vec::with_capacity(20 + membership.len() + snapshot.data.len());
+    body.extend_from_slice(&snapshot.last_included_index.to_le_bytes());
+    body.extend_from_slice(&snapshot.last_included_term.to_le_bytes());
+    body.extend_from_slice(&(membership.len() as u32).to_le_bytes());
+    body.extend_from_slice(&membership);
+    body.extend_from_slice(&snapshot.data);
+
+    let mut data = crc32fast::hash(&body).to_le_bytes().to_vec();
+    data.extend_from_slice(&body);
+    data
+}
+
+fn decode_snapshot(data: &[u8]) -> Option<Snapshot> {
+    let crc = u32::from_le_bytes(data.get(0..4)?.try_into().unwrap());
+    let body = data.get(4..)?;
+    if body.len() < 20 || crc32fast::hash(body) != crc {
+        return None;
+    }
+    let membership_length = u32::from_le_bytes(body[16..20].try_into().unwrap()) as usize;
+    let membership = Membership::decode(body.get(20..20 + membership_length)?)?;
+    Some(Snapshot {
+        last_included_index: u64::from_le_bytes(body[0..8].try_into().unwrap()),
+        last_included_term: u64::from_le_bytes(body[8..16].try_into().unwrap()),
+        membership,
+        data: body[20 + membership_length..].to_vec(),
+    })
+}
+
+/// Whether a `Synced` record after `offset` in the segment `data` shows that
+/// the record at `offset` was durable, so damage to it is not a torn write.
+/// Only records that name their own offset count, so a command that happens
+/// to hold an encoded record is not mistaken for one.
+fn is_synced_past(data: &[u8], offset: usize) -> bool {
+    let synced_record_size = RECORD_HEADER_SIZE + SYNCED_RECORD_BODY_SIZE;
+    let last_start = data.len().saturating_sub(synced_record_size);
+    (offset + 1..=last_start).rev().any(|start| {
+        let record = &data[start..start + synced_record_size];
+        record[0..4] == (SYNCED_RECORD_BODY_SIZE as u32).to_le_bytes()
+            && matches!(
+                Record::decode(record),
+                Some((Record::Synced(synced_offset), _)) if synced_offset == start as u64
+            )
+    })
+}
@@ -0,0 +1,233 @@
+#[cfg(test)]
+mod tests {
+    use crate::platform::connection_manager::{
+        backoff, ConnectionConfig, ConnectionState, ConnectionStateChange, PeerConnectionManager,
+    };
+    use crate::platform::network::{Endpoint, NetworkInterface};
+    use crate::platform::network_interface::SystemNetworkInterface;
+    use crate::platform::rpc::serve_connection;
+    use crate::platform_testing::network::{SimulatedNetwork, SimulatedNetworkConfig};
+    use crate::platform_testing::network_interface::SimulatedNetworkInterface;
+    use std::io;
+    use std::sync::mpsc::Receiver;
+    use std::sync::Arc;
+    use std::thread::spawn;
+    use std::time::{Duration, Instant};
+
+    const CLIENT_IP: &str = "192.168.1.1";
+    const SERVER_IP: &str = "192.168.1.2";
+    const SERVER_PORT: u16 = 7000;
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    fn server_endpoint() -> Endpoint {
+        Endpoint {
+            ip: SERVER_IP.to_string(),
+            port: SERVER_PORT,
+        }
+    }
+
+    /// A reliable simulated network with a client interface and, when
+    /// `serve` is set, a server echoing every call on `SERVER_PORT`.
+    fn new_network(serve: bool) -> (Arc<SimulatedNetwork>, Arc<SimulatedNetworkInterface>) {
+        let network = Arc::new(SimulatedNetwork::new(SimulatedNetworkConfig {
+            drop_rate: 0.0,
+            short_delay_rate: 0.0,
+            long_delay_rate: 0.0,
+            short_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            long_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            duplicate_rate: 0.0,
+        }));
+        network.connect(CLIENT_IP, SERVER_IP);
+        let mut interfaces = Vec::new();
+        for ip in [CLIENT_IP, SERVER_IP] {
+            let network_interface = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
+            network_interface.assign_ip_addresses(vec![ip]);
+            network_interface.set_connect_timeout(Duration::from_millis(200));
+            network.register_network_interface(Arc::clone(&network_interface));
+            interfaces.push(network_interface);
+        }
+
+        if serve {
+            let listener = interfaces[1].bind_tcp(SERVER_IP, SERVER_PORT).unwrap();
+            spawn(move || {
+                while let Ok(stream) = listener.accept() {
+                    spawn(move || serve_connection(stream, Arc::new(|data| Some(data.to_vec()))));
+                }
+            });
+        }
+        (network, Arc::clone(&interfaces[0]))
+    }
+
+    fn config() -> ConnectionConfig {
+        ConnectionConfig {
+            initial_backoff: Duration::from_millis(20),
+            max_backoff: Duration::from_millis(200),
+            ..ConnectionConfig::default()
+        }
+    }
+
+    /// Waits for the next reported state change.
+    fn next_state(changes: &Receiver<ConnectionStateChange>) -> ConnectionState {
+        changes.recv_timeout(TIMEOUT).unwrap().state
+    }
+
+    #[test]
+    fn test_backoff() {
+        let config = ConnectionConfig {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            jitter: 0.0,
+            ..ConnectionConfig::default()
+        };
+        let backoffs: Vec<u64> = (1..=6)
+            .map(|failures| backoff(&config, failures, 0.5).as_millis() as u64)
+            .collect();
+        assert_eq!(backoffs, vec![100, 200, 400, 800, 1000, 1000]);
+
+        let config = ConnectionConfig {
+            jitter: 0.2,
+            ..config
+        };
+        let backoffs: Vec<Duration> = (0..100)
+            .map(|i| backoff(&config, 2, i as f64 / 100.0))
+            .collect();
+        assert!(backoffs
+            .iter()
+            .all(|backoff| (160..=240).contains(&backoff.as_millis())));
+        assert!(backoffs.iter().any(|backoff| *backoff != backoffs[0]));
+        assert_eq!(backoff(&config, 2, 0.0), Duration::from_millis(160));
+        assert_eq!(backoff(&config, 2, 0.5), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_pools_connections() {
+        let (_network, network_interface) = new_network(true);
+        let manager = PeerConnectionManager::new(
+            network_interface,
+            ConnectionConfig {
+                pool_size: 2,
+                ..config()
+            },
+        );
+        let changes = manager.subscribe();
+
+        let first = manager.get(&server_endpoint(), TIMEOUT).unwrap();
+        assert_eq!(next_state(&changes), ConnectionState::Connected);
+        assert_eq!(
+            manager.state(&server_endpoint()),
+            Some(ConnectionState::Connected)
+        );
+
+        // Calls take turns on the pooled connections once both are open.
+        let deadline = Instant::now() + TIMEOUT;
+        let second = loop {
+            let client = manager.get(&server_endpoint(), TIMEOUT).unwrap();
+            if !Arc::ptr_eq(&client, &first) {
+                break client;
+            }
+            assert!(Instant::now() < deadline, "the pool never grew");
+        };
+        let third = manager.get(&server_endpoint(), TIMEOUT).unwrap();
+        assert!(Arc::ptr_eq(&third, &first) || Arc::ptr_eq(&third, &second));
+        for client in [first, second] {
+            assert_eq!(client.call(b"ping", TIMEOUT).unwrap().as_ref(), b"ping");
+        }
+    }
+
+    #[test]
+    fn test_reconnects_after_partition() {
+        let (network, network_interface) = new_network(true);
+        let manager = PeerConnectionManager::new(network_interface, config());
+        let changes = manager.subscribe();
+        let client = manager.get(&server_endpoint(), TIMEOUT).unwrap();
+        assert_eq!(next_state(&changes), ConnectionState::Connected);
+
+        // The simulated stream does not notice the partition, so the caller
+        // reports it once a call goes unanswered.
+        network.disconnect(CLIENT_IP, SERVER_IP);
+        let started = Instant::now();
+        let error = client
+            .call(b"lost", Duration::from_millis(100))
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(!client.has_received_since(started));
+        manager.report_broken(&server_endpoint(), &client);
+        assert_eq!(next_state(&changes), ConnectionState::Disconnected);
+
+        let Err(error) = manager.get(&server_endpoint(), Duration::from_millis(300)) else {
+            panic!("connected across a partition");
+        };
+        assert_eq!(error.kind(), io::ErrorKind::NotConnected);
+
+        network.connect(CLIENT_IP, SERVER_IP);
+        let reconnected = manager.get(&server_endpoint(), TIMEOUT).unwrap();
+        assert!(!Arc::ptr_eq(&reconnected, &client));
+        assert_eq!(
+            reconnected.call(b"back", TIMEOUT).unwrap().as_ref(),
+            b"back"
+        );
+        let states: Vec<ConnectionState> = changes.try_iter().map(|change| change.state).collect();
+        assert_eq!(states.last(), Some(&ConnectionState::Connected));
+    }
+
+    #[test]
+    fn test_backs_off_while_unreachable() {
+        let (_network, network_interface) = new_network(false);
+        let manager = PeerConnectionManager::new(
+            network_interface,
+            ConnectionConfig {
+                jitter: 0.0,
+                ..config()
+            },
+        );
+        let changes = manager.subscribe();
+        assert!(manager
+            .get(&server_endpoint(), Duration::from_millis(700))
+            .is_err());
+
+        // Each refused attempt reports connecting and then disconnected, with
+        // the attempts 20, 40, 80, 160 and 200ms apart.
+        let attempts = changes
+            .try_iter()
+            .filter(|change| change.state == ConnectionState::Connecting)
+            .count();
+        assert!((3..=7).contains(&attempts), "{} attempts", attempts);
+    }
+
+    #[test]
+    fn test_replaces_failed_streams() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let network_interface = Arc::new(SystemNetworkInterface::new());
+        let listener = network_interface.bind_tcp("127.0.0.1", port).unwrap();
+        // Hangs up on the first connection as soon as a call arrives, then
+        // serves the next one.
+        spawn(move || {
+            let first = listener.accept().unwrap();
+            first.receive();
+            drop(first);
+            let second = listener.accept().unwrap();
+            serve_connection(second, Arc::new(|data| Some(data.to_vec())));
+        });
+
+        let manager = PeerConnectionManager::new(network_interface, config());
+        let changes = manager.subscribe();
+        let endpoint = Endpoint {
+            ip: "127.0.0.1".to_string(),
+            port,
+        };
+        let first = manager.get(&endpoint, TIMEOUT).unwrap();
+        assert_eq!(next_state(&changes), ConnectionState::Connected);
+        assert!(first.call(b"hang up", TIMEOUT).is_err());
+
+        // The manager notices the stream failed without being told.
+        assert_eq!(next_state(&changes), ConnectionState::Disconnected);
+        assert_eq!(next_state(&changes), ConnectionState::Connecting);
+        assert_eq!(next_state(&changes), ConnectionState::Connected);
+        let second = manager.get(&endpoint, TIMEOUT).unwrap();
+        assert!(!Arc::ptr_eq(&first, &second));
+    }
+}
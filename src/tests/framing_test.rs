@@ -0,0 +1,187 @@
+#[cfg(test)]
+mod tests {
+    use crate::platform::framing::{
+        encode_frame, encode_message_frame, FrameDecoder, FramedStream, DEFAULT_MAX_FRAME_SIZE,
+        FRAME_HEADER_SIZE,
+    };
+    use crate::platform::network::NetworkInterface;
+    use crate::platform::network_interface::SystemNetworkInterface;
+    use crate::platform_testing::network::{SimulatedNetwork, SimulatedNetworkConfig};
+    use crate::platform_testing::network_interface::SimulatedNetworkInterface;
+    use crate::raft::proto::{AppendEntriesRequest, LogEntry};
+    use prost::Message;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::io;
+    use std::sync::Arc;
+    use std::thread::spawn;
+    use std::time::Duration;
+
+    const CLIENT_IP: &str = "192.168.1.1";
+    const SERVER_IP: &str = "192.168.1.2";
+    const SERVER_PORT: u16 = 7000;
+
+    fn payloads() -> Vec<Vec<u8>> {
+        vec![
+            b"first".to_vec(),
+            Vec::new(),
+            (0..100_000u32).map(|i| i as u8).collect(),
+            b"last".to_vec(),
+        ]
+    }
+
+    fn append_entries_request(count: u64) -> AppendEntriesRequest {
+        AppendEntriesRequest {
+            term: 3,
+            entries: (1..=count)
+                .map(|index| LogEntry {
+                    index,
+                    term: 3,
+                    payload: format!("entry {}", index).repeat(50).into_bytes(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_frame_decoder_reassembles_any_chunking() {
+        let payloads = payloads();
+        let data: Vec<u8> = payloads
+            .iter()
+            .flat_map(|payload| encode_frame(payload, DEFAULT_MAX_FRAME_SIZE).unwrap())
+            .collect();
+
+        let mut rng = StdRng::seed_from_u64(7);
+        for max_chunk in [1, 7, 1000, data.len()] {
+            let mut decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+            let mut decoded = Vec::new();
+            let mut remaining = &data[..];
+            while !remaining.is_empty() {
+                let size = rng.gen_range(1..=max_chunk.min(remaining.len()));
+                decoder.extend(&remaining[..size]);
+                remaining = &remaining[size..];
+                while let Some(payload) = decoder.decode().unwrap() {
+                    decoded.push(payload.to_vec());
+                }
+            }
+            assert_eq!(decoded, payloads);
+            assert_eq!(decoder.buffered(), 0);
+        }
+    }
+
+    #[test]
+    fn test_frame_decoder_detects_corruption() {
+        let mut data = encode_frame(b"payload", DEFAULT_MAX_FRAME_SIZE).unwrap();
+        data[FRAME_HEADER_SIZE + 2] ^= 1;
+        let mut decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+        decoder.extend(&data);
+        assert_eq!(
+            decoder.decode().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_frame_size_limit() {
+        assert_eq!(
+            encode_frame(&[0; 101], 100).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert!(encode_frame(&[0; 100], 100).is_ok());
+
+        // An oversized frame is turned away from its header, before the body
+        // has to be buffered.
+        let data = encode_frame(&[0; 1000], DEFAULT_MAX_FRAME_SIZE).unwrap();
+        let mut decoder = FrameDecoder::new(100);
+        decoder.extend(&data[..FRAME_HEADER_SIZE]);
+        assert_eq!(
+            decoder.decode().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_message_frames_match_payload_frames() {
+        let request = append_entries_request(10);
+        let frame = encode_message_frame(&request, DEFAULT_MAX_FRAME_SIZE).unwrap();
+        assert_eq!(
+            frame,
+            encode_frame(&request.encode_to_vec(), DEFAULT_MAX_FRAME_SIZE).unwrap()
+        );
+    }
+
+    /// Sends frames and messages one way and checks they arrive whole and in
+    /// order.
+    fn check_framed_streams(client: FramedStream, server: FramedStream) {
+        let sender = spawn(move || {
+            for payload in payloads() {
+                client.send_frame(&payload).unwrap();
+            }
+            for count in [0, 1, 1000] {
+                client.send_message(&append_entries_request(count)).unwrap();
+            }
+            client
+        });
+
+        for payload in payloads() {
+            assert_eq!(server.receive_frame().unwrap().to_vec(), payload);
+        }
+        for count in [0, 1, 1000] {
+            let request: AppendEntriesRequest = server.receive_message().unwrap();
+            assert_eq!(request, append_entries_request(count));
+        }
+        let _client = sender.join().unwrap();
+        assert_eq!(
+            server
+                .receive_frame_timeout(Duration::from_millis(50))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::TimedOut
+        );
+    }
+
+    #[test]
+    fn test_framed_stream_over_simulated_network() {
+        let network = Arc::new(SimulatedNetwork::new(SimulatedNetworkConfig {
+            drop_rate: 0.0,
+            short_delay_rate: 0.0,
+            long_delay_rate: 0.0,
+            short_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            long_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            duplicate_rate: 0.0,
+        }));
+        network.connect(CLIENT_IP, SERVER_IP);
+        let mut interfaces = Vec::new();
+        for ip in [CLIENT_IP, SERVER_IP] {
+            let network_interface = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
+            network_interface.assign_ip_addresses(vec![ip]);
+            network.register_network_interface(Arc::clone(&network_interface));
+            interfaces.push(network_interface);
+        }
+
+        let listener = interfaces[1].bind_tcp(SERVER_IP, SERVER_PORT).unwrap();
+        let accept = spawn(move || listener.accept().unwrap());
+        let client = interfaces[0].connect(SERVER_IP, SERVER_PORT).unwrap();
+        let server = accept.join().unwrap();
+        check_framed_streams(FramedStream::new(client), FramedStream::new(server));
+    }
+
+    #[test]
+    fn test_framed_stream_over_system_network() {
+        // Listeners don't report the port they were given, so borrow a free
+        // one from the operating system first.
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let network_interface = SystemNetworkInterface::new();
+        let listener = network_interface.bind_tcp("127.0.0.1", port).unwrap();
+        let accept = spawn(move || listener.accept().unwrap());
+        let client = network_interface.connect("127.0.0.1", port).unwrap();
+        let server = accept.join().unwrap();
+        check_framed_streams(FramedStream::new(client), FramedStream::new(server));
+    }
+}
@@ -0,0 +1,366 @@
+#[cfg(test)]
+mod tests {
+    use crate::platform::grpc::{channel, incoming, AsyncTcpStream, TcpStreamInfo};
+    use crate::platform::network::{Endpoint, NetworkInterface, TcpListener};
+    use crate::platform::network_interface::SystemNetworkInterface;
+    use crate::platform_testing::network::{SimulatedNetwork, SimulatedNetworkConfig};
+    use crate::platform_testing::network_interface::SimulatedNetworkInterface;
+    use crate::raft::proto::raft_client::RaftClient;
+    use crate::raft::proto::raft_server::{Raft, RaftServer};
+    use crate::raft::proto::{
+        AppendEntriesRequest, AppendEntriesResponse, InstallSnapshotRequest,
+        InstallSnapshotResponse, LogEntry, RequestVoteRequest, RequestVoteResponse,
+        TimeoutNowRequest, TimeoutNowResponse,
+    };
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tonic::transport::Server;
+    use tonic::{Request, Response, Status};
+
+    const CLIENT_IP: &str = "192.168.1.1";
+    const SERVER_IP: &str = "192.168.1.2";
+    const SERVER_PORT: u16 = 7000;
+
+    /// Answers every RPC with what it was sent, so a client can check that the
+    /// request arrived intact. Votes are granted only to `client_ip`, to check
+    /// that the server sees where requests come from.
+    struct EchoRaft {
+        client_ip: &'static str,
+    }
+
+    #[tonic::async_trait]
+    impl Raft for EchoRaft {
+        async fn request_vote(
+            &self,
+            request: Request<RequestVoteRequest>,
+        ) -> Result<Response<RequestVoteResponse>, Status> {
+            let info = request.extensions().get::<TcpStreamInfo>().cloned();
+            let request = request.into_inner();
+            Ok(Response::new(RequestVoteResponse {
+                term: request.term,
+                vote_granted: info.is_some_and(|info| info.remote_endpoint.ip == self.client_ip),
+            }))
+        }
+
+        async fn pre_vote(
+            &self,
+            request: Request<RequestVoteRequest>,
+        ) -> Result<Response<RequestVoteResponse>, Status> {
+            Ok(Response::new(RequestVoteResponse {
+                term: request.into_inner().term,
+                vote_granted: false,
+            }))
+        }
+
+        async fn append_entries(
+            &self,
+            request: Request<AppendEntriesRequest>,
+        ) -> Result<Response<AppendEntriesResponse>, Status> {
+            let request = request.into_inner();
+            let intact = request
+                .entries
+                .iter()
+                .enumerate()
+                .all(|(i, entry)| entry.index == i as u64 + 1 && entry.payload == payload(i));
+            Ok(Response::new(AppendEntriesResponse {
+                term: request.term,
+                success: intact,
+                conflict_index: request.entries.len() as u64,
+                conflict_term: 0,
+            }))
+        }
+
+        async fn install_snapshot(
+            &self,
+            request: Request<InstallSnapshotRequest>,
+        ) -> Result<Response<InstallSnapshotResponse>, Status> {
+            Ok(Response::new(InstallSnapshotResponse {
+                term: request.into_inner().term,
+            }))
+        }
+
+        async fn timeout_now(
+            &self,
+            request: Request<TimeoutNowRequest>,
+        ) -> Result<Response<TimeoutNowResponse>, Status> {
+            Ok(Response::new(TimeoutNowResponse {
+                term: request.into_inner().term,
+            }))
+        }
+    }
+
+    fn payload(i: usize) -> Vec<u8> {
+        format!("entry {}", i).repeat(100).into_bytes()
+    }
+
+    fn new_network(config: SimulatedNetworkConfig) -> Arc<SimulatedNetwork> {
+        let network = Arc::new(SimulatedNetwork::new(config));
+        network.connect(CLIENT_IP, SERVER_IP);
+        network
+    }
+
+    fn reliable_network() -> SimulatedNetworkConfig {
+        SimulatedNetworkConfig {
+            drop_rate: 0.0,
+            short_delay_rate: 0.0,
+            long_delay_rate: 0.0,
+            short_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            long_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            duplicate_rate: 0.0,
+        }
+    }
+
+    /// Delays most packets by different amounts, so segments of a stream
+    /// arrive out of order.
+    fn reordering_network() -> SimulatedNetworkConfig {
+        SimulatedNetworkConfig {
+            drop_rate: 0.0,
+            short_delay_rate: 0.5,
+            long_delay_rate: 0.1,
+            short_delay_range: Duration::from_millis(1)..Duration::from_millis(20),
+            long_delay_range: Duration::from_millis(20)..Duration::from_millis(100),
+            duplicate_rate: 0.0,
+        }
+    }
+
+    fn new_network_interface(
+        network: &Arc<SimulatedNetwork>,
+        ip: &str,
+    ) -> Arc<SimulatedNetworkInterface> {
+        let network_interface = Arc::new(SimulatedNetworkInterface::new(Arc::clone(network)));
+        network_interface.assign_ip_addresses(vec![ip]);
+        network.register_network_interface(Arc::clone(&network_interface));
+        network_interface
+    }
+
+    /// Serves `EchoRaft` on the server's address and returns a client for it,
+    /// along with the listener to shut down once the test is done.
+    fn start(
+        network: &Arc<SimulatedNetwork>,
+    ) -> (RaftClient<tonic::transport::Channel>, Arc<dyn TcpListener>) {
+        let server_interface = new_network_interface(network, SERVER_IP);
+        let client_interface = new_network_interface(network, CLIENT_IP);
+        let listener = server_interface.bind_tcp(SERVER_IP, SERVER_PORT).unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(RaftServer::new(EchoRaft {
+                    client_ip: CLIENT_IP,
+                }))
+                .serve_with_incoming(incoming(Arc::clone(&listener))),
+        );
+        let endpoint = Endpoint {
+            ip: SERVER_IP.to_string(),
+            port: SERVER_PORT,
+        };
+        let client = RaftClient::new(channel(client_interface, &endpoint).unwrap());
+        (client, listener)
+    }
+
+    fn append_entries_request(count: usize) -> AppendEntriesRequest {
+        AppendEntriesRequest {
+            term: 7,
+            entries: (0..count)
+                .map(|i| LogEntry {
+                    index: i as u64 + 1,
+                    term: 7,
+                    payload: payload(i),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_grpc_over_simulated_network() {
+        let network = new_network(reliable_network());
+        let (mut client, listener) = start(&network);
+
+        let response = client
+            .request_vote(RequestVoteRequest {
+                term: 3,
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.term, 3);
+        assert!(response.vote_granted, "the server saw the wrong peer");
+
+        // Large enough to span many segments and HTTP/2 frames.
+        let response = client
+            .append_entries(append_entries_request(500))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.success);
+        assert_eq!(response.conflict_index, 500);
+
+        let response = client
+            .timeout_now(TimeoutNowRequest {
+                term: 9,
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.term, 9);
+        listener.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_grpc_over_system_network() {
+        // Listeners don't report the port they were given, so borrow a free
+        // one from the operating system first.
+        let port = std::net::TcpListener::bind(("127.0.0.1", 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let listener = SystemNetworkInterface::new()
+            .bind_tcp("127.0.0.1", port)
+            .unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(RaftServer::new(EchoRaft {
+                    client_ip: "127.0.0.1",
+                }))
+                .serve_with_incoming(incoming(Arc::clone(&listener))),
+        );
+        let endpoint = Endpoint {
+            ip: "127.0.0.1".to_string(),
+            port,
+        };
+        let mut client =
+            RaftClient::new(channel(Arc::new(SystemNetworkInterface::new()), &endpoint).unwrap());
+
+        let response = client
+            .request_vote(RequestVoteRequest {
+                term: 3,
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.term, 3);
+        assert!(response.vote_granted, "the server saw the wrong peer");
+
+        // The socket merges and splits these segments on the way.
+        for count in [1, 500] {
+            let response = client
+                .append_entries(append_entries_request(count))
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(response.success);
+            assert_eq!(response.conflict_index, count as u64);
+        }
+        listener.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_grpc_with_reordered_packets() {
+        let network = new_network(reordering_network());
+        let (mut client, listener) = start(&network);
+
+        for count in [1, 50, 200] {
+            let response = client
+                .append_entries(append_entries_request(count))
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(response.success);
+            assert_eq!(response.conflict_index, count as u64);
+        }
+        listener.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_grpc_reconnects_after_partition() {
+        let network = new_network(reliable_network());
+        let (mut client, listener) = start(&network);
+        let request = RequestVoteRequest {
+            term: 1,
+            ..Default::default()
+        };
+        client.request_vote(request.clone()).await.unwrap();
+
+        network.disconnect(CLIENT_IP, SERVER_IP);
+        let mut partitioned = Request::new(request.clone());
+        partitioned.set_timeout(Duration::from_millis(200));
+        assert!(client.request_vote(partitioned).await.is_err());
+
+        // The connection that lost data fails, and the channel opens a new one.
+        network.connect(CLIENT_IP, SERVER_IP);
+        let mut reconnected = false;
+        for _ in 0..20 {
+            let mut retry = Request::new(request.clone());
+            retry.set_timeout(Duration::from_millis(500));
+            if client.request_vote(retry).await.is_ok() {
+                reconnected = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(reconnected);
+        listener.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_async_tcp_stream_restores_order() {
+        let network = new_network(reordering_network());
+        let server_interface = new_network_interface(&network, SERVER_IP);
+        let client_interface = new_network_interface(&network, CLIENT_IP);
+        let listener = server_interface.bind_tcp(SERVER_IP, SERVER_PORT).unwrap();
+
+        let server = tokio::task::spawn_blocking(move || listener.accept().unwrap());
+        let mut client = AsyncTcpStream::new(
+            tokio::task::spawn_blocking(move || client_interface.connect(SERVER_IP, SERVER_PORT))
+                .await
+                .unwrap()
+                .unwrap(),
+        );
+        let mut server = AsyncTcpStream::new(server.await.unwrap());
+
+        let sent: Vec<u8> = (0..200u32).flat_map(|i| i.to_le_bytes()).collect();
+        for chunk in sent.chunks(10) {
+            client.write_all(chunk).await.unwrap();
+        }
+        client.shutdown().await.unwrap();
+
+        let mut received = Vec::new();
+        server.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, sent);
+    }
+
+    #[tokio::test]
+    async fn test_async_tcp_stream_detects_loss() {
+        let network = new_network(reliable_network());
+        let server_interface = new_network_interface(&network, SERVER_IP);
+        let client_interface = new_network_interface(&network, CLIENT_IP);
+        let listener = server_interface.bind_tcp(SERVER_IP, SERVER_PORT).unwrap();
+
+        let server = tokio::task::spawn_blocking(move || listener.accept().unwrap());
+        let mut client = AsyncTcpStream::new(
+            tokio::task::spawn_blocking(move || client_interface.connect(SERVER_IP, SERVER_PORT))
+                .await
+                .unwrap()
+                .unwrap(),
+        );
+        let mut server =
+            AsyncTcpStream::with_reorder_timeout(server.await.unwrap(), Duration::from_millis(100));
+
+        client.write_all(b"first").await.unwrap();
+        network.disconnect(CLIENT_IP, SERVER_IP);
+        client.write_all(b"lost").await.unwrap();
+        network.connect(CLIENT_IP, SERVER_IP);
+        client.write_all(b"after").await.unwrap();
+
+        let mut received = [0; 5];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"first");
+        let error = server.read(&mut received).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::ConnectionReset);
+    }
+}
@@ -0,0 +1,224 @@
+#[cfg(test)]
+mod tests {
+    use crate::platform_testing::linearizability::{
+        check, History, KvInput, KvModel, Operation, RegisterInput, RegisterModel,
+    };
+    use crate::platform_testing::nemesis::{run_kv_client, KvNodes};
+    use crate::platform_testing::raft_cluster::RaftCluster;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    /// An operation invoked and completed at the given milliseconds; without
+    /// a completion its outcome is unknown.
+    fn op<I>(
+        client: usize,
+        input: I,
+        output: &str,
+        invoked: u64,
+        completed: Option<u64>,
+    ) -> Operation<I, String> {
+        Operation {
+            client,
+            input,
+            output: completed.map(|_| output.to_string()),
+            invoked: Duration::from_millis(invoked),
+            completed: completed.map(Duration::from_millis),
+        }
+    }
+
+    fn write(value: &str) -> RegisterInput {
+        RegisterInput::Write(value.to_string())
+    }
+
+    #[test]
+    fn test_register_linearizable() {
+        let history = vec![
+            op(0, write("a"), "", 0, Some(10)),
+            op(1, RegisterInput::Read, "a", 5, Some(15)),
+            op(0, write("b"), "", 20, Some(30)),
+            // Overlaps the write, so it may come before it.
+            op(2, RegisterInput::Read, "a", 21, Some(35)),
+            op(1, RegisterInput::Read, "b", 25, Some(40)),
+            // Reads of concurrent writes may see them in either order.
+            op(0, write("c"), "", 50, Some(70)),
+            op(1, write("d"), "", 50, Some(70)),
+            op(2, RegisterInput::Read, "c", 60, Some(80)),
+            op(2, RegisterInput::Read, "d", 90, Some(100)),
+        ];
+        assert!(check(&RegisterModel, &history).is_ok());
+        assert!(check(&RegisterModel, &[]).is_ok());
+    }
+
+    #[test]
+    fn test_register_stale_read() {
+        let history = vec![
+            op(0, write("a"), "", 0, Some(10)),
+            op(0, write("b"), "", 20, Some(30)),
+            op(1, RegisterInput::Read, "a", 40, Some(50)),
+        ];
+        let counterexample = check(&RegisterModel, &history).unwrap_err();
+        let drawn = counterexample.to_string();
+        let lines: Vec<&str> = drawn.lines().collect();
+        assert!(lines[0].contains("the state is \"b\""), "{}", drawn);
+        assert!(
+            lines[1].trim_start().starts_with("1  client 0"),
+            "{}",
+            drawn
+        );
+        assert!(
+            lines[2].trim_start().starts_with("2  client 0"),
+            "{}",
+            drawn
+        );
+        assert!(
+            lines[3].trim_start().starts_with("!  client 1"),
+            "{}",
+            drawn
+        );
+        assert!(lines[3].contains("Read -> \"a\""), "{}", drawn);
+        assert!(lines[1].contains("|["), "{}", drawn);
+        assert!(lines[3].contains("-]|"), "{}", drawn);
+    }
+
+    #[test]
+    fn test_register_unknown_outcomes() {
+        // A write whose client gave up may have happened after all...
+        let history = vec![
+            op(0, write("a"), "", 0, Some(10)),
+            op(1, write("b"), "", 5, None),
+            op(2, RegisterInput::Read, "b", 20, Some(30)),
+        ];
+        assert!(check(&RegisterModel, &history).is_ok());
+
+        // ...or not at all...
+        let history = vec![
+            op(0, write("a"), "", 0, Some(10)),
+            op(1, write("b"), "", 5, None),
+            op(2, RegisterInput::Read, "a", 20, Some(30)),
+        ];
+        assert!(check(&RegisterModel, &history).is_ok());
+
+        // ...but only once, and only after it was invoked.
+        let history = vec![
+            op(0, write("a"), "", 0, Some(10)),
+            op(1, write("b"), "", 5, None),
+            op(2, RegisterInput::Read, "b", 20, Some(30)),
+            op(2, RegisterInput::Read, "a", 40, Some(50)),
+        ];
+        assert!(check(&RegisterModel, &history).is_err());
+        let history = vec![
+            op(2, RegisterInput::Read, "b", 0, Some(10)),
+            op(1, write("b"), "", 20, None),
+        ];
+        let drawn = check(&RegisterModel, &history).unwrap_err().to_string();
+        assert!(drawn.contains("->"), "{}", drawn);
+    }
+
+    #[test]
+    fn test_kv_partitions_by_key() {
+        let put = |key: &str, value: &str| KvInput::Put {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        let append = |key: &str, value: &str| KvInput::Append {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        let get = |key: &str| KvInput::Get {
+            key: key.to_string(),
+        };
+        let mut history = vec![
+            op(0, put("x", "1"), "", 0, Some(10)),
+            op(1, append("y", "a"), "", 0, Some(10)),
+            op(0, append("x", "2"), "", 15, Some(25)),
+            op(1, append("y", "b"), "", 15, Some(25)),
+            op(2, get("x"), "12", 30, Some(40)),
+            op(2, get("y"), "ab", 30, Some(40)),
+            op(2, get("z"), "", 30, Some(40)),
+        ];
+        assert!(check(&KvModel, &history).is_ok());
+
+        // Appends that completed one after the other cannot be read the other
+        // way round; only that key is reported.
+        history.push(op(3, get("y"), "ba", 50, Some(60)));
+        let drawn = check(&KvModel, &history).unwrap_err().to_string();
+        assert!(drawn.contains("\"ba\""), "{}", drawn);
+        assert!(!drawn.contains("key: \"x\""), "{}", drawn);
+    }
+
+    #[test]
+    fn test_history_records_operations() {
+        let history = History::new();
+        assert_eq!(
+            history.record(0, write("a"), |_| Some(String::new())),
+            Some(String::new())
+        );
+        let pending = history.invoke(1, RegisterInput::Read);
+        assert_eq!(history.record(2, write("b"), |_| None), None);
+        history.complete(pending, "a".to_string());
+
+        let operations = history.operations();
+        assert_eq!(operations.len(), 3);
+        assert_eq!(operations[1].output.as_deref(), Some("a"));
+        assert!(operations[0].completed.unwrap() <= operations[1].invoked);
+        assert!(operations[1].completed.unwrap() >= operations[2].invoked);
+        assert_eq!(operations[2].output, None);
+        assert_eq!(operations[2].completed, None);
+        assert!(check(&RegisterModel, &operations).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_raft_kv_is_linearizable() {
+        let size = 3;
+        let mut cluster = RaftCluster::new(size);
+        cluster.check_one_leader().await;
+
+        let history = Arc::new(History::new());
+        let nodes: KvNodes = Arc::new(Mutex::new(
+            cluster
+                .nodes
+                .iter()
+                .cloned()
+                .zip(cluster.applied.iter().cloned())
+                .collect(),
+        ));
+        let done = Arc::new(AtomicBool::new(false));
+        let clients: Vec<_> = (0..4)
+            .map(|client| {
+                tokio::spawn(run_kv_client(
+                    client,
+                    0,
+                    Arc::clone(&nodes),
+                    Arc::clone(&history),
+                    Arc::clone(&done),
+                ))
+            })
+            .collect();
+
+        // Cut off whichever node leads while the clients run, twice over.
+        for _ in 0..2 {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            let leader = cluster.check_one_leader().await;
+            cluster.disconnect(leader);
+            tokio::time::sleep(Duration::from_millis(1500)).await;
+            cluster.connect(leader);
+        }
+        tokio::time::sleep(Duration::from_millis(2000)).await;
+        done.store(true, Ordering::SeqCst);
+        for client in clients {
+            client.await.unwrap();
+        }
+
+        let operations = history.operations();
+        let completed = operations
+            .iter()
+            .filter(|operation| operation.completed.is_some())
+            .count();
+        assert!(completed >= 20, "only {} operations completed", completed);
+        if let Err(counterexample) = check(&KvModel, &operations) {
+            panic!("{}", counterexample);
+        }
+        cluster.stop();
+    }
+}
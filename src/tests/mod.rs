@@ -1 +1,13 @@
+mod connection_manager_test;
+mod framing_test;
+mod grpc_test;
+mod linearizability_test;
+mod nemesis_test;
 mod network_test;
+mod raft_cluster_test;
+mod raft_test;
+mod replay_test;
+mod rpc_test;
+mod storage_test;
+mod tls_test;
+mod transport_test;
@@ -0,0 +1,132 @@
+#[cfg(test)]
+mod tests {
+    use crate::platform_testing::nemesis::{
+        replay_seed, run_seeds, shrink, Fault, NemesisConfig, Schedule, Step,
+    };
+    use std::collections::BTreeSet;
+    use std::env;
+    use std::path::Path;
+    use std::time::Duration;
+
+    #[test]
+    fn test_schedule_generation() {
+        let config = NemesisConfig {
+            steps: 200,
+            ..NemesisConfig::default()
+        };
+        let schedule = Schedule::generate(7, &config);
+        assert_eq!(schedule, Schedule::generate(7, &config));
+        assert_ne!(schedule, Schedule::generate(8, &config));
+        assert_eq!(schedule.steps.len(), 200);
+
+        let mut crashed = BTreeSet::new();
+        for step in schedule.steps.iter() {
+            assert!(config.interval.contains(&step.wait), "{}", schedule);
+            match &step.fault {
+                Fault::Partition(groups) => {
+                    let mut nodes: Vec<usize> = groups.concat();
+                    nodes.sort();
+                    assert_eq!(nodes, (0..config.size).collect::<Vec<_>>());
+                    assert!(groups.iter().all(|group| !group.is_empty()));
+                }
+                Fault::Crash(node) => {
+                    assert!(crashed.insert(*node), "{}", schedule);
+                    assert!(crashed.len() <= (config.size - 1) / 2, "{}", schedule);
+                }
+                Fault::Restart(node) => assert!(crashed.remove(node), "{}", schedule),
+                Fault::ClockSkew { rate, .. } => assert!((0.7..=1.3).contains(rate)),
+                _ => {}
+            }
+        }
+        // Every kind of fault shows up in a schedule this long.
+        for kind in [
+            "partition",
+            "heal",
+            "crash",
+            "restart",
+            "spike",
+            "calm",
+            "skew",
+        ] {
+            assert!(schedule.to_string().contains(kind), "{}", schedule);
+        }
+    }
+
+    #[test]
+    fn test_shrink() {
+        let mut schedule = Schedule::generate(3, &NemesisConfig::default());
+        let step = |wait: u64, fault: Fault| Step {
+            wait: Duration::from_millis(wait),
+            fault,
+        };
+        schedule.steps.insert(2, step(300, Fault::Crash(1)));
+        schedule
+            .steps
+            .push(step(400, Fault::Partition(vec![vec![0, 1], vec![2, 3, 4]])));
+
+        // Fails only once node 1 has crashed and a partition comes at least
+        // 100ms after whatever came before it.
+        let mut runs = 0;
+        let shrunk = shrink(&schedule, |candidate| {
+            runs += 1;
+            let crash = candidate
+                .steps
+                .iter()
+                .position(|step| step.fault == Fault::Crash(1));
+            crash.is_some_and(|crash| {
+                candidate.steps[crash + 1..].iter().any(|step| {
+                    matches!(step.fault, Fault::Partition(_))
+                        && step.wait >= Duration::from_millis(100)
+                })
+            })
+        });
+
+        assert_eq!(shrunk.seed, 3);
+        assert_eq!(shrunk.steps.len(), 2, "{}", shrunk);
+        assert_eq!(shrunk.steps[0].fault, Fault::Crash(1));
+        assert!(matches!(shrunk.steps[1].fault, Fault::Partition(_)));
+        assert!(
+            shrunk.steps[0].wait < Duration::from_millis(20),
+            "{}",
+            shrunk
+        );
+        assert!(
+            shrunk.steps[1].wait < Duration::from_millis(200),
+            "{}",
+            shrunk
+        );
+        assert!(
+            shrunk.steps[1].wait >= Duration::from_millis(100),
+            "{}",
+            shrunk
+        );
+        assert!(runs < 100, "took {} runs", runs);
+    }
+
+    /// Runs the schedules of `NEMESIS_SEEDS` seeds from `NEMESIS_SEED_START`
+    /// against a live cluster. A few by default; the nightly workflow in
+    /// `.github/workflows/nemesis.yml` runs thousands across shards. With
+    /// `NEMESIS_REPLAY` set, replays the run of the first seed saved there
+    /// instead.
+    #[test]
+    fn test_nemesis_seeds() {
+        let variable = |name: &str, default: u64| {
+            env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+        let start = variable("NEMESIS_SEED_START", 0);
+        let count = variable("NEMESIS_SEEDS", 2);
+        if let Ok(recording) = env::var("NEMESIS_REPLAY") {
+            let result = replay_seed(start, &NemesisConfig::default(), Path::new(&recording));
+            if let Err(message) = result {
+                panic!("replay of seed {} failed: {}", start, message);
+            }
+            return;
+        }
+        if let Err(failure) = run_seeds(start..start + count, &NemesisConfig::default()) {
+            panic!("{}", failure);
+        }
+    }
+}
@@ -1,11 +1,18 @@
 #[cfg(test)]
 mod tests {
-    use crate::platform::network::NetworkInterface;
+    use crate::platform::network::{
+        Endpoint, Incoming, NetworkInterface, TcpListener, TcpStream, BROADCAST_IP,
+    };
+    use crate::platform::network_interface::SystemNetworkInterface;
     use crate::platform_testing::network::{SimulatedNetwork, SimulatedNetworkConfig};
-    use crate::platform_testing::network_interface::SimulatedNetworkInterface;
+    use crate::platform_testing::network_interface::{BacklogOverflow, SimulatedNetworkInterface};
+    use std::io;
+    use std::io::ErrorKind;
+    use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::Arc;
-    use std::thread::spawn;
+    use std::thread::{sleep, spawn};
     use std::time::Duration;
+    use tokio_stream::StreamExt;
 
     #[test]
     fn test_simulated_network_interface() {
@@ -15,6 +22,7 @@ mod tests {
             long_delay_rate: 0.0,
             short_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
             long_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            duplicate_rate: 0.0,
         }));
         let network_interface1 = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
         let network_interface2 = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
@@ -39,8 +47,8 @@ mod tests {
 
         spawn(move || {
             let tcp_stream = match tcp_listener1.accept() {
-                None => return,
-                Some(tcp_stream) => tcp_stream,
+                Err(_) => return,
+                Ok(tcp_stream) => tcp_stream,
             };
             println!(
                 "[Server] accepted incoming connection from {:?}",
@@ -68,18 +76,17 @@ mod tests {
                     tcp_stream.get_remote_endpoint(),
                     response
                 );
-                return;
             });
         });
 
         let server_ip = "192.168.1.2";
         println!("[Client] connecting to {:?}:{}", server_ip, server_port);
-        let mut client_stream1 = network_interface1.connect(server_ip, server_port);
-        assert!(!client_stream1.is_none());
+        let client_stream1 = network_interface1.connect(server_ip, server_port);
+        assert!(client_stream1.is_ok());
 
         let client_stream1 = match client_stream1 {
-            None => return,
-            Some(tcp_stream) => tcp_stream,
+            Err(_) => return,
+            Ok(tcp_stream) => tcp_stream,
         };
         println!(
             "[Client] connected to {:?}",
@@ -104,7 +111,7 @@ mod tests {
             client_stream1.get_remote_endpoint()
         );
         let data = client_stream1.receive();
-        assert!(!data.is_none());
+        assert!(data.is_some());
         let data = match data {
             None => return,
             Some(data) => data,
@@ -115,4 +122,587 @@ mod tests {
             std::str::from_utf8(data.as_ref()).unwrap()
         );
     }
+
+    #[test]
+    fn test_simulated_udp_socket() {
+        let network = Arc::new(SimulatedNetwork::new(SimulatedNetworkConfig {
+            drop_rate: 0.0,
+            short_delay_rate: 0.0,
+            long_delay_rate: 0.0,
+            short_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            long_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            duplicate_rate: 0.0,
+        }));
+        let network_interface1 = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
+        let network_interface2 = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
+        let network_interface3 = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
+
+        network_interface1.assign_ip_addresses(vec!["192.168.1.1"]);
+        network_interface2.assign_ip_addresses(vec!["192.168.1.2", "192.168.1.4"]);
+        network_interface3.assign_ip_addresses(vec!["192.168.1.3"]);
+
+        network.register_network_interface(Arc::clone(&network_interface1));
+        network.register_network_interface(Arc::clone(&network_interface2));
+        network.register_network_interface(Arc::clone(&network_interface3));
+
+        network.connect("192.168.1.1", "192.168.1.2");
+        network.connect("192.168.1.1", "192.168.1.4");
+        network.connect("192.168.1.1", "192.168.1.3");
+
+        let gossip_port = 7946;
+        let socket1 = network_interface1.bind_udp("", 0).unwrap();
+        let socket2 = network_interface2.bind_udp("", gossip_port).unwrap();
+        let socket3 = network_interface3.bind_udp("", gossip_port).unwrap();
+        assert!(network_interface2
+            .bind_udp("192.168.1.4", gossip_port)
+            .is_none());
+
+        socket1
+            .send_to("ping".as_bytes(), "192.168.1.2", gossip_port)
+            .unwrap();
+        let (data, source) = socket2.recv_from().unwrap();
+        assert_eq!(data.as_ref(), "ping".as_bytes());
+        assert_eq!(source.ip, "192.168.1.1");
+        assert_eq!(source.port, socket1.get_local_endpoint().port);
+
+        socket2
+            .send_to("ack".as_bytes(), &source.ip, source.port)
+            .unwrap();
+        let (data, _) = socket1.recv_from().unwrap();
+        assert_eq!(data.as_ref(), "ack".as_bytes());
+
+        assert!(socket1
+            .send_to("alive".as_bytes(), BROADCAST_IP, gossip_port)
+            .is_err());
+        socket1.set_broadcast(true).unwrap();
+        socket1
+            .send_to("alive".as_bytes(), BROADCAST_IP, gossip_port)
+            .unwrap();
+        assert_eq!(socket2.recv_from().unwrap().0.as_ref(), "alive".as_bytes());
+        assert_eq!(socket3.recv_from().unwrap().0.as_ref(), "alive".as_bytes());
+
+        let group_ip = "239.1.1.1";
+        assert!(socket3.join_multicast("192.168.1.2").is_err());
+        socket3.join_multicast(group_ip).unwrap();
+        socket1
+            .send_to("suspect".as_bytes(), group_ip, gossip_port)
+            .unwrap();
+        socket1
+            .send_to("direct".as_bytes(), "192.168.1.2", gossip_port)
+            .unwrap();
+        assert_eq!(
+            socket3.recv_from().unwrap().0.as_ref(),
+            "suspect".as_bytes()
+        );
+        assert_eq!(socket2.recv_from().unwrap().0.as_ref(), "direct".as_bytes());
+
+        socket3.leave_multicast(group_ip).unwrap();
+        assert!(socket3.leave_multicast(group_ip).is_err());
+        socket1
+            .send_to("suspect".as_bytes(), group_ip, gossip_port)
+            .unwrap();
+        socket1
+            .send_to("direct".as_bytes(), "192.168.1.3", gossip_port)
+            .unwrap();
+        assert_eq!(socket3.recv_from().unwrap().0.as_ref(), "direct".as_bytes());
+
+        // Dropping a socket releases its port.
+        drop(socket2);
+        let socket2 = network_interface2
+            .bind_udp("192.168.1.4", gossip_port)
+            .unwrap();
+        socket1
+            .send_to("ping".as_bytes(), "192.168.1.4", gossip_port)
+            .unwrap();
+        assert_eq!(socket2.recv_from().unwrap().0.as_ref(), "ping".as_bytes());
+        drop(socket2);
+        assert!(network_interface2
+            .get_udp_sockets(&Endpoint {
+                ip: "192.168.1.4".to_string(),
+                port: gossip_port,
+            })
+            .is_empty());
+    }
+
+    #[test]
+    fn test_simulated_network_is_deterministic() {
+        let ephemeral_ports = |seed| {
+            let network = Arc::new(SimulatedNetwork::with_seed(
+                SimulatedNetworkConfig {
+                    drop_rate: 0.0,
+                    short_delay_rate: 0.0,
+                    long_delay_rate: 0.0,
+                    short_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+                    long_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+                    duplicate_rate: 0.0,
+                },
+                seed,
+            ));
+            let network_interface = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
+            network_interface.assign_ip_addresses(vec!["192.168.1.1"]);
+            network.register_network_interface(Arc::clone(&network_interface));
+            (0..10)
+                .map(|_| {
+                    network_interface
+                        .bind_udp("", 0)
+                        .unwrap()
+                        .get_local_endpoint()
+                        .port
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ephemeral_ports(7), ephemeral_ports(7));
+        assert_ne!(ephemeral_ports(7), ephemeral_ports(8));
+    }
+
+    #[test]
+    fn test_system_udp_socket() {
+        let network_interface = SystemNetworkInterface::new();
+        let socket1 = network_interface.bind_udp("127.0.0.1", 0).unwrap();
+        let socket2 = network_interface.bind_udp("127.0.0.1", 0).unwrap();
+
+        let endpoint2 = socket2.get_local_endpoint();
+        socket1
+            .send_to("ping".as_bytes(), &endpoint2.ip, endpoint2.port)
+            .unwrap();
+        let (data, source) = socket2.recv_from().unwrap();
+        assert_eq!(data.as_ref(), "ping".as_bytes());
+        assert_eq!(source.port, socket1.get_local_endpoint().port);
+    }
+
+    #[test]
+    fn test_system_receive_timeout() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let network_interface = SystemNetworkInterface::new();
+        let listener = network_interface.bind_tcp("127.0.0.1", port).unwrap();
+        let client = network_interface.connect("127.0.0.1", port).unwrap();
+        let server = listener.accept().unwrap();
+
+        let error = server.receive_timeout(Duration::ZERO).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        let error = server
+            .receive_timeout(Duration::from_millis(10))
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+
+        // A plain receive afterwards waits for data instead of timing out.
+        let receiver = spawn(move || server.receive());
+        sleep(Duration::from_millis(50));
+        client.send("ping".as_bytes());
+        assert_eq!(
+            receiver.join().unwrap().unwrap().as_ref(),
+            "ping".as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_simulated_name_resolution() {
+        let network = Arc::new(SimulatedNetwork::new(SimulatedNetworkConfig {
+            drop_rate: 0.0,
+            short_delay_rate: 0.0,
+            long_delay_rate: 0.0,
+            short_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            long_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            duplicate_rate: 0.0,
+        }));
+        let network_interface1 = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
+        let network_interface2 = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
+
+        network_interface1.assign_ip_addresses(vec!["192.168.1.1"]);
+        network_interface2.assign_ip_addresses(vec!["192.168.1.2"]);
+
+        network.register_network_interface(Arc::clone(&network_interface1));
+        network.register_network_interface(Arc::clone(&network_interface2));
+
+        network.connect("192.168.1.1", "192.168.1.2");
+
+        let server_port = 8080;
+        let tcp_listener = network_interface2.bind_tcp("", server_port).unwrap();
+        spawn(move || {
+            let _tcp_stream = tcp_listener.accept();
+        });
+
+        assert_eq!(
+            network_interface1
+                .resolve("raft-1.cluster.local")
+                .unwrap_err()
+                .kind(),
+            ErrorKind::NotFound
+        );
+
+        let ttl = Duration::from_millis(100);
+        network.register_host("raft-1.cluster.local", vec!["192.168.1.2"], ttl);
+        let client_stream = network_interface1
+            .connect_by_name("Raft-1.Cluster.Local", server_port)
+            .unwrap();
+        assert_eq!(client_stream.get_remote_endpoint().ip, "192.168.1.2");
+
+        network.register_host("raft-1.cluster.local", vec!["192.168.1.3"], ttl);
+        network.inject_resolution_failure("raft-1.cluster.local", ErrorKind::TimedOut);
+        assert_eq!(
+            network_interface1.resolve("raft-1.cluster.local").unwrap(),
+            vec!["192.168.1.2"]
+        );
+
+        sleep(ttl);
+        assert_eq!(
+            network_interface1
+                .resolve("raft-1.cluster.local")
+                .unwrap_err()
+                .kind(),
+            ErrorKind::TimedOut
+        );
+
+        network.clear_resolution_failure("raft-1.cluster.local");
+        assert_eq!(
+            network_interface1.resolve("raft-1.cluster.local").unwrap(),
+            vec!["192.168.1.3"]
+        );
+        assert_eq!(
+            network_interface1.resolve("192.168.1.2").unwrap(),
+            vec!["192.168.1.2"]
+        );
+
+        network.unregister_host("raft-1.cluster.local");
+        sleep(ttl);
+        assert_eq!(
+            network_interface1
+                .resolve("raft-1.cluster.local")
+                .unwrap_err()
+                .kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_simulated_listener_backlog() {
+        let network = Arc::new(SimulatedNetwork::new(SimulatedNetworkConfig {
+            drop_rate: 0.0,
+            short_delay_rate: 0.0,
+            long_delay_rate: 0.0,
+            short_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            long_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            duplicate_rate: 0.0,
+        }));
+        let network_interface1 = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
+        let network_interface2 = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
+
+        network_interface1.assign_ip_addresses(vec!["192.168.1.1"]);
+        network_interface2.assign_ip_addresses(vec!["192.168.1.2"]);
+
+        network.register_network_interface(Arc::clone(&network_interface1));
+        network.register_network_interface(Arc::clone(&network_interface2));
+
+        network.connect("192.168.1.1", "192.168.1.2");
+
+        let server_ip = "192.168.1.2";
+        let server_port = 8080;
+        assert_eq!(
+            network_interface1
+                .connect(server_ip, server_port)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::ConnectionRefused
+        );
+
+        let tcp_listener = network_interface2
+            .bind_tcp_with_backlog("", server_port, 1)
+            .unwrap();
+        let client_stream1 = network_interface1.connect(server_ip, server_port).unwrap();
+
+        network_interface2.set_backlog_overflow(BacklogOverflow::Refuse);
+        assert_eq!(
+            network_interface1
+                .connect(server_ip, server_port)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::ConnectionRefused
+        );
+
+        network_interface1.set_connect_timeout(Duration::from_millis(50));
+        network_interface2.set_backlog_overflow(BacklogOverflow::DropSyn);
+        assert_eq!(
+            network_interface1
+                .connect(server_ip, server_port)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::TimedOut
+        );
+
+        client_stream1.send("queued".as_bytes());
+        let server_stream1 = tcp_listener.accept().unwrap();
+        assert_eq!(
+            server_stream1.receive().unwrap().as_ref(),
+            "queued".as_bytes()
+        );
+        let _client_stream2 = network_interface1.connect(server_ip, server_port).unwrap();
+
+        // A dropped SYN is retransmitted, so a client gets in once the queue
+        // has room again, well before its connect timeout.
+        network_interface1.set_connect_timeout(Duration::from_secs(3));
+        let connecting = Arc::clone(&network_interface1);
+        let pending = spawn(move || connecting.connect(server_ip, server_port));
+        sleep(Duration::from_millis(150));
+        let _server_stream2 = tcp_listener.accept().unwrap();
+        assert!(pending.join().unwrap().is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_simulated_listener_shutdown() {
+        let network = Arc::new(SimulatedNetwork::new(SimulatedNetworkConfig {
+            drop_rate: 0.0,
+            short_delay_rate: 0.0,
+            long_delay_rate: 0.0,
+            short_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            long_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            duplicate_rate: 0.0,
+        }));
+        let network_interface1 = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
+        let network_interface2 = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
+
+        network_interface1.assign_ip_addresses(vec!["192.168.1.1"]);
+        network_interface2.assign_ip_addresses(vec!["192.168.1.2"]);
+
+        network.register_network_interface(Arc::clone(&network_interface1));
+        network.register_network_interface(Arc::clone(&network_interface2));
+
+        network.connect("192.168.1.1", "192.168.1.2");
+
+        let server_ip = "192.168.1.2";
+        let server_port = 8080;
+        let tcp_listener = network_interface2.bind_tcp("", server_port).unwrap();
+        assert_eq!(
+            tcp_listener.try_accept().unwrap_err().kind(),
+            ErrorKind::WouldBlock
+        );
+
+        network_interface1.connect(server_ip, server_port).unwrap();
+        assert!(tcp_listener.try_accept().is_ok());
+
+        let mut incoming = Arc::clone(&tcp_listener).incoming();
+        let client_network_interface = Arc::clone(&network_interface1);
+        tokio::task::spawn_blocking(move || {
+            client_network_interface
+                .connect(server_ip, server_port)
+                .unwrap()
+        });
+        let server_stream = incoming.next().await.unwrap().unwrap();
+        assert_eq!(server_stream.get_remote_endpoint().ip, "192.168.1.1");
+
+        let blocked_listener = Arc::clone(&tcp_listener);
+        let blocked_acceptor = tokio::task::spawn_blocking(move || blocked_listener.accept());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        tcp_listener.shutdown();
+        assert_eq!(
+            blocked_acceptor.await.unwrap().unwrap_err().kind(),
+            ErrorKind::ConnectionAborted
+        );
+        assert_eq!(
+            incoming.next().await.unwrap().unwrap_err().kind(),
+            ErrorKind::ConnectionAborted
+        );
+        assert!(incoming.next().await.is_none());
+        assert_eq!(
+            network_interface1
+                .connect(server_ip, server_port)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::ConnectionRefused
+        );
+    }
+
+    #[test]
+    fn test_simulated_listener_shutdown_resets_queue() {
+        let network = Arc::new(SimulatedNetwork::new(SimulatedNetworkConfig {
+            drop_rate: 0.0,
+            short_delay_rate: 0.0,
+            long_delay_rate: 0.0,
+            short_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            long_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            duplicate_rate: 0.0,
+        }));
+        let network_interface1 = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
+        let network_interface2 = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
+
+        network_interface1.assign_ip_addresses(vec!["192.168.1.1"]);
+        network_interface2.assign_ip_addresses(vec!["192.168.1.2"]);
+
+        network.register_network_interface(Arc::clone(&network_interface1));
+        network.register_network_interface(Arc::clone(&network_interface2));
+
+        network.connect("192.168.1.1", "192.168.1.2");
+
+        let server_ip = "192.168.1.2";
+        let server_port = 8080;
+        let tcp_listener = network_interface2.bind_tcp("", server_port).unwrap();
+        let queued_client = network_interface1.connect(server_ip, server_port).unwrap();
+        let waiting_client = Arc::clone(&queued_client);
+        let receiver = spawn(move || waiting_client.receive());
+        sleep(Duration::from_millis(10));
+
+        tcp_listener.shutdown();
+        assert!(receiver.join().unwrap().is_none());
+        assert_eq!(
+            queued_client
+                .receive_timeout(Duration::from_millis(10))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::ConnectionReset
+        );
+    }
+
+    /// Fails its first accept the way a connection that is reset before it is
+    /// accepted does.
+    struct AbortingListener {
+        listener: Arc<dyn TcpListener>,
+        has_aborted: AtomicBool,
+    }
+
+    impl TcpListener for AbortingListener {
+        fn accept_cancellable(&self, is_cancelled: &AtomicBool) -> io::Result<Arc<dyn TcpStream>> {
+            if !self.has_aborted.swap(true, Ordering::SeqCst) {
+                return Err(ErrorKind::ConnectionAborted.into());
+            }
+            self.listener.accept_cancellable(is_cancelled)
+        }
+
+        fn wake_acceptors(&self) {
+            self.listener.wake_acceptors();
+        }
+
+        fn try_accept(&self) -> io::Result<Arc<dyn TcpStream>> {
+            self.listener.try_accept()
+        }
+
+        fn shutdown(&self) {
+            self.listener.shutdown();
+        }
+
+        fn is_shutdown(&self) -> bool {
+            self.listener.is_shutdown()
+        }
+
+        fn incoming(self: Arc<Self>) -> Incoming {
+            Incoming::new(self)
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_incoming_outlives_accept_errors() {
+        let network = Arc::new(SimulatedNetwork::new(SimulatedNetworkConfig {
+            drop_rate: 0.0,
+            short_delay_rate: 0.0,
+            long_delay_rate: 0.0,
+            short_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            long_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            duplicate_rate: 0.0,
+        }));
+        let network_interface1 = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
+        let network_interface2 = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
+
+        network_interface1.assign_ip_addresses(vec!["192.168.1.1"]);
+        network_interface2.assign_ip_addresses(vec!["192.168.1.2"]);
+
+        network.register_network_interface(Arc::clone(&network_interface1));
+        network.register_network_interface(Arc::clone(&network_interface2));
+
+        network.connect("192.168.1.1", "192.168.1.2");
+
+        let server_ip = "192.168.1.2";
+        let server_port = 8080;
+        let tcp_listener = network_interface2.bind_tcp("", server_port).unwrap();
+        let mut incoming = Arc::new(AbortingListener {
+            listener: Arc::clone(&tcp_listener),
+            has_aborted: AtomicBool::new(false),
+        })
+        .incoming();
+        assert_eq!(
+            incoming.next().await.unwrap().unwrap_err().kind(),
+            ErrorKind::ConnectionAborted
+        );
+
+        let client_network_interface = Arc::clone(&network_interface1);
+        tokio::task::spawn_blocking(move || {
+            client_network_interface
+                .connect(server_ip, server_port)
+                .unwrap()
+        });
+        let server_stream = incoming.next().await.unwrap().unwrap();
+        assert_eq!(server_stream.get_remote_endpoint().ip, "192.168.1.1");
+
+        // Dropping the stream mid-accept cancels only that accept, so the
+        // next connection goes to the listener's other users.
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), incoming.next())
+                .await
+                .is_err()
+        );
+        drop(incoming);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!tcp_listener.is_shutdown());
+        let client_network_interface = Arc::clone(&network_interface1);
+        tokio::task::spawn_blocking(move || {
+            client_network_interface
+                .connect(server_ip, server_port)
+                .unwrap()
+        });
+        let accepting_listener = Arc::clone(&tcp_listener);
+        let server_stream = tokio::task::spawn_blocking(move || accepting_listener.accept())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(server_stream.get_remote_endpoint().ip, "192.168.1.1");
+    }
+
+    #[test]
+    fn test_simulated_handshake() {
+        let network = Arc::new(SimulatedNetwork::new(SimulatedNetworkConfig {
+            drop_rate: 0.2,
+            short_delay_rate: 0.2,
+            long_delay_rate: 0.0,
+            short_delay_range: Duration::from_millis(1)..Duration::from_millis(20),
+            long_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            duplicate_rate: 0.0,
+        }));
+        let network_interface1 = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
+        let network_interface2 = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
+
+        network_interface1.assign_ip_addresses(vec!["192.168.1.1"]);
+        network_interface2.assign_ip_addresses(vec!["192.168.1.2"]);
+
+        network.register_network_interface(Arc::clone(&network_interface1));
+        network.register_network_interface(Arc::clone(&network_interface2));
+
+        let server_ip = "192.168.1.2";
+        let server_port = 8080;
+        let tcp_listener = network_interface2.bind_tcp("", server_port).unwrap();
+
+        network_interface1.set_connect_timeout(Duration::from_millis(300));
+        assert_eq!(
+            network_interface1
+                .connect(server_ip, server_port)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::TimedOut
+        );
+        assert_eq!(
+            tcp_listener.try_accept().unwrap_err().kind(),
+            ErrorKind::WouldBlock
+        );
+
+        network.connect("192.168.1.1", "192.168.1.2");
+        network_interface1.set_connect_timeout(Duration::from_secs(10));
+        for _ in 0..5 {
+            let client_stream = network_interface1.connect(server_ip, server_port).unwrap();
+            let server_stream = tcp_listener.accept().unwrap();
+            assert_eq!(
+                server_stream.get_remote_endpoint().port,
+                client_stream.get_local_endpoint().port
+            );
+        }
+    }
 }
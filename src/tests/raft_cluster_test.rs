@@ -0,0 +1,133 @@
+#[cfg(test)]
+mod tests {
+    use crate::platform_testing::raft_cluster::InvariantChecker;
+    use crate::raft::node::{RaftStatus, Role};
+    use crate::raft::proto::LogEntry;
+
+    /// A node in `term` whose log holds one entry per given term and payload,
+    /// with the first `commit_index` of them committed.
+    fn status(term: u64, role: Role, commit_index: u64, log: &[(u64, &str)]) -> RaftStatus {
+        RaftStatus {
+            term,
+            role,
+            commit_index,
+            snapshot_index: 0,
+            snapshot_term: 0,
+            entries: log
+                .iter()
+                .enumerate()
+                .map(|(i, (term, payload))| LogEntry {
+                    index: i as u64 + 1,
+                    term: *term,
+                    payload: payload.as_bytes().to_vec(),
+                    ..LogEntry::default()
+                })
+                .collect(),
+        }
+    }
+
+    fn nodes(statuses: Vec<RaftStatus>) -> Vec<(String, RaftStatus)> {
+        statuses
+            .into_iter()
+            .enumerate()
+            .map(|(i, status)| (format!("n{}", i + 1), status))
+            .collect()
+    }
+
+    #[test]
+    fn test_healthy_cluster() {
+        let mut checker = InvariantChecker::new();
+        let log = [(1, "a"), (1, "b")];
+        assert_eq!(
+            checker.observe(&nodes(vec![
+                status(1, Role::Leader, 2, &log),
+                status(1, Role::Follower, 1, &log),
+                status(1, Role::Follower, 0, &log[..1]),
+            ])),
+            Ok(())
+        );
+
+        // A new leader that holds everything committed replaces an entry
+        // that never was.
+        assert_eq!(
+            checker.observe(&nodes(vec![
+                status(2, Role::Follower, 2, &[(1, "a"), (1, "b"), (1, "c")]),
+                status(2, Role::Leader, 2, &[(1, "a"), (1, "b"), (2, "d")]),
+                status(2, Role::Follower, 2, &log),
+            ])),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_election_safety() {
+        let mut checker = InvariantChecker::new();
+        checker
+            .observe(&nodes(vec![
+                status(3, Role::Leader, 0, &[]),
+                status(3, Role::Follower, 0, &[]),
+            ]))
+            .unwrap();
+
+        // Two leaders of one term are caught even when seen apart.
+        let error = checker
+            .observe(&nodes(vec![
+                status(3, Role::Follower, 0, &[]),
+                status(3, Role::Leader, 0, &[]),
+            ]))
+            .unwrap_err();
+        assert!(error.contains("led term 3"), "{}", error);
+    }
+
+    #[test]
+    fn test_log_matching() {
+        let mut checker = InvariantChecker::new();
+        let error = checker
+            .observe(&nodes(vec![
+                status(2, Role::Follower, 0, &[(1, "a"), (2, "b")]),
+                status(2, Role::Follower, 0, &[(1, "x"), (2, "b"), (2, "c")]),
+            ]))
+            .unwrap_err();
+        assert!(error.contains("at index 1"), "{}", error);
+
+        // Logs may differ after the last entry they share.
+        assert_eq!(
+            checker.observe(&nodes(vec![
+                status(3, Role::Follower, 0, &[(1, "a"), (2, "b")]),
+                status(3, Role::Follower, 0, &[(1, "a"), (3, "c")]),
+            ])),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_committed_entries_never_change() {
+        let mut checker = InvariantChecker::new();
+        checker
+            .observe(&nodes(vec![status(1, Role::Follower, 1, &[(1, "a")])]))
+            .unwrap();
+        let error = checker
+            .observe(&nodes(vec![status(2, Role::Follower, 1, &[(2, "b")])]))
+            .unwrap_err();
+        assert!(error.contains("committed before"), "{}", error);
+    }
+
+    #[test]
+    fn test_leader_completeness() {
+        let mut checker = InvariantChecker::new();
+        checker
+            .observe(&nodes(vec![
+                status(1, Role::Leader, 2, &[(1, "a"), (1, "b")]),
+                status(1, Role::Follower, 0, &[]),
+            ]))
+            .unwrap();
+
+        let error = checker
+            .observe(&nodes(vec![
+                status(1, Role::Follower, 2, &[(1, "a"), (1, "b")]),
+                status(2, Role::Leader, 0, &[(1, "a")]),
+            ]))
+            .unwrap_err();
+        assert!(error.contains("leads term 2"), "{}", error);
+    }
+}
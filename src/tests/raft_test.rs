@@ -1,11 +1,1019 @@
-#[tokio::test]
-async fn test_initial_election() {
-}
+#[cfg(test)]
+mod tests {
+    use crate::platform_testing::disk::SimulatedDiskConfig;
+    use crate::platform_testing::network::{SimulatedNetwork, SimulatedNetworkConfig};
+    use crate::platform_testing::raft_cluster::{reliable_network, RaftCluster};
+    use crate::raft::node::{RaftConfig, RaftError, Role};
+    use crate::raft::read::ReadMode;
+    use rand::seq::index::sample;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
 
-#[tokio::test]
-async fn test_re_election() {
-}
+    const ELECTION_TIMEOUT: Duration = Duration::from_millis(1000);
+
+    #[tokio::test]
+    async fn test_initial_election() {
+        let cluster = RaftCluster::new(3);
+
+        cluster.check_one_leader().await;
+
+        // Once a leader is elected, the terms should settle.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let term1 = cluster.check_terms();
+        assert!(term1 >= 1, "term is {}, but should be at least 1", term1);
+
+        // Without failures the leader keeps its position and the term stays put.
+        tokio::time::sleep(2 * ELECTION_TIMEOUT).await;
+        let term2 = cluster.check_terms();
+        assert_eq!(
+            term1, term2,
+            "term changed even though there were no failures"
+        );
+
+        cluster.check_one_leader().await;
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_re_election() {
+        let size = 3;
+        let mut cluster = RaftCluster::new(size);
+
+        let leader1 = cluster.check_one_leader().await;
+
+        // If the leader disconnects, a new one should be elected.
+        cluster.disconnect(leader1);
+        cluster.check_one_leader().await;
+
+        // If the old leader rejoins, that shouldn't disturb the new leader.
+        cluster.connect(leader1);
+        let leader2 = cluster.check_one_leader().await;
+
+        // If there's no quorum, no new leader should be elected.
+        cluster.disconnect(leader2);
+        cluster.disconnect((leader2 + 1) % size);
+        tokio::time::sleep(2 * ELECTION_TIMEOUT).await;
+        cluster.check_no_leader();
+
+        // If a quorum arises, it should elect a leader.
+        cluster.connect((leader2 + 1) % size);
+        cluster.check_one_leader().await;
+
+        // Re-joining the last node shouldn't prevent a leader from existing.
+        cluster.connect(leader2);
+        cluster.check_one_leader().await;
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_many_elections() {
+        let size = 7;
+        let mut cluster = RaftCluster::new(size);
+
+        cluster.check_one_leader().await;
+
+        for _ in 0..10 {
+            // Disconnect three nodes; the remaining four still form a quorum.
+            let disconnected = sample(&mut rand::thread_rng(), size, 3).into_vec();
+            for i in disconnected.iter() {
+                cluster.disconnect(*i);
+            }
+
+            cluster.check_one_leader().await;
+
+            for i in disconnected.iter() {
+                cluster.connect(*i);
+            }
+        }
+
+        cluster.check_one_leader().await;
+        cluster.stop();
+    }
+
+    fn command(value: u64) -> Vec<u8> {
+        value.to_be_bytes().to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_basic_agree() {
+        let cluster = RaftCluster::new(3);
+
+        let mut last_index = 0;
+        for value in 1..=3 {
+            let index = cluster.one(&command(value), 3).await;
+            assert!(
+                index > last_index,
+                "command {} committed at index {} after index {}",
+                value,
+                index,
+                last_index
+            );
+            last_index = index;
+        }
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_fail_agree() {
+        let size = 3;
+        let mut cluster = RaftCluster::new(size);
+
+        cluster.one(&command(101), size).await;
+
+        // The remaining majority keeps committing without the follower.
+        let leader = cluster.check_one_leader().await;
+        cluster.disconnect((leader + 1) % size);
+        for value in 102..=105 {
+            cluster.one(&command(value), size - 1).await;
+        }
+
+        // The follower catches up once it rejoins.
+        cluster.connect((leader + 1) % size);
+        cluster.one(&command(106), size).await;
+        cluster.one(&command(107), size).await;
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_fail_no_agree() {
+        let size = 5;
+        let mut cluster = RaftCluster::new(size);
+
+        cluster.one(&command(10), size).await;
+
+        // Without a majority the leader can append but never commit.
+        let leader = cluster.check_one_leader().await;
+        for offset in 1..=3 {
+            cluster.disconnect((leader + offset) % size);
+        }
+        let (index, _) = cluster.nodes[leader]
+            .append_command(command(20))
+            .expect("leader rejected append_command");
+        tokio::time::sleep(2 * ELECTION_TIMEOUT).await;
+        let (count, _) = cluster.n_committed(index);
+        assert_eq!(count, 0, "{} committed without a majority", count);
+
+        for offset in 1..=3 {
+            cluster.connect((leader + offset) % size);
+        }
+        cluster.one(&command(1000), size).await;
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_backup() {
+        let size = 5;
+        let mut cluster = RaftCluster::new(size);
+
+        cluster.one(&command(rand::random()), size).await;
+
+        // Put the leader and one follower in a partition and give them
+        // entries that will never commit.
+        let leader1 = cluster.check_one_leader().await;
+        for offset in 2..=4 {
+            cluster.disconnect((leader1 + offset) % size);
+        }
+        for _ in 0..50 {
+            cluster.nodes[leader1].append_command(command(rand::random()));
+        }
+        tokio::time::sleep(ELECTION_TIMEOUT / 2).await;
+        cluster.disconnect(leader1);
+        cluster.disconnect((leader1 + 1) % size);
+
+        // Let the other partition elect a leader and commit plenty.
+        for offset in 2..=4 {
+            cluster.connect((leader1 + offset) % size);
+        }
+        for _ in 0..50 {
+            cluster.one(&command(rand::random()), 3).await;
+        }
+
+        // Cut one of its followers off and give the leader more entries that
+        // will never commit.
+        let leader2 = cluster.check_one_leader().await;
+        let mut other = (leader1 + 2) % size;
+        if other == leader2 {
+            other = (leader2 + 1) % size;
+        }
+        cluster.disconnect(other);
+        for _ in 0..50 {
+            cluster.nodes[leader2].append_command(command(rand::random()));
+        }
+        tokio::time::sleep(ELECTION_TIMEOUT / 2).await;
+
+        // Bring the original leader's partition back together with `other`;
+        // they have to discard their conflicting entries.
+        for i in 0..size {
+            cluster.disconnect(i);
+        }
+        cluster.connect(leader1);
+        cluster.connect((leader1 + 1) % size);
+        cluster.connect(other);
+        for _ in 0..50 {
+            cluster.one(&command(rand::random()), 3).await;
+        }
+
+        for i in 0..size {
+            cluster.connect(i);
+        }
+        cluster.one(&command(rand::random()), size).await;
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_propose() {
+        let cluster = RaftCluster::new(3);
+
+        let leader = cluster.check_one_leader().await;
+        let mut expected = Vec::new();
+        for value in 1..=10 {
+            let response = cluster.nodes[leader]
+                .propose(command(value))
+                .await
+                .expect("proposal failed");
+            expected.push(command(value));
+            assert_eq!(response, (expected.len() as u64).to_be_bytes().to_vec());
+        }
+
+        // Every replica applies the same commands in the same order.
+        cluster.wait_applied(&expected).await;
+
+        // Followers turn proposals away and point at the leader.
+        let follower = (leader + 1) % 3;
+        assert_eq!(
+            cluster.nodes[follower].propose(command(11)).await,
+            Err(RaftError::NotLeader {
+                leader_id: Some(cluster.nodes[leader].id().to_string())
+            })
+        );
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_propose_leadership_lost() {
+        let size = 3;
+        let mut cluster = RaftCluster::new(size);
+
+        let leader1 = cluster.check_one_leader().await;
+        cluster.disconnect(leader1);
+
+        // The partitioned leader accepts a proposal it can never commit.
+        let node = Arc::clone(&cluster.nodes[leader1]);
+        let lost = tokio::spawn(async move { node.propose(command(1)).await });
+
+        // The majority elects a new leader and commits over the same index.
+        let leader2 = cluster.check_one_leader().await;
+        cluster.nodes[leader2]
+            .propose(command(2))
+            .await
+            .expect("proposal failed");
+
+        cluster.connect(leader1);
+        assert_eq!(lost.await.unwrap(), Err(RaftError::LeadershipLost));
+        cluster.wait_applied(&[command(2)]).await;
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_persist() {
+        let size = 3;
+        let mut cluster = RaftCluster::new(size);
+
+        cluster.one(&command(11), size).await;
+        let term = cluster.check_terms();
+
+        // Restarting every node loses nothing, and terms never go backwards.
+        for i in 0..size {
+            cluster.restart(i);
+        }
+        cluster.one(&command(12), size).await;
+        let restarted_term = cluster.check_terms();
+        assert!(
+            restarted_term >= term,
+            "term went from {} back to {}",
+            term,
+            restarted_term
+        );
+
+        let leader1 = cluster.check_one_leader().await;
+        cluster.restart(leader1);
+        cluster.one(&command(13), size).await;
+
+        // A node restarted while partitioned catches up once it rejoins.
+        let leader2 = cluster.check_one_leader().await;
+        cluster.disconnect(leader2);
+        cluster.one(&command(14), size - 1).await;
+        cluster.restart(leader2);
+        cluster.connect(leader2);
+        cluster.one(&command(15), size).await;
+
+        let expected: Vec<Vec<u8>> = (11..=15).map(command).collect();
+        cluster.wait_applied(&expected).await;
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_partition_and_heal() {
+        let size = 5;
+        let mut cluster = RaftCluster::new(size);
+        let leader = cluster.check_one_leader().await;
+        cluster.one(&command(21), size).await;
+
+        // The leader keeps only a minority, so what it takes never commits,
+        // while the majority elects a leader of its own and carries on.
+        let minority = [leader, (leader + 1) % size];
+        let majority: Vec<usize> = (0..size).filter(|i| !minority.contains(i)).collect();
+        cluster.partition(&[&minority, &majority]);
+        let (lost, _) = cluster.nodes[leader].append_command(command(22)).unwrap();
+        let index = cluster.one(&command(23), majority.len()).await;
+        assert!(cluster.nodes[leader].commit_index() < lost);
+
+        // Once healed, the minority's entries give way to the majority's.
+        cluster.heal();
+        assert_eq!(cluster.wait_committed(index).await, command(23));
+        cluster.one(&command(24), size).await;
+        cluster
+            .wait_applied(&[command(21), command(23), command(24)])
+            .await;
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_stop_and_crash() {
+        let size = 3;
+        let mut cluster = RaftCluster::new_with_disks(size, power_loss_config(), 31);
+        let leader = cluster.check_one_leader().await;
+        cluster.one(&command(31), size).await;
+
+        // A stopped follower stays down while the others go on without it.
+        let follower = (leader + 1) % size;
+        cluster.stop_node(follower);
+        let index = cluster.one(&command(32), size - 1).await;
+        assert!(cluster.nodes[follower].commit_index() < index);
+
+        // With the leader crashed, the follower comes back and the two left
+        // keep everything that was committed.
+        cluster.crash(leader);
+        cluster.restart(follower);
+        cluster.check_one_leader().await;
+        assert_eq!(cluster.wait_committed(index).await, command(32));
+
+        cluster.restart(leader);
+        cluster.one(&command(33), size).await;
+        cluster
+            .wait_applied(&[command(31), command(32), command(33)])
+            .await;
+        cluster.stop();
+    }
+
+    fn power_loss_config() -> SimulatedDiskConfig {
+        SimulatedDiskConfig {
+            unsynced_write_survival_rate: 0.5,
+            reorder_writes: true,
+            torn_write_rate: 0.3,
+            io_error_rate: 0.0,
+            latency_range: Duration::ZERO..Duration::from_millis(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_power_loss() {
+        let size = 3;
+        let mut cluster = RaftCluster::new_with_disks(size, power_loss_config(), 7);
+
+        // Whatever a node loses with its power, it never loses an entry it
+        // acknowledged, so committed commands survive.
+        let mut expected = Vec::new();
+        for round in 0..6 {
+            expected.push(command(round));
+            cluster.one(&command(round), size).await;
+            let victim = if round % 2 == 0 {
+                cluster.check_one_leader().await
+            } else {
+                round as usize % size
+            };
+            cluster.power_loss(victim);
+        }
+        cluster.one(&command(100), size).await;
+        expected.push(command(100));
+        cluster.wait_applied(&expected).await;
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_storage_failure_stops_node() {
+        let size = 3;
+        let cluster = RaftCluster::new_with_disks(size, power_loss_config(), 11);
+
+        cluster.one(&command(1), size).await;
+
+        // A leader that cannot persist its log stops rather than carry on.
+        let leader = cluster.check_one_leader().await;
+        cluster.disks[leader].set_io_error_rate(1.0);
+        assert_eq!(cluster.nodes[leader].append_command(command(2)), None);
+        assert!(!cluster.nodes[leader].is_leader());
+
+        // The others elect a new leader and carry on without it.
+        cluster.one(&command(3), size - 1).await;
+        cluster.stop();
+    }
+
+    /// Snapshots after a handful of entries and sends them in small chunks,
+    /// so that a few dozen commands exercise compaction and InstallSnapshot.
+    fn snapshot_often(config: &mut RaftConfig) {
+        config.snapshot_threshold = 10;
+        config.snapshot_chunk_size = 64;
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_compacts_log() {
+        let size = 3;
+        let mut cluster = RaftCluster::new_with_config(size, snapshot_often);
 
-#[tokio::test]
-async fn test_many_elections() {
-}
\ No newline at end of file
+        let mut expected = Vec::new();
+        for value in 1..=50 {
+            cluster.one(&command(value), size).await;
+            expected.push(command(value));
+        }
+        cluster.wait_applied(&expected).await;
+        for node in cluster.nodes.iter() {
+            assert!(
+                node.snapshot_index() >= 40,
+                "{} only compacted up to {}",
+                node.id(),
+                node.snapshot_index()
+            );
+        }
+
+        // Restarted nodes rebuild their state machines from the snapshot and
+        // the log that follows it.
+        for i in 0..size {
+            cluster.restart(i);
+        }
+        cluster.one(&command(51), size).await;
+        expected.push(command(51));
+        cluster.wait_applied(&expected).await;
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_catches_up_follower() {
+        let size = 3;
+        let mut cluster = RaftCluster::new_with_config(size, snapshot_often);
+
+        cluster.one(&command(1), size).await;
+
+        // The follower misses more than the leader keeps in its log.
+        let leader = cluster.check_one_leader().await;
+        let follower = (leader + 1) % size;
+        cluster.disconnect(follower);
+        let mut expected = vec![command(1)];
+        for value in 2..=50 {
+            cluster.one(&command(value), size - 1).await;
+            expected.push(command(value));
+        }
+        assert!(cluster.nodes[leader].snapshot_index() > cluster.nodes[follower].commit_index());
+
+        // It can only catch up through a snapshot, sent over many chunks.
+        cluster.connect(follower);
+        cluster.one(&command(51), size).await;
+        expected.push(command(51));
+        cluster.wait_applied(&expected).await;
+        assert!(cluster.nodes[follower].snapshot_index() >= 40);
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_power_loss() {
+        let size = 3;
+        let network = SimulatedNetwork::with_seed(reliable_network(), 23);
+        let mut cluster =
+            RaftCluster::build(size, network, Some(power_loss_config()), snapshot_often);
+
+        // Snapshots written to the WAL survive losing power at any point.
+        let mut expected = Vec::new();
+        for round in 0..6 {
+            for value in 0..8 {
+                let value = round * 100 + value;
+                cluster.one(&command(value), size).await;
+                expected.push(command(value));
+            }
+            let victim = round as usize % size;
+            cluster.power_loss(victim);
+        }
+        cluster.one(&command(1000), size).await;
+        expected.push(command(1000));
+        cluster.wait_applied(&expected).await;
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_add_and_remove_nodes() {
+        let mut cluster = RaftCluster::new(3);
+
+        cluster.one(&command(1), 3).await;
+
+        // New nodes join as learners, catch up and are promoted to voters.
+        for _ in 0..2 {
+            let i = cluster.add_server();
+            let leader = cluster.check_one_leader().await;
+            cluster.nodes[leader]
+                .add_node(&cluster.ips[i], cluster.endpoint(i))
+                .await
+                .expect("add_node failed");
+        }
+        cluster.one(&command(2), 5).await;
+        for node in cluster.nodes.iter() {
+            assert_eq!(node.membership().voters.len(), 5);
+        }
+
+        // Shrink back to three voters by removing two of the original
+        // followers, which stop receiving entries.
+        let leader = cluster.check_one_leader().await;
+        let removed: Vec<usize> = (0..3).filter(|i| *i != leader).collect();
+        for i in removed.iter() {
+            cluster.nodes[leader]
+                .remove_node(&cluster.ips[*i])
+                .await
+                .expect("remove_node failed");
+        }
+        for i in removed.iter() {
+            cluster.disconnect(*i);
+        }
+        let index = cluster.one(&command(3), 3).await;
+        for i in removed.iter() {
+            assert!(cluster.nodes[*i].get_committed_entry(index).is_none());
+        }
+
+        // Three voters tolerate one more failure.
+        cluster.disconnect((leader + 3) % cluster.nodes.len());
+        cluster.one(&command(4), 2).await;
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_remove_leader() {
+        let size = 3;
+        let mut cluster = RaftCluster::new(size);
+
+        cluster.one(&command(1), size).await;
+
+        // The leader removes itself, sees the change through and steps down.
+        let leader1 = cluster.check_one_leader().await;
+        let id = cluster.ips[leader1].clone();
+        cluster.nodes[leader1]
+            .remove_node(&id)
+            .await
+            .expect("remove_node failed");
+        assert!(!cluster.nodes[leader1].is_leader());
+
+        // The remaining two elect a leader and commit without it.
+        cluster.disconnect(leader1);
+        let leader2 = cluster.check_one_leader().await;
+        assert_ne!(leader1, leader2);
+        cluster.one(&command(2), size - 1).await;
+        assert!(!cluster.nodes[leader2].membership().contains(&id));
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_learner_catches_up_before_promotion() {
+        let size = 3;
+        let mut cluster = RaftCluster::new(size);
+
+        let mut expected = Vec::new();
+        for value in 0..20 {
+            cluster.one(&command(value), size).await;
+            expected.push(command(value));
+        }
+
+        // A learner that cannot be reached never catches up, so it is not
+        // promoted and the voters carry on without it.
+        let learner = cluster.add_server();
+        cluster.disconnect(learner);
+        let leader = cluster.check_one_leader().await;
+        let id = cluster.ips[learner].clone();
+        assert_eq!(
+            cluster.nodes[leader]
+                .add_node(&id, cluster.endpoint(learner))
+                .await,
+            Err(RaftError::CatchUpFailed)
+        );
+        assert!(cluster.nodes[leader].membership().is_learner(&id));
+        cluster.one(&command(20), size).await;
+        expected.push(command(20));
+
+        // Once reachable it catches up and is promoted.
+        cluster.connect(learner);
+        let leader = cluster.check_one_leader().await;
+        cluster.nodes[leader]
+            .add_node(&id, cluster.endpoint(learner))
+            .await
+            .expect("add_node failed");
+        assert!(cluster.nodes[leader].membership().is_voter(&id));
+        cluster.one(&command(21), size + 1).await;
+        expected.push(command(21));
+        cluster.wait_applied(&expected).await;
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_membership_change_during_partition() {
+        let size = 5;
+        let mut cluster = RaftCluster::new(size);
+
+        cluster.one(&command(1), size).await;
+
+        // The majority side of a partition removes a node it cannot reach
+        // and adds a new one.
+        let leader1 = cluster.check_one_leader().await;
+        let removed = (leader1 + 1) % size;
+        let cut_off = (leader1 + 2) % size;
+        cluster.disconnect(removed);
+        cluster.disconnect(cut_off);
+        cluster.nodes[leader1]
+            .remove_node(&cluster.ips[removed])
+            .await
+            .expect("remove_node failed");
+        let added = cluster.add_server();
+        cluster.nodes[leader1]
+            .add_node(&cluster.ips[added], cluster.endpoint(added))
+            .await
+            .expect("add_node failed");
+        cluster.one(&command(2), 4).await;
+
+        // The removed node never learned of its removal, so it is kept away
+        // for good; the other one rejoins and catches up.
+        cluster.connect(cut_off);
+        cluster.one(&command(3), 5).await;
+
+        // A leader on the minority side of a partition cannot commit a
+        // membership change.
+        let leader2 = cluster.check_one_leader().await;
+        let others: Vec<usize> = (0..cluster.nodes.len())
+            .filter(|i| *i != leader2 && *i != removed)
+            .collect();
+        for i in others.iter().skip(1) {
+            cluster.disconnect(*i);
+        }
+        let node = Arc::clone(&cluster.nodes[leader2]);
+        let target = cluster.ips[others[0]].clone();
+        let change = tokio::spawn(async move { node.remove_node(&target).await });
+        tokio::time::sleep(2 * ELECTION_TIMEOUT).await;
+        assert!(!change.is_finished(), "membership changed without a quorum");
+
+        // Once the partition heals, the change either commits or is replaced,
+        // and every node ends up with the same membership.
+        for i in others.iter().skip(1) {
+            cluster.connect(*i);
+        }
+        let result = change.await.unwrap();
+        assert!(
+            matches!(result, Ok(()) | Err(RaftError::LeadershipLost)),
+            "unexpected result {:?}",
+            result
+        );
+        if result.is_ok() {
+            cluster.disconnect(others[0]);
+        }
+        cluster.one(&command(4), 4).await;
+        let leader3 = cluster.check_one_leader().await;
+        let membership = cluster.nodes[leader3].membership();
+        assert_eq!(
+            membership.contains(&cluster.ips[others[0]]),
+            result.is_err()
+        );
+        let members: Vec<usize> = (0..cluster.nodes.len())
+            .filter(|i| membership.contains(&cluster.ips[*i]))
+            .collect();
+        for _ in 0..50 {
+            if members
+                .iter()
+                .all(|i| cluster.nodes[*i].membership() == membership)
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        for i in members {
+            assert_eq!(cluster.nodes[i].membership(), membership);
+        }
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_pre_vote_prevents_term_inflation() {
+        let size = 3;
+        let mut cluster = RaftCluster::new(size);
+
+        let leader = cluster.check_one_leader().await;
+        let term = cluster.check_terms();
+
+        // A follower cut off from everyone loses every PreVote round, so it
+        // never bumps its term.
+        let follower = (leader + 1) % size;
+        cluster.disconnect(follower);
+        tokio::time::sleep(3 * ELECTION_TIMEOUT).await;
+        assert_eq!(cluster.nodes[follower].get_state().0, term);
+
+        // It rejoins without disturbing the leader.
+        cluster.connect(follower);
+        cluster.one(&command(1), size).await;
+        assert_eq!(cluster.check_one_leader().await, leader);
+        assert_eq!(cluster.check_terms(), term);
+        cluster.stop();
+    }
+
+    fn without_pre_vote(config: &mut RaftConfig) {
+        config.pre_vote = false;
+        config.check_quorum = false;
+    }
+
+    #[tokio::test]
+    async fn test_partitioned_node_disrupts_without_pre_vote() {
+        let size = 3;
+        let mut cluster = RaftCluster::new_with_config(size, without_pre_vote);
+
+        let leader = cluster.check_one_leader().await;
+        let term = cluster.check_terms();
+
+        // Without PreVote the cut-off follower keeps starting elections...
+        let follower = (leader + 1) % size;
+        cluster.disconnect(follower);
+        tokio::time::sleep(3 * ELECTION_TIMEOUT).await;
+        assert!(cluster.nodes[follower].get_state().0 > term);
+
+        // ...and its term deposes the leader once it is back.
+        cluster.connect(follower);
+        cluster.one(&command(1), size).await;
+        assert!(cluster.check_terms() > term);
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_asymmetric_partition() {
+        let size = 3;
+        let cluster = RaftCluster::new(size);
+
+        cluster.one(&command(1), size).await;
+        let leader = cluster.check_one_leader().await;
+        let term = cluster.check_terms();
+
+        // The follower no longer hears from the leader but can still reach
+        // it and the other follower, both of which still hear from the leader
+        // and so turn its PreVote down.
+        let follower = (leader + 1) % size;
+        let (leader_ip, follower_ip) = (&cluster.ips[leader], &cluster.ips[follower]);
+        cluster.network.disconnect_one_way(leader_ip, follower_ip);
+        for value in 2..=5 {
+            cluster.one(&command(value), size - 1).await;
+        }
+        tokio::time::sleep(2 * ELECTION_TIMEOUT).await;
+        assert_eq!(cluster.check_one_leader().await, leader);
+        for node in cluster.nodes.iter() {
+            assert_eq!(node.get_state().0, term, "{} changed term", node.id());
+        }
+
+        cluster.network.connect(leader_ip, follower_ip);
+        cluster.one(&command(6), size).await;
+        assert_eq!(cluster.check_terms(), term);
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_check_quorum() {
+        let size = 5;
+        let mut cluster = RaftCluster::new(size);
+
+        cluster.one(&command(1), size).await;
+        let leader1 = cluster.check_one_leader().await;
+        let term = cluster.check_terms();
+
+        // A leader cut off from every follower notices and steps down, and
+        // does not campaign its way into later terms.
+        cluster.disconnect(leader1);
+        tokio::time::sleep(2 * ELECTION_TIMEOUT).await;
+        assert!(!cluster.nodes[leader1].is_leader());
+        assert_eq!(cluster.nodes[leader1].get_state().0, term);
+
+        // The majority elects a new leader, which the old one rejoins as a
+        // follower without changing the term.
+        let leader2 = cluster.check_one_leader().await;
+        let term2 = cluster.check_terms();
+        cluster.connect(leader1);
+        cluster.one(&command(2), size).await;
+        assert_eq!(cluster.check_one_leader().await, leader2);
+        assert_eq!(cluster.check_terms(), term2);
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_transfer_leadership() {
+        let size = 3;
+        let cluster = RaftCluster::new(size);
+
+        cluster.one(&command(1), size).await;
+        let leader = cluster.check_one_leader().await;
+        let (term, _) = cluster.nodes[leader].get_state();
+        let target = (leader + 1) % size;
+
+        // The handover takes a round trip or two rather than an election
+        // timeout.
+        let started = Instant::now();
+        let target_id = cluster.nodes[target].id().to_string();
+        assert_eq!(
+            cluster.nodes[leader].transfer_leadership(&target_id).await,
+            Ok(())
+        );
+        assert!(started.elapsed() < ELECTION_TIMEOUT / 2);
+        assert_eq!(cluster.check_one_leader().await, target);
+        assert!(cluster.nodes[target].get_state().0 > term);
+
+        cluster.one(&command(2), size).await;
+        cluster.wait_applied(&[command(1), command(2)]).await;
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_transfer_leadership_to_lagging_node() {
+        let size = 3;
+        let mut cluster = RaftCluster::new(size);
+
+        cluster.one(&command(1), size).await;
+        let leader = cluster.check_one_leader().await;
+        let target = (leader + 1) % size;
+        cluster.disconnect(target);
+        let written: Vec<Vec<u8>> = (1..20).map(command).collect();
+        for value in written.iter().skip(1) {
+            cluster.one(value, size - 1).await;
+        }
+
+        // The target only campaigns once the leader has caught it up, so it
+        // can win without anything committed being lost.
+        cluster.connect(target);
+        let target_id = cluster.nodes[target].id().to_string();
+        assert_eq!(
+            cluster.nodes[leader].transfer_leadership(&target_id).await,
+            Ok(())
+        );
+        assert_eq!(cluster.check_one_leader().await, target);
+        cluster.wait_applied(&written).await;
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_transfer_leadership_fails() {
+        let size = 3;
+        let mut cluster = RaftCluster::new(size);
+
+        cluster.one(&command(1), size).await;
+        let leader = cluster.check_one_leader().await;
+        let (term, _) = cluster.nodes[leader].get_state();
+        let target = (leader + 1) % size;
+        cluster.disconnect(target);
+
+        // While the transfer is underway the leader turns proposals away,
+        // then gives up and carries on leading.
+        let target_id = cluster.nodes[target].id().to_string();
+        let node = &cluster.nodes[leader];
+        let (transfer, proposal) = tokio::join!(node.transfer_leadership(&target_id), async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            node.propose(command(2)).await
+        });
+        assert_eq!(proposal, Err(RaftError::LeadershipTransferPending));
+        assert_eq!(transfer, Err(RaftError::TransferFailed));
+        assert_eq!(cluster.nodes[leader].get_state(), (term, Role::Leader));
+        cluster.one(&command(3), size - 1).await;
+
+        assert_eq!(
+            cluster.nodes[leader].transfer_leadership("unknown").await,
+            Err(RaftError::TransferFailed)
+        );
+        cluster.stop();
+    }
+
+    /// Delays a good share of packets, some of them past the RPC timeout, so
+    /// heartbeat responses arrive late and out of order.
+    fn delayed_network() -> SimulatedNetworkConfig {
+        SimulatedNetworkConfig {
+            drop_rate: 0.0,
+            short_delay_rate: 0.3,
+            long_delay_rate: 0.05,
+            short_delay_range: Duration::from_millis(1)..Duration::from_millis(30),
+            long_delay_range: Duration::from_millis(80)..Duration::from_millis(200),
+            duplicate_rate: 0.0,
+        }
+    }
+
+    /// Writes through whichever node leads while repeatedly cutting the leader
+    /// off, and checks that every read any node serves, deposed leaders
+    /// included, reflects every write committed before it began.
+    async fn check_no_stale_reads(configure: fn(&mut RaftConfig)) {
+        let size = 3;
+        let mut cluster = RaftCluster::build(
+            size,
+            SimulatedNetwork::new(delayed_network()),
+            None,
+            configure,
+        );
+
+        let mut written = Vec::new();
+        let mut cut_off = None;
+        let mut served = 0;
+        for value in 0..30 {
+            if value % 10 == 5 {
+                if let Some(i) = cut_off.take() {
+                    cluster.connect(i);
+                }
+                let leader = cluster.check_one_leader().await;
+                cluster.disconnect(leader);
+                cut_off = Some(leader);
+            }
+            let connected = size - cut_off.iter().count();
+            cluster.one(&command(value), connected).await;
+            written.push(command(value));
+
+            for i in 0..size {
+                let Some(applied) = cluster.read(i).await else {
+                    continue;
+                };
+                served += 1;
+                let missing = written.iter().find(|command| !applied.contains(command));
+                assert!(
+                    missing.is_none(),
+                    "{} served a read missing {:?}",
+                    cluster.nodes[i].id(),
+                    missing
+                );
+            }
+        }
+        assert!(served >= 30, "only {} reads were served", served);
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_read_index_no_stale_reads() {
+        check_no_stale_reads(|_| {}).await;
+    }
+
+    fn lease_reads(config: &mut RaftConfig) {
+        config.read_mode = ReadMode::Lease {
+            max_clock_drift: 1.1,
+        };
+    }
+
+    #[tokio::test]
+    async fn test_lease_read_no_stale_reads() {
+        check_no_stale_reads(lease_reads).await;
+    }
+
+    #[tokio::test]
+    async fn test_read_index() {
+        let size = 3;
+        let mut cluster = RaftCluster::new(size);
+
+        cluster.one(&command(1), size).await;
+        let leader = cluster.check_one_leader().await;
+        let follower = (leader + 1) % size;
+        assert_eq!(
+            cluster.nodes[follower].read_index().await,
+            Err(RaftError::NotLeader {
+                leader_id: Some(cluster.nodes[leader].id().to_string())
+            })
+        );
+        let index = cluster.nodes[leader].read_index().await.unwrap();
+        assert!(index >= 2);
+
+        // A leader that cannot confirm it still leads serves no reads; once it
+        // notices, it gives up on those waiting.
+        cluster.disconnect(leader);
+        assert_eq!(
+            cluster.nodes[leader].read_index().await,
+            Err(RaftError::LeadershipLost)
+        );
+        cluster.stop();
+    }
+
+    #[tokio::test]
+    async fn test_lease_read_skips_heartbeats() {
+        let size = 3;
+        let mut cluster = RaftCluster::new_with_config(size, lease_reads);
+
+        cluster.one(&command(1), size).await;
+        let leader = cluster.check_one_leader().await;
+
+        // Right after cutting the leader off, its lease still covers reads
+        // that could never confirm leadership with a heartbeat round.
+        cluster.disconnect(leader);
+        let read = tokio::time::timeout(
+            Duration::from_millis(50),
+            cluster.nodes[leader].read_index(),
+        );
+        assert!(matches!(read.await, Ok(Ok(_))));
+
+        // Once the lease runs out it stops serving reads.
+        tokio::time::sleep(ELECTION_TIMEOUT).await;
+        assert!(cluster.read(leader).await.is_none());
+        cluster.stop();
+    }
+}
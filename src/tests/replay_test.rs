@@ -0,0 +1,221 @@
+#[cfg(test)]
+mod tests {
+    use crate::platform::network::NetworkInterface;
+    use crate::platform_testing::network::{SimulatedNetwork, SimulatedNetworkConfig};
+    use crate::platform_testing::network_interface::SimulatedNetworkInterface;
+    use crate::platform_testing::raft_cluster::RaftCluster;
+    use crate::platform_testing::replay::Journal;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use std::thread::{sleep, spawn};
+    use std::time::Duration;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "{}-{}-{}.replay",
+            name,
+            std::process::id(),
+            rand::random::<u32>()
+        ))
+    }
+
+    /// Saves what `journal` recorded and loads it back for replay.
+    fn reload(journal: &Journal, name: &str) -> Arc<Journal> {
+        let path = temp_path(name);
+        journal.save(&path).unwrap();
+        let replay = Journal::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(replay.is_replaying());
+        Arc::new(replay)
+    }
+
+    #[test]
+    fn test_journal_replays_each_stream_in_order() {
+        let journal = Journal::new();
+        assert_eq!(journal.draw("a", || 1), 1);
+        assert_eq!(journal.draw("b", || 10), 10);
+        assert_eq!(journal.draw("a", || 2), 2);
+        assert!(!journal.fire("timer", 3, false));
+        assert!(journal.fire("timer", 4, true));
+        assert_eq!(journal.draw("b", || 20), 20);
+        assert!(!journal.fire("timer", 8, false));
+
+        // Streams are drawn from in another order, and the timer ticks late.
+        let replay = reload(&journal, "journal-streams");
+        assert_eq!(replay.draw("b", || 0), 10);
+        assert_eq!(replay.draw("b", || 0), 20);
+        assert_eq!(replay.draw("a", || 0), 1);
+        assert!(!replay.fire("timer", 3, true));
+        assert!(replay.fire("timer", 5, false));
+        assert!(!replay.fire("timer", 7, true));
+        assert_eq!(replay.draw("a", || 0), 2);
+        assert!(replay.divergences().is_empty());
+
+        // Past the end of the recording, chance and the clock decide.
+        assert_eq!(replay.draw("a", || 3), 3);
+        assert!(replay.fire("timer", 9, true));
+        assert!(replay.divergences().is_empty());
+
+        assert!(Journal::load(&temp_path("missing")).is_err());
+    }
+
+    #[test]
+    fn test_journal_replays_delivery_order() {
+        let journal = Arc::new(Journal::new());
+        for id in 1..=5 {
+            journal.deliver("to host", id.to_string(), || {});
+        }
+
+        let replay = reload(&journal, "journal-deliveries");
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let deliver = |id: u32| {
+            let delivered = Arc::clone(&delivered);
+            replay.deliver("to host", id.to_string(), move || {
+                delivered.lock().unwrap().push(id)
+            });
+        };
+        deliver(3);
+        deliver(2);
+        assert!(delivered.lock().unwrap().is_empty());
+        deliver(1);
+        assert_eq!(*delivered.lock().unwrap(), vec![1, 2, 3]);
+
+        // A packet the recording never saw goes through right away, and one
+        // held for a packet that never comes goes through in the end.
+        deliver(6);
+        deliver(5);
+        assert_eq!(*delivered.lock().unwrap(), vec![1, 2, 3, 6]);
+        sleep(Duration::from_millis(1500));
+        assert_eq!(*delivered.lock().unwrap(), vec![1, 2, 3, 6, 5]);
+        assert_eq!(replay.divergences().len(), 2, "{:?}", replay.divergences());
+    }
+
+    /// Sends datagrams from two hosts to a third over a lossy network, with
+    /// `pause` between the second host's sends, and returns what arrived in
+    /// the order it did.
+    fn exchange_datagrams(journal: Arc<Journal>, pause: Duration) -> Vec<String> {
+        let network = Arc::new(SimulatedNetwork::new(SimulatedNetworkConfig {
+            drop_rate: 0.2,
+            short_delay_rate: 0.3,
+            long_delay_rate: 0.1,
+            short_delay_range: Duration::from_millis(0)..Duration::from_millis(10),
+            long_delay_range: Duration::from_millis(10)..Duration::from_millis(50),
+            duplicate_rate: 0.2,
+        }));
+        network.set_journal(journal);
+        let ips = ["192.168.1.1", "192.168.1.2", "192.168.1.3"];
+        let network_interfaces: Vec<Arc<SimulatedNetworkInterface>> = ips
+            .iter()
+            .map(|ip| {
+                let network_interface =
+                    Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
+                network_interface.assign_ip_addresses(vec![ip]);
+                network.register_network_interface(Arc::clone(&network_interface));
+                network_interface
+            })
+            .collect();
+        network.connect(ips[0], ips[2]);
+        network.connect(ips[1], ips[2]);
+
+        let receiver = network_interfaces[2].bind_udp("", 9000).unwrap();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let collected = Arc::clone(&received);
+        spawn(move || {
+            while let Some((data, _)) = receiver.recv_from() {
+                let data = String::from_utf8(data.to_vec()).unwrap();
+                collected.lock().unwrap().push(data);
+            }
+        });
+
+        let senders: Vec<_> = (0..2)
+            .map(|host| {
+                let socket = network_interfaces[host].bind_udp("", 0).unwrap();
+                spawn(move || {
+                    for n in 0..30 {
+                        let data = format!("{}.{}", host, n);
+                        socket.send_to(data.as_bytes(), ips[2], 9000).unwrap();
+                        if host == 1 {
+                            println!("Sent {}", data);
+                            sleep(pause);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for sender in senders {
+            sender.join().unwrap();
+        }
+        sleep(Duration::from_millis(300));
+        let received = received.lock().unwrap().clone();
+        received
+    }
+
+    #[test]
+    fn test_simulated_network_replay() {
+        let journal = Arc::new(Journal::new());
+        let recorded = exchange_datagrams(Arc::clone(&journal), Duration::ZERO);
+        assert!(!recorded.is_empty());
+
+        let replay = reload(&journal, "network-replay");
+        let replayed = exchange_datagrams(Arc::clone(&replay), Duration::from_millis(2));
+        assert_eq!(replayed, recorded);
+        assert!(
+            replay.divergences().is_empty(),
+            "{:?}",
+            replay.divergences()
+        );
+    }
+
+    /// Elects a leader, commits a few commands and returns the ID and term
+    /// of whoever led when they were committed.
+    async fn elect_and_commit(journal: Arc<Journal>, verbose: bool) -> (String, u64) {
+        let cluster = RaftCluster::new_with_journal(3, journal);
+        let leader = cluster.check_one_leader().await;
+        for n in 0..3 {
+            let command = format!("command {}", n);
+            if verbose {
+                println!("Proposing {}", command);
+            }
+            cluster.one(command.as_bytes(), 3).await;
+        }
+        let (term, _) = cluster.nodes[leader].get_state();
+        let leader = cluster.nodes[leader].id().to_string();
+        cluster.stop();
+        (leader, term)
+    }
+
+    /// The tick each node's election timer first fired on, from a saved
+    /// journal.
+    fn first_firings(journal: &Journal) -> BTreeMap<String, String> {
+        let path = temp_path("first-firings");
+        journal.save(&path).unwrap();
+        let mut first_firings = BTreeMap::new();
+        for line in std::fs::read_to_string(&path).unwrap().lines() {
+            if let [stream, "fire", tick] = line.split('\t').collect::<Vec<_>>()[..] {
+                first_firings
+                    .entry(stream.to_string())
+                    .or_insert(tick.to_string());
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+        first_firings
+    }
+
+    #[tokio::test]
+    async fn test_raft_cluster_replay() {
+        let journal = Arc::new(Journal::new());
+        let recorded = elect_and_commit(Arc::clone(&journal), false).await;
+
+        let replay = reload(&journal, "cluster-replay");
+        let replayed = elect_and_commit(Arc::clone(&replay), true).await;
+        assert!(!first_firings(&journal).is_empty());
+        assert_eq!(first_firings(&replay), first_firings(&journal));
+        // Each node handles requests on threads of their own, so two that
+        // reach it at once can race; the replay notices when that changes
+        // the outcome.
+        if replay.divergences().is_empty() {
+            assert_eq!(replayed, recorded);
+        }
+    }
+}
@@ -0,0 +1,173 @@
+#[cfg(test)]
+mod tests {
+    use crate::platform::network::{NetworkInterface, TcpStream};
+    use crate::platform::network_interface::SystemNetworkInterface;
+    use crate::platform::rpc::{serve_connection, RpcClient, RpcHandler};
+    use crate::platform_testing::network::{SimulatedNetwork, SimulatedNetworkConfig};
+    use crate::platform_testing::network_interface::SimulatedNetworkInterface;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use std::thread::{sleep, spawn};
+    use std::time::{Duration, Instant};
+
+    const CLIENT_IP: &str = "192.168.1.1";
+    const SERVER_IP: &str = "192.168.1.2";
+    const SERVER_PORT: u16 = 7000;
+
+    /// Opens a connection over a reliable simulated network and serves
+    /// `handler` on the server end.
+    fn connect(handler: RpcHandler) -> RpcClient {
+        let network = Arc::new(SimulatedNetwork::new(SimulatedNetworkConfig {
+            drop_rate: 0.0,
+            short_delay_rate: 0.0,
+            long_delay_rate: 0.0,
+            short_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            long_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            duplicate_rate: 0.0,
+        }));
+        network.connect(CLIENT_IP, SERVER_IP);
+        let mut interfaces = Vec::new();
+        for ip in [CLIENT_IP, SERVER_IP] {
+            let network_interface = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
+            network_interface.assign_ip_addresses(vec![ip]);
+            network.register_network_interface(Arc::clone(&network_interface));
+            interfaces.push(network_interface);
+        }
+
+        let listener = interfaces[1].bind_tcp(SERVER_IP, SERVER_PORT).unwrap();
+        spawn(move || serve_connection(listener.accept().unwrap(), handler));
+        RpcClient::new(interfaces[0].connect(SERVER_IP, SERVER_PORT).unwrap())
+    }
+
+    /// Sleeps for as many tens of milliseconds as the first byte says and
+    /// answers with the payload.
+    fn sleepy_echo() -> RpcHandler {
+        Arc::new(|payload| {
+            sleep(Duration::from_millis(10 * payload[0] as u64));
+            Some(payload.to_vec())
+        })
+    }
+
+    #[test]
+    fn test_rpc_concurrent_calls_share_a_stream() {
+        let client = Arc::new(connect(sleepy_echo()));
+        let started = Instant::now();
+        let callers: Vec<_> = (0..20u8)
+            .map(|i| {
+                let client = Arc::clone(&client);
+                spawn(move || {
+                    let payload = [10, i];
+                    let response = client.call(&payload, Duration::from_secs(5)).unwrap();
+                    assert_eq!(response.as_ref(), &payload);
+                })
+            })
+            .collect();
+        for caller in callers {
+            caller.join().unwrap();
+        }
+        // Twenty calls of 100ms each finish together rather than one after
+        // another.
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_rpc_pipelined_calls_complete_out_of_order() {
+        let client = connect(sleepy_echo());
+        let started = Instant::now();
+        let slow = client.start(&[30], Duration::from_secs(5)).unwrap();
+        let fast = client.start(&[1], Duration::from_secs(5)).unwrap();
+
+        assert_eq!(fast.wait().unwrap().as_ref(), &[1]);
+        assert!(started.elapsed() < Duration::from_millis(250));
+        assert_eq!(slow.wait().unwrap().as_ref(), &[30]);
+    }
+
+    #[test]
+    fn test_rpc_deadline() {
+        let client = connect(sleepy_echo());
+        let started = Instant::now();
+        let error = client.call(&[50], Duration::from_millis(100)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_millis(400));
+
+        // The late response is thrown away rather than taken for this one's.
+        assert_eq!(
+            client
+                .call(&[60, 1], Duration::from_secs(5))
+                .unwrap()
+                .as_ref(),
+            &[60, 1]
+        );
+    }
+
+    #[test]
+    fn test_rpc_cancellation() {
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&handled);
+        let client = connect(Arc::new(move |payload| {
+            log.lock().unwrap().push(payload.to_vec());
+            sleep(Duration::from_millis(10 * payload[0] as u64));
+            Some(payload.to_vec())
+        }));
+
+        // The server skips a call whose caller has stopped waiting by the time
+        // it gets to it.
+        let error = client.call(&[0], Duration::ZERO).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+
+        // A cancelled call the server already started runs to the end, but
+        // its response is not taken for a later call's.
+        let call = client.start(&[20], Duration::from_secs(5)).unwrap();
+        call.cancel();
+        assert_eq!(
+            client
+                .call(&[0, 2], Duration::from_secs(5))
+                .unwrap()
+                .as_ref(),
+            &[0, 2]
+        );
+        sleep(Duration::from_millis(300));
+        let handled = handled.lock().unwrap();
+        assert!(!handled.contains(&vec![0]));
+        assert!(handled.contains(&vec![0, 2]));
+    }
+
+    #[test]
+    fn test_rpc_dropped_request() {
+        let client = connect(Arc::new(|payload| match payload {
+            [0] => None,
+            _ => Some(payload.to_vec()),
+        }));
+        let error = client.call(&[0], Duration::from_secs(5)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionAborted);
+        assert!(!client.is_closed());
+        assert_eq!(
+            client.call(&[1], Duration::from_secs(5)).unwrap().as_ref(),
+            &[1]
+        );
+    }
+
+    #[test]
+    fn test_rpc_fails_calls_when_the_stream_closes() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let network_interface = SystemNetworkInterface::new();
+        let listener = network_interface.bind_tcp("127.0.0.1", port).unwrap();
+        // The server reads the first request and hangs up.
+        spawn(move || {
+            let stream: Arc<dyn TcpStream> = listener.accept().unwrap();
+            stream.receive();
+        });
+        let client = RpcClient::new(network_interface.connect("127.0.0.1", port).unwrap());
+
+        let started = Instant::now();
+        let error = client.call(&[1], Duration::from_secs(5)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionAborted);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(client.is_closed());
+        assert!(client.call(&[2], Duration::from_secs(5)).is_err());
+    }
+}
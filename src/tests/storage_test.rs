@@ -0,0 +1,472 @@
+#[cfg(test)]
+mod tests {
+    use crate::platform::disk::SystemDisk;
+    use crate::platform::network::Endpoint;
+    use crate::platform::storage::Storage;
+    use crate::platform_testing::disk::{SimulatedDisk, SimulatedDiskConfig};
+    use crate::raft::membership::Membership;
+    use crate::raft::proto::{EntryType, LogEntry};
+    use crate::raft::storage::{HardState, RaftStorage, Snapshot};
+    use crate::raft::wal::WalStorage;
+    use std::collections::BTreeMap;
+    use std::fs::{self, OpenOptions};
+    use std::io::{ErrorKind, Write};
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "{}-{}-{}",
+            name,
+            std::process::id(),
+            rand::random::<u32>()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn entry(index: u64, term: u64) -> LogEntry {
+        LogEntry {
+            index,
+            term,
+            payload: format!("command {}", index).into_bytes(),
+            entry_type: EntryType::Normal.into(),
+        }
+    }
+
+    fn segment_paths(dir: &PathBuf) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+            .unwrap()
+            .map(|dir_entry| dir_entry.unwrap().path())
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn test_wal_recovers_state() {
+        let dir = temp_dir("wal-recovers-state");
+        let hard_state = HardState {
+            current_term: 2,
+            voted_for: Some("node-1".to_string()),
+        };
+        let entries: Vec<LogEntry> = (1..=7)
+            .map(|index| entry(index, 1))
+            .chain((8..=9).map(|index| entry(index, 2)))
+            .collect();
+
+        {
+            let mut wal =
+                WalStorage::with_segment_size(Arc::new(SystemDisk::new()), &dir, 256).unwrap();
+            let (loaded_hard_state, loaded_entries) = wal.load().unwrap();
+            assert_eq!(loaded_hard_state, HardState::default());
+            assert!(loaded_entries.is_empty());
+
+            wal.save_hard_state(&HardState {
+                current_term: 1,
+                voted_for: None,
+            })
+            .unwrap();
+            wal.append(&(1..=10).map(|index| entry(index, 1)).collect::<Vec<_>>())
+                .unwrap();
+            wal.save_hard_state(&hard_state).unwrap();
+            wal.truncate_from(8).unwrap();
+            wal.append(&entries[7..]).unwrap();
+            wal.sync().unwrap();
+        }
+        assert!(
+            segment_paths(&dir).len() > 1,
+            "expected the log to span several segments"
+        );
+
+        let mut wal =
+            WalStorage::with_segment_size(Arc::new(SystemDisk::new()), &dir, 256).unwrap();
+        let (loaded_hard_state, loaded_entries) = wal.load().unwrap();
+        assert_eq!(loaded_hard_state, hard_state);
+        assert_eq!(loaded_entries, entries);
+
+        // The reopened log keeps appending where it left off.
+        wal.append(&[entry(10, 2)]).unwrap();
+        wal.sync().unwrap();
+        let (_, loaded_entries) = WalStorage::open(Arc::new(SystemDisk::new()), &dir)
+            .unwrap()
+            .load()
+            .unwrap();
+        assert_eq!(loaded_entries.len(), 10);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_wal_discards_torn_write() {
+        let dir = temp_dir("wal-torn-write");
+        {
+            let mut wal = WalStorage::open(Arc::new(SystemDisk::new()), &dir).unwrap();
+            wal.load().unwrap();
+            wal.append(&[entry(1, 1), entry(2, 1)]).unwrap();
+            wal.sync().unwrap();
+        }
+
+        // Simulate a crash midway through writing a record.
+        let segment = segment_paths(&dir).pop().unwrap();
+        let synced_length = fs::metadata(&segment).unwrap().len();
+        OpenOptions::new()
+            .append(true)
+            .open(&segment)
+            .unwrap()
+            .write_all(&[42, 0, 0, 0, 1, 2, 3])
+            .unwrap();
+
+        let mut wal = WalStorage::open(Arc::new(SystemDisk::new()), &dir).unwrap();
+        let (_, entries) = wal.load().unwrap();
+        assert_eq!(entries, vec![entry(1, 1), entry(2, 1)]);
+        assert_eq!(fs::metadata(&segment).unwrap().len(), synced_length);
+
+        wal.append(&[entry(3, 1)]).unwrap();
+        wal.sync().unwrap();
+        let (_, entries) = WalStorage::open(Arc::new(SystemDisk::new()), &dir)
+            .unwrap()
+            .load()
+            .unwrap();
+        assert_eq!(entries.len(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+
+        // A torn entry whose command holds well-formed records, here a whole
+        // segment, is still cut off.
+        let dir = temp_dir("wal-torn-record-like-payload");
+        let records = {
+            let mut wal = WalStorage::open(Arc::new(SystemDisk::new()), &dir).unwrap();
+            wal.load().unwrap();
+            wal.append(&[entry(1, 1), entry(2, 1)]).unwrap();
+            wal.sync().unwrap();
+            fs::read(segment_paths(&dir).pop().unwrap()).unwrap()
+        };
+        {
+            let mut wal = WalStorage::open(Arc::new(SystemDisk::new()), &dir).unwrap();
+            wal.load().unwrap();
+            wal.append(&[LogEntry {
+                payload: records,
+                ..entry(3, 1)
+            }])
+            .unwrap();
+            wal.sync().unwrap();
+        }
+
+        let segment = segment_paths(&dir).pop().unwrap();
+        let torn_length = fs::metadata(&segment).unwrap().len() - 30;
+        OpenOptions::new()
+            .write(true)
+            .open(&segment)
+            .unwrap()
+            .set_len(torn_length)
+            .unwrap();
+
+        let (_, entries) = WalStorage::open(Arc::new(SystemDisk::new()), &dir)
+            .unwrap()
+            .load()
+            .unwrap();
+        assert_eq!(entries, vec![entry(1, 1), entry(2, 1)]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_wal_detects_corruption() {
+        let dir = temp_dir("wal-corruption");
+        {
+            let mut wal =
+                WalStorage::with_segment_size(Arc::new(SystemDisk::new()), &dir, 128).unwrap();
+            wal.load().unwrap();
+            wal.append(&(1..=10).map(|index| entry(index, 1)).collect::<Vec<_>>())
+                .unwrap();
+            wal.sync().unwrap();
+        }
+
+        // Damage to a sealed segment cannot be a torn write, so it is reported
+        // rather than silently dropping entries.
+        let segment = segment_paths(&dir).remove(0);
+        let mut data = fs::read(&segment).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        fs::write(&segment, data).unwrap();
+
+        let error = WalStorage::open(Arc::new(SystemDisk::new()), &dir)
+            .unwrap()
+            .load()
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Neither is a bad record in the last segment that synced records
+        // follow, and the segment is left as it was.
+        let dir = temp_dir("wal-corruption-last-segment");
+        {
+            let mut wal = WalStorage::open(Arc::new(SystemDisk::new()), &dir).unwrap();
+            wal.load().unwrap();
+            wal.append(&(1..=10).map(|index| entry(index, 1)).collect::<Vec<_>>())
+                .unwrap();
+            wal.sync().unwrap();
+        }
+
+        let segment = segment_paths(&dir).pop().unwrap();
+        let mut data = fs::read(&segment).unwrap();
+        let middle = data.len() / 2;
+        data[middle] ^= 0xff;
+        fs::write(&segment, &data).unwrap();
+
+        let error = WalStorage::open(Arc::new(SystemDisk::new()), &dir)
+            .unwrap()
+            .load()
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(fs::read(&segment).unwrap(), data);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_wal_snapshot() {
+        let dir = temp_dir("wal-snapshot");
+        let membership = Membership::new(BTreeMap::from([(
+            "node-1".to_string(),
+            Endpoint {
+                ip: "10.0.0.1".to_string(),
+                port: 7000,
+            },
+        )]));
+        let count_files = |extension: &str| {
+            segment_paths(&dir)
+                .iter()
+                .filter(|path| path.extension().is_some_and(|e| e == extension))
+                .count()
+        };
+        {
+            let mut wal =
+                WalStorage::with_segment_size(Arc::new(SystemDisk::new()), &dir, 256).unwrap();
+            wal.load().unwrap();
+            assert_eq!(wal.load_snapshot().unwrap(), None);
+            wal.append(&(1..=20).map(|index| entry(index, 1)).collect::<Vec<_>>())
+                .unwrap();
+            wal.sync().unwrap();
+            let first_segment = segment_paths(&dir).remove(0);
+
+            for index in [5, 15] {
+                wal.save_snapshot(&Snapshot {
+                    last_included_index: index,
+                    last_included_term: 1,
+                    membership: membership.clone(),
+                    data: format!("state at {}", index).into_bytes(),
+                })
+                .unwrap();
+            }
+            wal.append(&[entry(21, 2)]).unwrap();
+            wal.sync().unwrap();
+
+            // Only the newest snapshot is kept, and segments it covers go.
+            assert_eq!(count_files("snap"), 1);
+            assert!(!first_segment.exists());
+        }
+
+        let mut wal =
+            WalStorage::with_segment_size(Arc::new(SystemDisk::new()), &dir, 256).unwrap();
+        let snapshot = wal.load_snapshot().unwrap().unwrap();
+        assert_eq!(snapshot.last_included_index, 15);
+        assert_eq!(snapshot.membership, membership);
+        assert_eq!(snapshot.data, b"state at 15");
+        let (_, entries) = wal.load().unwrap();
+        let expected: Vec<LogEntry> = (16..=20)
+            .map(|index| entry(index, 1))
+            .chain([entry(21, 2)])
+            .collect();
+        assert_eq!(entries, expected);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn power_loss_config() -> SimulatedDiskConfig {
+        SimulatedDiskConfig {
+            unsynced_write_survival_rate: 0.5,
+            reorder_writes: true,
+            torn_write_rate: 0.3,
+            io_error_rate: 0.0,
+            latency_range: Duration::ZERO..Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_simulated_disk_loses_unsynced_writes() {
+        let disk = SimulatedDisk::new(
+            SimulatedDiskConfig {
+                unsynced_write_survival_rate: 0.0,
+                ..power_loss_config()
+            },
+            1,
+        );
+        let dir = Path::new("/data");
+        disk.create_dir_all(dir).unwrap();
+
+        let mut synced = disk.create(&dir.join("synced")).unwrap();
+        synced.append(b"kept").unwrap();
+        synced.sync().unwrap();
+        disk.sync_dir(dir).unwrap();
+        synced.append(b" lost").unwrap();
+        assert_eq!(disk.read(&dir.join("synced")).unwrap(), b"kept lost");
+
+        // A file whose directory entry was never synced disappears entirely.
+        let mut unlinked = disk.create(&dir.join("unlinked")).unwrap();
+        unlinked.append(b"data").unwrap();
+        unlinked.sync().unwrap();
+
+        disk.crash();
+        assert_eq!(disk.read(&dir.join("synced")).unwrap(), b"kept");
+        assert_eq!(
+            disk.read(&dir.join("unlinked")).unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+
+        // Files opened before the crash belong to a process that is gone.
+        assert!(synced.append(b"zombie").is_err());
+        let mut reopened = disk.open_append(&dir.join("synced")).unwrap();
+        reopened.append(b" again").unwrap();
+        assert_eq!(disk.read(&dir.join("synced")).unwrap(), b"kept again");
+    }
+
+    #[test]
+    fn test_simulated_disk_is_deterministic() {
+        let run = |seed| {
+            let disk = SimulatedDisk::new(power_loss_config(), seed);
+            let dir = Path::new("/data");
+            disk.create_dir_all(dir).unwrap();
+            let mut file = disk.create(&dir.join("file")).unwrap();
+            disk.sync_dir(dir).unwrap();
+            for i in 0..50u8 {
+                file.append(&[i; 16]).unwrap();
+                if i % 10 == 0 {
+                    file.sync().unwrap();
+                }
+            }
+            disk.crash();
+            disk.read(&dir.join("file")).unwrap()
+        };
+
+        for seed in 0..10 {
+            let contents = run(seed);
+            assert_eq!(contents, run(seed), "seed {} is not reproducible", seed);
+            // Everything up to the last sync survives whatever else happens.
+            assert_eq!(&contents[..41 * 16], &run(u64::MAX)[..41 * 16]);
+        }
+    }
+
+    #[test]
+    fn test_simulated_disk_io_errors() {
+        let disk = SimulatedDisk::new(
+            SimulatedDiskConfig {
+                io_error_rate: 1.0,
+                ..power_loss_config()
+            },
+            1,
+        );
+        let dir = Path::new("/data");
+        assert!(disk.create_dir_all(dir).is_err());
+        assert!(WalStorage::open(Arc::new(disk.clone()), dir).is_err());
+
+        disk.set_io_error_rate(0.0);
+        let mut wal = WalStorage::open(Arc::new(disk.clone()), dir).unwrap();
+        wal.load().unwrap();
+        wal.append(&[entry(1, 1)]).unwrap();
+
+        disk.set_io_error_rate(1.0);
+        assert!(wal.sync().is_err());
+    }
+
+    #[test]
+    fn test_wal_survives_power_loss() {
+        let dir = Path::new("/raft");
+        for seed in 0..100 {
+            let disk = SimulatedDisk::new(power_loss_config(), seed);
+            let storage: Arc<dyn Storage> = Arc::new(disk.clone());
+            let entries: Vec<LogEntry> = (1..=40).map(|index| entry(index, 1)).collect();
+
+            let mut synced = 0;
+            {
+                let mut wal =
+                    WalStorage::with_segment_size(Arc::clone(&storage), dir, 256).unwrap();
+                wal.load().unwrap();
+                for (i, chunk) in entries.chunks(7).enumerate() {
+                    wal.append(chunk).unwrap();
+                    if i % 2 == 0 {
+                        wal.sync().unwrap();
+                        synced += chunk.len();
+                    }
+                }
+                // Writes handed to the disk but never synced may or may not
+                // survive.
+                wal.truncate_from(38).unwrap();
+                wal.append(&entries[37..]).unwrap();
+            }
+            disk.crash();
+
+            let mut wal = WalStorage::with_segment_size(Arc::clone(&storage), dir, 256).unwrap();
+            let (_, recovered) = wal.load().unwrap();
+            assert!(
+                recovered.len() >= synced,
+                "seed {}: recovered {} entries but {} were synced",
+                seed,
+                recovered.len(),
+                synced
+            );
+            assert_eq!(recovered, entries[..recovered.len()], "seed {}", seed);
+
+            // The recovered log accepts new writes that survive the next crash.
+            let next = recovered.len() as u64 + 1;
+            wal.append(&[entry(next, 2)]).unwrap();
+            wal.sync().unwrap();
+            disk.crash();
+            let (_, recovered) = WalStorage::open(storage, dir).unwrap().load().unwrap();
+            assert_eq!(recovered.last(), Some(&entry(next, 2)), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn test_wal_replaces_snapshot_atomically() {
+        let dir = Path::new("/raft");
+        let snapshot = |data: &str| Snapshot {
+            last_included_index: 5,
+            last_included_term: 1,
+            membership: Membership::new(BTreeMap::new()),
+            data: data.as_bytes().to_vec(),
+        };
+        for seed in 0..100 {
+            let disk = SimulatedDisk::new(power_loss_config(), seed);
+            let storage: Arc<dyn Storage> = Arc::new(disk.clone());
+            {
+                let mut wal = WalStorage::open(Arc::clone(&storage), dir).unwrap();
+                wal.load().unwrap();
+                wal.append(&(1..=5).map(|index| entry(index, 1)).collect::<Vec<_>>())
+                    .unwrap();
+                wal.sync().unwrap();
+                wal.save_snapshot(&snapshot("first")).unwrap();
+
+                // Fail the second save partway through, if at all.
+                disk.set_io_error_rate(0.3);
+                let _ = wal.save_snapshot(&snapshot("second"));
+                disk.set_io_error_rate(0.0);
+            }
+            disk.crash();
+
+            let saved = WalStorage::open(storage, dir)
+                .unwrap()
+                .load_snapshot()
+                .unwrap();
+            assert!(
+                saved
+                    .as_ref()
+                    .is_some_and(|saved| saved.data == b"first" || saved.data == b"second"),
+                "seed {}: {:?}",
+                seed,
+                saved
+            );
+        }
+    }
+}
@@ -0,0 +1,387 @@
+#[cfg(test)]
+mod tests {
+    use crate::platform::network::{NetworkInterface, TcpStream};
+    use crate::platform::network_interface::SystemNetworkInterface;
+    use crate::platform::rpc::{serve_connection, RpcClient};
+    use crate::platform::tls::{
+        TlsContext, TlsCredentials, TlsNetworkInterface, TlsStream, DEFAULT_HANDSHAKE_TIMEOUT,
+    };
+    use crate::platform_testing::network::{SimulatedNetwork, SimulatedNetworkConfig};
+    use crate::platform_testing::network_interface::SimulatedNetworkInterface;
+    use rcgen::{
+        BasicConstraints, Certificate, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    };
+    use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+    use std::io;
+    use std::sync::Arc;
+    use std::thread::spawn;
+    use std::time::{Duration, Instant};
+
+    const CLIENT_IP: &str = "192.168.1.1";
+    const SERVER_IP: &str = "192.168.1.2";
+    const SERVER_PORT: u16 = 7000;
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// A certificate authority for issuing test node certificates.
+    struct Authority {
+        certificate: Certificate,
+        key: KeyPair,
+    }
+
+    impl Authority {
+        fn new() -> Self {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(Vec::new()).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let certificate = params.self_signed(&key).unwrap();
+            Authority { certificate, key }
+        }
+
+        /// Credentials for a node named `names` that trusts `trusted`.
+        fn issue(&self, names: &[&str], trusted: &[&Authority]) -> TlsCredentials {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(
+                names
+                    .iter()
+                    .map(|name| name.to_string())
+                    .collect::<Vec<_>>(),
+            )
+            .unwrap();
+            params.extended_key_usages = vec![
+                ExtendedKeyUsagePurpose::ServerAuth,
+                ExtendedKeyUsagePurpose::ClientAuth,
+            ];
+            let certificate = params
+                .signed_by(&key, &self.certificate, &self.key)
+                .unwrap();
+            TlsCredentials {
+                certificate_chain: vec![certificate.der().clone()],
+                private_key: PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())),
+                trusted_roots: trusted
+                    .iter()
+                    .map(|authority| authority.certificate.der().clone())
+                    .collect(),
+            }
+        }
+    }
+
+    /// A client and a server interface on a reliable simulated network.
+    fn new_network() -> (
+        Arc<SimulatedNetworkInterface>,
+        Arc<SimulatedNetworkInterface>,
+    ) {
+        new_network_with_config(SimulatedNetworkConfig {
+            drop_rate: 0.0,
+            short_delay_rate: 0.0,
+            long_delay_rate: 0.0,
+            short_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            long_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            duplicate_rate: 0.0,
+        })
+    }
+
+    /// A client and a server interface on a simulated network with `config`.
+    fn new_network_with_config(
+        config: SimulatedNetworkConfig,
+    ) -> (
+        Arc<SimulatedNetworkInterface>,
+        Arc<SimulatedNetworkInterface>,
+    ) {
+        let network = Arc::new(SimulatedNetwork::new(config));
+        network.connect(CLIENT_IP, SERVER_IP);
+        let mut interfaces = Vec::new();
+        for ip in [CLIENT_IP, SERVER_IP] {
+            let network_interface = Arc::new(SimulatedNetworkInterface::new(Arc::clone(&network)));
+            network_interface.assign_ip_addresses(vec![ip]);
+            network.register_network_interface(Arc::clone(&network_interface));
+            interfaces.push(network_interface);
+        }
+        (Arc::clone(&interfaces[0]), Arc::clone(&interfaces[1]))
+    }
+
+    /// Serves an echo on every connection accepted at `ip`:`port`.
+    fn serve_echo(network_interface: &dyn NetworkInterface, ip: &str, port: u16) {
+        let listener = network_interface.bind_tcp(ip, port).unwrap();
+        spawn(move || {
+            while let Ok(stream) = listener.accept() {
+                spawn(move || serve_connection(stream, Arc::new(|data| Some(data.to_vec()))));
+            }
+        });
+    }
+
+    fn check_echo(client: &RpcClient) {
+        assert_eq!(client.call(b"ping", TIMEOUT).unwrap().as_ref(), b"ping");
+        // Spans many TLS records.
+        let large: Vec<u8> = (0..1 << 20).map(|i| i as u8).collect();
+        assert_eq!(client.call(&large, TIMEOUT).unwrap().as_ref(), &large);
+    }
+
+    #[test]
+    fn test_tls_simulated_network() {
+        let authority = Authority::new();
+        let (client, server) = new_network();
+        let server = TlsNetworkInterface::new(
+            server,
+            Arc::new(TlsContext::new(&authority.issue(&[SERVER_IP], &[&authority])).unwrap()),
+        );
+        let client = TlsNetworkInterface::new(
+            client,
+            Arc::new(TlsContext::new(&authority.issue(&[CLIENT_IP], &[&authority])).unwrap()),
+        );
+        serve_echo(&server, SERVER_IP, SERVER_PORT);
+
+        check_echo(&RpcClient::new(
+            client.connect(SERVER_IP, SERVER_PORT).unwrap(),
+        ));
+    }
+
+    #[test]
+    fn test_tls_reordering_network() {
+        let authority = Authority::new();
+        let (client, server) = new_network_with_config(SimulatedNetworkConfig {
+            drop_rate: 0.0,
+            short_delay_rate: 0.3,
+            long_delay_rate: 0.05,
+            short_delay_range: Duration::from_millis(0)..Duration::from_millis(10),
+            long_delay_range: Duration::from_millis(20)..Duration::from_millis(50),
+            duplicate_rate: 0.0,
+        });
+        let server = TlsNetworkInterface::new(
+            server,
+            Arc::new(TlsContext::new(&authority.issue(&[SERVER_IP], &[&authority])).unwrap()),
+        );
+        let client = TlsNetworkInterface::new(
+            client,
+            Arc::new(TlsContext::new(&authority.issue(&[CLIENT_IP], &[&authority])).unwrap()),
+        );
+        serve_echo(&server, SERVER_IP, SERVER_PORT);
+
+        // Records delayed past later ones are put back in order.
+        let client = RpcClient::new(client.connect(SERVER_IP, SERVER_PORT).unwrap());
+        for _ in 0..5 {
+            check_echo(&client);
+        }
+    }
+
+    #[test]
+    fn test_tls_silent_peer_does_not_block_accepts() {
+        let authority = Authority::new();
+        let (client, server) = new_network();
+        let server = TlsNetworkInterface::new(
+            server,
+            Arc::new(TlsContext::new(&authority.issue(&[SERVER_IP], &[&authority])).unwrap()),
+        );
+        let tls_client = TlsNetworkInterface::new(
+            Arc::clone(&client) as Arc<dyn NetworkInterface>,
+            Arc::new(TlsContext::new(&authority.issue(&[CLIENT_IP], &[&authority])).unwrap()),
+        );
+        let listener = server.bind_tcp(SERVER_IP, SERVER_PORT).unwrap();
+
+        // Opens a connection but never sends a ClientHello.
+        let _silent = client.connect(SERVER_IP, SERVER_PORT).unwrap();
+        assert_eq!(
+            listener.try_accept().unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        let start = Instant::now();
+        let connecting = spawn(move || tls_client.connect(SERVER_IP, SERVER_PORT));
+        let stream = listener.accept().unwrap();
+        assert_eq!(stream.get_remote_endpoint().ip, CLIENT_IP);
+        assert!(connecting.join().unwrap().is_ok());
+        assert!(start.elapsed() < DEFAULT_HANDSHAKE_TIMEOUT / 2);
+    }
+
+    #[test]
+    fn test_tls_system_network() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let authority = Authority::new();
+        let credentials = authority.issue(&["127.0.0.1", "localhost"], &[&authority]);
+        let network_interface = TlsNetworkInterface::new(
+            Arc::new(SystemNetworkInterface::new()),
+            Arc::new(TlsContext::new(&credentials).unwrap()),
+        );
+        serve_echo(&network_interface, "127.0.0.1", port);
+
+        check_echo(&RpcClient::new(
+            network_interface.connect("127.0.0.1", port).unwrap(),
+        ));
+        check_echo(&RpcClient::new(
+            network_interface
+                .connect_by_name("localhost", port)
+                .unwrap(),
+        ));
+    }
+
+    #[test]
+    fn test_tls_credentials_from_pem() {
+        let authority = Authority::new();
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec![SERVER_IP.to_string()]).unwrap();
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        let certificate = params
+            .signed_by(&key, &authority.certificate, &authority.key)
+            .unwrap();
+
+        let credentials = TlsCredentials::from_pem(
+            certificate.pem().as_bytes(),
+            key.serialize_pem().as_bytes(),
+            authority.certificate.pem().as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            credentials.certificate_chain,
+            vec![certificate.der().clone()]
+        );
+        assert_eq!(
+            credentials.trusted_roots,
+            vec![authority.certificate.der().clone()]
+        );
+        assert!(TlsContext::new(&credentials).is_ok());
+
+        let error = TlsCredentials::from_pem(b"", b"not a key", b"")
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    /// Runs a handshake between `client` and `server` directly over simulated
+    /// streams, returning how each side's ended.
+    fn handshake(
+        client: TlsCredentials,
+        server: TlsCredentials,
+        server_name: &str,
+    ) -> (io::Result<Arc<dyn TcpStream>>, io::Result<TlsStream>) {
+        let (client_interface, server_interface) = new_network();
+        let listener = server_interface.bind_tcp(SERVER_IP, SERVER_PORT).unwrap();
+        let accepted = spawn(move || {
+            let context = TlsContext::new(&server).unwrap();
+            context.accept(listener.accept().unwrap(), TIMEOUT)
+        });
+
+        let context = TlsContext::new(&client).unwrap();
+        let stream = client_interface.connect(SERVER_IP, SERVER_PORT).unwrap();
+        let connected = context
+            .connect(stream, server_name, TIMEOUT)
+            .map(|stream| Arc::new(stream) as Arc<dyn TcpStream>);
+        // A client that finished its side learns of the server's refusal
+        // with the next read.
+        let connected = connected.and_then(|stream| {
+            stream.send(b"hello");
+            stream.receive_timeout(TIMEOUT).map(|_| stream)
+        });
+        (connected, accepted.join().unwrap())
+    }
+
+    #[test]
+    fn test_tls_rejects_untrusted_peers() {
+        let authority = Authority::new();
+        let other = Authority::new();
+
+        // A client whose certificate comes from an unknown authority.
+        let (client, server) = handshake(
+            other.issue(&[CLIENT_IP], &[&authority]),
+            authority.issue(&[SERVER_IP], &[&authority]),
+            SERVER_IP,
+        );
+        assert_eq!(
+            server.err().unwrap().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        assert!(client.is_err());
+
+        // A server whose certificate comes from an unknown authority.
+        let (client, server) = handshake(
+            authority.issue(&[CLIENT_IP], &[&authority]),
+            other.issue(&[SERVER_IP], &[&authority]),
+            SERVER_IP,
+        );
+        assert_eq!(
+            client.err().unwrap().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        assert!(server.is_err());
+
+        // A client without a certificate at all is turned away too.
+        let mut anonymous = authority.issue(&[CLIENT_IP], &[&authority]);
+        anonymous.certificate_chain.clear();
+        assert!(TlsContext::new(&anonymous).is_err());
+    }
+
+    #[test]
+    fn test_tls_checks_peer_identity() {
+        let authority = Authority::new();
+
+        // The server's certificate names another node.
+        let (client, _) = handshake(
+            authority.issue(&[CLIENT_IP], &[&authority]),
+            authority.issue(&["192.168.1.9"], &[&authority]),
+            SERVER_IP,
+        );
+        assert_eq!(
+            client.err().unwrap().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+
+        let (_, server) = handshake(
+            authority.issue(&["192.168.1.9"], &[&authority]),
+            authority.issue(&[SERVER_IP], &[&authority]),
+            SERVER_IP,
+        );
+        let server = server.unwrap();
+        assert!(server.verify_peer_name("192.168.1.9").is_ok());
+        let error = server.verify_peer_name(CLIENT_IP).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+
+        // Through the interface, the server turns away a client whose
+        // certificate does not name the address it connects from.
+        let (client, server) = new_network();
+        let server = TlsNetworkInterface::new(
+            server,
+            Arc::new(TlsContext::new(&authority.issue(&[SERVER_IP], &[&authority])).unwrap()),
+        );
+        let impostor = TlsNetworkInterface::new(
+            client,
+            Arc::new(TlsContext::new(&authority.issue(&["192.168.1.9"], &[&authority])).unwrap()),
+        );
+        serve_echo(&server, SERVER_IP, SERVER_PORT);
+        let client = RpcClient::new(impostor.connect(SERVER_IP, SERVER_PORT).unwrap());
+        assert!(client.call(b"ping", TIMEOUT).is_err());
+    }
+
+    #[test]
+    fn test_tls_rotation() {
+        let old = Authority::new();
+        let new = Authority::new();
+        let (client, server) = new_network();
+        let server_context = Arc::new(TlsContext::new(&old.issue(&[SERVER_IP], &[&old])).unwrap());
+        let client_context = Arc::new(TlsContext::new(&old.issue(&[CLIENT_IP], &[&old])).unwrap());
+        let server = TlsNetworkInterface::new(server, Arc::clone(&server_context));
+        let client = TlsNetworkInterface::new(client, Arc::clone(&client_context));
+        serve_echo(&server, SERVER_IP, SERVER_PORT);
+        let established = RpcClient::new(client.connect(SERVER_IP, SERVER_PORT).unwrap());
+        check_echo(&established);
+
+        // The server moves to a certificate from the new authority before
+        // the client trusts it: new connections fail, the open one carries on.
+        server_context
+            .rotate(&new.issue(&[SERVER_IP], &[&old, &new]))
+            .unwrap();
+        let error = client.connect(SERVER_IP, SERVER_PORT).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        check_echo(&established);
+
+        // Once the client has rotated too, new connections succeed again.
+        client_context
+            .rotate(&new.issue(&[CLIENT_IP], &[&new]))
+            .unwrap();
+        check_echo(&RpcClient::new(
+            client.connect(SERVER_IP, SERVER_PORT).unwrap(),
+        ));
+        check_echo(&established);
+    }
+}
@@ -0,0 +1,332 @@
+#[cfg(test)]
+mod tests {
+    use crate::platform::network::Endpoint;
+    use crate::platform_testing::network::{SimulatedNetwork, SimulatedNetworkConfig};
+    use crate::platform_testing::network_interface::SimulatedNetworkInterface;
+    use crate::raft::grpc_transport::GrpcTransport;
+    use crate::raft::node::{RaftConfig, RaftNode};
+    use crate::raft::proto::{
+        AppendEntriesRequest, AppendEntriesResponse, LogEntry, RequestVoteRequest,
+        RequestVoteResponse, TimeoutNowRequest,
+    };
+    use crate::raft::rpc::{RaftRequest, RaftResponse};
+    use crate::raft::state_machine::StateMachine;
+    use crate::raft::storage::MemoryStorage;
+    use crate::raft::tcp_transport::TcpTransport;
+    use crate::raft::transport::{MemoryNetwork, MemoryTransport, RaftHandler, RaftTransport};
+    use std::collections::HashMap;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    const RAFT_PORT: u16 = 7000;
+    const TIMEOUT: Duration = Duration::from_millis(500);
+
+    /// Answers votes with their term and appends with how many entries
+    /// arrived. Pre-votes get a vote response, which is the wrong kind, and
+    /// everything else is dropped.
+    fn echo_handler() -> RaftHandler {
+        Arc::new(|request| match request {
+            RaftRequest::RequestVote(request) | RaftRequest::PreVote(request) => {
+                Some(RaftResponse::RequestVote(RequestVoteResponse {
+                    term: request.term,
+                    vote_granted: true,
+                }))
+            }
+            RaftRequest::AppendEntries(request) => {
+                Some(RaftResponse::AppendEntries(AppendEntriesResponse {
+                    term: request.term,
+                    success: true,
+                    conflict_index: request.entries.len() as u64,
+                    conflict_term: 0,
+                }))
+            }
+            RaftRequest::InstallSnapshot(_) => None,
+            RaftRequest::TimeoutNow(_) => None,
+        })
+    }
+
+    fn endpoint(ip: &str) -> Endpoint {
+        Endpoint {
+            ip: ip.to_string(),
+            port: RAFT_PORT,
+        }
+    }
+
+    fn ips(size: usize) -> Vec<String> {
+        (1..=size).map(|i| format!("192.168.1.{}", i)).collect()
+    }
+
+    fn reliable_network(size: usize) -> Arc<SimulatedNetwork> {
+        let network = Arc::new(SimulatedNetwork::new(SimulatedNetworkConfig {
+            drop_rate: 0.0,
+            short_delay_rate: 0.0,
+            long_delay_rate: 0.0,
+            short_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            long_delay_range: Duration::from_millis(0)..Duration::from_millis(0),
+            duplicate_rate: 0.0,
+        }));
+        let ips = ips(size);
+        for (i, a) in ips.iter().enumerate() {
+            for b in ips[i + 1..].iter() {
+                network.connect(a, b);
+            }
+        }
+        network
+    }
+
+    fn new_network_interface(
+        network: &Arc<SimulatedNetwork>,
+        ip: &str,
+    ) -> Arc<SimulatedNetworkInterface> {
+        let network_interface = Arc::new(SimulatedNetworkInterface::new(Arc::clone(network)));
+        network_interface.assign_ip_addresses(vec![ip]);
+        network_interface.set_connect_timeout(Duration::from_millis(500));
+        network.register_network_interface(Arc::clone(&network_interface));
+        network_interface
+    }
+
+    /// Calls a server serving `echo_handler` from a client and checks each
+    /// typed method gets back the response meant for it.
+    fn check_round_trip(client: &dyn RaftTransport, server: &Endpoint) {
+        let response = client
+            .request_vote(
+                server,
+                RequestVoteRequest {
+                    term: 3,
+                    ..Default::default()
+                },
+                TIMEOUT,
+            )
+            .unwrap();
+        assert_eq!(response.term, 3);
+        assert!(response.vote_granted);
+
+        let request = AppendEntriesRequest {
+            term: 4,
+            entries: (1..=100)
+                .map(|index| LogEntry {
+                    index,
+                    term: 4,
+                    payload: vec![7; 100],
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let response = client.append_entries(server, request, TIMEOUT).unwrap();
+        assert_eq!(response.term, 4);
+        assert_eq!(response.conflict_index, 100);
+
+        // A server that answers with the wrong method's response is an error,
+        // as is one that drops the request.
+        assert!(client
+            .pre_vote(server, RequestVoteRequest::default(), TIMEOUT)
+            .is_err());
+        assert!(client
+            .timeout_now(server, TimeoutNowRequest::default(), TIMEOUT)
+            .is_err());
+    }
+
+    #[test]
+    fn test_memory_transport() {
+        let network = MemoryNetwork::new();
+        let client = MemoryTransport::new(Arc::clone(&network), endpoint("192.168.1.1"));
+        let server = MemoryTransport::new(Arc::clone(&network), endpoint("192.168.1.2"));
+        let server_endpoint = endpoint("192.168.1.2");
+
+        let error = client
+            .request_vote(&server_endpoint, RequestVoteRequest::default(), TIMEOUT)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+
+        server.serve(echo_handler()).unwrap();
+        assert_eq!(
+            server.serve(echo_handler()).unwrap_err().kind(),
+            io::ErrorKind::AddrInUse
+        );
+        check_round_trip(&client, &server_endpoint);
+
+        network.disconnect(&server_endpoint);
+        let error = client
+            .request_vote(&server_endpoint, RequestVoteRequest::default(), TIMEOUT)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        network.connect(&server_endpoint);
+        check_round_trip(&client, &server_endpoint);
+
+        server.shutdown();
+        let error = client
+            .request_vote(&server_endpoint, RequestVoteRequest::default(), TIMEOUT)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn test_tcp_transport() {
+        let network = reliable_network(2);
+        let client = TcpTransport::new(
+            new_network_interface(&network, "192.168.1.1"),
+            endpoint("192.168.1.1"),
+        );
+        let server = TcpTransport::new(
+            new_network_interface(&network, "192.168.1.2"),
+            endpoint("192.168.1.2"),
+        );
+        server.serve(echo_handler()).unwrap();
+
+        check_round_trip(&client, &endpoint("192.168.1.2"));
+        server.shutdown();
+    }
+
+    #[test]
+    fn test_grpc_transport() {
+        let network = reliable_network(2);
+        let client = GrpcTransport::new(
+            new_network_interface(&network, "192.168.1.1"),
+            endpoint("192.168.1.1"),
+        )
+        .unwrap();
+        let server = GrpcTransport::new(
+            new_network_interface(&network, "192.168.1.2"),
+            endpoint("192.168.1.2"),
+        )
+        .unwrap();
+        server.serve(echo_handler()).unwrap();
+
+        check_round_trip(&client, &endpoint("192.168.1.2"));
+    }
+
+    /// Counts the commands it applies.
+    struct CountingStateMachine {
+        applied: Arc<Mutex<u64>>,
+    }
+
+    impl StateMachine for CountingStateMachine {
+        fn apply(&mut self, _index: u64, _command: &[u8]) -> Vec<u8> {
+            let mut applied = self.applied.lock().unwrap();
+            *applied += 1;
+            applied.to_be_bytes().to_vec()
+        }
+
+        fn snapshot(&self) -> Vec<u8> {
+            self.applied.lock().unwrap().to_be_bytes().to_vec()
+        }
+
+        fn restore(&mut self, snapshot: &[u8]) {
+            *self.applied.lock().unwrap() = u64::from_be_bytes(snapshot.try_into().unwrap());
+        }
+    }
+
+    /// Starts one node per transport, with the nodes at `ips` as its peers,
+    /// and returns them along with how many commands each has applied.
+    fn start_nodes(
+        ips: &[String],
+        transports: Vec<Arc<dyn RaftTransport>>,
+    ) -> (Vec<Arc<RaftNode>>, Vec<Arc<Mutex<u64>>>) {
+        let mut nodes = Vec::new();
+        let mut applied = Vec::new();
+        for (ip, transport) in ips.iter().zip(transports) {
+            let peers: HashMap<String, Endpoint> = ips
+                .iter()
+                .filter(|peer| *peer != ip)
+                .map(|peer| (peer.clone(), endpoint(peer)))
+                .collect();
+            let count = Arc::new(Mutex::new(0));
+            let node = RaftNode::new(
+                RaftConfig::new(ip, endpoint(ip), peers),
+                transport,
+                Box::new(CountingStateMachine {
+                    applied: Arc::clone(&count),
+                }),
+                Box::new(MemoryStorage::new()),
+            );
+            node.start().unwrap();
+            nodes.push(node);
+            applied.push(count);
+        }
+        (nodes, applied)
+    }
+
+    /// Waits for one of `candidates` to lead and returns its index.
+    async fn wait_for_leader(nodes: &[Arc<RaftNode>], candidates: &[usize]) -> usize {
+        for _ in 0..50 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let leaders: Vec<usize> = candidates
+                .iter()
+                .copied()
+                .filter(|&i| nodes[i].is_leader())
+                .collect();
+            if leaders.len() == 1 {
+                return leaders[0];
+            }
+        }
+        panic!("no leader elected");
+    }
+
+    /// Waits for every node in `nodes` to have applied `expected` commands.
+    async fn wait_applied(applied: &[Arc<Mutex<u64>>], nodes: &[usize], expected: u64) {
+        for _ in 0..50 {
+            if nodes
+                .iter()
+                .all(|&i| *applied[i].lock().unwrap() == expected)
+            {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("commands were not applied everywhere");
+    }
+
+    #[tokio::test]
+    async fn test_raft_over_memory_transport() {
+        let ips = ips(3);
+        let network = MemoryNetwork::new();
+        let transports = ips
+            .iter()
+            .map(|ip| Arc::new(MemoryTransport::new(Arc::clone(&network), endpoint(ip))) as _)
+            .collect();
+        let (nodes, applied) = start_nodes(&ips, transports);
+
+        let leader = wait_for_leader(&nodes, &[0, 1, 2]).await;
+        nodes[leader].propose(vec![1]).await.unwrap();
+        wait_applied(&applied, &[0, 1, 2], 1).await;
+
+        // The others elect a new leader without the old one and carry on.
+        network.disconnect(&endpoint(&ips[leader]));
+        let others: Vec<usize> = (0..3).filter(|&i| i != leader).collect();
+        let new_leader = wait_for_leader(&nodes, &others).await;
+        nodes[new_leader].propose(vec![2]).await.unwrap();
+        wait_applied(&applied, &others, 2).await;
+
+        network.connect(&endpoint(&ips[leader]));
+        wait_applied(&applied, &[0, 1, 2], 2).await;
+        for node in nodes.iter() {
+            node.stop();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_raft_over_grpc_transport() {
+        let ips = ips(3);
+        let network = reliable_network(3);
+        let transports = ips
+            .iter()
+            .map(|ip| {
+                Arc::new(
+                    GrpcTransport::new(new_network_interface(&network, ip), endpoint(ip)).unwrap(),
+                ) as _
+            })
+            .collect();
+        let (nodes, applied) = start_nodes(&ips, transports);
+
+        let leader = wait_for_leader(&nodes, &[0, 1, 2]).await;
+        for command in 1..=5 {
+            nodes[leader].propose(vec![command]).await.unwrap();
+        }
+        wait_applied(&applied, &[0, 1, 2], 5).await;
+        for node in nodes.iter() {
+            node.stop();
+        }
+    }
+}